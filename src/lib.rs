@@ -1,10 +1,18 @@
 pub use anathema::geometry::{Pos, Size};
 pub use parser::parse;
+pub use parser::registry::{explain, summaries};
 
-pub use crate::parser::Variable;
+pub use crate::parser::{LoopCount, Variable, Warning};
 pub use crate::ui::instructions::Instruction;
-pub use crate::ui::{compile, print_syntaxes, print_themes, run, setup_paths};
+pub use crate::ui::{
+    Budget, Collision, CompileOptions, CompiledInstructions, Definition, Diagnostic, Ending, FsLoader, Load, Loader,
+    Left, Margins, MemoryLoader, Options, Progress, Seek, Severity, Source, StepBy, TIME_SCALE, Timings, attach,
+    budgets, calibration, compile, compile_all, compile_with_loader, compile_with_options, instructions_from_json,
+    instructions_to_json, marker_collisions, preview, print_syntaxes, print_themes, run, run_with, scenes, setup_paths,
+    validate, validate_with_loader,
+};
 
 mod parser;
+pub mod testkit;
 
 mod ui;
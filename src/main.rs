@@ -1,6 +1,12 @@
 use std::env::args;
+use std::path::Path;
+use std::time::Duration;
 
-use mimic::{compile, parse};
+use mimic::{
+    CompileOptions, Instruction, LoopCount, Margins, Options, Progress, Seek, Severity, StepBy, TIME_SCALE, Variable,
+    budgets, compile_with_options, instructions_from_json, instructions_to_json, marker_collisions, parse, scenes,
+    validate,
+};
 
 fn help() {
     println!(
@@ -8,9 +14,38 @@ fn help() {
 Usage
 -----
 
-run:            mimic <file path>
+run:            mimic [options] <file path>
 print syntaxes: mimic --syntax
 print themes:   mimic --themes
+list scenes:    mimic --list-scenes <file path>
+watch a mirror: mimic --attach <host:port>
+html preview:   mimic --preview-http <host:port> <file path>
+calibrate:      mimic --audio-calibrate <audio dir> [--audio-offset <ms>]
+instructions:   mimic --explain [instruction]
+
+options:
+    --deny-warnings     treat warnings as errors
+    --optimize          merge waits and drop instructions that do nothing
+    --var <name=value>  set a variable, over any `set` or `let` of it in the script (repeatable)
+    --dump-instructions <path>
+                        write the compiled instructions to the file as JSON instead of running,
+                        a .json file in place of the script runs them
+    --stats             print the actual time of each budget and of loading syntaxes after running
+    --serve <port>      mirror the editor to anyone attaching to this port
+    --audio-offset <ms> play typing sounds this much ahead of the glyphs
+    --margins <t,r,b,l> keep this many cells around the editor empty
+    --scrolloff <rows[,cols]>
+                        keep this many rows above and below the cursor, and columns to either side
+    --time-scale <x>    play the script x times faster, + and - change it while running
+    --skip-splash       don't show splash blocks
+    --step              hold before every instruction until n or enter is pressed, s turns it on and off
+    --step-chars        like --step, a typed character or an instruction at a time
+    --loop [<times>]    play the script again when it ends, forever unless it's given how many times
+    --from @<marker>    apply the script at once up to where the marker is set, then play the rest
+    --from <scene>      like --from @<marker>, starting at the scene
+    --from-instruction <n>
+                        like --from, starting at the nth instruction
+    --events <path>     write document stats and markers to the file as JSON lines
 
 example: mimic code.echo
 
@@ -20,28 +55,293 @@ For more information see https://github.com/togglebyte/mimic
 }
 
 fn main() -> anyhow::Result<()> {
-    let mut args = args().skip(1);
+    let mut path = None;
+    let mut deny_warnings = false;
+    let mut stats = false;
+    let mut list_scenes = false;
+    let mut options = Options::default();
+    let mut calibrate = None;
+    let mut audio_offset = None;
+    let mut margins = None;
+    let mut scrolloff = None;
+    let mut skip_splash = false;
+    let mut preview = None;
+    let mut compile_options = CompileOptions::default();
+    let mut dump_instructions = None;
+
+    let mut args = args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                mimic::setup_paths::ensure_exists()?;
+                mimic::print_syntaxes();
+                return Ok(());
+            }
+            "--themes" => {
+                mimic::setup_paths::ensure_exists()?;
+                mimic::print_themes();
+                return Ok(());
+            }
+            "--explain" => {
+                match args.next() {
+                    None => print!("{}", mimic::summaries()),
+                    Some(name) => match mimic::explain(&name) {
+                        Some(explanation) => print!("{explanation}"),
+                        None => anyhow::bail!("no instruction named `{name}`"),
+                    },
+                }
+                return Ok(());
+            }
+            "--deny-warnings" => deny_warnings = true,
+            "--stats" => stats = true,
+            "--optimize" => compile_options.optimize = true,
+            "--var" => {
+                let Some(var) = args.next() else { anyhow::bail!("--var needs name=value") };
+                let Some((name, value)) = var.split_once('=') else { anyhow::bail!("--var needs name=value") };
+                compile_options.variables.insert(name.into(), variable(value));
+            }
+            "--dump-instructions" => {
+                let Some(path) = args.next() else { anyhow::bail!("--dump-instructions needs a path") };
+                dump_instructions = Some(path);
+            }
+            "--list-scenes" => list_scenes = true,
+            "--skip-splash" => skip_splash = true,
+            "--step" => options.step = Some(StepBy::Instruction),
+            "--step-chars" => options.step = Some(StepBy::Char),
+            "--loop" => {
+                let times = args.peek().and_then(|times| times.parse::<u32>().ok());
+                options.looping = match times {
+                    Some(0) => anyhow::bail!("--loop needs to play at least once"),
+                    Some(times) => {
+                        args.next();
+                        Some(LoopCount::Times(times))
+                    }
+                    None => Some(LoopCount::Forever),
+                };
+            }
+            "--from" => {
+                let Some(from) = args.next().as_deref().and_then(Seek::from_arg) else {
+                    anyhow::bail!("--from needs a marker, like @name, or the name of a scene")
+                };
+                options.from = Some(from);
+            }
+            "--from-instruction" => {
+                let index = args.next().and_then(|index| index.parse().ok()).filter(|index| *index > 0);
+                let Some(index) = index else { anyhow::bail!("--from-instruction needs a number from 1") };
+                options.from = Some(Seek::Instruction(index));
+            }
+            "--events" => {
+                let Some(path) = args.next() else { anyhow::bail!("--events needs a path") };
+                options.events = Some(path.into());
+            }
+            "--serve" => {
+                let Some(port) = args.next() else { anyhow::bail!("--serve needs a port") };
+                options.serve = Some(port.parse()?);
+            }
+            "--attach" => {
+                let Some(addr) = args.next() else { anyhow::bail!("--attach needs an address (host:port)") };
+                mimic::attach(&addr)?;
+                return Ok(());
+            }
+            "--preview-http" => {
+                let Some(addr) = args.next() else { anyhow::bail!("--preview-http needs an address (host:port)") };
+                preview = Some(addr);
+            }
+            "--audio-calibrate" => {
+                let Some(audio) = args.next() else { anyhow::bail!("--audio-calibrate needs an audio directory") };
+                calibrate = Some(audio);
+            }
+            "--audio-offset" => {
+                let Some(ms) = args.next() else { anyhow::bail!("--audio-offset needs milliseconds") };
+                audio_offset = Some(ms.parse()?);
+            }
+            "--margins" => {
+                let Some(value) = args.next() else { anyhow::bail!("--margins needs top,right,bottom,left") };
+                let values = value.split(',').map(str::parse).collect::<Result<Vec<u16>, _>>()?;
+                let [top, right, bottom, left] = values[..] else {
+                    anyhow::bail!("--margins needs four values: top,right,bottom,left")
+                };
+                margins = Some(Margins {
+                    top,
+                    right,
+                    bottom,
+                    left,
+                });
+            }
+            "--scrolloff" => {
+                let Some(value) = args.next() else { anyhow::bail!("--scrolloff needs rows[,columns]") };
+                let values = value.split(',').map(str::parse).collect::<Result<Vec<u16>, _>>()?;
+                scrolloff = match values[..] {
+                    [rows] => Some(Instruction::ScrollOff { rows, columns: None }),
+                    [rows, columns] => Some(Instruction::ScrollOff {
+                        rows,
+                        columns: Some(columns),
+                    }),
+                    _ => anyhow::bail!("--scrolloff needs rows, or rows,columns"),
+                };
+            }
+            "--time-scale" => {
+                let Some(factor) = args.next() else { anyhow::bail!("--time-scale needs a factor") };
+                let factor = factor.parse::<f64>()?;
+                let scale = factor.clamp(*TIME_SCALE.start(), *TIME_SCALE.end());
+                if scale != factor {
+                    eprintln!("warning: time scale {factor} is out of range, using {scale}");
+                }
+                options.time_scale = Some(scale);
+            }
+            _ => path = Some(arg),
+        }
+    }
+
+    if let Some(audio) = calibrate {
+        mimic::setup_paths::ensure_exists()?;
+        mimic::run(mimic::calibration(audio.into(), audio_offset.unwrap_or(0)))?;
+        return Ok(());
+    }
 
-    let Some(arg) = args.next() else {
+    let Some(path) = path else {
         help();
         return Ok(());
     };
 
+    if let Some(addr) = preview {
+        eprintln!("serving the preview of {path} on http://{addr}");
+        mimic::preview(&addr, path.into())?;
+        return Ok(());
+    }
+
     mimic::setup_paths::ensure_exists()?;
 
-    if arg == "--syntax" {
-        mimic::print_syntaxes();
+    let mut warnings = 0;
+    let mut instructions = match Path::new(&path).extension().is_some_and(|ext| ext == "json") {
+        // Already compiled, there is nothing for them to change
+        true if compile_options.optimize || !compile_options.variables.is_empty() => {
+            anyhow::bail!("--var and --optimize can't be used with compiled instructions (.json)")
+        }
+        true => instructions_from_json(&std::fs::read_to_string(&path)?)?,
+        false => compile_script(&path, compile_options, &mut warnings)?,
+    };
+
+    // What the script compiled to with `--var` and `--optimize`, before the options that only change how it plays
+    if let Some(out) = dump_instructions {
+        std::fs::write(&out, instructions_to_json(&instructions))?;
         return Ok(());
     }
 
-    if arg == "--themes" {
-        mimic::print_themes();
+    if let Some(ms) = audio_offset {
+        instructions.insert(0, Instruction::AudioOffset(ms));
+    }
+    if let Some(margins) = margins {
+        instructions.insert(0, Instruction::Margins(margins));
+    }
+    if let Some(scrolloff) = scrolloff {
+        instructions.insert(0, scrolloff);
+    }
+    if skip_splash {
+        instructions.retain(|inst| !matches!(inst, Instruction::Splash(_)));
+    }
+
+    if list_scenes {
+        for (name, start) in scenes(&instructions) {
+            match options.time_scale {
+                Some(scale) => println!("{}  ({} scaled)  {name}", minutes(start), minutes(start.div_f64(scale)).trim_start()),
+                None => println!("{}  {name}", minutes(start)),
+            }
+        }
         return Ok(());
     }
 
-    let echo = std::fs::read_to_string(arg)?;
-    let instructions = parse(&echo)?;
-    let instructions = compile(instructions)?;
-    mimic::run(instructions)?;
+    let budgets = budgets(&instructions)?;
+    for budget in &budgets {
+        if let Some(overshoot) = budget.overshoot() {
+            warnings += 1;
+            eprintln!(
+                "warning: budget on line {} is {:.1}s over (estimated {:.1}s, budget {:.1}s)",
+                budget.line,
+                overshoot.as_secs_f64(),
+                budget.estimated.as_secs_f64(),
+                budget.limit.as_secs_f64(),
+            );
+        }
+    }
+
+    for collision in marker_collisions(&instructions) {
+        warnings += 1;
+        eprintln!("warning: {collision}");
+    }
+
+    if deny_warnings && warnings > 0 {
+        anyhow::bail!("{warnings} warning(s) denied");
+    }
+
+    let time_scale = options.time_scale;
+    options.progress = Some(Progress::new(&instructions));
+    let (_, timings) = mimic::run_with(instructions, options)?;
+
+    if stats {
+        if let Some(load) = timings.load() {
+            eprintln!("syntaxes and themes: {load}");
+        }
+        for (budget, actual) in budgets.iter().zip(timings.actual()) {
+            let actual = match actual {
+                Some(actual) => format!("{:.1}s", actual.as_secs_f64()),
+                None => "-".into(),
+            };
+            let scaled = match time_scale {
+                Some(scale) => format!(" ({:.1}s scaled)", budget.estimated.div_f64(scale).as_secs_f64()),
+                None => String::new(),
+            };
+            eprintln!(
+                "budget on line {}: estimated {:.1}s{scaled}, actual {actual}, budget {:.1}s",
+                budget.line,
+                budget.estimated.as_secs_f64(),
+                budget.limit.as_secs_f64(),
+            );
+        }
+    }
+
     Ok(())
 }
+
+// Parse, check and compile the script, printing the warnings and counting them
+fn compile_script(path: &str, mut options: CompileOptions, warnings: &mut usize) -> anyhow::Result<Vec<Instruction>> {
+    let echo = std::fs::read_to_string(path)?;
+    let instructions = parse(&echo)?;
+    for warning in instructions.warnings() {
+        *warnings += 1;
+        eprintln!("warning: {warning}");
+    }
+
+    let mut errors = 0;
+    for diagnostic in validate(&instructions, Some(Path::new(path))) {
+        match diagnostic.severity {
+            Severity::Warning => *warnings += 1,
+            Severity::Error => errors += 1,
+        }
+        eprintln!("{diagnostic}");
+    }
+    if errors > 0 {
+        anyhow::bail!("{errors} error(s) found before running");
+    }
+
+    options.script = Some(path.into());
+    Ok(compile_with_options(instructions, &options)?)
+}
+
+// true and false are bools, whole numbers are ints, anything else is a string
+fn variable(value: &str) -> Variable {
+    match value {
+        "true" => Variable::Bool(true),
+        "false" => Variable::Bool(false),
+        _ => match value.parse() {
+            Ok(int) => Variable::Int(int),
+            Err(_) => Variable::Str(value.into()),
+        },
+    }
+}
+
+// m:ss, right aligned
+fn minutes(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:>3}:{:02}", secs / 60, secs % 60)
+}
@@ -1,5 +1,8 @@
 use std::fmt::{Display, Formatter};
 
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::parser::registry;
 use crate::parser::token::{Span, Token};
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -23,6 +26,64 @@ impl Error {
         Err(error)
     }
 
+    #[cfg(test)]
+    pub(crate) fn start(&self) -> Span {
+        self.start
+    }
+
+    /// The line the error is on, from 1
+    pub fn line(&self) -> usize {
+        self.start.line as usize
+    }
+
+    /// The column the error starts at, in characters from 1
+    pub fn col(&self) -> usize {
+        let (start, _) = self.line_range();
+        self.source[start..self.offset()].chars().count() + 1
+    }
+
+    /// The text of the line the error is on
+    pub fn line_text(&self) -> &str {
+        let (start, end) = self.line_range();
+        &self.source[start..end]
+    }
+
+    // The start of the error, at most the end of the source
+    fn offset(&self) -> usize {
+        let mut offset = self.start.offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    // Byte range of the line the error is on, without the newline
+    fn line_range(&self) -> (usize, usize) {
+        let offset = self.offset();
+        let start = self.source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.source[offset..].find('\n').map_or(self.source.len(), |i| offset + i);
+        (start, end)
+    }
+
+    // Spaces up to the start of the error, keeping tabs so the caret lines up,
+    // and a caret under every column of the token
+    fn underline(&self) -> String {
+        let (line_start, line_end) = self.line_range();
+        let offset = self.offset();
+        let mut underline = self.source[line_start..offset]
+            .chars()
+            .map(|c| match c {
+                '\t' => "\t".to_string(),
+                c => " ".repeat(c.width().unwrap_or(0)),
+            })
+            .collect::<String>();
+
+        let end = self.end.offset.clamp(offset, line_end);
+        let width = self.source[offset..end].trim_end().width().max(1);
+        underline.push_str(&"^".repeat(width));
+        underline
+    }
+
     // -----------------------------------------------------------------------------
     //   - Lex errors -
     // -----------------------------------------------------------------------------
@@ -30,10 +91,30 @@ impl Error {
         Self::err(ErrorKind::InvalidInteger, (span, span), source)
     }
 
+    pub(crate) fn invalid_duration<T>(reason: &'static str, span: Span, source: impl Into<String>) -> Result<T> {
+        Self::err(ErrorKind::InvalidDuration(reason), (span, span), source)
+    }
+
     pub(crate) fn unterminated_string<T>(span: Span, source: impl Into<String>) -> Result<T> {
         Self::err(ErrorKind::UnterminatedString, (span, span), source)
     }
 
+    pub(crate) fn invalid_heredoc<T>(reason: &'static str, span: Span, source: impl Into<String>) -> Result<T> {
+        Self::err(ErrorKind::InvalidHeredoc(reason), (span, span), source)
+    }
+
+    pub(crate) fn unterminated_heredoc<T>(delimiter: String, span: Span, source: impl Into<String>) -> Result<T> {
+        let kind = ErrorKind::UnterminatedHeredoc {
+            delimiter,
+            line: span.line,
+        };
+        Self::err(kind, (span, span), source)
+    }
+
+    pub(crate) fn invalid_escape<T>(reason: &'static str, span: Span, source: impl Into<String>) -> Result<T> {
+        Self::err(ErrorKind::InvalidEscape(reason), (span, span), source)
+    }
+
     // -----------------------------------------------------------------------------
     //   - Parse errors -
     // -----------------------------------------------------------------------------
@@ -41,10 +122,6 @@ impl Error {
         Self::err(ErrorKind::InvalidInstruction(token), spans, source)
     }
 
-    pub(crate) fn invalid_include_path<T>(path: String, spans: (Span, Span), source: impl Into<String>) -> Result<T> {
-        Self::err(ErrorKind::InvalidIncludePath(path), spans, source)
-    }
-
     pub(crate) fn invalid_arg<T>(
         expected: &'static str,
         token: Token,
@@ -89,12 +166,13 @@ impl Display for Error {
         let row_width = (from + to - 1).to_string().len();
 
         writeln!(f)?;
+        writeln!(f, "line {}, column {}: {}", self.line(), self.col(), self.kind)?;
         for (no, line) in lines {
             let gutter = format!("{:>row_width$}: ", no + 1);
             writeln!(f, "{gutter}{line}")?;
-            if no + 1 == self.start.line as usize {
+            if no + 1 == self.line() {
                 let indent = " ".repeat(gutter.len());
-                writeln!(f, "{indent}^-- {}", self.kind)?;
+                writeln!(f, "{indent}{}", self.underline())?;
             }
         }
         Ok(())
@@ -104,17 +182,33 @@ impl Display for Error {
 impl std::error::Error for Error {
 }
 
+/// Something that parses, but should be written differently
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
 #[derive(Debug)]
 pub enum ErrorKind {
     // Lex errors
     UnterminatedString,
     InvalidInteger,
+    InvalidDuration(&'static str),
+    InvalidEscape(&'static str),
+    InvalidHeredoc(&'static str),
+    UnterminatedHeredoc { delimiter: String, line: u16 },
 
     // Parse errors
     InvalidArg { expected: &'static str, found: String },
     InvalidInstruction(Token),
     UnexpectedToken { expected: &'static str, found: String },
-    InvalidIncludePath(String),
 }
 
 impl Display for ErrorKind {
@@ -122,12 +216,21 @@ impl Display for ErrorKind {
         match self {
             ErrorKind::UnterminatedString => write!(f, "unterminated string"),
             ErrorKind::InvalidInteger => write!(f, "invalid integer"),
+            ErrorKind::InvalidDuration(reason) => write!(f, "invalid duration: {reason}"),
+            ErrorKind::InvalidEscape(reason) => write!(f, "invalid escape: {reason}"),
+            ErrorKind::InvalidHeredoc(reason) => write!(f, "invalid heredoc: {reason}"),
+            ErrorKind::UnterminatedHeredoc { delimiter, line } => {
+                write!(f, "unterminated heredoc: `<<{delimiter}` on line {line} has no line with `{delimiter}` after it")
+            }
             ErrorKind::InvalidArg { expected, found } => write!(f, "expected `{expected}`, found `{found}`"),
+            ErrorKind::InvalidInstruction(Token::Ident(name)) => match registry::suggest(name) {
+                Some(suggestion) => write!(f, "invalid instruction: `{name}`, did you mean `{suggestion}`?"),
+                None => write!(f, "invalid instruction: `{name}`"),
+            },
             ErrorKind::InvalidInstruction(token) => write!(f, "invalid instruction: `{token}`"),
             ErrorKind::UnexpectedToken { expected, found } => {
                 write!(f, "unexpected token, `{expected}`, found `{found}`")
             }
-            ErrorKind::InvalidIncludePath(path) => write!(f, "invalid include path: `{path}`"),
         }
     }
 }
@@ -1,9 +1,21 @@
 use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::error::Warning;
 
 #[derive(Debug, PartialEq)]
 pub enum Dest {
     Relative { row: i32, col: i32 },
-    Marker(String),
+    /// `row` is the line number, counting from 1 like the line numbers do
+    Absolute { row: usize, col: usize },
+    /// The start of the document
+    Start,
+    /// The start of the last line that isn't empty
+    End,
+    /// The marker, moved by `row` and `col`
+    Marker { name: String, row: i32, col: i32 },
 }
 
 impl From<(i32, i32)> for Dest {
@@ -14,41 +26,283 @@ impl From<(i32, i32)> for Dest {
 
 impl From<&str> for Dest {
     fn from(dest: &str) -> Self {
-        Self::Marker(dest.into())
+        Self::Marker {
+            name: dest.into(),
+            row: 0,
+            col: 0,
+        }
     }
 }
 
+/// A change to the content of a source, like `indent 4` in `type code | indent 4`.
+/// The name and arguments are checked when compiling.
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    pub args: Vec<Variable>,
+    pub line: usize,
+}
+
+/// Lines of a file, counting from 1 and including the end.
+/// Without an end the range goes to the end of the file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Lines {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Source {
     Str(String),
     Ident(String),
+    /// An environment variable, read when compiling
+    Env { name: String, default: Option<String> },
 }
 
-#[derive(Debug, PartialEq)]
+/// Where a popup is drawn
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum PopupPosition {
+    /// Below the cursor, following it
+    #[default]
+    Cursor,
+    Top,
+    Bottom,
+    Center,
+    /// A row and column of the editor, from 0 at the top left
+    At { row: u16, col: u16 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TransitionKind {
+    Dissolve,
+    WipeLeft,
+    WipeDown,
+}
+
+/// How the view scrolls horizontally to follow the cursor
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum HScroll {
+    /// Center the cursor once it leaves the view
+    Jump,
+    /// Scroll continuously to keep a margin around the cursor
+    Smooth,
+    /// Keep the view as long as the cursor is visible
+    #[default]
+    Keep,
+}
+
+/// How the time between typed characters varies
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum JitterProfile {
+    /// Random jitter between every character
+    #[default]
+    Uniform,
+    /// Type words in bursts, with a pause before every space and newline
+    Burst,
+    Off,
+}
+
+/// What happens when `find` doesn't find anything
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum FindMiss {
+    /// Leave the cursor where it is and carry on
+    #[default]
+    Stay,
+    /// Stop the script with an error
+    Error,
+    /// Drop the next instructions, as they depend on the find
+    Skip(usize),
+}
+
+/// How many times the script plays
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LoopCount {
+    Times(u32),
+    Forever,
+}
+
+/// The line virtual text belongs to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Anchor {
+    /// Zero based row
+    Row(usize),
+    Marker(String),
+}
+
+/// An attribute of text drawn outside the document
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TextStyle {
+    Bold,
+    Dim,
+    Italic,
+    Underlined,
+    Reversed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Variable {
     Bool(bool),
     Str(String),
     Int(i64),
 }
 
+/// Integer arithmetic on numbers and int variables, worked out when compiling
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Int(i64),
+    Variable(String),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Expr {
+    pub fn binary(lhs: Expr, op: Op, rhs: Expr) -> Self {
+        Self::Binary(Box::new(lhs), op, Box::new(rhs))
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Int(i) => write!(f, "{i}"),
+            Expr::Variable(name) => write!(f, "{name}"),
+            Expr::Binary(lhs, op, rhs) => write!(f, "{lhs} {op} {rhs}"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl std::fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Op::Add => write!(f, "+"),
+            Op::Sub => write!(f, "-"),
+            Op::Mul => write!(f, "*"),
+            Op::Div => write!(f, "/"),
+        }
+    }
+}
+
+impl std::fmt::Display for Variable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Variable::Bool(b) => write!(f, "{b}"),
+            Variable::Str(s) => write!(f, "{s}"),
+            Variable::Int(i) => write!(f, "{i}"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Instruction {
-    Load(PathBuf, String),
-    Include(Instructions),
+    Load(PathBuf, String, Option<Lines>),
+    /// Run a shell command and store its output, like `Load`
+    Exec {
+        command: String,
+        timeout: Option<Duration>,
+        key: String,
+    },
+    Include {
+        path: PathBuf,
+        line: usize,
+    },
     WriteBuffer(PathBuf),
-    Find { needle: String, count: usize },
-    FindEnd { needle: String, count: usize },
+    /// `reverse` searches from the cursor towards the start of the line
+    Find { needle: String, count: usize, reverse: bool },
+    FindEnd { needle: String, count: usize, reverse: bool },
+    FindMiss(FindMiss),
     Goto(Dest),
+    /// Scroll the view this many rows, positive is down, without moving the cursor
+    Scroll(i32),
+    /// Play the script again from the start when it ends, after the pause
+    Loop {
+        count: LoopCount,
+        pause: Duration,
+    },
+    /// Stop here and quit
+    Exit,
+    /// Quit once the script has ended and this much time has passed
+    ExitOnEnd(Duration),
     Type {
         source: Source,
+        filters: Vec<Filter>,
         trim_trailing_newline: bool,
         prefix_newline: bool,
+        keep_selection: bool,
     },
     Command(Source),
-    CommandClearTimeout(u64),
-    Insert(Source),
-    Jitter(u64),
+    CommandClearTimeout(Duration),
+    /// Characters per second on the command line
+    CommandSpeed(u64),
+    Insert {
+        source: Source,
+        filters: Vec<Filter>,
+    },
+    Jitter(Duration),
+    JitterProfile(JitterProfile),
+    BurstPause {
+        min: Duration,
+        max: Duration,
+    },
+    /// Typos in `rate` percent of the words, at most `max_per_line` on a line
+    AutoTypo {
+        rate: u8,
+        max_per_line: Option<usize>,
+    },
+    Backspace(usize),
     Delete,
+    DeleteLine(usize),
+    DeleteWord,
+    DuplicateLine(usize),
+    MoveLine {
+        up: bool,
+        count: usize,
+    },
+    ClearEol,
+    Snapshot(String),
+    Restore(String),
+    Yank,
+    Paste {
+        above: bool,
+    },
+    Join(usize),
+    SplitLine,
+    Align {
+        c: char,
+        lines: usize,
+        instant: bool,
+    },
+    AutoIndent(bool),
+    Indent {
+        lines: Option<usize>,
+        levels: usize,
+    },
+    Dedent {
+        lines: Option<usize>,
+        levels: usize,
+    },
+    IndentUnit(String),
+    TabWidth(usize),
+    ToggleComment {
+        lines: Option<usize>,
+        instant: bool,
+    },
+    CommentLeader(String),
+    /// The leader for extensions without one of their own
+    CommentPrefix(String),
+    /// Lowers the music while typing sounds play. Left out of the docs until music
+    /// playback lands, as it does nothing without it
+    Duck {
+        percent: u8,
+        attack: Duration,
+        release: Duration,
+    },
 
     /// This instructions requires that the cursor is placed on the
     /// same line as the src.
@@ -65,33 +319,180 @@ pub enum Instruction {
         src: String,
         replacement: Source,
     },
+    /// Every occurrence in the document, at once
+    ReplaceAll {
+        needle: String,
+        replacement: Source,
+    },
     Select {
-        width: u16,
-        height: u16,
+        width: Expr,
+        height: Expr,
+    },
+    SelectEol,
+    /// The lines from the `start` marker up to the `end` marker
+    SelectMarkers {
+        start: String,
+        end: String,
     },
     SetTitle(String),
     SetTheme(String),
     SetExtension(String),
     ShowLineNumbers(bool),
-    LinePause(u64),
+    HScroll(HScroll),
+    BreakLigatures(bool),
+    AddLigature(String),
+    ColorColumn(Vec<u16>),
+    Margins {
+        top: u16,
+        right: u16,
+        bottom: u16,
+        left: u16,
+    },
+    /// Rows to keep above and below the cursor, and columns to each side of it
+    ScrollOff {
+        rows: u16,
+        columns: Option<u16>,
+    },
+    /// Wrap long lines, starting the rows they continue on with the indicator. `None` turns it off
+    Wrap(Option<String>),
+    StrictMarkers(bool),
+    /// A marker on the row of the cursor, made while running
+    SetMarker(String),
+    RemoveMarker(String),
+    VirtualText {
+        anchor: Anchor,
+        text: String,
+        below: bool,
+    },
+    ClearVirtualText(Option<usize>),
+    Splash {
+        lines: Vec<String>,
+        duration: Option<Duration>,
+        styles: Vec<TextStyle>,
+    },
+    LinePause(Duration),
     Speed(u64),
     LoadAudio(PathBuf),
-    Popup(Source),
+    AudioClasses(bool),
+    AudioOffset(i64),
+    KeyOverlay(bool),
+    KeyOverlayHold(Duration),
+    Hyperlinks(bool),
+    Link {
+        text: String,
+        url: String,
+    },
+    Popup {
+        source: Source,
+        /// Close the popup after this long, without waiting for it
+        close_after: Option<Duration>,
+        position: PopupPosition,
+        title: Option<String>,
+    },
+    PopupAt(PopupPosition),
+    PopupFile(PathBuf),
+    PopupScroll(i32),
+    PopupAutoscroll(u32),
+    Menu {
+        items: Vec<String>,
+        choice: usize,
+        step: Duration,
+        then_type: bool,
+    },
     ClosePopup,
     Clear,
-    Wait(u64),
+    Scene {
+        name: String,
+        theme: Option<String>,
+        extension: Option<String>,
+    },
+    SceneDefaults {
+        clear: bool,
+    },
+    Transition(TransitionKind, Duration),
+    ResetCounters,
+    Wait(Duration),
+    /// Wait for a random duration between the two, inclusive
+    WaitRange(Duration, Duration),
+    /// Wait for the value of an int variable, times the unit
+    WaitVariable {
+        name: String,
+        unit: Duration,
+    },
+    Every {
+        interval: Duration,
+        body: Instructions,
+    },
+    EveryStop,
+    Repeat {
+        count: usize,
+        body: Instructions,
+    },
+    Def {
+        name: String,
+        body: Instructions,
+    },
+    Call(String),
+    If {
+        condition: String,
+        then: Instructions,
+        otherwise: Instructions,
+    },
+    Budget {
+        limit: Duration,
+        line: usize,
+    },
+    BudgetEnd,
+    PresetDefine {
+        name: String,
+        body: Instructions,
+    },
+    PresetUse(String),
+    PresetPop,
+    PresetInclude(PathBuf),
 
     SetVariable(String, Variable),
+    Let {
+        name: String,
+        value: Expr,
+    },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Instructions {
     inner: Vec<Instruction>,
+    warnings: Vec<Warning>,
+    // The line each instruction starts on, empty if they weren't parsed
+    lines: Vec<usize>,
 }
 
 impl Instructions {
     pub fn new(inner: Vec<Instruction>) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            warnings: vec![],
+            lines: vec![],
+        }
+    }
+
+    pub(super) fn with_lines(mut self, lines: Vec<usize>) -> Self {
+        self.lines = lines;
+        self
+    }
+
+    pub(super) fn with_warnings(mut self, warnings: Vec<Warning>) -> Self {
+        self.warnings = warnings;
+        self
+    }
+
+    /// Everything that parsed, but should be written differently
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Every instruction with the line it starts on, or 0 if it wasn't parsed
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Instruction)> {
+        self.inner.iter().enumerate().map(|(i, inst)| (self.lines.get(i).copied().unwrap_or(0), inst))
     }
 
     #[cfg(test)]
@@ -100,6 +501,13 @@ impl Instructions {
     }
 }
 
+// Where the instructions are written isn't part of what they are
+impl PartialEq for Instructions {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner && self.warnings == other.warnings
+    }
+}
+
 impl IntoIterator for Instructions {
     type IntoIter = <Vec<Instruction> as IntoIterator>::IntoIter;
     type Item = Instruction;
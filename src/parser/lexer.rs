@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::iter::Peekable;
 use std::str::Chars;
+use std::time::Duration;
 
 use unicode_width::UnicodeWidthChar;
 
@@ -10,6 +12,120 @@ pub fn lex<'a>(code: &'a str) -> Result<Tokens<'a>> {
     Lexer::new(code).lex()
 }
 
+/// Every word the lexer turns into a token of its own, rather than an [`Token::Ident`]
+pub static KEYWORDS: &[(&str, Token)] = &[
+    ("as", Token::As),
+    ("align", Token::Align),
+    ("audio", Token::Audio),
+    ("audio_classes", Token::AudioClasses),
+    ("audio_offset", Token::AudioOffset),
+    ("auto_typo", Token::AutoTypo),
+    ("autoindent", Token::AutoIndent),
+    ("backspace", Token::Backspace),
+    ("budget", Token::Budget),
+    ("burst_pause", Token::BurstPause),
+    ("call", Token::Call),
+    ("clear", Token::Clear),
+    ("clear_eol", Token::ClearEol),
+    ("closepopup", Token::ClosePopup),
+    ("close_popup", Token::ClosePopup),
+    ("colorcolumn", Token::ColorColumn),
+    ("color_column", Token::ColorColumn),
+    ("command", Token::Command),
+    ("command_clear_timeout", Token::CommandClear),
+    ("command_speed", Token::CommandSpeed),
+    ("comment_leader", Token::CommentLeader),
+    ("comment_prefix", Token::CommentPrefix),
+    ("dedent", Token::Dedent),
+    ("def", Token::Def),
+    ("delete", Token::Delete),
+    ("delete_line", Token::DeleteLine),
+    ("delete_word", Token::DeleteWord),
+    ("duck", Token::Duck),
+    ("duplicate_line", Token::DuplicateLine),
+    ("else", Token::Else),
+    ("end", Token::End),
+    ("every", Token::Every),
+    ("exec", Token::Exec),
+    ("exit", Token::Exit),
+    ("exit_on_end", Token::ExitOnEnd),
+    ("extension", Token::SetExtension),
+    ("false", Token::Bool(false)),
+    ("find", Token::Find),
+    ("finde", Token::FindEnd),
+    ("find_miss", Token::FindMiss),
+    ("goto", Token::Goto),
+    ("hscroll", Token::HScroll),
+    ("hyperlinks", Token::Hyperlinks),
+    ("if", Token::If),
+    ("include", Token::Include),
+    ("indent", Token::Indent),
+    ("indent_unit", Token::IndentUnit),
+    ("insert", Token::Insert),
+    ("jitter", Token::Jitter),
+    ("jitter_profile", Token::JitterProfile),
+    ("join", Token::Join),
+    ("keep_selection", Token::KeepSelection),
+    ("key_overlay", Token::KeyOverlay),
+    ("let", Token::Let),
+    ("ligatures", Token::Ligatures),
+    ("linepause", Token::LinePause),
+    ("line_pause", Token::LinePause),
+    ("link", Token::Link),
+    ("load", Token::Load),
+    ("loop", Token::Loop),
+    ("margins", Token::Margins),
+    ("mark", Token::Mark),
+    ("markers", Token::Markers),
+    ("menu", Token::Menu),
+    ("move_line_down", Token::MoveLineDown),
+    ("move_line_up", Token::MoveLineUp),
+    ("nonl", Token::NoNewline),
+    ("numbers", Token::ShowLineNumbers),
+    ("paste", Token::Paste),
+    ("paste_above", Token::PasteAbove),
+    ("popup", Token::Popup),
+    ("popup_at", Token::PopupAt),
+    ("popup_autoscroll", Token::PopupAutoscroll),
+    ("popup_from", Token::PopupFrom),
+    ("popup_scroll", Token::PopupScroll),
+    ("preset", Token::Preset),
+    ("repeat", Token::Repeat),
+    ("replace", Token::Replace),
+    ("replace_all", Token::ReplaceAll),
+    ("reset_counters", Token::ResetCounters),
+    ("restore", Token::Restore),
+    ("rfind", Token::RFind),
+    ("rfinde", Token::RFindEnd),
+    ("scene", Token::Scene),
+    ("scene_defaults", Token::SceneDefaults),
+    ("scroll", Token::Scroll),
+    ("scrolloff", Token::ScrollOff),
+    ("select", Token::Select),
+    ("select_eol", Token::SelectEol),
+    ("set", Token::SetVariable),
+    ("snapshot", Token::Snapshot),
+    ("speed", Token::Speed),
+    ("splash", Token::Splash),
+    ("splitline", Token::SplitLine),
+    ("split_line", Token::SplitLine),
+    ("tab_width", Token::TabWidth),
+    ("theme", Token::Theme),
+    ("title", Token::SetTitle),
+    ("toggle_comment", Token::ToggleComment),
+    ("transition", Token::Transition),
+    ("true", Token::Bool(true)),
+    ("type", Token::Type),
+    ("typenl", Token::TypeNl),
+    ("unmark", Token::Unmark),
+    ("virtual_text", Token::VirtualText),
+    ("wait", Token::Wait),
+    ("sleep", Token::Wait),
+    ("wrap", Token::Wrap),
+    ("write", Token::WriteBuffer),
+    ("yank", Token::Yank),
+];
+
 struct Lexer<'src> {
     source: &'src str,
     input: Peekable<Chars<'src>>,
@@ -17,6 +133,12 @@ struct Lexer<'src> {
     spans: Vec<Span>,
     current_span: Span,
     next_span: Span,
+    // Heredocs started on the current line: the delimiter, the index of the
+    // string token and where it started
+    heredocs: Vec<(String, usize, Span)>,
+    // The strings that read differently for `${name}`, by the index of their token
+    templates: HashMap<usize, String>,
+    parens: usize,
 }
 
 impl<'src> Lexer<'src> {
@@ -28,6 +150,9 @@ impl<'src> Lexer<'src> {
             spans: vec![],
             current_span: Span::INITIAL,
             next_span: Span::INITIAL,
+            heredocs: vec![],
+            templates: HashMap::new(),
+            parens: 0,
         }
     }
 
@@ -36,7 +161,12 @@ impl<'src> Lexer<'src> {
             .input
             .next()
             .expect("every character has to be checked before consume is called");
+        self.advance(c);
+    }
 
+    // Move the next span past the character
+    fn advance(&mut self, c: char) {
+        self.next_span.offset += c.len_utf8();
         if c == '\n' {
             self.next_span.line += 1;
             self.next_span.col = 1;
@@ -46,39 +176,55 @@ impl<'src> Lexer<'src> {
     }
 
     fn single_char_token(&mut self, token: Token) {
-        if token == Token::Newline {
-            self.next_span.line += 1;
-            self.next_span.col = 1;
-        } else {
-            self.next_span.col += 1;
-        }
         self.push_token(token);
     }
 
     fn multi_char_token(&mut self, token: Token) {
-        _ = self.input.next();
-        self.next_span.col += 2;
+        self.consume_char();
         self.push_token(token);
     }
 
     fn lex(mut self) -> Result<Tokens<'src>> {
-        loop {
-            let Some(c) = self.input.next() else { break };
-
+        while let Some(c) = self.input.next() {
+            self.advance(c);
             match c {
                 // -----------------------------------------------------------------------------
                 //   - Multi char tokens-
                 // -----------------------------------------------------------------------------
                 '/' if Some('/') == self.input.peek().copied() => self.comment(),
+                '#' => self.hash_comment(),
                 '@' if Some('@') == self.input.peek().copied() => self.multi_char_token(Token::AtAt),
+                '.' if Some('.') == self.input.peek().copied() => self.multi_char_token(Token::DotDot),
+                '<' if Some('<') == self.input.peek().copied() => self.heredoc()?,
+                '-' if !self.input.peek().is_some_and(|c| c.is_ascii_digit()) => self.single_char_token(Token::Minus),
 
                 // -----------------------------------------------------------------------------
                 //   - Single char tokens -
                 // -----------------------------------------------------------------------------
-                '\n' => self.single_char_token(Token::Newline),
+                '\n' => {
+                    self.single_char_token(Token::Newline);
+                    self.heredoc_bodies()?;
+                }
                 '=' => self.single_char_token(Token::Equal),
                 '@' => self.single_char_token(Token::At),
+                ':' => self.single_char_token(Token::Colon),
                 '!' => self.single_char_token(Token::Bang),
+                '|' => self.single_char_token(Token::Pipe),
+                '{' => self.single_char_token(Token::LBrace),
+                '}' => self.single_char_token(Token::RBrace),
+                '+' => self.single_char_token(Token::Plus),
+                '*' => self.single_char_token(Token::Star),
+                '/' => self.single_char_token(Token::Slash),
+                '(' => {
+                    self.parens += 1;
+                    self.single_char_token(Token::LParen)
+                }
+                ')' => {
+                    self.parens = self.parens.saturating_sub(1);
+                    self.single_char_token(Token::RParen)
+                }
+                // Outside of parentheses a comma is whitespace, like in `goto 1, 2`
+                ',' if self.parens > 0 => self.single_char_token(Token::Comma),
 
                 '-' | '0'..='9' => self.int(c)?,
                 'a'..='z' | 'A'..='Z' => self.ident(c)?,
@@ -87,99 +233,168 @@ impl<'src> Lexer<'src> {
             }
         }
 
+        if let Some((delimiter, _, start)) = self.heredocs.pop() {
+            return Error::unterminated_heredoc(delimiter, start, self.source);
+        }
         self.push_token(Token::Eof);
 
-        let tokens = Tokens::new(self.source, self.tokens, self.spans);
+        let tokens = Tokens::new(self.source, self.tokens, self.spans, self.templates);
         Ok(tokens)
     }
 
     fn string(&mut self, quote: char) -> Result<()> {
         let mut buffer = String::new();
-        let mut escaping = false;
+        // The string as it's read for `${name}`, where a `$` that was escaped
+        // doesn't start one, and so `\` has to be escaped as well
+        let mut template = String::new();
 
         loop {
-            match self.input.peek() {
-                Some('\\') if !escaping => {
-                    if let Some('"' | '\\') = self.input.peek() {
-                        escaping = true;
-                    } else {
-                        escaping = false;
+            match self.input.peek().copied() {
+                Some('\\') => {
+                    self.consume_char();
+                    let c = self.escape()?;
+                    if let '$' | '\\' = c {
+                        template.push('\\');
                     }
-                }
-                Some('n') if escaping => {
-                    buffer.push('\n');
-                    escaping = false;
-                }
-                Some(c @ '\\') if escaping => {
-                    buffer.push(*c);
-                    escaping = false;
-                }
-                Some(c) if escaping && *c == quote => {
-                    buffer.push(*c);
-                    escaping = false;
+                    template.push(c);
+                    buffer.push(c);
+                    continue;
                 }
                 // Closing quote
-                Some(c) if *c == quote => {
+                Some(c) if c == quote => {
                     self.consume_char();
                     break;
                 }
-                Some(c) => buffer.push(*c),
-                None => return Error::unterminated_string(self.next_span, self.source),
+                Some(c) => {
+                    template.push(c);
+                    buffer.push(c);
+                }
+                None => return Error::unterminated_string(self.current_span, self.source),
             }
 
             self.consume_char();
         }
 
-        self.push_token(Token::Str(buffer));
+        self.push_str(buffer, template);
         Ok(())
     }
 
-    fn ident(&mut self, initial: char) -> Result<()> {
-        let mut buffer = String::from(initial);
+    fn push_str(&mut self, s: String, template: String) {
+        if template != s {
+            self.templates.insert(self.tokens.len(), template);
+        }
+        self.push_token(Token::Str(s));
+    }
 
-        loop {
-            match self.input.peek() {
-                Some(c @ ('a'..='z' | 'A'..='Z' | '0'..'9' | '_' | '-')) => {
-                    buffer.push(*c);
+    // The character after a `\`
+    fn escape(&mut self) -> Result<char> {
+        let span = self.next_span;
+        let c = match self.input.peek().copied() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some(c @ ('"' | '\'' | '\\' | '$')) => c,
+            Some('u') => {
+                self.consume_char();
+                return self.unicode_escape(span);
+            }
+            Some(_) => return Error::invalid_escape("use \\n, \\t, \\\", \\', \\\\, \\$ or \\u{..}", span, self.source),
+            None => return Error::unterminated_string(span, self.source),
+        };
+        self.consume_char();
+        Ok(c)
+    }
+
+    // {<hex>}, after `\u`
+    fn unicode_escape(&mut self, span: Span) -> Result<char> {
+        if self.input.peek() != Some(&'{') {
+            return Error::invalid_escape("\\u has to be followed by {", span, self.source);
+        }
+        self.consume_char();
+
+        let mut hex = String::new();
+        while let Some(c) = self.input.peek().copied().filter(|c| c.is_ascii_hexdigit()) {
+            hex.push(c);
+            self.consume_char();
+        }
+        if self.input.peek() != Some(&'}') || hex.is_empty() || hex.len() > 6 {
+            return Error::invalid_escape("\\u{..} takes 1 to 6 hex digits", span, self.source);
+        }
+        self.consume_char();
+
+        let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+        let Some(c) = c else { return Error::invalid_escape("not a unicode character", span, self.source) };
+        Ok(c)
+    }
+
+    // <<<delimiter>, where the string is on the lines after this one
+    fn heredoc(&mut self) -> Result<()> {
+        let start = self.current_span;
+        self.consume_char();
+
+        let mut delimiter = String::new();
+        while let Some(c @ ('a'..='z' | 'A'..='Z' | '0'..='9' | '_')) = self.input.peek().copied() {
+            delimiter.push(c);
+            self.consume_char();
+        }
+        if delimiter.is_empty() {
+            return Error::invalid_heredoc("a heredoc needs a delimiter, like <<EOF", start, self.source);
+        }
+
+        self.heredocs.push((delimiter, self.tokens.len(), start));
+        self.push_token(Token::Str(String::new()));
+        Ok(())
+    }
+
+    // Every line up to the delimiter is the string, as written, so `${name}` isn't a variable in it.
+    // The line with the delimiter is left, for the newline at the end of it.
+    fn heredoc_bodies(&mut self) -> Result<()> {
+        for (i, (delimiter, index, start)) in std::mem::take(&mut self.heredocs).into_iter().enumerate() {
+            // The newline after the delimiter of the heredoc before this one
+            if i > 0 {
+                match self.input.peek() {
+                    Some(_) => self.consume_char(),
+                    None => return Error::unterminated_heredoc(delimiter, start, self.source),
+                }
+            }
+
+            let mut content = String::new();
+            loop {
+                let mut line = String::new();
+                while let Some(c) = self.input.peek().copied().filter(|c| *c != '\n') {
+                    line.push(c);
                     self.consume_char();
                 }
-                Some(_) | None => break,
+                if line.trim() == delimiter {
+                    break;
+                }
+                if self.input.peek().is_none() {
+                    return Error::unterminated_heredoc(delimiter, start, self.source);
+                }
+                self.consume_char();
+                content.push_str(&line);
+                content.push('\n');
+            }
+            let template = content.replace('\\', "\\\\").replace('$', "\\$");
+            if template != content {
+                self.templates.insert(index, template);
             }
+            self.tokens[index] = Token::Str(content);
+        }
+        self.current_span = self.next_span;
+        Ok(())
+    }
+
+    fn ident(&mut self, initial: char) -> Result<()> {
+        let mut buffer = String::from(initial);
+
+        while let Some(c @ ('a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-')) = self.input.peek() {
+            buffer.push(*c);
+            self.consume_char();
         }
 
-        let token = match buffer.as_str() {
-            "as" => Token::As,
-            "audio" => Token::Audio,
-            "clear" => Token::Clear,
-            "closepopup" | "close_popup" => Token::ClosePopup,
-            "command" => Token::Command,
-            "command_clear_timeout" => Token::CommandClear,
-            "delete" => Token::Delete,
-            "extension" => Token::SetExtension,
-            "false" => Token::Bool(false),
-            "find" => Token::Find,
-            "finde" => Token::FindEnd,
-            "goto" => Token::Goto,
-            "include" => Token::Include,
-            "insert" => Token::Insert,
-            "jitter" => Token::Jitter,
-            "linepause" | "line_pause" => Token::LinePause,
-            "load" => Token::Load,
-            "nonl" => Token::NoNewline,
-            "numbers" => Token::ShowLineNumbers,
-            "popup" => Token::Popup,
-            "replace" => Token::Replace,
-            "select" => Token::Select,
-            "set" => Token::SetVariable,
-            "speed" => Token::Speed,
-            "theme" => Token::Theme,
-            "title" => Token::SetTitle,
-            "true" => Token::Bool(true),
-            "type" => Token::Type,
-            "typenl" => Token::TypeNl,
-            "wait" | "sleep" => Token::Wait,
-            "write" => Token::WriteBuffer,
-            _ => Token::Ident(buffer),
+        let token = match KEYWORDS.iter().find(|(keyword, _)| *keyword == buffer) {
+            Some((_, token)) => token.clone(),
+            None => Token::Ident(buffer),
         };
         self.push_token(token);
         Ok(())
@@ -187,19 +402,21 @@ impl<'src> Lexer<'src> {
 
     fn int(&mut self, c: char) -> Result<()> {
         let mut buffer = String::from(c);
-        loop {
-            match self.input.peek() {
-                Some(c @ '0'..='9') => {
-                    buffer.push(*c);
-                    self.consume_char();
-                }
-                Some(_) | None => break,
-            }
+        while let Some(c @ '0'..='9') = self.input.peek() {
+            buffer.push(*c);
+            self.consume_char();
+        }
+
+        // A fraction or a unit makes it a duration
+        let mut ahead = self.input.clone();
+        let fraction = ahead.next() == Some('.') && ahead.next().is_some_and(|c| c.is_ascii_digit());
+        if fraction || self.input.peek().is_some_and(|c| c.is_alphabetic()) {
+            return self.duration(buffer);
         }
 
         let int = match buffer.parse() {
             Ok(int) => int,
-            Err(_) => return Error::invalid_int(self.next_span, self.source),
+            Err(_) => return Error::invalid_int(self.current_span, self.source),
         };
 
         let token = Token::Int(int);
@@ -207,6 +424,62 @@ impl<'src> Lexer<'src> {
         Ok(())
     }
 
+    // <int>[.<int>]s|ms|us
+    fn duration(&mut self, whole: String) -> Result<()> {
+        let mut fraction = String::new();
+        if self.input.peek() == Some(&'.') {
+            self.consume_char();
+            while let Some(c @ '0'..='9') = self.input.peek() {
+                fraction.push(*c);
+                self.consume_char();
+            }
+        }
+
+        let mut unit = String::new();
+        while let Some(c) = self.input.peek().filter(|c| c.is_alphabetic()) {
+            unit.push(*c);
+            self.consume_char();
+        }
+
+        let nanos_per_unit: u128 = match unit.as_str() {
+            "s" => 1_000_000_000,
+            "ms" => 1_000_000,
+            "us" | "µs" => 1_000,
+            // A number like `0.25`, read as a duration in the unit the instruction uses without one
+            "" if !fraction.is_empty() => return self.decimal(whole, fraction),
+            "" => return Error::invalid_duration("a duration needs a unit: s, ms or us", self.current_span, self.source),
+            _ => return Error::invalid_duration("the unit has to be s, ms or us", self.current_span, self.source),
+        };
+
+        if whole.starts_with('-') {
+            return Error::invalid_duration("a duration can't be negative", self.current_span, self.source);
+        }
+
+        let Ok(whole) = whole.parse::<u128>() else { return Error::invalid_int(self.current_span, self.source) };
+        let mut nanos = whole * nanos_per_unit;
+        if !fraction.is_empty() {
+            let Ok(digits) = fraction.parse::<u128>() else { return Error::invalid_int(self.current_span, self.source) };
+            let Some(scale) = 10u128.checked_pow(fraction.len() as u32) else {
+                return Error::invalid_duration("too many decimals", self.current_span, self.source);
+            };
+            nanos += digits * nanos_per_unit / scale;
+        }
+
+        let Ok(nanos) = u64::try_from(nanos) else {
+            return Error::invalid_duration("the duration is too long", self.current_span, self.source);
+        };
+        self.push_token(Token::Duration(Duration::from_nanos(nanos)));
+        Ok(())
+    }
+
+    fn decimal(&mut self, whole: String, fraction: String) -> Result<()> {
+        let Ok(decimal) = format!("{whole}.{fraction}").parse() else {
+            return Error::invalid_int(self.current_span, self.source);
+        };
+        self.push_token(Token::Decimal(decimal));
+        Ok(())
+    }
+
     fn push_token(&mut self, token: Token) {
         self.current_span.token = self.tokens.len() as u32;
         self.spans.push(self.current_span);
@@ -228,15 +501,19 @@ impl<'src> Lexer<'src> {
         self.push_token(Token::Comment);
     }
 
+    // `#` to the end of the line, without a token, leaving the newline
+    fn hash_comment(&mut self) {
+        while self.input.peek().is_some_and(|&c| c != '\n') {
+            self.consume_char();
+        }
+        self.current_span = self.next_span;
+    }
+
     fn whitespace(&mut self) {
-        loop {
-            match self.input.peek() {
-                Some(c) if c.is_ascii_whitespace() => {
-                    self.consume_char();
-                    break;
-                }
-                Some(_) | None => break,
-            }
+        if let Some(c) = self.input.peek()
+            && c.is_ascii_whitespace()
+        {
+            self.consume_char();
         }
         self.push_token(Token::Whitespace);
     }
@@ -278,6 +555,13 @@ mod test {
         lex(input).unwrap().take_tokens()
     }
 
+    #[test]
+    fn lex_ident_with_digits() {
+        let tokens = lex_tokens("key9 a0_9");
+        let expected = vec![ident("key9"), whitespace(), ident("a0_9"), eof()];
+        assert_eq!(tokens, expected);
+    }
+
     #[test]
     fn lex_load() {
         let input = "load \"hello\"\n=";
@@ -305,6 +589,95 @@ mod test {
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn lex_escapes() {
+        let tokens = lex_tokens(r#""a\"b" 'c\'d' "\\ \n \t" "\u{41}\u{e9}\u{1F600}" "\${a}" "\'""#)
+            .into_iter()
+            .filter(|token| *token != Token::Whitespace)
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            string("a\"b"),
+            string("c'd"),
+            string("\\ \n \t"),
+            string("Aé😀"),
+            string("${a}"),
+            string("'"),
+            eof(),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn lex_invalid_escapes() {
+        for input in [r#""\x""#, r#""\u41""#, r#""\u{}""#, r#""\u{1234567}""#, r#""\u{d800}""#, r#""\u{41""#] {
+            let err = lex(input).unwrap_err();
+            assert!(err.to_string().contains("invalid escape"), "{input}: {err}");
+        }
+        assert!(lex("\"\\").is_err());
+    }
+
+    #[test]
+    fn span_for_invalid_escape() {
+        let err = lex("type \"ab\ncd\\q\"").unwrap_err();
+        // The character after the backslash
+        let start = err.start();
+        assert_eq!((start.line, start.col), (2, 4));
+    }
+
+    #[test]
+    fn lex_heredoc() {
+        let input = "load <<EOF as a\nfn main() {\n\n    \"raw\" \\n\n}\nEOF\ngoto 1 2";
+        let tokens = lex_tokens(input)
+            .into_iter()
+            .filter(|token| *token != Token::Whitespace)
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            load(),
+            string("fn main() {\n\n    \"raw\" \\n\n}\n"),
+            Token::As,
+            ident("a"),
+            nl(),
+            nl(),
+            goto(),
+            int(1),
+            int(2),
+            eof(),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn lex_indented_and_empty_heredocs() {
+        let tokens = lex_tokens("<<A <<B\n  a\n  A\n  B\n");
+        let expected = vec![string("  a\n"), whitespace(), string(""), nl(), nl(), eof()];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn unterminated_heredoc() {
+        let err = lex("wait 1s\ntype <<EOF\na\nEOFF").unwrap_err();
+        assert!(err.to_string().contains("`<<EOF` on line 2 has no line with `EOF` after it"), "{err}");
+        assert_eq!(err.start().line, 2);
+
+        assert!(lex("type <<EOF").is_err());
+        assert!(lex("type << EOF\nEOF").is_err());
+    }
+
+    #[test]
+    fn span_after_heredoc() {
+        let goto_span = |input| {
+            let mut tokens = lex(input).unwrap();
+            while *tokens.current() != Token::Goto {
+                tokens.consume();
+            }
+            let start = tokens.span();
+            (start.line, start.col)
+        };
+        assert_eq!(goto_span("type <<EOF\na\nEOF\n  goto"), goto_span("type \"a\"\n\n\n  goto"));
+    }
+
     #[test]
     fn lex_negative_int() {
         let input = "-123";
@@ -323,6 +696,59 @@ mod test {
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn lex_range() {
+        let input = "80..220";
+        let tokens = lex_tokens(input);
+
+        let expected = vec![int(80), Token::DotDot, int(220), eof()];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn lex_durations() {
+        let input = "2s 200ms 1.5s 90us 0s 0.25ms";
+        let tokens = lex_tokens(input)
+            .into_iter()
+            .filter(|token| *token != Token::Whitespace)
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            Token::Duration(Duration::from_secs(2)),
+            Token::Duration(Duration::from_millis(200)),
+            Token::Duration(Duration::from_millis(1500)),
+            Token::Duration(Duration::from_micros(90)),
+            Token::Duration(Duration::ZERO),
+            Token::Duration(Duration::from_micros(250)),
+            eof(),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn lex_decimals() {
+        let tokens = lex_tokens("0.25 -1.5 3..4");
+        let expected = vec![
+            Token::Decimal(0.25),
+            whitespace(),
+            Token::Decimal(-1.5),
+            whitespace(),
+            int(3),
+            Token::DotDot,
+            int(4),
+            eof(),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn lex_invalid_durations() {
+        assert!(lex("-2s").is_err());
+        assert!(lex("1.5x").is_err());
+        assert!(lex("2h").is_err());
+        assert!(lex("2sec").is_err());
+    }
+
     #[test]
     fn lext_comments() {
         let input = "// comment";
@@ -332,6 +758,39 @@ mod test {
         assert_eq!(tokens, expected);
     }
 
+    #[test]
+    fn lex_hash_comments() {
+        let tokens = lex_tokens("# intro\n   # indented\ngoto 1 2 # trailing\n#");
+        let expected = vec![
+            nl(),
+            whitespace(),
+            whitespace(),
+            nl(),
+            goto(),
+            whitespace(),
+            int(1),
+            whitespace(),
+            int(2),
+            whitespace(),
+            nl(),
+            eof(),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn lex_hash_in_strings() {
+        let tokens = lex_tokens("load \"#1 # not a comment\" # a comment");
+        let expected = vec![load(), whitespace(), string("#1 # not a comment"), whitespace(), eof()];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn span_after_hash_comment() {
+        let tokens = lex("# note\ngoto").unwrap();
+        assert_eq!(tokens.span(), Span { token: 0, line: 1, col: 7, offset: 6 });
+    }
+
     #[test]
     fn span_for_comments() {
         let input = "// comment";
@@ -341,7 +800,8 @@ mod test {
             Span {
                 token: 0,
                 line: 1,
-                col: 1
+                col: 1,
+                offset: 0,
             },
             start
         );
@@ -349,7 +809,8 @@ mod test {
             Span {
                 token: 1,
                 line: 1,
-                col: 10,
+                col: 11,
+                offset: 10,
             },
             end
         );
@@ -1,12 +1,17 @@
-pub use instruction::{Dest, Instruction, Instructions, Source, Variable};
+pub use error::Warning;
+pub use instruction::{
+    Anchor, Dest, Expr, Filter, FindMiss, HScroll, Instruction, Instructions, JitterProfile, Lines, LoopCount, Op,
+    PopupPosition, Source, TextStyle, TransitionKind, Variable,
+};
 
-mod error;
+pub(crate) mod error;
 mod instruction;
 mod lexer;
 mod parse;
+pub mod registry;
 pub(crate) mod token;
 
-pub fn parse<'a>(input: &'a str) -> error::Result<Instructions> {
+pub fn parse(input: &str) -> error::Result<Instructions> {
     let tokens = lexer::lex(input)?;
     parse::parse(tokens)
 }
@@ -1,37 +1,131 @@
-use super::error::{Error, Result};
-use super::instruction::{Dest, Instruction, Instructions, Source};
+use std::time::Duration;
+
+use super::error::{Error, Result, Warning};
+use super::instruction::{Anchor, Dest, Expr, Filter, FindMiss, HScroll, Instruction, Instructions, JitterProfile, Lines, LoopCount, Op, PopupPosition, Source, TextStyle, TransitionKind};
 use super::token::{Token, Tokens};
 use crate::parser::Variable;
 
+// What a number without a unit means. Durations used to be written without units,
+// and each instruction had its own
+#[derive(Debug, Copy, Clone)]
+enum Bare {
+    Seconds,
+    Millis,
+}
+
+// What ends a list of instructions
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Block {
+    File,
+    End,
+    Brace,
+}
+
+// How many times a body can be repeated, counting the repeats it's nested in
+const MAX_REPEAT: usize = 10_000;
+
 struct Parser<'src> {
     tokens: Tokens<'src>,
+    warnings: Vec<Warning>,
+    // How many times the instructions being parsed are repeated by the repeats around them
+    repeated: usize,
 }
 
 impl<'src> Parser<'src> {
     fn new(tokens: Tokens<'src>) -> Self {
-        Self { tokens }
+        Self {
+            tokens,
+            warnings: vec![],
+            repeated: 1,
+        }
     }
 
     fn parse(&mut self) -> Result<Instructions> {
+        let instructions = self.instructions(Block::File)?;
+        Ok(instructions.with_warnings(std::mem::take(&mut self.warnings)))
+    }
+
+    // <int>s|ms|us, or a bare number in the unit the instruction has always used
+    fn duration(&mut self, bare: Bare) -> Result<Duration> {
+        match self.optional_duration(bare)? {
+            Some(duration) => Ok(duration),
+            None => {
+                let token = self.tokens.take();
+                Error::invalid_arg("duration, like 2s or 200ms", token, self.tokens.spans(), self.tokens.source)
+            }
+        }
+    }
+
+    // The duration, if the current token is one
+    fn optional_duration(&mut self, bare: Bare) -> Result<Option<Duration>> {
+        let duration = match *self.tokens.current() {
+            Token::Duration(duration) => duration,
+            Token::Int(n @ 0..) => self.bare_duration(n as f64, false, bare)?,
+            Token::Decimal(n) if n >= 0.0 => self.bare_duration(n, true, bare)?,
+            _ => return Ok(None),
+        };
+        self.tokens.consume();
+        Ok(Some(duration))
+    }
+
+    // `wait 0.25` is how a fraction of a second is written, so bare decimal seconds don't warn
+    fn bare_duration(&mut self, n: f64, decimal: bool, bare: Bare) -> Result<Duration> {
+        let span = self.tokens.span();
+        let (secs, name, unit) = match bare {
+            Bare::Seconds => (n, "seconds", "s"),
+            Bare::Millis => (n / 1000.0, "milliseconds", "ms"),
+        };
+        let Ok(duration) = Duration::try_from_secs_f64(secs) else {
+            return Error::invalid_duration("the duration is too long", span, self.tokens.source);
+        };
+        if !(decimal && matches!(bare, Bare::Seconds)) {
+            self.warnings.push(Warning {
+                line: span.line as usize,
+                message: format!("`{n}` without a unit is read as {name}, write `{n}{unit}`"),
+            });
+        }
+        Ok(duration)
+    }
+
+    // Parse instructions until the end of the file, or until `end` or `}`
+    // if the instructions belong to a block
+    fn instructions(&mut self, block: Block) -> Result<Instructions> {
         let mut instructions = vec![];
+        let mut lines = vec![];
 
         loop {
-            match self.tokens.current() {
-                Token::Newline | Token::Comment | Token::Whitespace => {
+            match (self.tokens.current(), block) {
+                (Token::Newline | Token::Comment | Token::Whitespace, _) => {
                     self.tokens.consume();
                     continue;
                 }
-                Token::Eof => break,
+                (Token::End, Block::End) | (Token::RBrace, Block::Brace) => {
+                    self.tokens.consume();
+                    break;
+                }
+                (Token::Eof, Block::End) => {
+                    let token = self.tokens.take();
+                    return Error::unexpected_token("end", token, self.tokens.spans(), self.tokens.source);
+                }
+                (Token::Eof, Block::Brace) => {
+                    let token = self.tokens.take();
+                    return Error::unexpected_token("}", token, self.tokens.spans(), self.tokens.source);
+                }
+                (Token::Eof, Block::File) => break,
                 _ => (),
             }
 
+            lines.push(self.tokens.span().line as usize);
             let inst = self.next_instruction()?;
             instructions.push(inst);
 
-            match self.tokens.take() {
-                Token::Newline | Token::Comment | Token::Whitespace => continue,
-                Token::Eof => break,
-                token => {
+            // there has to be either newline OR eof here, or the end of a block in braces
+            match self.tokens.current() {
+                Token::Newline | Token::Comment | Token::Whitespace => self.tokens.consume(),
+                Token::Eof => continue,
+                Token::RBrace if block == Block::Brace => continue,
+                _ => {
+                    let token = self.tokens.take();
                     return Error::unexpected_token(
                         "newline or end of file",
                         token,
@@ -40,64 +134,213 @@ impl<'src> Parser<'src> {
                     );
                 }
             }
-
-            // there has to be either newline OR eof here
         }
 
-        Ok(Instructions::new(instructions))
+        Ok(Instructions::new(instructions).with_lines(lines))
     }
 
     fn next_instruction(&mut self) -> Result<Instruction> {
         match self.tokens.take() {
             Token::Load => self.load(),
+            Token::Exec => self.exec(),
             Token::Goto => self.goto(),
+            Token::Scroll => self.scroll(),
+            Token::Loop => self.loop_playback(),
+            Token::Exit => Ok(Instruction::Exit),
+            Token::ExitOnEnd => self.exit_on_end(),
             Token::Type => self.print(false),
             Token::TypeNl => self.print(true),
             Token::Insert => self.insert(),
-            Token::Replace => self.change(),
+            Token::Replace => self.change(false),
+            Token::ReplaceAll => self.change(true),
+            Token::Backspace => self.backspace(),
             Token::Delete => self.delete(),
+            Token::DeleteLine => self.delete_line(),
+            Token::DuplicateLine => self.duplicate_line(),
+            Token::MoveLineUp => self.move_line(true),
+            Token::MoveLineDown => self.move_line(false),
+            Token::DeleteWord => Ok(Instruction::DeleteWord),
+            Token::ClearEol => Ok(Instruction::ClearEol),
+            Token::Snapshot => Ok(Instruction::Snapshot(self.snapshot_name()?)),
+            Token::Restore => Ok(Instruction::Restore(self.snapshot_name()?)),
+            Token::Yank => Ok(Instruction::Yank),
+            Token::Paste => Ok(Instruction::Paste { above: false }),
+            Token::PasteAbove => Ok(Instruction::Paste { above: true }),
+            Token::Join => self.join(),
+            Token::SplitLine => self.split_line(),
+            Token::AutoIndent => self.autoindent(),
+            Token::Align => self.align(),
+            Token::Indent => self.indent(false),
+            Token::Dedent => self.indent(true),
+            Token::IndentUnit => self.indent_unit(),
+            Token::TabWidth => self.tab_width(),
+            Token::ToggleComment => self.toggle_comment(),
+            Token::CommentLeader => self.comment_leader(false),
+            Token::CommentPrefix => self.comment_leader(true),
             Token::Speed => self.speed(),
             Token::Select => self.select(),
-            Token::Find => self.find(),
-            Token::FindEnd => self.find_end(),
+            Token::SelectEol => Ok(Instruction::SelectEol),
+            Token::Find => self.find(false),
+            Token::FindEnd => self.find_end(false),
+            Token::RFind => self.find(true),
+            Token::RFindEnd => self.find_end(true),
+            Token::FindMiss => self.find_miss(),
             Token::LinePause => self.linepause(),
             Token::SetExtension => self.set_extension(),
             Token::SetTitle => self.set_title(),
             Token::ShowLineNumbers => self.numbers(),
+            Token::HScroll => self.hscroll(),
+            Token::Ligatures => self.ligatures(),
+            Token::ColorColumn => self.color_column(),
+            Token::Margins => self.margins(),
+            Token::ScrollOff => self.scrolloff(),
+            Token::Wrap => self.wrap(),
+            Token::Markers => self.markers(),
+            Token::Mark => Ok(Instruction::SetMarker(self.marker_name()?)),
+            Token::Unmark => Ok(Instruction::RemoveMarker(self.marker_name()?)),
+            Token::Splash => self.splash(),
+            Token::VirtualText => self.virtual_text(),
             Token::Clear => self.clear(),
+            Token::Scene => self.scene(),
+            Token::SceneDefaults => self.scene_defaults(),
+            Token::Transition => self.transition(),
+            Token::ResetCounters => self.reset_counters(),
             Token::Jitter => self.jitter(),
+            Token::JitterProfile => self.jitter_profile(),
+            Token::BurstPause => self.burst_pause(),
+            Token::AutoTypo => self.auto_typo(),
             Token::Theme => self.theme(),
             Token::Audio => self.audio(),
+            Token::AudioClasses => self.audio_classes(),
+            Token::AudioOffset => self.audio_offset(),
+            Token::KeyOverlay => self.key_overlay(),
+            Token::Hyperlinks => self.hyperlinks(),
+            Token::Link => self.link(),
+            Token::Duck => self.duck(),
             Token::Popup => self.popup(),
+            Token::PopupFrom => self.popup_from(),
+            Token::PopupScroll => self.popup_scroll(),
+            Token::PopupAutoscroll => self.popup_autoscroll(),
+            Token::PopupAt => self.popup_at(),
+            Token::Menu => self.menu(),
             Token::ClosePopup => self.closepopup(),
             Token::WriteBuffer => self.write_buffer(),
             Token::Command => self.command(),
             Token::CommandClear => self.command_clear(),
+            Token::CommandSpeed => self.command_speed(),
             Token::SetVariable => self.set_variable(),
+            Token::Let => self.let_variable(),
             Token::Include => self.include(),
             Token::Wait => self.wait(),
+            Token::Every => self.every(),
+            Token::Repeat => self.repeat(),
+            Token::If => self.if_else(),
+            Token::Def => self.def(),
+            Token::Call => self.call(),
+            Token::Budget => self.budget(),
+            Token::Preset => self.preset(),
             token => Error::invalid_instruction(token, self.tokens.spans(), self.tokens.source),
         }
     }
 
+    // load <string>[:<int>..[<int>]] as <ident>
     fn load(&mut self) -> Result<Instruction> {
         match self.tokens.take() {
-            Token::Str(path) => match self.tokens.take() {
-                Token::As => match self.tokens.take() {
-                    Token::Ident(key) => Ok(Instruction::Load(path.into(), key)),
-                    token => return Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+            Token::Str(path) => match (self.lines()?, self.tokens.take()) {
+                (lines, Token::As) => match self.tokens.take() {
+                    Token::Ident(key) => Ok(Instruction::Load(path.into(), key, lines)),
+                    token => Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
                 },
-                token => return Error::invalid_arg("as", token, self.tokens.spans(), self.tokens.source),
+                (_, token) => Error::invalid_arg("as", token, self.tokens.spans(), self.tokens.source),
             },
             token => Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
         }
     }
 
+    // exec <string> [<timeout>] as <ident>
+    fn exec(&mut self) -> Result<Instruction> {
+        let command = match self.tokens.take() {
+            Token::Str(command) => self.tokens.template(command),
+            token => return Error::invalid_arg("command", token, self.tokens.spans(), self.tokens.source),
+        };
+        let timeout = self.optional_duration(Bare::Seconds)?;
+        match self.tokens.take() {
+            Token::As => (),
+            token => return Error::invalid_arg("as", token, self.tokens.spans(), self.tokens.source),
+        }
+        match self.tokens.take() {
+            Token::Ident(key) => Ok(Instruction::Exec { command, timeout, key }),
+            token => Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // [:<int>..[<int>]]
+    fn lines(&mut self) -> Result<Option<Lines>> {
+        if !self.tokens.consume_if(Token::Colon) {
+            return Ok(None);
+        }
+
+        let start = match self.tokens.take() {
+            Token::Int(start @ 1..) => start as usize,
+            token => return Error::invalid_arg("line number, from 1", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        match self.tokens.take() {
+            Token::DotDot => (),
+            token => return Error::invalid_arg("..", token, self.tokens.spans(), self.tokens.source),
+        }
+
+        if !matches!(self.tokens.current(), Token::Int(_)) {
+            return Ok(Some(Lines { start, end: None }));
+        }
+        let end = match self.tokens.take() {
+            Token::Int(end @ 0..) if end as usize >= start => end as usize,
+            token => {
+                return Error::invalid_arg("line number not before the start", token, self.tokens.spans(), self.tokens.source);
+            }
+        };
+
+        Ok(Some(Lines { start, end: Some(end) }))
+    }
+
     fn goto(&mut self) -> Result<Instruction> {
-        // goto <ident>|<int> <int>
+        // goto [@]<ident> [+<int>:<int>]|<int> <int>|<int>:<int>|start|end
         // <ident>
+        if !self.tokens.consume_if(Token::At) {
+            if self.tokens.consume_if(Token::Ident("start".into())) {
+                return Ok(Instruction::Goto(Dest::Start));
+            }
+            if self.tokens.consume_if(Token::End) {
+                return Ok(Instruction::Goto(Dest::End));
+            }
+        }
         let instr = match self.tokens.take() {
-            Token::Ident(ident) => Instruction::Goto(Dest::Marker(ident)),
+            // `end` is only a marker after the `@`
+            token @ (Token::Ident(_) | Token::End) => {
+                let name = match token {
+                    Token::Ident(name) => name,
+                    _ => "end".into(),
+                };
+                let (row, col) = match self.tokens.current() {
+                    Token::Int(_) | Token::Plus => self.row_col()?,
+                    _ => (0, 0),
+                };
+                Instruction::Goto(Dest::Marker { name, row, col })
+            }
+            Token::Int(row) if self.tokens.consume_if(Token::Colon) => {
+                let (row, col) = match (row, self.tokens.take()) {
+                    (row @ 1.., Token::Int(col @ 0..)) => (row as usize, col as usize),
+                    (_, token) => {
+                        return Error::invalid_arg(
+                            "line from 1 and column from 0",
+                            token,
+                            self.tokens.spans(),
+                            self.tokens.source,
+                        );
+                    }
+                };
+                Instruction::Goto(Dest::Absolute { row, col })
+            }
             Token::Int(row) => match self.tokens.take() {
                 Token::Int(col) => Instruction::Goto(Dest::Relative {
                     row: row as i32,
@@ -111,44 +354,159 @@ impl<'src> Parser<'src> {
         Ok(instr)
     }
 
-    fn print(&mut self, prefix_newline: bool) -> Result<Instruction> {
+    // @<ident>, where the ident can be `end` too
+    fn marker(&mut self) -> Result<String> {
+        match self.tokens.take() {
+            Token::At => {}
+            token => return Error::invalid_arg("@marker", token, self.tokens.spans(), self.tokens.source),
+        }
+        match self.tokens.take() {
+            Token::Ident(name) => Ok(name),
+            Token::End => Ok("end".into()),
+            token => Error::invalid_arg("marker", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // snapshot|restore <ident>
+    fn snapshot_name(&mut self) -> Result<String> {
+        match self.tokens.take() {
+            Token::Ident(name) => Ok(name),
+            token => Error::invalid_arg("snapshot name", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // mark|unmark [@]<ident>, where the ident can be `end` like in `goto @end`
+    fn marker_name(&mut self) -> Result<String> {
+        self.tokens.consume_if(Token::At);
+        match self.tokens.take() {
+            Token::Ident(name) => Ok(name),
+            Token::End => Ok("end".into()),
+            token => Error::invalid_arg("marker name", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // <int> <int> or <int>:<int>, where `+1:-2` is two ints as well
+    fn row_col(&mut self) -> Result<(i32, i32)> {
+        let int = |parser: &mut Self| match parser.tokens.take() {
+            Token::Int(int) => Ok(int as i32),
+            token => Error::invalid_arg("number", token, parser.tokens.spans(), parser.tokens.source),
+        };
+        self.tokens.consume_if(Token::Plus);
+        let row = int(self)?;
+        self.tokens.consume_if(Token::Colon);
+        Ok((row, int(self)?))
+    }
+
+    // <string>|<ident>|env(<string>)|env_or(<string>, <string>),
+    // where the string is read for `${name}` if it has `variables`
+    fn source(&mut self, expected: &'static str, variables: bool) -> Result<Source> {
         let source = match self.tokens.take() {
+            Token::Str(s) if variables => Source::Str(self.tokens.template(s)),
             Token::Str(s) => Source::Str(s),
+            Token::Ident(name) if matches!(name.as_str(), "env" | "env_or") && self.tokens.consume_if(Token::LParen) => {
+                self.env(name == "env_or")?
+            }
             Token::Ident(ident) => Source::Ident(ident),
-            token => return Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+            token => return Error::invalid_arg(expected, token, self.tokens.spans(), self.tokens.source),
+        };
+        Ok(source)
+    }
+
+    // <string>) or <string>, <string>) with a default
+    fn env(&mut self, with_default: bool) -> Result<Source> {
+        let string = |parser: &mut Self, expected| match parser.tokens.take() {
+            Token::Str(s) => Ok(s),
+            token => Error::invalid_arg(expected, token, parser.tokens.spans(), parser.tokens.source),
         };
+        let name = string(self, "variable name")?;
+        let default = match with_default {
+            true => {
+                match self.tokens.take() {
+                    Token::Comma => (),
+                    token => return Error::invalid_arg(",", token, self.tokens.spans(), self.tokens.source),
+                }
+                Some(string(self, "default")?)
+            }
+            false => None,
+        };
+
+        match self.tokens.take() {
+            Token::RParen => (),
+            token => return Error::invalid_arg(")", token, self.tokens.spans(), self.tokens.source),
+        }
+        Ok(Source::Env { name, default })
+    }
 
+    fn print(&mut self, prefix_newline: bool) -> Result<Instruction> {
+        // <source> [| <filter>]* [nonl] [keep_selection]
+        let source = self.source("ident", true)?;
+        let filters = self.filters()?;
         let trim_trailing_newline = self.tokens.consume_if(Token::NoNewline);
+        let keep_selection = !prefix_newline && self.tokens.consume_if(Token::KeepSelection);
         Ok(Instruction::Type {
             source,
+            filters,
             trim_trailing_newline,
             prefix_newline,
+            keep_selection,
         })
     }
 
     fn insert(&mut self) -> Result<Instruction> {
-        match self.tokens.take() {
-            Token::Str(s) => return Ok(Instruction::Insert(Source::Str(s))),
-            Token::Ident(ident) => return Ok(Instruction::Insert(Source::Ident(ident))),
-            token => return Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+        // <source> [| <filter>]*
+        let source = self.source("ident", true)?;
+        let filters = self.filters()?;
+        Ok(Instruction::Insert { source, filters })
+    }
+
+    // | <name> <arg>* | ...
+    fn filters(&mut self) -> Result<Vec<Filter>> {
+        let mut filters = vec![];
+        while self.tokens.consume_if(Token::Pipe) {
+            let line = self.tokens.span().line as usize;
+            let name = match self.tokens.take() {
+                Token::Ident(name) => name,
+                // Keywords that are also filters
+                token @ (Token::Indent | Token::Dedent) => token.to_string(),
+                token => return Error::invalid_arg("filter", token, self.tokens.spans(), self.tokens.source),
+            };
+
+            let mut args = vec![];
+            loop {
+                let arg = match self.tokens.current() {
+                    Token::Int(int) => Variable::Int(*int),
+                    Token::Str(s) => Variable::Str(s.clone()),
+                    _ => break,
+                };
+                self.tokens.consume();
+                args.push(arg);
+            }
+            filters.push(Filter { name, args, line });
         }
+        Ok(filters)
     }
 
-    fn change(&mut self) -> Result<Instruction> {
+    fn change(&mut self, all: bool) -> Result<Instruction> {
         // <string>
         let src = match self.tokens.take() {
+            Token::Str(string) if all && string.is_empty() => {
+                let token = Token::Str(string);
+                return Error::invalid_arg("non-empty string", token, self.tokens.spans(), self.tokens.source);
+            }
             Token::Str(string) => string,
             token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
         };
 
-        // <string|ident>
-        let replacement = match self.tokens.take() {
-            Token::Str(string) => Source::Str(string),
-            Token::Ident(ident) => Source::Ident(ident),
-            token => return Error::invalid_arg("string or ident", token, self.tokens.spans(), self.tokens.source),
-        };
+        // <source>
+        let replacement = self.source("string or ident", false)?;
 
-        let instr = Instruction::Replace { src, replacement };
+        let instr = match all {
+            true => Instruction::ReplaceAll {
+                needle: src,
+                replacement,
+            },
+            false => Instruction::Replace { src, replacement },
+        };
         Ok(instr)
     }
 
@@ -156,347 +514,2464 @@ impl<'src> Parser<'src> {
         Ok(Instruction::Delete)
     }
 
-    fn speed(&mut self) -> Result<Instruction> {
-        // <int>
-        let instr = match self.tokens.take() {
-            Token::Int(speed) => Instruction::Speed(speed as u64),
-            token => return Error::invalid_arg("int", token, self.tokens.spans(), self.tokens.source),
-        };
-
-        Ok(instr)
+    fn backspace(&mut self) -> Result<Instruction> {
+        // backspace <int>?
+        Ok(Instruction::Backspace(self.count()?))
     }
 
-    fn select(&mut self) -> Result<Instruction> {
-        let instr = match self.tokens.take() {
-            Token::Int(width) => match self.tokens.take() {
-                Token::Int(height) => Instruction::Select {
-                    width: width as u16,
-                    height: height as u16,
-                },
-                token => return Error::invalid_arg("col", token, self.tokens.spans(), self.tokens.source),
-            },
-            token => return Error::invalid_arg("row", token, self.tokens.spans(), self.tokens.source),
-        };
+    fn delete_line(&mut self) -> Result<Instruction> {
+        // delete_line <int>?
+        Ok(Instruction::DeleteLine(self.count()?))
+    }
 
-        Ok(instr)
+    fn duplicate_line(&mut self) -> Result<Instruction> {
+        // duplicate_line <int>?
+        Ok(Instruction::DuplicateLine(self.count()?))
     }
 
-    fn find(&mut self) -> Result<Instruction> {
-        let instr = match self.tokens.take() {
-            Token::Str(needle) => {
-                let count = match self.tokens.current() {
-                    &Token::Int(count) => {
-                        self.tokens.consume();
-                        count
-                    }
-                    _ => 1,
-                };
-                Instruction::Find {
-                    needle,
-                    count: count as usize,
-                }
-            }
-            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
-        };
+    fn move_line(&mut self, up: bool) -> Result<Instruction> {
+        // move_line_up|move_line_down <int>?
+        Ok(Instruction::MoveLine {
+            up,
+            count: self.count()?,
+        })
+    }
 
-        Ok(instr)
+    fn join(&mut self) -> Result<Instruction> {
+        // join <int>?
+        Ok(Instruction::Join(self.count()?))
     }
 
-    fn find_end(&mut self) -> Result<Instruction> {
-        let instr = match self.tokens.take() {
-            Token::Str(needle) => {
-                let count = match self.tokens.current() {
-                    &Token::Int(count) => {
-                        self.tokens.consume();
-                        count
-                    }
-                    _ => 1,
-                };
-                Instruction::FindEnd {
-                    needle,
-                    count: count as usize,
-                }
+    // An optional positive count, one if there is none
+    fn count(&mut self) -> Result<usize> {
+        match self.tokens.current() {
+            &Token::Int(count @ 1..) => {
+                self.tokens.consume();
+                Ok(count as usize)
             }
-            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
-        };
+            Token::Int(_) => {
+                let token = self.tokens.take();
+                Error::invalid_arg("positive number", token, self.tokens.spans(), self.tokens.source)
+            }
+            _ => Ok(1),
+        }
+    }
 
-        Ok(instr)
+    fn split_line(&mut self) -> Result<Instruction> {
+        Ok(Instruction::SplitLine)
     }
 
-    fn linepause(&mut self) -> Result<Instruction> {
+    fn autoindent(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Int(ms) => Instruction::LinePause(ms as u64),
-            token => return Error::invalid_arg("int", token, self.tokens.spans(), self.tokens.source),
+            Token::Bool(b) => Instruction::AutoIndent(b),
+            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn set_extension(&mut self) -> Result<Instruction> {
-        let instr = match self.tokens.take() {
-            Token::Str(ext) => Instruction::SetExtension(ext),
-            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+    fn indent(&mut self, dedent: bool) -> Result<Instruction> {
+        // indent|dedent [<lines> <levels>?]
+        let lines = match self.tokens.current() {
+            &Token::Int(lines @ 1..) => {
+                self.tokens.consume();
+                lines as usize
+            }
+            Token::Int(_) => {
+                let token = self.tokens.take();
+                return Error::invalid_arg("number of lines", token, self.tokens.spans(), self.tokens.source);
+            }
+            _ => match dedent {
+                true => return Ok(Instruction::Dedent { lines: None, levels: 1 }),
+                false => return Ok(Instruction::Indent { lines: None, levels: 1 }),
+            },
         };
 
-        Ok(instr)
+        let levels = match self.tokens.current() {
+            &Token::Int(levels @ 1..) => {
+                self.tokens.consume();
+                levels as usize
+            }
+            Token::Int(_) => {
+                let token = self.tokens.take();
+                return Error::invalid_arg("positive number", token, self.tokens.spans(), self.tokens.source);
+            }
+            _ => 1,
+        };
+
+        let lines = Some(lines);
+        match dedent {
+            true => Ok(Instruction::Dedent { lines, levels }),
+            false => Ok(Instruction::Indent { lines, levels }),
+        }
     }
 
-    fn set_title(&mut self) -> Result<Instruction> {
-        let instr = match self.tokens.take() {
-            Token::Str(title) => Instruction::SetTitle(title),
-            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+    fn tab_width(&mut self) -> Result<Instruction> {
+        // tab_width <int>
+        match self.tokens.take() {
+            Token::Int(width @ 1..) => Ok(Instruction::TabWidth(width as usize)),
+            token => Error::invalid_arg("positive number", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // align "<char>" <lines> [instant]
+    fn align(&mut self) -> Result<Instruction> {
+        let c = match self.tokens.take() {
+            Token::Str(s) if s.chars().count() == 1 => s.chars().next().unwrap(),
+            token => return Error::invalid_arg("single character string", token, self.tokens.spans(), self.tokens.source),
         };
 
-        Ok(instr)
+        let lines = match self.tokens.take() {
+            Token::Int(lines @ 1..) => lines as usize,
+            token => return Error::invalid_arg("number of lines", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let instant = self.tokens.consume_if(Token::Ident("instant".into()));
+
+        Ok(Instruction::Align { c, lines, instant })
     }
 
-    fn numbers(&mut self) -> Result<Instruction> {
-        let instr = match self.tokens.take() {
-            Token::Bool(b) => Instruction::ShowLineNumbers(b),
-            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
+    // toggle_comment [<lines>] [instant]
+    fn toggle_comment(&mut self) -> Result<Instruction> {
+        let lines = match self.tokens.current() {
+            &Token::Int(lines @ 1..) => {
+                self.tokens.consume();
+                Some(lines as usize)
+            }
+            Token::Int(_) => {
+                let token = self.tokens.take();
+                return Error::invalid_arg("number of lines", token, self.tokens.spans(), self.tokens.source);
+            }
+            _ => None,
         };
 
-        Ok(instr)
+        let instant = self.tokens.consume_if(Token::Ident("instant".into()));
+
+        Ok(Instruction::ToggleComment { lines, instant })
     }
 
-    fn clear(&mut self) -> Result<Instruction> {
-        Ok(Instruction::Clear)
+    // comment_leader|comment_prefix "<string>"
+    fn comment_leader(&mut self, fallback: bool) -> Result<Instruction> {
+        match self.tokens.take() {
+            Token::Str(leader) if !leader.trim().is_empty() => match fallback {
+                true => Ok(Instruction::CommentPrefix(leader)),
+                false => Ok(Instruction::CommentLeader(leader)),
+            },
+            token => Error::invalid_arg("comment leader string", token, self.tokens.spans(), self.tokens.source),
+        }
     }
 
-    fn jitter(&mut self) -> Result<Instruction> {
+    fn indent_unit(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Int(jitter) => Instruction::Jitter(jitter as u64),
-            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
+            Token::Str(unit) if !unit.is_empty() && unit.chars().all(|c| c == ' ' || c == '\t') => {
+                Instruction::IndentUnit(unit)
+            }
+            token => {
+                return Error::invalid_arg(
+                    "string of spaces or tabs",
+                    token,
+                    self.tokens.spans(),
+                    self.tokens.source,
+                );
+            }
         };
 
         Ok(instr)
     }
 
-    fn theme(&mut self) -> Result<Instruction> {
+    // command_speed <chars per second>
+    fn command_speed(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Str(theme) => Instruction::SetTheme(theme),
-            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
+            Token::Int(speed @ 1..) => Instruction::CommandSpeed(speed as u64),
+            token => return Error::invalid_arg("chars per second, from 1", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn audio(&mut self) -> Result<Instruction> {
+    fn speed(&mut self) -> Result<Instruction> {
+        // <int>
         let instr = match self.tokens.take() {
-            Token::Str(path) => Instruction::LoadAudio(path.into()),
-            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
+            Token::Int(speed) => Instruction::Speed(speed as u64),
+            token => return Error::invalid_arg("int", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn popup(&mut self) -> Result<Instruction> {
-        let instr = match self.tokens.take() {
-            Token::Str(msg) => Instruction::Popup(Source::Str(msg)),
-            Token::Ident(ident) => Instruction::Popup(Source::Ident(ident)),
+    // select <int> <int> | select @<ident> @<ident>
+    fn select(&mut self) -> Result<Instruction> {
+        if let Token::At = self.tokens.current() {
+            let start = self.marker()?;
+            let end = self.marker()?;
+            return Ok(Instruction::SelectMarkers { start, end });
+        }
+
+        let width = self.operand("width")?;
+        let height = self.operand("height")?;
+        Ok(Instruction::Select { width, height })
+    }
+
+    // find|rfind <string> [count]
+    fn find(&mut self, reverse: bool) -> Result<Instruction> {
+        let (needle, count) = self.needle()?;
+        Ok(Instruction::Find { needle, count, reverse })
+    }
+
+    // finde|rfinde <string> [count]
+    fn find_end(&mut self, reverse: bool) -> Result<Instruction> {
+        let (needle, count) = self.needle()?;
+        Ok(Instruction::FindEnd { needle, count, reverse })
+    }
+
+    // <string> [count]
+    fn needle(&mut self) -> Result<(String, usize)> {
+        let needle = match self.tokens.take() {
+            Token::Str(needle) => needle,
             token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
         };
+        let count = match self.tokens.current() {
+            &Token::Int(count) => {
+                self.tokens.consume();
+                count
+            }
+            _ => 1,
+        };
+        Ok((needle, count as usize))
+    }
 
-        Ok(instr)
+    // find_miss stay|error|skip <n>
+    fn find_miss(&mut self) -> Result<Instruction> {
+        let policy = match self.tokens.take() {
+            Token::Ident(policy) if policy == "stay" => FindMiss::Stay,
+            Token::Ident(policy) if policy == "error" => FindMiss::Error,
+            Token::Ident(policy) if policy == "skip" => match self.tokens.take() {
+                Token::Int(count @ 0..) => FindMiss::Skip(count as usize),
+                token => {
+                    return Error::invalid_arg("number of instructions", token, self.tokens.spans(), self.tokens.source);
+                }
+            },
+            token => return Error::invalid_arg("stay, error or skip", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(Instruction::FindMiss(policy))
     }
 
-    fn closepopup(&mut self) -> Result<Instruction> {
-        Ok(Instruction::ClosePopup)
+    fn linepause(&mut self) -> Result<Instruction> {
+        Ok(Instruction::LinePause(self.duration(Bare::Millis)?))
     }
 
-    fn write_buffer(&mut self) -> Result<Instruction> {
+    fn set_extension(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Str(path) => Instruction::WriteBuffer(path.into()),
+            Token::Str(ext) => Instruction::SetExtension(ext),
             token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn command(&mut self) -> Result<Instruction> {
+    fn set_title(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Str(cmd) => Instruction::Command(Source::Str(cmd)),
-            Token::Ident(cmd) => Instruction::Command(Source::Ident(cmd)),
+            Token::Str(title) => Instruction::SetTitle(self.tokens.template(title)),
             token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn command_clear(&mut self) -> Result<Instruction> {
+    fn numbers(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Int(millis) => Instruction::CommandClearTimeout(millis as u64),
-            token => return Error::invalid_arg("milliseconds", token, self.tokens.spans(), self.tokens.source),
+            Token::Bool(b) => Instruction::ShowLineNumbers(b),
+            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn set_variable(&mut self) -> Result<Instruction> {
+    // hscroll jump|smooth|keep
+    fn hscroll(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Ident(name) => {
-                let var = match self.tokens.take() {
-                    Token::Int(i) => Variable::Int(i),
-                    Token::Str(s) => Variable::Str(s),
-                    Token::Bool(b) => Variable::Bool(b),
-                    token => {
-                        return Error::invalid_arg(
-                            "either a boolean, string or integer",
-                            token,
-                            self.tokens.spans(),
-                            self.tokens.source,
-                        );
-                    }
-                };
-                Instruction::SetVariable(name, var)
-            }
-            token => return Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+            Token::Ident(policy) if policy == "jump" => Instruction::HScroll(HScroll::Jump),
+            Token::Ident(policy) if policy == "smooth" => Instruction::HScroll(HScroll::Smooth),
+            Token::Ident(policy) if policy == "keep" => Instruction::HScroll(HScroll::Keep),
+            token => return Error::invalid_arg("jump, smooth or keep", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn include(&mut self) -> Result<Instruction> {
+    // ligatures break|allow
+    // ligatures break add <string>
+    fn ligatures(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Str(path) => {
-                let src = match std::fs::read_to_string(&path) {
-                    Ok(src) => src,
-                    Err(_) => return Error::invalid_include_path(path, self.tokens.spans(), self.tokens.source),
-                };
-                let tokens = crate::parser::lexer::lex(&src)?;
-                let instructions = parse(tokens)?;
-                Instruction::Include(instructions)
-            }
-            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+            Token::Ident(mode) if mode == "allow" => Instruction::BreakLigatures(false),
+            Token::Ident(mode) if mode == "break" => match self.tokens.current() {
+                Token::Ident(add) if add == "add" => {
+                    self.tokens.consume();
+                    match self.tokens.take() {
+                        Token::Str(seq) if seq.chars().count() > 1 => Instruction::AddLigature(seq),
+                        token => {
+                            return Error::invalid_arg(
+                                "at least two characters",
+                                token,
+                                self.tokens.spans(),
+                                self.tokens.source,
+                            );
+                        }
+                    }
+                }
+                _ => Instruction::BreakLigatures(true),
+            },
+            token => return Error::invalid_arg("break or allow", token, self.tokens.spans(), self.tokens.source),
         };
 
         Ok(instr)
     }
 
-    fn wait(&mut self) -> Result<Instruction> {
+    fn color_column(&mut self) -> Result<Instruction> {
+        // colorcolumn <col>[,<col>...]|off
+        let mut columns = vec![];
+        match self.tokens.take() {
+            Token::Ident(off) if off == "off" => return Ok(Instruction::ColorColumn(columns)),
+            Token::Int(col @ 1..=0xFFFF) => columns.push(col as u16),
+            token => return Error::invalid_arg("column or `off`", token, self.tokens.spans(), self.tokens.source),
+        }
+
+        while let &Token::Int(col) = self.tokens.current() {
+            match col {
+                1..=0xFFFF => columns.push(col as u16),
+                _ => {
+                    let token = self.tokens.take();
+                    return Error::invalid_arg("column", token, self.tokens.spans(), self.tokens.source);
+                }
+            }
+            self.tokens.consume();
+        }
+
+        Ok(Instruction::ColorColumn(columns))
+    }
+
+    fn clear(&mut self) -> Result<Instruction> {
+        Ok(Instruction::Clear)
+    }
+
+    // scene <name> [theme <theme>] [ext <extension>]
+    fn scene(&mut self) -> Result<Instruction> {
+        let name = match self.tokens.take() {
+            Token::Str(name) => name,
+            token => return Error::invalid_arg("scene name", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let mut theme = None;
+        let mut extension = None;
+        loop {
+            let value = match self.tokens.current() {
+                Token::Theme => &mut theme,
+                Token::SetExtension => &mut extension,
+                Token::Ident(ext) if ext == "ext" => &mut extension,
+                _ => break,
+            };
+            self.tokens.consume();
+
+            match self.tokens.take() {
+                Token::Str(s) => *value = Some(s),
+                token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+            }
+        }
+
+        Ok(Instruction::Scene { name, theme, extension })
+    }
+
+    // scene_defaults keep_buffer|clear
+    fn scene_defaults(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(mode) if mode == "keep_buffer" => Instruction::SceneDefaults { clear: false },
+            Token::Clear => Instruction::SceneDefaults { clear: true },
+            token => return Error::invalid_arg("keep_buffer or clear", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // transition dissolve|wipe_left|wipe_down <duration>
+    fn transition(&mut self) -> Result<Instruction> {
+        let kind = match self.tokens.take() {
+            Token::Ident(kind) if kind == "dissolve" => TransitionKind::Dissolve,
+            Token::Ident(kind) if kind == "wipe_left" => TransitionKind::WipeLeft,
+            Token::Ident(kind) if kind == "wipe_down" => TransitionKind::WipeDown,
+            token => {
+                return Error::invalid_arg(
+                    "dissolve, wipe_left or wipe_down",
+                    token,
+                    self.tokens.spans(),
+                    self.tokens.source,
+                );
+            }
+        };
+
+        let duration = self.duration(Bare::Millis)?;
+        Ok(Instruction::Transition(kind, duration))
+    }
+
+    fn reset_counters(&mut self) -> Result<Instruction> {
+        Ok(Instruction::ResetCounters)
+    }
+
+    fn jitter(&mut self) -> Result<Instruction> {
+        let span = self.tokens.span();
+        let jitter = self.duration(Bare::Millis)?;
+        // The editor keeps it in milliseconds
+        if jitter.subsec_nanos() % 1_000_000 != 0 {
+            return Error::invalid_duration("jitter is in whole milliseconds", span, self.tokens.source);
+        }
+        Ok(Instruction::Jitter(jitter))
+    }
+
+    // jitter_profile uniform|burst|off
+    fn jitter_profile(&mut self) -> Result<Instruction> {
+        let profile = match self.tokens.take() {
+            Token::Ident(profile) if profile == "uniform" => JitterProfile::Uniform,
+            Token::Ident(profile) if profile == "burst" => JitterProfile::Burst,
+            Token::Ident(profile) if profile == "off" => JitterProfile::Off,
+            token => return Error::invalid_arg("uniform, burst or off", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(Instruction::JitterProfile(profile))
+    }
+
+    // burst_pause <min>..<max>
+    fn burst_pause(&mut self) -> Result<Instruction> {
+        let min = self.duration(Bare::Millis)?;
+
+        match self.tokens.take() {
+            Token::DotDot => (),
+            token => return Error::invalid_arg("..", token, self.tokens.spans(), self.tokens.source),
+        }
+
+        let max = self.duration(Bare::Millis)?;
+        if max < min {
+            let token = Token::Duration(max);
+            return Error::invalid_arg("duration not less than the minimum", token, self.tokens.spans(), self.tokens.source);
+        }
+
+        Ok(Instruction::BurstPause { min, max })
+    }
+
+    // auto_typo <rate_percent> <max_per_line>?
+    fn auto_typo(&mut self) -> Result<Instruction> {
+        let rate = match self.tokens.take() {
+            Token::Int(rate @ 0..=100) => rate as u8,
+            token => return Error::invalid_arg("percent (0-100)", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let max_per_line = match self.tokens.current() {
+            &Token::Int(max @ 1..) => {
+                self.tokens.consume();
+                Some(max as usize)
+            }
+            Token::Int(_) => {
+                let token = self.tokens.take();
+                return Error::invalid_arg("positive number", token, self.tokens.spans(), self.tokens.source);
+            }
+            _ => None,
+        };
+
+        Ok(Instruction::AutoTypo { rate, max_per_line })
+    }
+
+    fn theme(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Str(theme) => Instruction::SetTheme(theme),
+            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    fn audio(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Str(path) => Instruction::LoadAudio(path.into()),
+            token => return Error::invalid_arg("boolean", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // audio_classes on|off
+    fn audio_classes(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(on) if on == "on" => Instruction::AudioClasses(true),
+            Token::Ident(off) if off == "off" => Instruction::AudioClasses(false),
+            Token::Bool(b) => Instruction::AudioClasses(b),
+            token => return Error::invalid_arg("on or off", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // menu "<item>"... choose <index> [step] [then_type]
+    fn menu(&mut self) -> Result<Instruction> {
+        let mut items = vec![];
+        loop {
+            match self.tokens.take() {
+                Token::Str(item) => items.push(item),
+                Token::Ident(choose) if choose == "choose" && !items.is_empty() => break,
+                token => return Error::invalid_arg("item or choose", token, self.tokens.spans(), self.tokens.source),
+            }
+        }
+
+        let choice = match self.tokens.take() {
+            Token::Int(index @ 0..) => index as usize,
+            token => return Error::invalid_arg("index", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let step = self.optional_duration(Bare::Millis)?.unwrap_or(Duration::from_millis(150));
+
+        let then_type = self.tokens.consume_if(Token::Ident("then_type".into()));
+
+        Ok(Instruction::Menu {
+            items,
+            choice,
+            step,
+            then_type,
+        })
+    }
+
+    // markers strict|warn
+    fn markers(&mut self) -> Result<Instruction> {
+        let strict = match self.tokens.take() {
+            Token::Ident(mode) if mode == "strict" => true,
+            Token::Ident(mode) if mode == "warn" => false,
+            token => return Error::invalid_arg("strict or warn", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(Instruction::StrictMarkers(strict))
+    }
+
+    // splash
+    //     <string>...
+    //     [duration <duration>]
+    //     [style bold|dim|italic|underlined|reversed...]
+    // end
+    fn splash(&mut self) -> Result<Instruction> {
+        let mut lines = vec![];
+        let mut duration = None;
+        let mut styles = vec![];
+
+        loop {
+            match self.tokens.take() {
+                Token::Newline | Token::Comment | Token::Whitespace => continue,
+                Token::End => break,
+                Token::Str(text) => lines.extend(text.split('\n').map(String::from)),
+                Token::Ident(entry) if entry == "duration" => duration = Some(self.duration(Bare::Seconds)?),
+                Token::Ident(entry) if entry == "style" => loop {
+                    let style = match self.tokens.current() {
+                        Token::Ident(style) if style == "bold" => TextStyle::Bold,
+                        Token::Ident(style) if style == "dim" => TextStyle::Dim,
+                        Token::Ident(style) if style == "italic" => TextStyle::Italic,
+                        Token::Ident(style) if style == "underlined" => TextStyle::Underlined,
+                        Token::Ident(style) if style == "reversed" => TextStyle::Reversed,
+                        Token::Newline | Token::Eof if !styles.is_empty() => break,
+                        _ => {
+                            let token = self.tokens.take();
+                            let expected = "bold, dim, italic, underlined or reversed";
+                            return Error::invalid_arg(expected, token, self.tokens.spans(), self.tokens.source);
+                        }
+                    };
+                    styles.push(style);
+                    self.tokens.consume();
+                },
+                Token::Eof => {
+                    return Error::unexpected_token("end", Token::Eof, self.tokens.spans(), self.tokens.source);
+                }
+                token => {
+                    let expected = "string, duration, style or end";
+                    return Error::invalid_arg(expected, token, self.tokens.spans(), self.tokens.source);
+                }
+            }
+        }
+
+        Ok(Instruction::Splash { lines, duration, styles })
+    }
+
+    // virtual_text <row>|@<marker> <string> [eol|below]
+    // virtual_text clear [<row>]
+    fn virtual_text(&mut self) -> Result<Instruction> {
+        let anchor = match self.tokens.take() {
+            Token::Clear => {
+                let mut row = None;
+                if let &Token::Int(n @ 1..) = self.tokens.current() {
+                    row = Some(n as usize - 1);
+                    self.tokens.consume();
+                }
+                return Ok(Instruction::ClearVirtualText(row));
+            }
+            Token::Int(row @ 1..) => Anchor::Row(row as usize - 1),
+            Token::At => match self.tokens.take() {
+                Token::Ident(marker) => Anchor::Marker(marker),
+                token => return Error::invalid_arg("marker", token, self.tokens.spans(), self.tokens.source),
+            },
+            token => return Error::invalid_arg("row or @marker", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let text = match self.tokens.take() {
+            Token::Str(text) => text,
+            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let below = match self.tokens.current() {
+            Token::Ident(place) if place == "below" => true,
+            Token::Ident(place) if place == "eol" => false,
+            _ => return Ok(Instruction::VirtualText { anchor, text, below: false }),
+        };
+        self.tokens.consume();
+
+        Ok(Instruction::VirtualText { anchor, text, below })
+    }
+
+    // margins <top> <right> <bottom> <left>
+    fn margins(&mut self) -> Result<Instruction> {
+        let mut margins = [0; 4];
+        for margin in &mut margins {
+            *margin = match self.tokens.take() {
+                Token::Int(n @ 0..=0xffff) => n as u16,
+                token => return Error::invalid_arg("margin", token, self.tokens.spans(), self.tokens.source),
+            };
+        }
+
+        let [top, right, bottom, left] = margins;
+        Ok(Instruction::Margins {
+            top,
+            right,
+            bottom,
+            left,
+        })
+    }
+
+    // scrolloff <rows> [<columns>]
+    fn scrolloff(&mut self) -> Result<Instruction> {
+        let rows = match self.tokens.take() {
+            Token::Int(n @ 0..=0xffff) => n as u16,
+            token => return Error::invalid_arg("rows", token, self.tokens.spans(), self.tokens.source),
+        };
+        let columns = match *self.tokens.current() {
+            Token::Int(n @ 0..=0xffff) => {
+                self.tokens.consume();
+                Some(n as u16)
+            }
+            _ => None,
+        };
+        Ok(Instruction::ScrollOff { rows, columns })
+    }
+
+    // wrap on ["<indicator>"]|off
+    fn wrap(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(on) if on == "on" => {
+                let indicator = match self.tokens.current() {
+                    Token::Str(indicator) => Some(indicator.clone()),
+                    _ => None,
+                };
+                if indicator.is_some() {
+                    self.tokens.consume();
+                }
+                Instruction::Wrap(Some(indicator.unwrap_or_default()))
+            }
+            Token::Ident(off) if off == "off" => Instruction::Wrap(None),
+            Token::Bool(true) => Instruction::Wrap(Some(String::new())),
+            Token::Bool(false) => Instruction::Wrap(None),
+            token => return Error::invalid_arg("on or off", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // audio_offset <ms>
+    fn audio_offset(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Int(ms) => Instruction::AudioOffset(ms),
+            token => return Error::invalid_arg("milliseconds", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // key_overlay on|off
+    // key_overlay hold <duration>
+    fn key_overlay(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(on) if on == "on" => Instruction::KeyOverlay(true),
+            Token::Ident(off) if off == "off" => Instruction::KeyOverlay(false),
+            Token::Bool(b) => Instruction::KeyOverlay(b),
+            Token::Ident(hold) if hold == "hold" => Instruction::KeyOverlayHold(self.duration(Bare::Millis)?),
+            token => return Error::invalid_arg("on, off or hold", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // hyperlinks on|off
+    fn hyperlinks(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(on) if on == "on" => Instruction::Hyperlinks(true),
+            Token::Ident(off) if off == "off" => Instruction::Hyperlinks(false),
+            Token::Bool(b) => Instruction::Hyperlinks(b),
+            token => return Error::invalid_arg("on or off", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // link "<text>" "<url>"
+    fn link(&mut self) -> Result<Instruction> {
+        let text = match self.tokens.take() {
+            Token::Str(text) => text,
+            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let url = match self.tokens.take() {
+            Token::Str(url) => url,
+            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(Instruction::Link { text, url })
+    }
+
+    fn duck(&mut self) -> Result<Instruction> {
+        // duck <percent> <attack> <release>
+        let percent = match self.tokens.take() {
+            Token::Int(percent @ 0..=100) => percent as u8,
+            token => return Error::invalid_arg("percent (0-100)", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let attack = self.duration(Bare::Millis)?;
+        let release = self.duration(Bare::Millis)?;
+
+        Ok(Instruction::Duck {
+            percent,
+            attack,
+            release,
+        })
+    }
+
+    // popup <source> [<duration>] [at <position>] [title <string>], with the options in any order
+    fn popup(&mut self) -> Result<Instruction> {
+        let source = self.source("string", true)?;
+        let mut close_after = None;
+        let mut position = PopupPosition::Cursor;
+        let mut title = None;
+        loop {
+            if let Some(duration) = self.optional_duration(Bare::Seconds)? {
+                close_after = Some(duration);
+            } else if self.tokens.consume_if(Token::Ident("at".into())) {
+                position = self.popup_position()?;
+            } else if self.tokens.consume_if(Token::SetTitle) {
+                match self.tokens.take() {
+                    Token::Str(s) => title = Some(self.tokens.template(s)),
+                    token => return Error::invalid_arg("title", token, self.tokens.spans(), self.tokens.source),
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(Instruction::Popup {
+            source,
+            close_after,
+            position,
+            title,
+        })
+    }
+
+    // popup_at <position>
+    fn popup_at(&mut self) -> Result<Instruction> {
+        Ok(Instruction::PopupAt(self.popup_position()?))
+    }
+
+    // top|bottom|center|cursor|<row>:<col>
+    fn popup_position(&mut self) -> Result<PopupPosition> {
+        const EXPECTED: &str = "top, bottom, center, cursor or row:col";
+        let position = match self.tokens.take() {
+            Token::Ident(name) => match name.as_str() {
+                "top" => PopupPosition::Top,
+                "bottom" => PopupPosition::Bottom,
+                "center" | "centre" => PopupPosition::Center,
+                "cursor" => PopupPosition::Cursor,
+                _ => return Error::invalid_arg(EXPECTED, Token::Ident(name), self.tokens.spans(), self.tokens.source),
+            },
+            Token::Int(row @ 0..=0xFFFF) if self.tokens.consume_if(Token::Colon) => match self.tokens.take() {
+                Token::Int(col @ 0..=0xFFFF) => PopupPosition::At {
+                    row: row as u16,
+                    col: col as u16,
+                },
+                token => return Error::invalid_arg("column", token, self.tokens.spans(), self.tokens.source),
+            },
+            token => return Error::invalid_arg(EXPECTED, token, self.tokens.spans(), self.tokens.source),
+        };
+        Ok(position)
+    }
+
+    // popup_from <ident>|<path>
+    fn popup_from(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(ident) => Instruction::Popup {
+                source: Source::Ident(ident),
+                close_after: None,
+                position: PopupPosition::Cursor,
+                title: None,
+            },
+            Token::Str(path) => Instruction::PopupFile(path.into()),
+            token => return Error::invalid_arg("ident or path", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // scroll <rows>
+    fn scroll(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Int(rows) => Instruction::Scroll(rows.clamp(i32::MIN as i64, i32::MAX as i64) as i32),
+            token => return Error::invalid_arg("rows", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // loop [<count>] [pause <duration>]
+    fn loop_playback(&mut self) -> Result<Instruction> {
+        let count = match *self.tokens.current() {
+            Token::Int(count @ 1..=0xffff_ffff) => {
+                self.tokens.consume();
+                LoopCount::Times(count as u32)
+            }
+            Token::Int(_) => {
+                let token = self.tokens.take();
+                return Error::invalid_arg("number of times to play", token, self.tokens.spans(), self.tokens.source);
+            }
+            _ => LoopCount::Forever,
+        };
+
+        let pause = match self.tokens.consume_if(Token::Ident("pause".into())) {
+            true => self.duration(Bare::Seconds)?,
+            false => Duration::ZERO,
+        };
+
+        Ok(Instruction::Loop { count, pause })
+    }
+
+    // popup_scroll <lines>
+    fn popup_scroll(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Int(lines) => Instruction::PopupScroll(lines.clamp(i32::MIN as i64, i32::MAX as i64) as i32),
+            token => return Error::invalid_arg("lines", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // popup_autoscroll <lines per second>
+    fn popup_autoscroll(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Int(speed @ 0..=0xffff) => Instruction::PopupAutoscroll(speed as u32),
+            token => return Error::invalid_arg("lines per second", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    fn closepopup(&mut self) -> Result<Instruction> {
+        Ok(Instruction::ClosePopup)
+    }
+
+    fn write_buffer(&mut self) -> Result<Instruction> {
         let instr = match self.tokens.take() {
-            Token::Int(seconds) => Instruction::Wait(seconds as u64),
-            token => return Error::invalid_arg("seconds", token, self.tokens.spans(), self.tokens.source),
+            Token::Str(path) => Instruction::WriteBuffer(path.into()),
+            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
         };
 
-        Ok(instr)
+        Ok(instr)
+    }
+
+    fn command(&mut self) -> Result<Instruction> {
+        Ok(Instruction::Command(self.source("string", true)?))
+    }
+
+    fn command_clear(&mut self) -> Result<Instruction> {
+        Ok(Instruction::CommandClearTimeout(self.duration(Bare::Millis)?))
+    }
+
+    // exit_on_end <duration>
+    fn exit_on_end(&mut self) -> Result<Instruction> {
+        Ok(Instruction::ExitOnEnd(self.duration(Bare::Seconds)?))
+    }
+
+    fn set_variable(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(name) => {
+                let var = match self.tokens.take() {
+                    Token::Int(i) => Variable::Int(i),
+                    Token::Str(s) => Variable::Str(s),
+                    Token::Bool(b) => Variable::Bool(b),
+                    token => {
+                        return Error::invalid_arg(
+                            "either a boolean, string or integer",
+                            token,
+                            self.tokens.spans(),
+                            self.tokens.source,
+                        );
+                    }
+                };
+                Instruction::SetVariable(name, var)
+            }
+            token => return Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // let <ident> = <expr>
+    fn let_variable(&mut self) -> Result<Instruction> {
+        let name = match self.tokens.take() {
+            Token::Ident(name) => name,
+            token => return Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+        };
+        match self.tokens.take() {
+            Token::Equal => (),
+            token => return Error::invalid_arg("=", token, self.tokens.spans(), self.tokens.source),
+        }
+
+        let value = self.expr()?;
+        Ok(Instruction::Let { name, value })
+    }
+
+    // <term> [(+|-) <term>]*
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term(None)?;
+        loop {
+            let (op, first) = match *self.tokens.current() {
+                Token::Plus => (Op::Add, None),
+                Token::Minus => (Op::Sub, None),
+                // `a -4` is lexed as `a` and `-4`
+                Token::Int(int @ ..0) => (Op::Sub, Some(Expr::Int(int.saturating_neg()))),
+                _ => return Ok(lhs),
+            };
+            self.tokens.consume();
+            lhs = Expr::binary(lhs, op, self.term(first)?);
+        }
+    }
+
+    // <operand> [(*|/) <operand>]*
+    fn term(&mut self, first: Option<Expr>) -> Result<Expr> {
+        let mut lhs = match first {
+            Some(first) => first,
+            None => self.operand("number or variable")?,
+        };
+        loop {
+            let op = match self.tokens.current() {
+                Token::Star => Op::Mul,
+                Token::Slash => Op::Div,
+                _ => return Ok(lhs),
+            };
+            self.tokens.consume();
+            lhs = Expr::binary(lhs, op, self.operand("number or variable")?);
+        }
+    }
+
+    // <int>|<ident>
+    fn operand(&mut self, expected: &'static str) -> Result<Expr> {
+        match self.tokens.take() {
+            Token::Int(int) => Ok(Expr::Int(int)),
+            Token::Ident(name) => Ok(Expr::Variable(name)),
+            token => Error::invalid_arg(expected, token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    fn include(&mut self) -> Result<Instruction> {
+        let line = self.tokens.span().line as usize;
+        let instr = match self.tokens.take() {
+            Token::Str(path) => Instruction::Include { path: path.into(), line },
+            token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        Ok(instr)
+    }
+
+    // wait <duration>[..<duration>]|<ident> [ms]
+    fn wait(&mut self) -> Result<Instruction> {
+        if let Token::Ident(_) = self.tokens.current() {
+            let Token::Ident(name) = self.tokens.take() else { unreachable!() };
+            let unit = match self.tokens.consume_if(Token::Ident("ms".into())) {
+                true => Duration::from_millis(1),
+                false => Duration::from_secs(1),
+            };
+            return Ok(Instruction::WaitVariable { name, unit });
+        }
+
+        let min = self.duration(Bare::Seconds)?;
+        if !self.tokens.consume_if(Token::DotDot) {
+            return Ok(Instruction::Wait(min));
+        }
+
+        let max = self.duration(Bare::Seconds)?;
+        if max < min {
+            let token = Token::Duration(max);
+            return Error::invalid_arg("duration not less than the minimum", token, self.tokens.spans(), self.tokens.source);
+        }
+        Ok(Instruction::WaitRange(min, max))
+    }
+
+    // every <duration>
+    //     <instructions>
+    // end
+    //
+    // every stop
+    fn every(&mut self) -> Result<Instruction> {
+        if self.tokens.consume_if(Token::Ident("stop".into())) {
+            return Ok(Instruction::EveryStop);
+        }
+
+        let interval = self.positive_duration(Bare::Seconds, "duration above zero or stop")?;
+        let body = self.instructions(Block::End)?;
+        Ok(Instruction::Every { interval, body })
+    }
+
+    // repeat <count> { <instructions> }
+    fn repeat(&mut self) -> Result<Instruction> {
+        let count = match self.tokens.take() {
+            Token::Int(count @ 0..) if self.repeated.saturating_mul(count as usize) <= MAX_REPEAT => count as usize,
+            token @ Token::Int(0..) => {
+                let expected = "number of times, at most 10000 with the repeats around it";
+                return Error::invalid_arg(expected, token, self.tokens.spans(), self.tokens.source);
+            }
+            token => return Error::invalid_arg("number of times", token, self.tokens.spans(), self.tokens.source),
+        };
+
+        let repeated = self.repeated;
+        self.repeated *= count.max(1);
+        let body = self.braces();
+        self.repeated = repeated;
+        Ok(Instruction::Repeat { count, body: body? })
+    }
+
+    // if <ident> { <instructions> } [else { <instructions> }]
+    fn if_else(&mut self) -> Result<Instruction> {
+        let condition = match self.tokens.take() {
+            Token::Ident(condition) => condition,
+            token => return Error::invalid_arg("ident", token, self.tokens.spans(), self.tokens.source),
+        };
+        let then = self.braces()?;
+        let otherwise = match self.tokens.consume_if(Token::Else) {
+            true => self.braces()?,
+            false => Instructions::new(vec![]),
+        };
+        Ok(Instruction::If {
+            condition,
+            then,
+            otherwise,
+        })
+    }
+
+    // def <ident> { <instructions> }
+    fn def(&mut self) -> Result<Instruction> {
+        let name = self.macro_name()?;
+        let body = self.braces()?;
+        Ok(Instruction::Def { name, body })
+    }
+
+    // call <ident>
+    fn call(&mut self) -> Result<Instruction> {
+        Ok(Instruction::Call(self.macro_name()?))
+    }
+
+    fn macro_name(&mut self) -> Result<String> {
+        match self.tokens.take() {
+            Token::Ident(name) => Ok(name),
+            token => Error::invalid_arg("macro name", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // { <instructions> }
+    fn braces(&mut self) -> Result<Instructions> {
+        match self.tokens.take() {
+            Token::LBrace => self.instructions(Block::Brace),
+            token => Error::invalid_arg("{", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // budget <duration>
+    // budget end
+    fn budget(&mut self) -> Result<Instruction> {
+        let line = self.tokens.span().line as usize;
+        if self.tokens.consume_if(Token::End) {
+            return Ok(Instruction::BudgetEnd);
+        }
+
+        let limit = self.positive_duration(Bare::Seconds, "duration above zero or end")?;
+        Ok(Instruction::Budget { limit, line })
+    }
+
+    // preset define <name>
+    //     <settings>
+    // end
+    //
+    // preset use <name>
+    // preset pop
+    // preset include "<path>"
+    fn preset(&mut self) -> Result<Instruction> {
+        let instr = match self.tokens.take() {
+            Token::Ident(action) if action == "define" => {
+                let name = self.preset_name()?;
+                let body = self.instructions(Block::End)?;
+                Instruction::PresetDefine { name, body }
+            }
+            Token::Ident(action) if action == "use" => Instruction::PresetUse(self.preset_name()?),
+            Token::Ident(action) if action == "pop" => Instruction::PresetPop,
+            Token::Include => match self.tokens.take() {
+                Token::Str(path) => Instruction::PresetInclude(path.into()),
+                token => return Error::invalid_arg("string", token, self.tokens.spans(), self.tokens.source),
+            },
+            token => {
+                return Error::invalid_arg("define, use, pop or include", token, self.tokens.spans(), self.tokens.source);
+            }
+        };
+
+        Ok(instr)
+    }
+
+    fn preset_name(&mut self) -> Result<String> {
+        match self.tokens.take() {
+            Token::Ident(name) => Ok(name),
+            token => Error::invalid_arg("preset name", token, self.tokens.spans(), self.tokens.source),
+        }
+    }
+
+    // Zero is allowed everywhere else, but not for something that repeats or has to fit in the time
+    fn positive_duration(&mut self, bare: Bare, expected: &'static str) -> Result<Duration> {
+        match self.tokens.current() {
+            Token::Duration(duration) if duration.is_zero() => {}
+            Token::Duration(_) | Token::Int(1..) => return self.duration(bare),
+            _ => {}
+        }
+        let token = self.tokens.take();
+        Error::invalid_arg(expected, token, self.tokens.spans(), self.tokens.source)
+    }
+}
+
+pub fn parse(tokens: Tokens<'_>) -> Result<Instructions> {
+    Parser::new(tokens).parse()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parser::lexer::lex;
+
+    fn parse(input: &str) -> Result<Vec<Instruction>> {
+        let tokens = lex(input)?;
+        super::parse(tokens).map(|i| i.take_instructions())
+    }
+
+    fn parse_ok(input: &str) -> Vec<Instruction> {
+        parse(input).unwrap()
+    }
+
+    // -----------------------------------------------------------------------------
+    //   - Util functions -
+    // -----------------------------------------------------------------------------
+    fn load(path: impl Into<PathBuf>, key: impl Into<String>) -> Instruction {
+        let path = path.into();
+        let key = key.into();
+        Instruction::Load(path, key, None)
+    }
+
+    fn goto(dest: impl Into<Dest>) -> Instruction {
+        Instruction::Goto(dest.into())
+    }
+
+    fn print_str(s: &str) -> Instruction {
+        Instruction::Type {
+            source: Source::Str(s.into()),
+            filters: vec![],
+            trim_trailing_newline: false,
+            prefix_newline: false,
+            keep_selection: false,
+        }
+    }
+
+    fn popup(source: Source) -> Instruction {
+        Instruction::Popup {
+            source,
+            close_after: None,
+            position: PopupPosition::Cursor,
+            title: None,
+        }
+    }
+
+    fn print_ident(s: &str) -> Instruction {
+        Instruction::Type {
+            source: Source::Ident(s.into()),
+            filters: vec![],
+            trim_trailing_newline: false,
+            prefix_newline: false,
+            keep_selection: false,
+        }
+    }
+
+    fn replace_str(src: &str, s: &str) -> Instruction {
+        let src = src.into();
+        Instruction::Replace {
+            src,
+            replacement: Source::Str(s.into()),
+        }
+    }
+
+    fn replace_ident(src: &str, s: &str) -> Instruction {
+        let src = src.into();
+        Instruction::Replace {
+            src,
+            replacement: Source::Ident(s.into()),
+        }
+    }
+
+    fn wait(secs: u64) -> Instruction {
+        Instruction::Wait(Duration::from_secs(secs))
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn parse_load() {
+        let output = parse_ok("load \"foo.rs\" as hoppy");
+        let expected = vec![load("foo.rs", "hoppy")];
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parse_load_lines() {
+        let output = parse_ok("load \"lib.rs\":40..65 as code\nload \"lib.rs\":40.. as rest");
+        let expected = vec![
+            Instruction::Load("lib.rs".into(), "code".into(), Some(Lines { start: 40, end: Some(65) })),
+            Instruction::Load("lib.rs".into(), "rest".into(), Some(Lines { start: 40, end: None })),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("load \"lib.rs\":0..2 as code").is_err());
+        assert!(parse("load \"lib.rs\":3..2 as code").is_err());
+        assert!(parse("load \"lib.rs\":3..-1 as code").is_err());
+        assert!(parse("load \"lib.rs\":3 as code").is_err());
+    }
+
+    #[test]
+    fn parse_exec() {
+        let output = parse_ok("exec \"cargo build 2>&1\" as output\nexec \"ls\" 2 as files");
+        let expected = vec![
+            Instruction::Exec {
+                command: "cargo build 2>&1".into(),
+                timeout: None,
+                key: "output".into(),
+            },
+            Instruction::Exec {
+                command: "ls".into(),
+                timeout: Some(Duration::from_secs(2)),
+                key: "files".into(),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("exec \"ls\"").is_err());
+        assert!(parse("exec ls as files").is_err());
+    }
+
+    #[test]
+    fn parse_env() {
+        let output = parse_ok("insert env(\"HOME\")\npopup env_or(\"USER\", \"demo\")\ntype env");
+        let expected = vec![
+            Instruction::Insert {
+                source: Source::Env {
+                    name: "HOME".into(),
+                    default: None,
+                },
+                filters: vec![],
+            },
+            popup(Source::Env {
+                name: "USER".into(),
+                default: Some("demo".into()),
+            }),
+            print_ident("env"),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("insert env(HOME)").is_err());
+        assert!(parse("insert env(\"HOME\"").is_err());
+        assert!(parse("insert env_or(\"USER\")").is_err());
+    }
+
+    #[test]
+    fn parse_goto() {
+        let output = parse_ok("goto aaa");
+        let expected = vec![goto("aaa")];
+        assert_eq!(output, expected);
+
+        let output = parse_ok("goto 1, 2");
+        let expected = vec![goto((1, 2))];
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parse_goto_marker_offset() {
+        let output = parse_ok("goto @aaa\ngoto @aaa +1:0\ngoto aaa -2:3");
+        let marker = |row, col| {
+            goto(Dest::Marker {
+                name: "aaa".into(),
+                row,
+                col,
+            })
+        };
+        let expected = vec![marker(0, 0), marker(1, 0), marker(-2, 3)];
+        assert_eq!(output, expected);
+
+        assert!(parse("goto @aaa +1").is_err());
+    }
+
+    #[test]
+    fn parse_goto_start_and_end() {
+        let output = parse_ok("goto start\ngoto end\ngoto @start\ngoto @end +1:0");
+        let expected = vec![
+            goto(Dest::Start),
+            goto(Dest::End),
+            goto(Dest::Marker {
+                name: "start".into(),
+                row: 0,
+                col: 0,
+            }),
+            goto(Dest::Marker {
+                name: "end".into(),
+                row: 1,
+                col: 0,
+            }),
+        ];
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parse_goto_absolute() {
+        let output = parse_ok("goto 12:0\ngoto 1:4\ngoto 1 4");
+        let expected = vec![
+            goto(Dest::Absolute { row: 12, col: 0 }),
+            goto(Dest::Absolute { row: 1, col: 4 }),
+            goto(Dest::Relative { row: 1, col: 4 }),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("goto 0:0").is_err());
+        assert!(parse("goto 1:-1").is_err());
+        assert!(parse("goto 1:").is_err());
+    }
+
+    #[test]
+    fn parse_type() {
+        let output = parse_ok("type \"a string\"");
+        let expected = vec![print_str("a string")];
+        assert_eq!(output, expected);
+
+        let output = parse_ok("type aaa");
+        let expected = vec![print_ident("aaa")];
+        assert_eq!(output, expected);
+
+        let output = parse_ok("type \"a\" nonl keep_selection");
+        let expected = vec![Instruction::Type {
+            source: Source::Str("a".into()),
+            filters: vec![],
+            trim_trailing_newline: true,
+            prefix_newline: false,
+            keep_selection: true,
+        }];
+        assert_eq!(output, expected);
+
+        // Only `type` can keep the selection
+        assert!(parse("typenl \"a\" keep_selection").is_err());
+    }
+
+    #[test]
+    fn parse_filters() {
+        let output = parse_ok("type code | indent 4 | prefix \"- \" nonl\ninsert \"a\" | upper");
+        let filter = |name: &str, args, line| Filter {
+            name: name.into(),
+            args,
+            line,
+        };
+        let expected = vec![
+            Instruction::Type {
+                source: Source::Ident("code".into()),
+                filters: vec![
+                    filter("indent", vec![Variable::Int(4)], 1),
+                    filter("prefix", vec![Variable::Str("- ".into())], 1),
+                ],
+                trim_trailing_newline: true,
+                prefix_newline: false,
+                keep_selection: false,
+            },
+            Instruction::Insert {
+                source: Source::Str("a".into()),
+                filters: vec![filter("upper", vec![], 2)],
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("type code |").is_err());
+        assert!(parse("type code | 4").is_err());
+    }
+
+    #[test]
+    fn parse_replace() {
+        let output = parse_ok("replace \"a\" \"b\"");
+        let expected = vec![replace_str("a", "b")];
+        assert_eq!(output, expected);
+
+        let output = parse_ok("replace \"a\" b");
+        let expected = vec![replace_ident("a", "b")];
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parse_replace_all() {
+        let output = parse_ok("replace_all \"old\" \"new\"\nreplace_all \"old\" code");
+        let expected = vec![
+            Instruction::ReplaceAll {
+                needle: "old".into(),
+                replacement: Source::Str("new".into()),
+            },
+            Instruction::ReplaceAll {
+                needle: "old".into(),
+                replacement: Source::Ident("code".into()),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("replace_all \"\" \"new\"").is_err());
+        assert!(parse("replace_all \"old\"").is_err());
+    }
+
+    #[test]
+    fn parse_select_eol() {
+        let output = parse_ok("select_eol\nselect 2 1");
+        let expected = vec![Instruction::SelectEol, Instruction::Select {
+            width: Expr::Int(2),
+            height: Expr::Int(1),
+        }];
+        assert_eq!(output, expected);
+
+        assert!(parse("select_eol 2").is_err());
+    }
+
+    #[test]
+    fn parse_mark() {
+        let output = parse_ok("mark here\nunmark here\nmark @there\nmark end\nunmark @end\ngoto @end");
+        let expected = vec![
+            Instruction::SetMarker("here".into()),
+            Instruction::RemoveMarker("here".into()),
+            Instruction::SetMarker("there".into()),
+            Instruction::SetMarker("end".into()),
+            Instruction::RemoveMarker("end".into()),
+            Instruction::Goto(Dest::Marker {
+                name: "end".into(),
+                row: 0,
+                col: 0,
+            }),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("mark").is_err());
+        assert!(parse("unmark 1").is_err());
+    }
+
+    #[test]
+    fn parse_let() {
+        let var = |name: &str| Expr::Variable(name.into());
+        let output = parse_ok("let total = width + 4 * n - 2 / 1\nlet a = b -4\nlet c = 3");
+        let expected = vec![
+            Instruction::Let {
+                name: "total".into(),
+                value: Expr::binary(
+                    Expr::binary(var("width"), Op::Add, Expr::binary(Expr::Int(4), Op::Mul, var("n"))),
+                    Op::Sub,
+                    Expr::binary(Expr::Int(2), Op::Div, Expr::Int(1)),
+                ),
+            },
+            Instruction::Let {
+                name: "a".into(),
+                value: Expr::binary(var("b"), Op::Sub, Expr::Int(4)),
+            },
+            Instruction::Let {
+                name: "c".into(),
+                value: Expr::Int(3),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("let total width").is_err());
+        assert!(parse("let total = ").is_err());
+        assert!(parse("let total = a +").is_err());
+        assert!(parse("let total = \"a\"").is_err());
+    }
+
+    #[test]
+    fn parse_variables_as_numbers() {
+        let output = parse_ok("select width 1\nwait total\nwait pause ms");
+        let expected = vec![
+            Instruction::Select {
+                width: Expr::Variable("width".into()),
+                height: Expr::Int(1),
+            },
+            Instruction::WaitVariable {
+                name: "total".into(),
+                unit: Duration::from_secs(1),
+            },
+            Instruction::WaitVariable {
+                name: "pause".into(),
+                unit: ms(1),
+            },
+        ];
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parse_select_markers() {
+        let output = parse_ok("select @start @end\nselect @a @b");
+        let expected = vec![
+            Instruction::SelectMarkers {
+                start: "start".into(),
+                end: "end".into(),
+            },
+            Instruction::SelectMarkers {
+                start: "a".into(),
+                end: "b".into(),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("select @a").is_err());
+        assert!(parse("select @a b").is_err());
+        assert!(parse("select @a 2").is_err());
+    }
+
+    #[test]
+    fn parse_find() {
+        let output = parse_ok("find \"a\"\nfinde \"b\" 2\nrfind \"c\" 3\nrfinde \"d\"");
+        let expected = vec![
+            Instruction::Find {
+                needle: "a".into(),
+                count: 1,
+                reverse: false,
+            },
+            Instruction::FindEnd {
+                needle: "b".into(),
+                count: 2,
+                reverse: false,
+            },
+            Instruction::Find {
+                needle: "c".into(),
+                count: 3,
+                reverse: true,
+            },
+            Instruction::FindEnd {
+                needle: "d".into(),
+                count: 1,
+                reverse: true,
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("rfind c").is_err());
+    }
+
+    #[test]
+    fn parse_wait() {
+        let output = parse_ok("wait 123\nwait 0.25\nwait 250ms\nwait 2s\nwait 1.5");
+        let expected = vec![
+            wait(123),
+            Instruction::Wait(ms(250)),
+            Instruction::Wait(ms(250)),
+            wait(2),
+            Instruction::Wait(ms(1500)),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("wait -1").is_err());
+        assert!(parse("wait -0.25").is_err());
+        assert!(parse("wait 100000000000000000000.5").is_err());
+        assert!(parse("wait -250ms").is_err());
+        assert!(parse("wait").is_err());
+    }
+
+    #[test]
+    fn parse_wait_range() {
+        let output = parse_ok("wait 1..3\nwait 200ms..800ms\nwait 1s..1s");
+        let expected = vec![
+            Instruction::WaitRange(Duration::from_secs(1), Duration::from_secs(3)),
+            Instruction::WaitRange(ms(200), ms(800)),
+            Instruction::WaitRange(Duration::from_secs(1), Duration::from_secs(1)),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("wait 3s..1s").is_err());
+        assert!(parse("wait 1s..").is_err());
+    }
+
+    #[test]
+    fn parse_duck() {
+        let output = parse_ok("duck 30 20 150");
+        let expected = vec![Instruction::Duck {
+            percent: 30,
+            attack: ms(20),
+            release: ms(150),
+        }];
+        assert_eq!(output, expected);
+
+        assert!(parse("duck 101 20 150").is_err());
+    }
+
+    #[test]
+    fn parse_auto_typo() {
+        let output = parse_ok("auto_typo 5\nauto_typo 10 2");
+        let expected = vec![
+            Instruction::AutoTypo {
+                rate: 5,
+                max_per_line: None,
+            },
+            Instruction::AutoTypo {
+                rate: 10,
+                max_per_line: Some(2),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("auto_typo 101").is_err());
+        assert!(parse("auto_typo 10 0").is_err());
+    }
+
+    #[test]
+    fn parse_color_column() {
+        let output = parse_ok("colorcolumn 80");
+        assert_eq!(output, vec![Instruction::ColorColumn(vec![80])]);
+
+        let output = parse_ok("colorcolumn 80,100, 120");
+        assert_eq!(output, vec![Instruction::ColorColumn(vec![80, 100, 120])]);
+
+        let output = parse_ok("colorcolumn off");
+        assert_eq!(output, vec![Instruction::ColorColumn(vec![])]);
+
+        assert!(parse("colorcolumn 0").is_err());
+        assert!(parse("colorcolumn 80,0").is_err());
+    }
+
+    #[test]
+    fn parse_backspace() {
+        let output = parse_ok("backspace\nbackspace 12");
+        let expected = vec![Instruction::Backspace(1), Instruction::Backspace(12)];
+        assert_eq!(output, expected);
+
+        assert!(parse("backspace 0").is_err());
+        assert!(parse("backspace -2").is_err());
+    }
+
+    #[test]
+    fn parse_delete_line() {
+        let output = parse_ok("delete_line\ndelete_line 3\ndelete");
+        let expected = vec![Instruction::DeleteLine(1), Instruction::DeleteLine(3), Instruction::Delete];
+        assert_eq!(output, expected);
+
+        assert!(parse("delete_line 0").is_err());
+    }
+
+    #[test]
+    fn parse_duplicate_line() {
+        let output = parse_ok("duplicate_line\nduplicate_line 2");
+        let expected = vec![Instruction::DuplicateLine(1), Instruction::DuplicateLine(2)];
+        assert_eq!(output, expected);
+
+        assert!(parse("duplicate_line 0").is_err());
+    }
+
+    #[test]
+    fn parse_move_line() {
+        let output = parse_ok("move_line_up\nmove_line_down 3");
+        let expected = vec![
+            Instruction::MoveLine { up: true, count: 1 },
+            Instruction::MoveLine { up: false, count: 3 },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("move_line_up 0").is_err());
+    }
+
+    #[test]
+    fn parse_delete_word() {
+        let output = parse_ok("delete_word\ndelete_word");
+        assert_eq!(output, vec![Instruction::DeleteWord, Instruction::DeleteWord]);
+        assert!(parse("delete_word 2").is_err());
+    }
+
+    #[test]
+    fn parse_snapshot_and_restore() {
+        let output = parse_ok("snapshot before\nrestore before");
+        let expected = vec![Instruction::Snapshot("before".into()), Instruction::Restore("before".into())];
+        assert_eq!(output, expected);
+        assert!(parse("snapshot").is_err());
+        assert!(parse("restore \"before\"").is_err());
     }
-}
 
-pub fn parse(tokens: Tokens<'_>) -> Result<Instructions> {
-    Parser::new(tokens).parse()
-}
+    #[test]
+    fn parse_yank_and_paste() {
+        let output = parse_ok("yank\npaste\npaste_above");
+        let expected = vec![
+            Instruction::Yank,
+            Instruction::Paste { above: false },
+            Instruction::Paste { above: true },
+        ];
+        assert_eq!(output, expected);
+        assert!(parse("paste 2").is_err());
+    }
 
-#[cfg(test)]
-mod test {
-    use std::path::PathBuf;
+    #[test]
+    fn parse_clear_eol() {
+        let output = parse_ok("clear_eol\nclear");
+        assert_eq!(output, vec![Instruction::ClearEol, Instruction::Clear]);
+        assert!(parse("clear_eol 2").is_err());
+    }
 
-    use super::*;
-    use crate::lexer::lex;
+    #[test]
+    fn parse_join() {
+        let output = parse_ok("join\njoin 3");
+        let expected = vec![Instruction::Join(1), Instruction::Join(3)];
+        assert_eq!(output, expected);
 
-    fn parse(input: &str) -> Result<Vec<Instruction>> {
-        let tokens = lex(input)?;
-        super::parse(tokens).map(|i| i.take_instructions())
+        assert!(parse("join 0").is_err());
     }
 
-    fn parse_ok(input: &str) -> Vec<Instruction> {
-        parse(input).unwrap()
+    #[test]
+    fn parse_split_line() {
+        let output = parse_ok("autoindent true\nsplit_line");
+        let expected = vec![Instruction::AutoIndent(true), Instruction::SplitLine];
+        assert_eq!(output, expected);
     }
 
-    // -----------------------------------------------------------------------------
-    //   - Util functions -
-    // -----------------------------------------------------------------------------
-    fn load(path: impl Into<PathBuf>, key: impl Into<String>) -> Instruction {
-        let path = path.into();
-        let key = key.into();
-        Instruction::Load(path, key)
+    #[test]
+    fn parse_indent() {
+        let output = parse_ok("indent 5\ndedent 2 3\nindent_unit \"\t\"\nindent\ndedent\ntab_width 8");
+        let expected = vec![
+            Instruction::Indent {
+                lines: Some(5),
+                levels: 1,
+            },
+            Instruction::Dedent {
+                lines: Some(2),
+                levels: 3,
+            },
+            Instruction::IndentUnit("\t".into()),
+            Instruction::Indent { lines: None, levels: 1 },
+            Instruction::Dedent { lines: None, levels: 1 },
+            Instruction::TabWidth(8),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("indent 0").is_err());
+        assert!(parse("tab_width 0").is_err());
+        assert!(parse("indent_unit \"x\"").is_err());
+        assert!(parse("indent_unit \"\"").is_err());
     }
 
-    fn goto(dest: impl Into<Dest>) -> Instruction {
-        Instruction::Goto(dest.into())
+    #[test]
+    fn parse_toggle_comment() {
+        let src = "toggle_comment 3\ntoggle_comment 1 instant\ntoggle_comment\ntoggle_comment instant\ncomment_leader \"REM\"\n\
+                   comment_prefix \"#\"";
+        let output = parse_ok(src);
+        let expected = vec![
+            Instruction::ToggleComment {
+                lines: Some(3),
+                instant: false,
+            },
+            Instruction::ToggleComment {
+                lines: Some(1),
+                instant: true,
+            },
+            Instruction::ToggleComment {
+                lines: None,
+                instant: false,
+            },
+            Instruction::ToggleComment {
+                lines: None,
+                instant: true,
+            },
+            Instruction::CommentLeader("REM".into()),
+            Instruction::CommentPrefix("#".into()),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("toggle_comment 0").is_err());
+        assert!(parse("comment_leader \" \"").is_err());
+        assert!(parse("comment_prefix").is_err());
     }
 
-    fn print_str(s: &str) -> Instruction {
-        Instruction::Type {
-            source: Source::Str(s.into()),
-            trim_trailing_newline: false,
-            prefix_newline: false,
-        }
+    #[test]
+    fn parse_align() {
+        let output = parse_ok("align \"=\" 3\nalign \":\" 2 instant");
+        let expected = vec![
+            Instruction::Align {
+                c: '=',
+                lines: 3,
+                instant: false,
+            },
+            Instruction::Align {
+                c: ':',
+                lines: 2,
+                instant: true,
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("align \"==\" 3").is_err());
+        assert!(parse("align \"=\" 0").is_err());
+        assert!(parse("align \"=\"").is_err());
     }
 
-    fn print_ident(s: &str) -> Instruction {
-        Instruction::Type {
-            source: Source::Ident(s.into()),
-            trim_trailing_newline: false,
-            prefix_newline: false,
-        }
+    #[test]
+    fn parse_if_else() {
+        let output = parse_ok("if long {\n    if demo { clear }\n} else {\n    close_popup\n}\nif demo {}");
+        let expected = vec![
+            Instruction::If {
+                condition: "long".into(),
+                then: Instructions::new(vec![Instruction::If {
+                    condition: "demo".into(),
+                    then: Instructions::new(vec![Instruction::Clear]),
+                    otherwise: Instructions::new(vec![]),
+                }]),
+                otherwise: Instructions::new(vec![Instruction::ClosePopup]),
+            },
+            Instruction::If {
+                condition: "demo".into(),
+                then: Instructions::new(vec![]),
+                otherwise: Instructions::new(vec![]),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("if true {}").is_err());
+        assert!(parse("if long\n    clear\nend").is_err());
+        assert!(parse("if long {} else clear").is_err());
     }
 
-    fn replace_str(src: &str, s: &str) -> Instruction {
-        let src = src.into();
-        Instruction::Replace {
-            src,
-            replacement: Source::Str(s.into()),
-        }
+    #[test]
+    fn parse_def_and_call() {
+        let output = parse_ok("def save {\n    command \":w\"\n    popup \"saved\"\n}\ncall save");
+        let expected = vec![
+            Instruction::Def {
+                name: "save".into(),
+                body: Instructions::new(vec![
+                    Instruction::Command(Source::Str(":w".into())),
+                    popup(Source::Str("saved".into())),
+                ]),
+            },
+            Instruction::Call("save".into()),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("def save\n    clear\nend").is_err());
+        assert!(parse("def \"save\" {}").is_err());
+        assert!(parse("call").is_err());
     }
 
-    fn replace_ident(src: &str, s: &str) -> Instruction {
-        let src = src.into();
-        Instruction::Replace {
-            src,
-            replacement: Source::Ident(s.into()),
-        }
+    #[test]
+    fn parse_hash_comments() {
+        let output = parse_ok("# intro\nrepeat 2 { # twice\n    close_popup # trailing\n}\n# the end");
+        let expected = vec![Instruction::Repeat {
+            count: 2,
+            body: Instructions::new(vec![Instruction::ClosePopup]),
+        }];
+        assert_eq!(output, expected);
     }
 
-    fn wait(secs: u64) -> Instruction {
-        Instruction::Wait(secs)
+    #[test]
+    fn parse_every() {
+        let output = parse_ok("every 5\n    popup \"hi\"\n    close_popup\nend\nevery stop");
+        let expected = vec![
+            Instruction::Every {
+                interval: Duration::from_secs(5),
+                body: Instructions::new(vec![
+                    popup(Source::Str("hi".into())),
+                    Instruction::ClosePopup,
+                ]),
+            },
+            Instruction::EveryStop,
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("every 5\n    popup \"hi\"").is_err());
+        assert!(parse("every 0\nend").is_err());
     }
 
     #[test]
-    fn parse_load() {
-        let output = parse_ok("load \"foo.rs\" as hoppy");
-        let expected = vec![load("foo.rs", "hoppy")];
+    fn parse_repeat() {
+        let output = parse_ok("repeat 2 {\n    insert \"a\"\n    repeat 0 { close_popup }\n}\nrepeat 3 {}");
+        let expected = vec![
+            Instruction::Repeat {
+                count: 2,
+                body: Instructions::new(vec![
+                    Instruction::Insert {
+                        source: Source::Str("a".into()),
+                        filters: vec![],
+                    },
+                    Instruction::Repeat {
+                        count: 0,
+                        body: Instructions::new(vec![Instruction::ClosePopup]),
+                    },
+                ]),
+            },
+            Instruction::Repeat {
+                count: 3,
+                body: Instructions::new(vec![]),
+            },
+        ];
         assert_eq!(output, expected);
+
+        assert!(parse("repeat 2 {\n    close_popup").is_err());
+        assert!(parse("repeat 2\n    close_popup\nend").is_err());
+        assert!(parse("repeat -1 {}").is_err());
+
+        // Not too many copies, nested or not
+        assert!(parse("repeat 10000 {}\nrepeat 100 { repeat 100 {} }").is_ok());
+        assert!(parse("repeat 10001 {}").is_err());
+        assert!(parse("repeat 100 { repeat 0 {} repeat 101 {} }").is_err());
+        assert!(parse("repeat 100 { repeat 100 {} }\nrepeat 100 {}").is_ok());
+        assert!(parse("close_popup }").is_err());
     }
 
     #[test]
-    fn parse_goto() {
-        let output = parse_ok("goto aaa");
-        let expected = vec![goto("aaa")];
+    fn parse_preset() {
+        let src = "preset define fast\n    speed 100\n    theme \"x\"\nend\npreset use fast\npreset pop\npreset include \"common.echo\"";
+        let output = parse_ok(src);
+        let expected = vec![
+            Instruction::PresetDefine {
+                name: "fast".into(),
+                body: Instructions::new(vec![Instruction::Speed(100), Instruction::SetTheme("x".into())]),
+            },
+            Instruction::PresetUse("fast".into()),
+            Instruction::PresetPop,
+            Instruction::PresetInclude("common.echo".into()),
+        ];
         assert_eq!(output, expected);
 
-        let output = parse_ok("goto 1, 2");
-        let expected = vec![goto((1, 2))];
+        assert!(parse("preset define fast\n    speed 100").is_err());
+        assert!(parse("preset use \"fast\"").is_err());
+        assert!(parse("preset fast").is_err());
+    }
+
+    #[test]
+    fn parse_transition() {
+        let output = parse_ok("transition dissolve 500\ntransition wipe_left 200\ntransition wipe_down 0");
+        let expected = vec![
+            Instruction::Transition(TransitionKind::Dissolve, ms(500)),
+            Instruction::Transition(TransitionKind::WipeLeft, ms(200)),
+            Instruction::Transition(TransitionKind::WipeDown, ms(0)),
+        ];
         assert_eq!(output, expected);
+
+        assert!(parse("transition fade 500").is_err());
+        assert!(parse("transition dissolve -1").is_err());
     }
 
     #[test]
-    fn parse_type() {
-        let output = parse_ok("type \"a string\"");
-        let expected = vec![print_str("a string")];
+    fn parse_hscroll() {
+        let output = parse_ok("hscroll jump\nhscroll smooth\nhscroll keep");
+        let expected = vec![
+            Instruction::HScroll(HScroll::Jump),
+            Instruction::HScroll(HScroll::Smooth),
+            Instruction::HScroll(HScroll::Keep),
+        ];
         assert_eq!(output, expected);
 
-        let output = parse_ok("type aaa");
-        let expected = vec![print_ident("aaa")];
+        assert!(parse("hscroll 1").is_err());
+    }
+
+    #[test]
+    fn parse_ligatures() {
+        let output = parse_ok("ligatures break\nligatures break add \"<=>\"\nligatures allow");
+        let expected = vec![
+            Instruction::BreakLigatures(true),
+            Instruction::AddLigature("<=>".into()),
+            Instruction::BreakLigatures(false),
+        ];
         assert_eq!(output, expected);
+
+        assert!(parse("ligatures break add \"=\"").is_err());
+        assert!(parse("ligatures off").is_err());
     }
 
     #[test]
-    fn parse_replace() {
-        let output = parse_ok("replace \"a\" \"b\"");
-        let expected = vec![replace_str("a", "b")];
+    fn parse_budget() {
+        let output = parse_ok("wait 1\nbudget 90\nwait 2\nbudget end");
+        let expected = vec![
+            wait(1),
+            Instruction::Budget {
+                limit: Duration::from_secs(90),
+                line: 2,
+            },
+            wait(2),
+            Instruction::BudgetEnd,
+        ];
         assert_eq!(output, expected);
 
-        let output = parse_ok("replace \"a\" b");
-        let expected = vec![replace_ident("a", "b")];
+        assert!(parse("budget 0").is_err());
+    }
+
+    #[test]
+    fn parse_find_miss() {
+        let output = parse_ok("find_miss error\nfind_miss skip 3\nfind_miss stay");
+        let expected = vec![
+            Instruction::FindMiss(FindMiss::Error),
+            Instruction::FindMiss(FindMiss::Skip(3)),
+            Instruction::FindMiss(FindMiss::Stay),
+        ];
         assert_eq!(output, expected);
+
+        assert!(parse("find_miss skip").is_err());
+        assert!(parse("find_miss skip -1").is_err());
+        assert!(parse("find_miss ignore").is_err());
     }
 
     #[test]
-    fn parse_wait() {
-        let output = parse_ok("wait 123");
-        let expected = vec![wait(123)];
+    fn parse_jitter_profile() {
+        let output = parse_ok("jitter_profile burst\nburst_pause 80..220\njitter_profile off\njitter_profile uniform");
+        let expected = vec![
+            Instruction::JitterProfile(JitterProfile::Burst),
+            Instruction::BurstPause { min: ms(80), max: ms(220) },
+            Instruction::JitterProfile(JitterProfile::Off),
+            Instruction::JitterProfile(JitterProfile::Uniform),
+        ];
         assert_eq!(output, expected);
+
+        assert!(parse("burst_pause 220..80").is_err());
+        assert!(parse("burst_pause 80 220").is_err());
+        assert!(parse("jitter_profile on").is_err());
+    }
+
+    #[test]
+    fn parse_scene() {
+        let output = parse_ok("scene \"intro\"\nscene \"main\" theme \"togglebit\" ext \"rs\"\nscene_defaults keep_buffer");
+        let expected = vec![
+            Instruction::Scene {
+                name: "intro".into(),
+                theme: None,
+                extension: None,
+            },
+            Instruction::Scene {
+                name: "main".into(),
+                theme: Some("togglebit".into()),
+                extension: Some("rs".into()),
+            },
+            Instruction::SceneDefaults { clear: false },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("scene intro").is_err());
+        assert!(parse("scene \"intro\" theme").is_err());
+        assert!(parse("scene_defaults keep").is_err());
+    }
+
+    #[test]
+    fn parse_audio_classes() {
+        let output = parse_ok("audio_classes on\naudio_classes off\naudio_classes true");
+        let expected = vec![
+            Instruction::AudioClasses(true),
+            Instruction::AudioClasses(false),
+            Instruction::AudioClasses(true),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("audio_classes 1").is_err());
+    }
+
+    #[test]
+    fn parse_menu() {
+        let output = parse_ok("menu \"a\" \"b\" choose 1\nmenu \"a\" \"b\" \"c\" choose 2 80 then_type");
+        let expected = vec![
+            Instruction::Menu {
+                items: vec!["a".into(), "b".into()],
+                choice: 1,
+                step: ms(150),
+                then_type: false,
+            },
+            Instruction::Menu {
+                items: vec!["a".into(), "b".into(), "c".into()],
+                choice: 2,
+                step: ms(80),
+                then_type: true,
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("menu choose 0").is_err());
+        assert!(parse("menu \"a\" choose").is_err());
+        assert!(parse("menu \"a\" choose -1").is_err());
+    }
+
+    #[test]
+    fn parse_audio_offset() {
+        let output = parse_ok("audio_offset 60\naudio_offset -25");
+        let expected = vec![Instruction::AudioOffset(60), Instruction::AudioOffset(-25)];
+        assert_eq!(output, expected);
+
+        assert!(parse("audio_offset on").is_err());
+    }
+
+    #[test]
+    fn parse_command_speed() {
+        let output = parse_ok("command_speed 60");
+        assert_eq!(output, vec![Instruction::CommandSpeed(60)]);
+
+        assert!(parse("command_speed 0").is_err());
+        assert!(parse("command_speed fast").is_err());
+    }
+
+    #[test]
+    fn parse_popup_timed() {
+        let output = parse_ok("popup \"Hello\" 3s\npopup notes 2\npopup \"stays\"");
+        let expected = vec![
+            Instruction::Popup {
+                source: Source::Str("Hello".into()),
+                close_after: Some(Duration::from_secs(3)),
+                position: PopupPosition::Cursor,
+                title: None,
+            },
+            Instruction::Popup {
+                source: Source::Ident("notes".into()),
+                close_after: Some(Duration::from_secs(2)),
+                position: PopupPosition::Cursor,
+                title: None,
+            },
+            popup(Source::Str("stays".into())),
+        ];
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn parse_popup_position_and_title() {
+        let output = parse_ok("popup \"a\" at top title \"Note\"\npopup \"b\" title \"T\" 2s at 3:10\npopup_at bottom");
+        let expected = vec![
+            Instruction::Popup {
+                source: Source::Str("a".into()),
+                close_after: None,
+                position: PopupPosition::Top,
+                title: Some("Note".into()),
+            },
+            Instruction::Popup {
+                source: Source::Str("b".into()),
+                close_after: Some(Duration::from_secs(2)),
+                position: PopupPosition::At { row: 3, col: 10 },
+                title: Some("T".into()),
+            },
+            Instruction::PopupAt(PopupPosition::Bottom),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("popup \"a\" at left").is_err());
+        assert!(parse("popup \"a\" at 3").is_err());
+        assert!(parse("popup \"a\" title").is_err());
+        assert!(parse("popup_at -1:2").is_err());
+    }
+
+    #[test]
+    fn parse_popup_scrolling() {
+        let output = parse_ok("popup_from notes\npopup_from \"CHANGES.md\"\npopup_scroll -3\npopup_autoscroll 2");
+        let expected = vec![
+            popup(Source::Ident("notes".into())),
+            Instruction::PopupFile("CHANGES.md".into()),
+            Instruction::PopupScroll(-3),
+            Instruction::PopupAutoscroll(2),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("popup_scroll").is_err());
+
+        let output = parse_ok("scroll 5\nscroll -2");
+        let expected = vec![Instruction::Scroll(5), Instruction::Scroll(-2)];
+        assert_eq!(output, expected);
+        assert!(parse("scroll").is_err());
+        assert!(parse("scroll down").is_err());
+
+        let output = parse_ok("loop\nloop 3\nloop pause 2s\nloop 2 pause 500ms");
+        let expected = vec![
+            Instruction::Loop {
+                count: LoopCount::Forever,
+                pause: Duration::ZERO,
+            },
+            Instruction::Loop {
+                count: LoopCount::Times(3),
+                pause: Duration::ZERO,
+            },
+            Instruction::Loop {
+                count: LoopCount::Forever,
+                pause: Duration::from_secs(2),
+            },
+            Instruction::Loop {
+                count: LoopCount::Times(2),
+                pause: ms(500),
+            },
+        ];
+        assert_eq!(output, expected);
+        assert!(parse("loop 0").is_err());
+        assert!(parse("loop pause").is_err());
+
+        let output = parse_ok("exit_on_end 3\nexit");
+        let expected = vec![Instruction::ExitOnEnd(Duration::from_secs(3)), Instruction::Exit];
+        assert_eq!(output, expected);
+        assert!(parse("exit_on_end").is_err());
+        assert!(parse("popup_autoscroll -1").is_err());
+    }
+
+    #[test]
+    fn parse_margins() {
+        let output = parse_ok("margins 1 2 3 4");
+        let expected = vec![Instruction::Margins {
+            top: 1,
+            right: 2,
+            bottom: 3,
+            left: 4,
+        }];
+        assert_eq!(output, expected);
+
+        assert!(parse("margins 1 2 3").is_err());
+        assert!(parse("margins 1 2 3 -4").is_err());
+    }
+
+    #[test]
+    fn parse_scrolloff() {
+        let output = parse_ok("scrolloff 3\nscrolloff 0 8");
+        let expected = vec![
+            Instruction::ScrollOff { rows: 3, columns: None },
+            Instruction::ScrollOff {
+                rows: 0,
+                columns: Some(8),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("scrolloff").is_err());
+        assert!(parse("scrolloff -1").is_err());
+        assert!(parse("scrolloff 1 -1").is_err());
+    }
+
+    #[test]
+    fn parse_wrap() {
+        let output = parse_ok("wrap on\nwrap on \"↪ \"\nwrap off");
+        let expected = vec![
+            Instruction::Wrap(Some(String::new())),
+            Instruction::Wrap(Some("↪ ".into())),
+            Instruction::Wrap(None),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("wrap").is_err());
+        assert!(parse("wrap 2").is_err());
+    }
+
+    #[test]
+    fn parse_markers() {
+        let output = parse_ok("markers strict\nmarkers warn");
+        let expected = vec![Instruction::StrictMarkers(true), Instruction::StrictMarkers(false)];
+        assert_eq!(output, expected);
+
+        assert!(parse("markers").is_err());
+        assert!(parse("markers on").is_err());
+    }
+
+    // Every duration in the instruction
+    fn durations(inst: &Instruction) -> Vec<Duration> {
+        match inst {
+            Instruction::Wait(d)
+            | Instruction::LinePause(d)
+            | Instruction::CommandClearTimeout(d)
+            | Instruction::Jitter(d)
+            | Instruction::KeyOverlayHold(d)
+            | Instruction::Transition(_, d)
+            | Instruction::Popup { close_after: Some(d), .. }
+            | Instruction::Menu { step: d, .. }
+            | Instruction::Every { interval: d, .. }
+            | Instruction::Budget { limit: d, .. }
+            | Instruction::Loop { pause: d, .. }
+            | Instruction::ExitOnEnd(d) => vec![*d],
+            Instruction::BurstPause { min, max } => vec![*min, *max],
+            Instruction::Duck { attack, release, .. } => vec![*attack, *release],
+            inst => panic!("no duration in {inst:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_durations() {
+        let instructions = [
+            "wait {}",
+            "line_pause {}",
+            "command_clear_timeout {}",
+            "jitter {}",
+            "burst_pause {}..{}",
+            "key_overlay hold {}",
+            "duck 30 {} {}",
+            "transition dissolve {}",
+            "menu \"a\" choose 0 {}",
+            "popup \"a\" {}",
+            "every {}\nend",
+            "budget {}",
+            "loop pause {}",
+            "exit_on_end {}",
+        ];
+        let units = [
+            ("2s", Duration::from_secs(2)),
+            ("1.5s", ms(1500)),
+            ("200ms", ms(200)),
+            ("0.5ms", Duration::from_micros(500)),
+            ("90us", Duration::from_micros(90)),
+        ];
+
+        for template in instructions {
+            for (literal, duration) in units {
+                let src = template.replace("{}", literal);
+                if template == "jitter {}" && duration.subsec_micros() % 1000 != 0 {
+                    assert!(parse(&src).is_err(), "{src}");
+                    continue;
+                }
+                let tokens = lex(&src).unwrap();
+                let output = super::parse(tokens).unwrap();
+                assert!(output.warnings().is_empty(), "{src}");
+
+                let output = output.take_instructions();
+                assert!(durations(&output[0]).iter().all(|d| *d == duration), "{src}");
+            }
+
+            assert!(parse(&template.replace("{}", "-2s")).is_err(), "{template}");
+            assert!(parse(&template.replace("{}", "-2")).is_err(), "{template}");
+            assert!(parse(&template.replace("{}", "2h")).is_err(), "{template}");
+        }
+
+        // Zero is fine, unless it repeats or is a budget
+        for template in ["wait {}", "line_pause {}", "transition dissolve {}", "burst_pause {}..{}"] {
+            let output = parse_ok(&template.replace("{}", "0s"));
+            assert!(durations(&output[0]).iter().all(Duration::is_zero), "{template}");
+        }
+        assert!(parse("every 0s\nend").is_err());
+        assert!(parse("budget 0ms").is_err());
+    }
+
+    #[test]
+    fn bare_durations_warn() {
+        let src = "wait 2\nline_pause 200\nwait 2s\nburst_pause 80..120\nevery 5\n    wait 1\nend\nwait 0.5\nline_pause 2.5";
+        let output = super::parse(lex(src).unwrap()).unwrap();
+        let warnings = output.warnings().iter().map(ToString::to_string).collect::<Vec<_>>();
+        let expected = [
+            "line 1: `2` without a unit is read as seconds, write `2s`",
+            "line 2: `200` without a unit is read as milliseconds, write `200ms`",
+            "line 4: `80` without a unit is read as milliseconds, write `80ms`",
+            "line 4: `120` without a unit is read as milliseconds, write `120ms`",
+            "line 5: `5` without a unit is read as seconds, write `5s`",
+            "line 6: `1` without a unit is read as seconds, write `1s`",
+            "line 9: `2.5` without a unit is read as milliseconds, write `2.5ms`",
+        ];
+        assert_eq!(warnings, expected);
+
+        // Bare numbers keep their meaning
+        let output = output.take_instructions();
+        assert_eq!(output[0], wait(2));
+        assert_eq!(output[1], Instruction::LinePause(ms(200)));
+        assert_eq!(output[6], Instruction::LinePause(Duration::from_micros(2500)));
+    }
+
+    #[test]
+    fn parse_splash() {
+        let output = parse_ok("splash\n    \"(o.o)\"\n    \"Mimic\\nby me\"\n    duration 1.5s\n    style bold dim\nend\nsplash\nend");
+        let expected = vec![
+            Instruction::Splash {
+                lines: vec!["(o.o)".into(), "Mimic".into(), "by me".into()],
+                duration: Some(ms(1500)),
+                styles: vec![TextStyle::Bold, TextStyle::Dim],
+            },
+            Instruction::Splash {
+                lines: vec![],
+                duration: None,
+                styles: vec![],
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("splash\n    \"Mimic\"").is_err());
+        assert!(parse("splash\n    style\nend").is_err());
+        assert!(parse("splash\n    style blink\nend").is_err());
+        assert!(parse("splash\n    wait 1s\nend").is_err());
+    }
+
+    #[test]
+    fn parse_virtual_text() {
+        let output = parse_ok(
+            "virtual_text 3 \"=> 42\"\nvirtual_text @result \"Ok(())\" below\nvirtual_text 1 \"x\" eol\nvirtual_text clear 3\nvirtual_text clear",
+        );
+        let expected = vec![
+            Instruction::VirtualText {
+                anchor: Anchor::Row(2),
+                text: "=> 42".into(),
+                below: false,
+            },
+            Instruction::VirtualText {
+                anchor: Anchor::Marker("result".into()),
+                text: "Ok(())".into(),
+                below: true,
+            },
+            Instruction::VirtualText {
+                anchor: Anchor::Row(0),
+                text: "x".into(),
+                below: false,
+            },
+            Instruction::ClearVirtualText(Some(2)),
+            Instruction::ClearVirtualText(None),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("virtual_text 0 \"x\"").is_err());
+        assert!(parse("virtual_text result \"x\"").is_err());
+        assert!(parse("virtual_text 1").is_err());
+        assert!(parse("virtual_text 1 \"x\" above").is_err());
+    }
+
+    #[test]
+    fn parse_hyperlinks() {
+        let output = parse_ok("hyperlinks on\nhyperlinks off\nlink \"the docs\" \"https://docs.rs\"");
+        let expected = vec![
+            Instruction::Hyperlinks(true),
+            Instruction::Hyperlinks(false),
+            Instruction::Link {
+                text: "the docs".into(),
+                url: "https://docs.rs".into(),
+            },
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("hyperlinks").is_err());
+        assert!(parse("link \"the docs\"").is_err());
+    }
+
+    #[test]
+    fn parse_key_overlay() {
+        let output = parse_ok("key_overlay on\nkey_overlay off\nkey_overlay hold 800");
+        let expected = vec![
+            Instruction::KeyOverlay(true),
+            Instruction::KeyOverlay(false),
+            Instruction::KeyOverlayHold(ms(800)),
+        ];
+        assert_eq!(output, expected);
+
+        assert!(parse("key_overlay hold").is_err());
+        assert!(parse("key_overlay hold -1").is_err());
     }
 
     #[test]
@@ -523,4 +2998,26 @@ goto 1     2
         let expected = vec![goto((1, 2)), wait(1), wait(2)];
         assert_eq!(output, expected);
     }
+
+    #[test]
+    fn error_positions() {
+        let first = parse("tpye \"a\"\nwait 1s").unwrap_err();
+        assert_eq!((first.line(), first.col()), (1, 1));
+        assert_eq!(first.line_text(), "tpye \"a\"");
+
+        let middle = parse("wait 1s\n  select 1 \"x\"\nwait 1s").unwrap_err();
+        assert_eq!((middle.line(), middle.col()), (2, 12));
+        assert_eq!(middle.line_text(), "  select 1 \"x\"");
+
+        let last = parse("wait 1s\nwait 1s\ntype \"a").unwrap_err();
+        assert_eq!((last.line(), last.col()), (3, 6));
+        assert_eq!(last.line_text(), "type \"a");
+    }
+
+    #[test]
+    fn error_underline() {
+        let err = parse("wait 1s\ntpye \"a\"").unwrap_err().to_string();
+        assert!(err.contains("line 2, column 1: "), "{err}");
+        assert!(err.contains("2: tpye \"a\"\n   ^^^^\n"), "{err}");
+    }
 }
@@ -0,0 +1,888 @@
+use std::fmt::Write;
+
+/// Everything there is to know about an instruction, for `--explain`
+/// and for suggestions on misspelled instructions
+#[derive(Debug)]
+pub struct Entry {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub summary: &'static str,
+    pub syntax: &'static str,
+    pub args: &'static str,
+    pub default: Option<&'static str>,
+    pub example: &'static str,
+}
+
+macro_rules! entry {
+    ($name:literal $(| $alias:literal)*, $summary:literal, $syntax:literal, $args:literal, $default:expr, $example:literal) => {
+        Entry {
+            name: $name,
+            aliases: &[$($alias),*],
+            summary: $summary,
+            syntax: $syntax,
+            args: $args,
+            default: $default,
+            example: $example,
+        }
+    };
+}
+
+pub static REGISTRY: &[Entry] = &[
+    entry!(
+        "align",
+        "line up a character across lines",
+        "align \"<char>\" <lines> [instant]",
+        "char: a single character string, lines: number of lines from the cursor (at least 1)",
+        None,
+        "align \"=\" 3"
+    ),
+    entry!(
+        "audio",
+        "load typing sounds from a directory",
+        "audio <path>",
+        "path: string, a directory with a default.mp3",
+        None,
+        "audio \"sounds\""
+    ),
+    entry!(
+        "audio_classes",
+        "pick typing sounds by character class",
+        "audio_classes on|off",
+        "on or off",
+        Some("off"),
+        "audio_classes on"
+    ),
+    entry!(
+        "audio_offset",
+        "play typing sounds ahead of (or after) the glyphs",
+        "audio_offset <ms>",
+        "ms: milliseconds, negative plays the sound late",
+        Some("0"),
+        "audio_offset 40"
+    ),
+    entry!(
+        "auto_typo",
+        "make the odd typo while typing, and fix it",
+        "auto_typo <rate_percent> [max_per_line]",
+        "rate_percent: chance of a typo in a word (0-100, 0 turns it off), max_per_line: at least 1",
+        Some("0"),
+        "auto_typo 5 1"
+    ),
+    entry!(
+        "autoindent",
+        "keep the indentation when splitting lines",
+        "autoindent <bool>",
+        "true or false",
+        Some("false"),
+        "autoindent true"
+    ),
+    entry!(
+        "backspace",
+        "remove characters before the cursor, one per frame",
+        "backspace [count]",
+        "count: number of characters",
+        Some("1"),
+        "backspace 4"
+    ),
+    entry!(
+        "budget",
+        "warn when a section is estimated to run too long",
+        "budget <duration> ... budget end",
+        "duration: above zero, like 30s",
+        None,
+        "budget 30s\ntype \"hello\"\nbudget end"
+    ),
+    entry!(
+        "burst_pause",
+        "the pause between bursts of typing",
+        "burst_pause <min>..<max>",
+        "min, max: durations, max not less than min",
+        Some("80ms..220ms"),
+        "burst_pause 100ms..300ms"
+    ),
+    entry!(
+        "call",
+        "the instructions of a macro",
+        "call <ident>",
+        "ident: a macro defined with def",
+        None,
+        "def save {\n    command \":w\"\n}\ncall save"
+    ),
+    entry!("clear", "clear the editor", "clear", "none", None, "clear"),
+    entry!("clear_eol", "delete from the cursor to the end of the line", "clear_eol", "none", None, "clear_eol"),
+    entry!(
+        "close_popup" | "closepopup",
+        "close the popup",
+        "close_popup",
+        "none",
+        None,
+        "close_popup"
+    ),
+    entry!(
+        "colorcolumn" | "color_column",
+        "tint columns as ruler lines",
+        "colorcolumn <col>... | off",
+        "col: column, starting at 1",
+        Some("off"),
+        "colorcolumn 80 100"
+    ),
+    entry!(
+        "command",
+        "type on the command line",
+        "command <string>|<ident>",
+        "string, the name of something loaded with `load`, or env(<name>)",
+        None,
+        "command \":w\""
+    ),
+    entry!(
+        "command_clear_timeout",
+        "how long the command line stays after a command",
+        "command_clear_timeout <duration>",
+        "duration: like 500ms",
+        Some("1s"),
+        "command_clear_timeout 500ms"
+    ),
+    entry!(
+        "command_speed",
+        "the typing speed on the command line",
+        "command_speed <chars per second>",
+        "number of characters typed per second, from 1",
+        Some("the same as speed"),
+        "command_speed 40"
+    ),
+    entry!(
+        "comment_leader",
+        "the line comment leader for toggle_comment",
+        "comment_leader \"<string>\"",
+        "string: the leader, like \"//\"",
+        Some("picked by the extension"),
+        "comment_leader \"REM\""
+    ),
+    entry!(
+        "comment_prefix",
+        "the line comment leader for extensions toggle_comment doesn't know",
+        "comment_prefix \"<string>\"",
+        "string: the leader, like \"#\"",
+        Some("//"),
+        "comment_prefix \"#\""
+    ),
+    entry!(
+        "dedent",
+        "remove indentation from lines",
+        "dedent [<lines> [levels]]",
+        "lines: number of lines from the cursor, or the selection, levels: number of indent units (at least 1)",
+        Some("the selection or the cursor's line, levels 1"),
+        "dedent 3"
+    ),
+    entry!(
+        "def",
+        "give instructions a name, to call later",
+        "def <ident> { ... }",
+        "ident: the name of the macro",
+        None,
+        "def save {\n    command \":w\"\n    popup \"saved\"\n}"
+    ),
+    entry!(
+        "delete",
+        "delete the selection or the character under the cursor",
+        "delete",
+        "none",
+        None,
+        "delete"
+    ),
+    entry!(
+        "delete_line",
+        "delete lines from the cursor, with their line breaks",
+        "delete_line [count]",
+        "count: number of lines",
+        Some("1"),
+        "delete_line 3"
+    ),
+    entry!("delete_word", "delete the word under the cursor", "delete_word", "none", None, "delete_word"),
+    entry!(
+        "duplicate_line",
+        "copy the line under the cursor below it, and move to the copy",
+        "duplicate_line [count]",
+        "count: number of copies",
+        Some("1"),
+        "duplicate_line 2"
+    ),
+    entry!(
+        "every",
+        "run instructions on an interval",
+        "every <duration> ... end | every stop",
+        "duration: above zero, like 5s",
+        None,
+        "every 5s\n    popup \"hi\"\nend"
+    ),
+    entry!(
+        "exec",
+        "run a shell command when compiling and keep its output, like `load`",
+        "exec <string> [<timeout>] as <ident>",
+        "string: a command for `sh -c`, only stdout is kept, timeout: duration, a bare number is seconds",
+        Some("10s"),
+        "exec \"cargo build 2>&1\" 30s as output"
+    ),
+    entry!("exit", "stop the script here and quit", "exit", "none", None, "exit"),
+    entry!(
+        "exit_on_end",
+        "quit once the script has ended",
+        "exit_on_end <duration>",
+        "duration: how long the last frame stays, a bare number is seconds",
+        None,
+        "exit_on_end 3s"
+    ),
+    entry!(
+        "extension",
+        "the file extension used for syntax highlighting",
+        "extension <string>",
+        "string",
+        Some("\"txt\""),
+        "extension \"rs\""
+    ),
+    entry!(
+        "find",
+        "move the cursor to the start of a string on the current line",
+        "find <string> [count]",
+        "string, count: the nth occurrence",
+        Some("count 1"),
+        "find \"fn\" 2"
+    ),
+    entry!(
+        "find_miss",
+        "what happens when find doesn't find anything",
+        "find_miss stay|error|skip <n>",
+        "stay, error, or skip the next n instructions",
+        Some("stay"),
+        "find_miss skip 1"
+    ),
+    entry!(
+        "finde",
+        "move the cursor to the end of a string on the current line",
+        "finde <string> [count]",
+        "string, count: the nth occurrence",
+        Some("count 1"),
+        "finde \"main\""
+    ),
+    entry!(
+        "goto",
+        "move the cursor to a marker, by rows and columns or to a line",
+        "goto [@]<marker> [+<row>:<col>] | goto <row> <col> | goto <line>:<col> | goto start|end",
+        "marker: ident, row, col: relative, to the marker if there is one, line: from 1",
+        None,
+        "goto 2 -4"
+    ),
+    entry!(
+        "hscroll",
+        "how the view scrolls horizontally",
+        "hscroll jump|smooth|keep",
+        "jump, smooth or keep",
+        Some("keep"),
+        "hscroll smooth"
+    ),
+    entry!(
+        "hyperlinks",
+        "underline URLs",
+        "hyperlinks on|off",
+        "on or off",
+        Some("off"),
+        "hyperlinks on"
+    ),
+    entry!(
+        "if",
+        "only the instructions for the value of a variable",
+        "if <ident> { ... } [else { ... }]",
+        "ident: a variable set to true or false",
+        None,
+        "set long true\nif long {\n    type \"a\"\n} else {\n    type \"b\"\n}"
+    ),
+    entry!(
+        "include",
+        "run the instructions from another file",
+        "include <path>",
+        "path: string",
+        None,
+        "include \"other.echo\""
+    ),
+    entry!(
+        "indent",
+        "indent lines",
+        "indent [<lines> [levels]]",
+        "lines: number of lines from the cursor, or the selection, levels: number of indent units (at least 1)",
+        Some("the selection or the cursor's line, levels 1"),
+        "indent 3 2"
+    ),
+    entry!(
+        "indent_unit",
+        "the string used for one level of indentation",
+        "indent_unit <string>",
+        "string of spaces and tabs",
+        Some("\"    \""),
+        "indent_unit \"  \""
+    ),
+    entry!(
+        "insert",
+        "insert text at once, at the start of the line or over the selection",
+        "insert <string>|<ident> [| <filter>]...",
+        "string, the name of something loaded with `load`, or env(<name>), filters: indent <n>, dedent, prefix <string>, \
+         suffix <string>, upper, lower, trim, trim_end, head <n>, tail <n>",
+        None,
+        "insert \"fn main() {}\""
+    ),
+    entry!(
+        "jitter",
+        "random variation in the typing speed",
+        "jitter <duration>",
+        "duration: like 25ms",
+        Some("20ms"),
+        "jitter 25ms"
+    ),
+    entry!(
+        "jitter_profile",
+        "how the jitter is spread",
+        "jitter_profile uniform|burst|off",
+        "uniform, burst or off",
+        Some("uniform"),
+        "jitter_profile burst"
+    ),
+    entry!(
+        "join",
+        "join lines onto the current line",
+        "join [lines]",
+        "lines: number of lines",
+        Some("1"),
+        "join 2"
+    ),
+    entry!(
+        "key_overlay",
+        "show the typed keys",
+        "key_overlay on|off | key_overlay hold <duration>",
+        "on or off, duration: how long each key is shown",
+        Some("off, hold 1500ms"),
+        "key_overlay on"
+    ),
+    entry!(
+        "let",
+        "set an int variable to a sum of numbers and int variables",
+        "let <ident> = <expr>",
+        "ident: the name of the variable, expr: numbers and int variables with + - * and /",
+        None,
+        "let total = width + 4"
+    ),
+    entry!(
+        "ligatures",
+        "stop the terminal from drawing ligatures",
+        "ligatures break|allow | ligatures break add <string>",
+        "break or allow, string: a sequence of at least two characters",
+        Some("allow"),
+        "ligatures break add \"|>\""
+    ),
+    entry!(
+        "line_pause" | "linepause",
+        "pause after every typed newline",
+        "line_pause <duration>",
+        "duration: like 300ms",
+        Some("0s"),
+        "line_pause 300ms"
+    ),
+    entry!(
+        "link",
+        "type text that is underlined as a link",
+        "link <text> <url>",
+        "text, url: strings",
+        None,
+        "link \"the docs\" \"https://docs.rs\""
+    ),
+    entry!(
+        "load",
+        "load a file to type later",
+        "load <path>[:<start>..[<end>]] as <ident>",
+        "path: string, start, end: lines from 1, inclusive, ident: the name to refer to the content by",
+        None,
+        "load \"src/main.rs\" as main"
+    ),
+    entry!(
+        "loop",
+        "play the script again when it ends",
+        "loop [<count>] [pause <duration>]",
+        "count: times to play it in all, forever without one, duration: the pause between plays",
+        None,
+        "loop 3 pause 2s"
+    ),
+    entry!(
+        "margins",
+        "keep the edges of the screen empty",
+        "margins <top> <right> <bottom> <left>",
+        "number of cells",
+        Some("0 0 0 0"),
+        "margins 1 2 1 2"
+    ),
+    entry!(
+        "mark",
+        "put a marker on the line of the cursor",
+        "mark <ident>",
+        "ident: the name of the marker, replacing a marker with the same name",
+        None,
+        "mark main"
+    ),
+    entry!(
+        "markers",
+        "what happens when a marker is defined twice",
+        "markers strict|warn",
+        "strict: stop with an error, warn: show a warning",
+        Some("warn"),
+        "markers strict"
+    ),
+    entry!(
+        "menu",
+        "walk a selection down to an item",
+        "menu <item>... choose <index> [step] [then_type]",
+        "item: string, index: the chosen item starting at 0, step: duration per item",
+        Some("150ms"),
+        "menu \"open\" \"save\" \"quit\" choose 1 then_type"
+    ),
+    entry!(
+        "move_line_down",
+        "swap the line under the cursor with the one below it",
+        "move_line_down [count]",
+        "count: number of lines to move it",
+        Some("1"),
+        "move_line_down 2"
+    ),
+    entry!(
+        "move_line_up",
+        "swap the line under the cursor with the one above it",
+        "move_line_up [count]",
+        "count: number of lines to move it",
+        Some("1"),
+        "move_line_up"
+    ),
+    entry!(
+        "numbers",
+        "show line numbers",
+        "numbers <bool>",
+        "true or false",
+        Some("false"),
+        "numbers true"
+    ),
+    entry!(
+        "paste",
+        "paste what was yanked, whole lines below the cursor's line",
+        "paste",
+        "none",
+        None,
+        "paste"
+    ),
+    entry!(
+        "paste_above",
+        "paste what was yanked, whole lines above the cursor's line",
+        "paste_above",
+        "none",
+        None,
+        "paste_above"
+    ),
+    entry!(
+        "popup",
+        "show a popup at the cursor",
+        "popup <string>|<ident> [<duration>] [at <position>] [title <string>]",
+        "string, the name of something loaded with `load`, or env(<name>), duration: close the popup after it, \
+         without waiting for it, a bare number is seconds, position: top, bottom, center, cursor or row:col",
+        None,
+        "popup \"look here\" 3s at top title \"Note\""
+    ),
+    entry!(
+        "popup_at",
+        "move the open popup",
+        "popup_at <position>",
+        "position: top, bottom, center, cursor or row:col, from 0 at the top left of the editor",
+        Some("cursor"),
+        "popup_at bottom"
+    ),
+    entry!(
+        "popup_autoscroll",
+        "scroll the popup like credits",
+        "popup_autoscroll <lines per second>",
+        "lines per second, 0 stops",
+        Some("0"),
+        "popup_autoscroll 2"
+    ),
+    entry!(
+        "popup_from",
+        "show loaded content or a file in the popup",
+        "popup_from <ident>|<path>",
+        "the name of something loaded with `load`, or the path to a file",
+        None,
+        "popup_from \"CHANGES.md\""
+    ),
+    entry!(
+        "popup_scroll",
+        "scroll the popup",
+        "popup_scroll <lines>",
+        "lines: negative scrolls up",
+        None,
+        "popup_scroll 5"
+    ),
+    entry!(
+        "preset",
+        "define, use and restore named groups of settings",
+        "preset define <name> ... end | preset use <name> | preset pop | preset include <string>",
+        "name: ident, the body can only contain settings",
+        None,
+        "preset define fast\n    speed 100\n    jitter 0\nend\npreset use fast\npreset pop"
+    ),
+    entry!(
+        "repeat",
+        "repeat instructions a number of times",
+        "repeat <int> { ... }",
+        "int: the number of times, where 0 leaves the instructions out",
+        None,
+        "repeat 3 {\n    type \"a\"\n}"
+    ),
+    entry!(
+        "replace",
+        "replace a string in the editor",
+        "replace <string> <string>|<ident>",
+        "the string to replace, and what to replace it with",
+        None,
+        "replace \"foo\" \"bar\""
+    ),
+    entry!(
+        "replace_all",
+        "replace every occurrence of a string in the document at once",
+        "replace_all <string> <string>|<ident>",
+        "the string to replace, and what to replace it with",
+        None,
+        "replace_all \"foo\" \"bar\""
+    ),
+    entry!(
+        "reset_counters",
+        "reset the typing counters",
+        "reset_counters",
+        "none",
+        None,
+        "reset_counters"
+    ),
+    entry!(
+        "restore",
+        "put back the text, markers and cursor of a snapshot",
+        "restore <ident>",
+        "ident: the name given to `snapshot`",
+        None,
+        "restore before"
+    ),
+    entry!(
+        "rfind",
+        "move the cursor back to the start of a string on the current line",
+        "rfind <string> [count]",
+        "string, count: the nth occurrence before the cursor",
+        Some("count 1"),
+        "rfind \"let\""
+    ),
+    entry!(
+        "rfinde",
+        "move the cursor back to the end of a string on the current line",
+        "rfinde <string> [count]",
+        "string, count: the nth occurrence before the cursor",
+        Some("count 1"),
+        "rfinde \"=\" 2"
+    ),
+    entry!(
+        "scene",
+        "start a scene",
+        "scene <name> [theme <theme>] [ext <extension>]",
+        "name, theme, extension: strings",
+        None,
+        "scene \"intro\" theme \"togglebit\" ext \"rs\""
+    ),
+    entry!(
+        "scene_defaults",
+        "whether a scene clears the editor",
+        "scene_defaults keep_buffer|clear",
+        "keep_buffer or clear",
+        Some("clear"),
+        "scene_defaults keep_buffer"
+    ),
+    entry!(
+        "scroll",
+        "scroll the view without moving the cursor",
+        "scroll <rows>",
+        "rows: negative scrolls up",
+        None,
+        "scroll 5"
+    ),
+    entry!(
+        "scrolloff",
+        "rows kept above and below the cursor, and columns to either side of it",
+        "scrolloff <rows> [<columns>]",
+        "rows, columns: number of cells, more than half the view keeps the cursor in the middle",
+        Some("7 4"),
+        "scrolloff 3"
+    ),
+    entry!(
+        "select",
+        "select a region from the cursor, or the lines between two markers",
+        "select <width> <height> | select @<start> @<end>",
+        "width, height: number of cells or int variables, start, end: markers",
+        None,
+        "select 4 1"
+    ),
+    entry!("select_eol", "select from the cursor to the end of the line", "select_eol", "none", None, "select_eol"),
+    entry!(
+        "set",
+        "set a value for the template",
+        "set <ident> <string>|<bool>|<int>",
+        "ident: the name of the value",
+        None,
+        "set mood \"happy\""
+    ),
+    entry!(
+        "snapshot",
+        "keep the text, markers and cursor to restore later",
+        "snapshot <ident>",
+        "ident: the name to restore it by, replacing a snapshot with the same name",
+        None,
+        "snapshot before"
+    ),
+    entry!(
+        "speed",
+        "the typing speed",
+        "speed <chars per second>",
+        "number of characters typed per second",
+        Some("20"),
+        "speed 30"
+    ),
+    entry!(
+        "splash",
+        "an intro card centred on the canvas",
+        "splash <string>... [duration <duration>] [style <style>...] end",
+        "string: a line of the card, style: bold, dim, italic, underlined or reversed",
+        Some("duration 3s"),
+        "splash\n    \"Mimic\"\n    duration 2s\nend"
+    ),
+    entry!(
+        "split_line" | "splitline",
+        "split the line at the cursor",
+        "split_line",
+        "none",
+        None,
+        "split_line"
+    ),
+    entry!(
+        "tab_width",
+        "how many spaces a tab counts as when dedenting",
+        "tab_width <int>",
+        "at least 1",
+        Some("4"),
+        "tab_width 8"
+    ),
+    entry!(
+        "theme",
+        "the syntax highlighting theme",
+        "theme <string>",
+        "string",
+        Some("\"togglebit\""),
+        "theme \"togglebit\""
+    ),
+    entry!(
+        "title",
+        "the title in the status bar",
+        "title <string>",
+        "string",
+        None,
+        "title \"main.rs\""
+    ),
+    entry!(
+        "toggle_comment",
+        "comment out lines, or uncomment them",
+        "toggle_comment [<lines>] [instant]",
+        "lines: number of lines from the cursor (at least 1)",
+        Some("the selected lines, or the cursor's line"),
+        "toggle_comment 3"
+    ),
+    entry!(
+        "transition",
+        "a transition for the next clear",
+        "transition dissolve|wipe_left|wipe_down <duration>",
+        "the kind of transition, duration: how long it takes",
+        None,
+        "transition dissolve 500ms"
+    ),
+    entry!(
+        "type",
+        "type text at the cursor",
+        "type <string>|<ident> [| <filter>]... [nonl] [keep_selection]",
+        "string, the name of something loaded with `load`, or env(<name>), keep_selection: type at the cursor, not over the selection, \
+         filters: see insert",
+        None,
+        "type \"hello\""
+    ),
+    entry!(
+        "typenl",
+        "type text on a new line",
+        "typenl <string>|<ident> [| <filter>]... [nonl]",
+        "string, the name of something loaded with `load`, or env(<name>), nonl: keep the trailing newline out",
+        None,
+        "typenl \"hello\""
+    ),
+    entry!("unmark", "remove a marker", "unmark <ident>", "ident: the name of the marker", None, "unmark main"),
+    entry!(
+        "virtual_text",
+        "dimmed text after or below a line that isn't part of the document",
+        "virtual_text <row>|@<marker> <string> [eol|below] | virtual_text clear [row]",
+        "row: starting at 1, marker: a marker name, clear: remove all of it or the text of a row",
+        Some("eol"),
+        "virtual_text @result \"=> 42\""
+    ),
+    entry!(
+        "wait" | "sleep",
+        "pause",
+        "wait <duration>[..<duration>] | wait <ident> [ms]",
+        "duration: like 2s or 1.5s, a range waits a random time in it, ident: an int variable, in seconds or milliseconds",
+        None,
+        "wait 2s"
+    ),
+    entry!(
+        "wrap",
+        "wrap long lines instead of scrolling sideways",
+        "wrap on [\"<indicator>\"]|off",
+        "on or off, and the text drawn at the start of the rows a line continues on",
+        Some("off"),
+        "wrap on \"↪ \""
+    ),
+    entry!(
+        "write",
+        "write the editor content to a file",
+        "write <path>",
+        "path: string",
+        None,
+        "write \"out.txt\""
+    ),
+    entry!("yank", "copy the selection or the cursor's line", "yank", "none", None, "yank"),
+];
+
+pub fn lookup(name: &str) -> Option<&'static Entry> {
+    REGISTRY.iter().find(|entry| entry.name == name || entry.aliases.contains(&name))
+}
+
+/// The instruction closest to a misspelled name, if any is close enough
+pub fn suggest(name: &str) -> Option<&'static str> {
+    let max = name.chars().count().div_ceil(3);
+    REGISTRY
+        .iter()
+        .flat_map(|entry| std::iter::once(&entry.name).chain(entry.aliases))
+        .map(|candidate| (distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| lookup(candidate).map_or(candidate, |entry| entry.name))
+}
+
+// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = match a == *b {
+                true => prev,
+                false => 1 + prev.min(row[j]).min(current),
+            };
+            prev = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// The full description of an instruction
+pub fn explain(name: &str) -> Option<String> {
+    let entry = lookup(name)?;
+    let mut out = String::new();
+    _ = writeln!(out, "{} - {}", entry.name, entry.summary);
+    if !entry.aliases.is_empty() {
+        _ = writeln!(out, "\naliases: {}", entry.aliases.join(", "));
+    }
+    _ = writeln!(out, "\nsyntax:    {}", entry.syntax);
+    _ = writeln!(out, "arguments: {}", entry.args);
+    if let Some(default) = entry.default {
+        _ = writeln!(out, "default:   {default}");
+    }
+    _ = writeln!(out, "\nexample:");
+    entry.example.lines().for_each(|line| _ = writeln!(out, "    {line}"));
+    Some(out)
+}
+
+/// Every instruction with a one line summary
+pub fn summaries() -> String {
+    let width = REGISTRY.iter().map(|entry| entry.name.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for entry in REGISTRY {
+        _ = writeln!(out, "{:width$}  {}", entry.name, entry.summary);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::lexer::{KEYWORDS, lex};
+    use crate::parser::token::Token;
+
+    #[test]
+    fn examples_parse() {
+        for entry in REGISTRY {
+            if let Err(e) = crate::parser::parse(entry.example) {
+                panic!("example for `{}` does not parse: {e}", entry.name);
+            }
+        }
+    }
+
+    #[test]
+    fn names_are_keywords() {
+        for name in REGISTRY.iter().flat_map(|entry| std::iter::once(&entry.name).chain(entry.aliases)) {
+            let tokens = lex(name).unwrap();
+            assert!(!matches!(tokens.current(), Token::Ident(_)), "`{name}` is not an instruction");
+        }
+    }
+
+    #[test]
+    fn keywords_are_registered() {
+        // Words that only appear inside other instructions, and `duck`,
+        // which stays out of the docs until there is music to duck
+        let not_instructions = ["as", "duck", "else", "end", "false", "keep_selection", "nonl", "true"];
+        for (keyword, _) in KEYWORDS.iter().filter(|(keyword, _)| !not_instructions.contains(keyword)) {
+            assert!(lookup(keyword).is_some(), "`{keyword}` is not in the registry");
+        }
+    }
+
+    #[test]
+    fn sorted_by_name() {
+        let names = REGISTRY.iter().map(|entry| entry.name).collect::<Vec<_>>();
+        let mut sorted = names.clone();
+        sorted.sort();
+        assert_eq!(names, sorted);
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(suggest("tpye"), Some("type"));
+        assert_eq!(suggest("fnde"), Some("finde"));
+        assert_eq!(suggest("close_pop"), Some("close_popup"));
+        assert_eq!(suggest("linpause"), Some("line_pause"));
+        assert_eq!(suggest("banana"), None);
+    }
+
+    #[test]
+    fn suggest_in_parse_errors() {
+        let error = crate::parser::parse("tpye \"hello\"").unwrap_err().to_string();
+        assert!(error.contains("invalid instruction: `tpye`, did you mean `type`?"));
+    }
+
+    #[test]
+    fn explain_aliases() {
+        assert_eq!(explain("sleep"), explain("wait"));
+        assert!(explain("wait").unwrap().starts_with("wait - pause\n"));
+        assert!(explain("banana").is_none());
+    }
+}
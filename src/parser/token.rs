@@ -1,50 +1,141 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub enum Token {
     // Single char tokens
     Newline,
     Bang,
+    Pipe,
+    LBrace,
+    RBrace,
     Equal,
     At,
+    Colon,
     AtAt,
+    DotDot,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+    Comma,
 
     // Multi char tokens
     As,
     Audio,
+    AudioClasses,
+    AudioOffset,
+    Backspace,
     Delete,
+    DeleteLine,
+    DuplicateLine,
+    DeleteWord,
+    Duck,
     Bool(bool),
     Int(i64),
+    /// A number with decimals and no unit
+    Decimal(f64),
+    Duration(Duration),
     Str(String),
     Ident(String),
     Comment,
     Whitespace,
     NoNewline,
+    KeepSelection,
+    Let,
+    End,
+    Else,
 
     // Actions
+    Align,
+    AutoIndent,
+    AutoTypo,
+    Budget,
+    BurstPause,
+    Call,
     Clear,
+    ClearEol,
     ClosePopup,
+    Dedent,
+    Def,
+    ColorColumn,
+    Every,
+    Exec,
     Command,
     CommandClear,
+    CommandSpeed,
+    CommentLeader,
+    CommentPrefix,
     Find,
     FindEnd,
+    FindMiss,
     Goto,
+    HScroll,
+    Hyperlinks,
+    If,
     Include,
+    Indent,
+    IndentUnit,
+    TabWidth,
     Insert,
     Jitter,
+    JitterProfile,
+    KeyOverlay,
+    Margins,
+    ScrollOff,
+    Wrap,
+    Menu,
+    MoveLineDown,
+    MoveLineUp,
+    Join,
+    Ligatures,
+    Link,
     LinePause,
     Load,
+    Loop,
+    Exit,
+    ExitOnEnd,
+    Mark,
+    Paste,
+    PasteAbove,
+    Restore,
+    Snapshot,
+    Markers,
     Popup,
+    PopupAt,
+    PopupAutoscroll,
+    PopupFrom,
+    PopupScroll,
+    Preset,
+    Repeat,
     Replace,
+    ReplaceAll,
+    ResetCounters,
+    RFind,
+    RFindEnd,
+    Scene,
+    SceneDefaults,
+    Scroll,
     Select,
+    SelectEol,
     SetVariable,
     SetExtension,
     SetTitle,
     ShowLineNumbers,
     Speed,
+    SplitLine,
+    Splash,
     Theme,
+    ToggleComment,
+    Transition,
     Type,
     TypeNl,
+    Unmark,
+    VirtualText,
+    Yank,
     Wait,
     WriteBuffer,
 
@@ -60,46 +151,134 @@ impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Token::At => write!(f, "@"),
+            Token::Colon => write!(f, ":"),
             Token::AtAt => write!(f, "@@"),
             Token::Equal => write!(f, "="),
             Token::Bang => write!(f, "!"),
+            Token::Pipe => write!(f, "|"),
+            Token::LBrace => write!(f, "{{"),
+            Token::RBrace => write!(f, "}}"),
             Token::Newline => write!(f, "<nl>"),
 
             Token::As => write!(f, "as"),
+            Token::Backspace => write!(f, "backspace"),
             Token::Delete => write!(f, "delete"),
+            Token::DeleteLine => write!(f, "delete_line"),
+            Token::DuplicateLine => write!(f, "duplicate_line"),
+            Token::DeleteWord => write!(f, "delete_word"),
+            Token::Duck => write!(f, "duck"),
             Token::Ident(s) => write!(f, "{s}"),
             Token::Int(int) => write!(f, "{int}"),
+            Token::Decimal(n) => write!(f, "{n}"),
+            Token::Duration(duration) => write!(f, "{duration:?}"),
             Token::NoNewline => write!(f, "no newline"),
+            Token::KeepSelection => write!(f, "keep selection"),
             Token::Str(s) => write!(f, "\"{s}\""),
             Token::Bool(b) => write!(f, "{b}"),
 
             Token::Audio => write!(f, "audio"),
+            Token::AutoIndent => write!(f, "autoindent"),
+            Token::AutoTypo => write!(f, "auto_typo"),
             Token::Clear => write!(f, "clear"),
+            Token::ClearEol => write!(f, "clear_eol"),
             Token::ClosePopup => write!(f, "close popup"),
+            Token::ColorColumn => write!(f, "color column"),
             Token::Command => write!(f, "command"),
+            Token::Dedent => write!(f, "dedent"),
             Token::CommandClear => write!(f, "command clear"),
+            Token::CommandSpeed => write!(f, "command_speed"),
             Token::Find => write!(f, "find"),
             Token::FindEnd => write!(f, "findend"),
+            Token::RFind => write!(f, "rfind"),
+            Token::RFindEnd => write!(f, "rfinde"),
+            Token::FindMiss => write!(f, "find_miss"),
             Token::Goto => write!(f, "goto"),
             Token::Include => write!(f, "include"),
+            Token::Indent => write!(f, "indent"),
+            Token::IndentUnit => write!(f, "indent unit"),
+            Token::TabWidth => write!(f, "tab_width"),
             Token::Insert => write!(f, "insert"),
             Token::Jitter => write!(f, "jitter"),
+            Token::Join => write!(f, "join"),
             Token::LinePause => write!(f, "line pause"),
             Token::Load => write!(f, "load"),
+            Token::Loop => write!(f, "loop"),
+            Token::Exit => write!(f, "exit"),
+            Token::ExitOnEnd => write!(f, "exit_on_end"),
+            Token::Mark => write!(f, "mark"),
+            Token::Paste => write!(f, "paste"),
+            Token::PasteAbove => write!(f, "paste_above"),
+            Token::Restore => write!(f, "restore"),
+            Token::Snapshot => write!(f, "snapshot"),
+            Token::Markers => write!(f, "markers"),
             Token::Popup => write!(f, "popup"),
+            Token::Repeat => write!(f, "repeat"),
             Token::Replace => write!(f, "change"),
+            Token::ReplaceAll => write!(f, "replace_all"),
+            Token::ResetCounters => write!(f, "reset counters"),
             Token::Select => write!(f, "select"),
+            Token::SelectEol => write!(f, "select_eol"),
             Token::SetExtension => write!(f, "set extenion"),
             Token::SetVariable => write!(f, "set variable"),
+            Token::Let => write!(f, "let"),
             Token::SetTitle => write!(f, "set title"),
             Token::ShowLineNumbers => write!(f, "show line numbers"),
             Token::Speed => write!(f, "speed"),
+            Token::SplitLine => write!(f, "split line"),
+            Token::Splash => write!(f, "splash"),
             Token::Theme => write!(f, "theme"),
             Token::Type => write!(f, "type"),
             Token::TypeNl => write!(f, "typenl"),
+            Token::ToggleComment => write!(f, "toggle comment"),
+            Token::CommentLeader => write!(f, "comment leader"),
+            Token::CommentPrefix => write!(f, "comment prefix"),
+            Token::Unmark => write!(f, "unmark"),
+            Token::VirtualText => write!(f, "virtual_text"),
+            Token::Yank => write!(f, "yank"),
             Token::Wait => write!(f, "wait"),
             Token::WriteBuffer => write!(f, "write buffer"),
 
+            Token::End => write!(f, "end"),
+            Token::Else => write!(f, "else"),
+            Token::If => write!(f, "if"),
+            Token::PopupFrom => write!(f, "popup_from"),
+            Token::PopupScroll => write!(f, "popup_scroll"),
+            Token::PopupAutoscroll => write!(f, "popup_autoscroll"),
+            Token::PopupAt => write!(f, "popup_at"),
+            Token::Preset => write!(f, "preset"),
+            Token::Margins => write!(f, "margins"),
+            Token::ScrollOff => write!(f, "scrolloff"),
+            Token::Wrap => write!(f, "wrap"),
+            Token::Align => write!(f, "align"),
+            Token::Hyperlinks => write!(f, "hyperlinks"),
+            Token::Link => write!(f, "link"),
+            Token::Menu => write!(f, "menu"),
+            Token::MoveLineDown => write!(f, "move_line_down"),
+            Token::MoveLineUp => write!(f, "move_line_up"),
+            Token::AudioOffset => write!(f, "audio_offset"),
+            Token::KeyOverlay => write!(f, "key_overlay"),
+            Token::AudioClasses => write!(f, "audio_classes"),
+            Token::Scene => write!(f, "scene"),
+            Token::Scroll => write!(f, "scroll"),
+            Token::SceneDefaults => write!(f, "scene_defaults"),
+            Token::DotDot => write!(f, ".."),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::BurstPause => write!(f, "burst_pause"),
+            Token::JitterProfile => write!(f, "jitter_profile"),
+            Token::Budget => write!(f, "budget"),
+            Token::Call => write!(f, "call"),
+            Token::Def => write!(f, "def"),
+            Token::Ligatures => write!(f, "ligatures"),
+            Token::HScroll => write!(f, "hscroll"),
+            Token::Transition => write!(f, "transition"),
+            Token::Every => write!(f, "every"),
+            Token::Exec => write!(f, "exec"),
             Token::Eof => write!(f, "EOF"),
 
             Token::Consumed => write!(f, "<consumed>"),
@@ -114,6 +293,8 @@ pub struct Span {
     pub token: u32,
     pub line: u16,
     pub col: u16,
+    // Bytes into the source
+    pub offset: usize,
 }
 
 impl Span {
@@ -121,6 +302,7 @@ impl Span {
         token: 0,
         line: 1,
         col: 1,
+        offset: 0,
     };
 }
 
@@ -128,17 +310,28 @@ impl Span {
 pub struct Tokens<'src> {
     pub(crate) source: &'src str,
     index: usize,
+    // The token that was taken or consumed last, which is what errors are about
+    last: usize,
     tokens: Vec<Token>,
     spans: Vec<Span>,
+    // The strings that read differently for `${name}`, by the index of their token
+    templates: HashMap<usize, String>,
 }
 
 impl<'src> Tokens<'src> {
-    pub(crate) fn new(source: &'src str, tokens: Vec<Token>, spans: Vec<Span>) -> Self {
+    pub(crate) fn new(
+        source: &'src str,
+        tokens: Vec<Token>,
+        spans: Vec<Span>,
+        templates: HashMap<usize, String>,
+    ) -> Self {
         Self {
             source,
             index: 0,
+            last: 0,
             tokens,
             spans,
+            templates,
         }
     }
 
@@ -153,6 +346,7 @@ impl<'src> Tokens<'src> {
     }
 
     pub(crate) fn consume(&mut self) {
+        self.last = self.index;
         if self.index == self.tokens.len() - 1 {
             return;
         }
@@ -180,13 +374,25 @@ impl<'src> Tokens<'src> {
         token
     }
 
+    /// Where the token that was taken last starts and ends
     pub(crate) fn spans(&self) -> (Span, Span) {
-        let start = self.spans[self.index];
-        let end = if self.index + 1 == self.tokens.len() { start } else { self.spans[self.index + 1] };
+        let start = self.spans[self.last];
+        let end = self.spans.get(self.last + 1).copied().unwrap_or(start);
 
         (start, end)
     }
 
+    /// The string `s` that was taken last, as it's read for `${name}`:
+    /// an escaped `$` is `\$`, and a `\` is `\\`
+    pub(crate) fn template(&self, s: String) -> String {
+        self.templates.get(&self.last).cloned().unwrap_or(s)
+    }
+
+    /// Where the current token starts
+    pub(crate) fn span(&self) -> Span {
+        self.spans[self.index]
+    }
+
     #[cfg(test)]
     pub fn take_tokens(self) -> Vec<Token> {
         self.tokens
@@ -0,0 +1,154 @@
+//! Snapshot tests for scripts.
+//!
+//! A script is run without a terminal and the editor canvas is rendered as text,
+//! with ANSI escape sequences for the colours and attributes. The result is
+//! compared with the `.snap` file next to the script.
+//!
+//! Set `MIMIC_BLESS=1` to write the snapshots instead of comparing them.
+//!
+//! ```no_run
+//! #[test]
+//! fn intro() {
+//!     mimic::testkit::assert_script_snapshot("scripts/intro.echo", 80, 24, 1);
+//! }
+//! ```
+use std::path::Path;
+
+use anathema::geometry::Size;
+
+use crate::ui::{FsLoader, compile_with_loader, headless};
+
+/// Set to `1` to update the snapshots rather than compare against them
+pub const BLESS_VAR: &str = "MIMIC_BLESS";
+
+/// Run the script and render the final frame, and with `every` set, every nth
+/// drawn frame before it. The output only depends on the script, the size and the seed.
+pub fn render_script(
+    path: impl AsRef<Path>,
+    width: u16,
+    height: u16,
+    seed: u64,
+    every: Option<usize>,
+) -> anyhow::Result<String> {
+    let path = path.as_ref();
+    let src = std::fs::read_to_string(path)?;
+    let instructions = crate::parse(&src)?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let instructions = compile_with_loader(instructions, &FsLoader, base_dir)?;
+    let frames = headless::frames(instructions, Size::new(width, height), seed, every)?;
+    Ok(frames.join("\n"))
+}
+
+/// Compare the final frame of the script with the snapshot next to it
+pub fn assert_script_snapshot(path: impl AsRef<Path>, width: u16, height: u16, seed: u64) {
+    assert_snapshot(path.as_ref(), width, height, seed, None);
+}
+
+/// Like [`assert_script_snapshot`], but every nth drawn frame is part of the snapshot too
+pub fn assert_script_snapshot_every(path: impl AsRef<Path>, width: u16, height: u16, seed: u64, every: usize) {
+    assert_snapshot(path.as_ref(), width, height, seed, Some(every));
+}
+
+fn assert_snapshot(path: &Path, width: u16, height: u16, seed: u64, every: Option<usize>) {
+    let actual = match render_script(path, width, height, seed, every) {
+        Ok(actual) => actual,
+        Err(e) => panic!("failed to run {}: {e}", path.display()),
+    };
+
+    let snap = path.with_extension("snap");
+    if std::env::var(BLESS_VAR).is_ok_and(|bless| bless == "1") {
+        if let Err(e) = std::fs::write(&snap, &actual) {
+            panic!("failed to write {}: {e}", snap.display());
+        }
+        return;
+    }
+
+    let Ok(expected) = std::fs::read_to_string(&snap) else {
+        panic!("no snapshot at {}, run with {BLESS_VAR}=1 to create it", snap.display());
+    };
+
+    if let Some(msg) = difference(&expected, &actual) {
+        panic!("{} does not match the snapshot: {msg}\nrun with {BLESS_VAR}=1 to update it", path.display());
+    }
+}
+
+// The first line that differs, with escape sequences made visible
+fn difference(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (a, b) => {
+                let a = a.map_or("<end>".into(), |a| a.escape_debug().to_string());
+                let b = b.map_or("<end>".into(), |b| b.escape_debug().to_string());
+                return Some(format!("line {line}\nexpected: {a}\n  actual: {b}"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/", $name)
+        };
+    }
+
+    #[test]
+    fn typing() {
+        assert_script_snapshot(fixture!("typing.echo"), 40, 8, 1);
+    }
+
+    #[test]
+    fn selection() {
+        assert_script_snapshot(fixture!("selection.echo"), 40, 8, 1);
+    }
+
+    #[test]
+    fn replace_selection() {
+        assert_script_snapshot(fixture!("replace_selection.echo"), 40, 8, 1);
+    }
+
+    #[test]
+    fn markers() {
+        assert_script_snapshot(fixture!("markers.echo"), 40, 8, 1);
+    }
+
+    #[test]
+    fn theming() {
+        assert_script_snapshot(fixture!("theming.echo"), 40, 8, 1);
+    }
+
+    #[test]
+    fn virtual_text() {
+        assert_script_snapshot(fixture!("virtual_text.echo"), 40, 8, 1);
+    }
+
+    #[test]
+    fn every_nth_frame() {
+        assert_script_snapshot_every(fixture!("every.echo"), 20, 3, 7, 4);
+    }
+
+    #[test]
+    fn stable_with_seed() {
+        let first = render_script(fixture!("every.echo"), 20, 3, 7, Some(1)).unwrap();
+        let second = render_script(fixture!("every.echo"), 20, 3, 7, Some(1)).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn differences() {
+        assert_eq!(difference("a\nb", "a\nb"), None);
+        assert_eq!(difference("a\nb", "a\nc").unwrap(), "line 2\nexpected: b\n  actual: c");
+        assert_eq!(difference("a", "a\n\x1b[0m").unwrap(), "line 2\nexpected: <end>\n  actual: \\u{1b}[0m");
+    }
+}
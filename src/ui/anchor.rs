@@ -0,0 +1,74 @@
+// -----------------------------------------------------------------------------
+//   - Shift -
+// -----------------------------------------------------------------------------
+/// A change to the rows of the document. Anything anchored to a row
+/// (markers, virtual text, overlays) follows it through the same shifts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shift {
+    /// `count` lines were inserted, moving `from` and every row after it down
+    Down { from: usize, count: usize },
+    /// `count` lines were joined onto `row`, moving the rows after it up
+    Up { row: usize, count: usize },
+    /// `row` and the row after it traded places
+    Swap { row: usize },
+    /// The text is gone, and so is everything anchored to it
+    Clear,
+}
+
+impl Shift {
+    /// Where an anchor on `row` ends up, if it still exists.
+    /// Anchors on joined lines end up on the line they were joined onto.
+    pub fn row(self, row: usize) -> Option<usize> {
+        match self {
+            Shift::Down { from, count } if row >= from => Some(row + count),
+            Shift::Up { row: onto, count } if row > onto => Some(row.saturating_sub(count).max(onto)),
+            Shift::Swap { row: first } if row == first => Some(first + 1),
+            Shift::Swap { row: first } if row == first + 1 => Some(first),
+            Shift::Down { .. } | Shift::Up { .. } | Shift::Swap { .. } => Some(row),
+            Shift::Clear => None,
+        }
+    }
+}
+
+/// Something that holds anchors to rows of the document
+pub trait Anchored {
+    fn shift(&mut self, shift: Shift);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn down() {
+        let shift = Shift::Down { from: 2, count: 3 };
+        assert_eq!(shift.row(1), Some(1));
+        assert_eq!(shift.row(2), Some(5));
+        assert_eq!(shift.row(10), Some(13));
+    }
+
+    #[test]
+    fn up() {
+        let shift = Shift::Up { row: 2, count: 2 };
+        assert_eq!(shift.row(1), Some(1));
+        assert_eq!(shift.row(2), Some(2));
+        // Joined onto row 2
+        assert_eq!(shift.row(3), Some(2));
+        assert_eq!(shift.row(4), Some(2));
+        assert_eq!(shift.row(5), Some(3));
+    }
+
+    #[test]
+    fn swap() {
+        let shift = Shift::Swap { row: 2 };
+        assert_eq!(shift.row(1), Some(1));
+        assert_eq!(shift.row(2), Some(3));
+        assert_eq!(shift.row(3), Some(2));
+        assert_eq!(shift.row(4), Some(4));
+    }
+
+    #[test]
+    fn clear() {
+        assert_eq!(Shift::Clear.row(0), None);
+    }
+}
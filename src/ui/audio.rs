@@ -1,29 +1,112 @@
 use std::collections::HashMap;
 use std::fs::read_dir;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use kira::sound::static_sound::StaticSoundData;
-use kira::{AudioManager, AudioManagerSettings, DefaultBackend};
+use kira::sound::static_sound::{StaticSoundData, StaticSoundHandle, StaticSoundSettings};
+use kira::{AudioManager, AudioManagerSettings, Decibels, DefaultBackend, Frame, Tween};
 
 use super::error::{Error, Result};
+use super::keys::BACKSPACE;
+use super::random::Random;
 
 pub struct AudioShell {
     audio: Option<Audio>,
+    // Where `audio` was loaded from, so playing the script again doesn't load it twice
+    loaded: Option<PathBuf>,
+    duck: Duck,
+    classes: bool,
+    rand: Random,
+    sync: SoundSync,
 }
 
 impl AudioShell {
     pub fn new() -> Self {
-        Self { audio: None }
+        Self {
+            audio: None,
+            loaded: None,
+            duck: Duck::OFF,
+            classes: false,
+            rand: Random::new(),
+            sync: SoundSync::new(),
+        }
+    }
+
+    /// Milliseconds between the keystroke sound and the glyph appearing.
+    /// A positive offset plays the sound before the glyph, a negative after.
+    pub fn set_offset(&mut self, ms: i64) {
+        self.sync.set_offset(ms);
+    }
+
+    /// `typed` was just typed, and `next` is expected to be typed in `until_next`
+    pub fn typed(&mut self, typed: &str, next: Option<&str>, until_next: Duration) {
+        if self.audio.is_none() {
+            return;
+        }
+        self.sync.typed(typed, next, until_next);
+        self.play_due();
+    }
+
+    fn play_due(&mut self) {
+        for name in self.sync.due() {
+            self.play(&name);
+        }
+    }
+
+    /// Choose the keystroke sounds by character class
+    pub fn set_classes(&mut self, classes: bool) {
+        self.classes = classes;
+    }
+
+    pub fn duck(&mut self, percent: u8, attack: Duration, release: Duration) {
+        self.duck = Duck::new(percent, attack, release);
+    }
+
+    /// Advance the ducking envelope and apply it to the music.
+    /// This is a no-op unless there is music playing.
+    pub fn tick(&mut self, dt: Duration) {
+        self.sync.tick(dt);
+        self.play_due();
+
+        let Some(audio) = self.audio.as_mut() else { return };
+        if audio.music.is_none() {
+            return;
+        }
+        self.duck.tick(dt);
+        audio.set_music_volume(self.duck.volume());
+    }
+
+    /// Forget the sounds that are yet to play, the ones already playing finish
+    pub fn restart(&mut self) {
+        self.sync = SoundSync::new();
+        self.duck = Duck::OFF;
     }
 
     pub fn load(&mut self, path: PathBuf) -> Result<()> {
-        self.audio = Some(Audio::load(path)?);
+        if self.audio.is_some() && self.loaded.as_ref() == Some(&path) {
+            return Ok(());
+        }
+        self.audio = Some(Audio::load(path.clone())?);
+        self.loaded = Some(path);
         Ok(())
     }
 
     pub fn play(&mut self, name: &str) {
         let Some(audio) = self.audio.as_mut() else { return };
-        audio.play(name);
+        let class_sound = match self.classes {
+            true => audio.classes.pick(name, &mut self.rand).cloned(),
+            false => None,
+        };
+        let sound = class_sound.unwrap_or_else(|| audio.sound(name));
+        let duration = sound.duration();
+        _ = audio.manager.play(sound);
+        self.duck.trigger(duration);
+    }
+
+    /// The metronome click, played as it is without an offset or ducking
+    pub fn click(&mut self) {
+        let Some(audio) = self.audio.as_mut() else { return };
+        _ = audio.manager.play(audio.click.clone());
     }
 
     // pub fn set_volume(&mut self, vol: f32) {
@@ -36,6 +119,10 @@ struct Audio {
     manager: AudioManager<DefaultBackend>,
     sounds: HashMap<String, StaticSoundData>,
     default: StaticSoundData,
+    click: StaticSoundData,
+    classes: Classes<StaticSoundData>,
+    music: Option<StaticSoundHandle>,
+    music_volume: f32,
 }
 
 impl Audio {
@@ -43,25 +130,44 @@ impl Audio {
         let manager = AudioManager::<DefaultBackend>::new(AudioManagerSettings::default())?;
 
         let default = StaticSoundData::from_file(root.join("default.mp3")).map_err(|_| Error::NoDefaultSound)?;
+        let mut classes = Classes::new();
+        for class in CharClass::ALL {
+            let Ok(sounds) = load_sounds(root.join(class.dir())) else { continue };
+            classes.insert(class, sounds.into_values().collect());
+        }
         let sounds = load_sounds(root)?;
 
         let inst = Self {
             manager,
             sounds,
             default,
+            click: click(),
+            classes,
+            music: None,
+            music_volume: 1.0,
         };
 
         Ok(inst)
     }
 
-    pub fn play(&mut self, name: &str) {
-        let sound = match name {
+    // The sound for a key
+    fn sound(&self, name: &str) -> StaticSoundData {
+        match name {
             "\n" => self.get_sound("enter"),
+            BACKSPACE => self.get_sound("backspace"),
             " " => self.get_sound("space"),
             ";" | "<" | ">" => self.get_sound("semicolon"),
             name => self.get_sound(name),
-        };
-        _ = self.manager.play(sound);
+        }
+    }
+
+    fn set_music_volume(&mut self, volume: f32) {
+        let Some(music) = self.music.as_mut() else { return };
+        if volume == self.music_volume {
+            return;
+        }
+        self.music_volume = volume;
+        music.set_volume(to_decibels(volume), Tween::default());
     }
 
     fn get_sound(&self, name: &str) -> StaticSoundData {
@@ -76,6 +182,248 @@ impl Audio {
     // }
 }
 
+// -----------------------------------------------------------------------------
+//   - Character classes -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum CharClass {
+    Letter,
+    Digit,
+    Whitespace,
+    Punctuation,
+    Newline,
+}
+
+impl CharClass {
+    const ALL: [Self; 5] = [
+        Self::Letter,
+        Self::Digit,
+        Self::Whitespace,
+        Self::Punctuation,
+        Self::Newline,
+    ];
+
+    // Classify a grapheme by its first character
+    fn of(s: &str) -> Option<Self> {
+        let c = s.chars().next()?;
+        let class = match c {
+            '\n' => Self::Newline,
+            c if c.is_whitespace() => Self::Whitespace,
+            // Keys like backspace sound like themselves
+            c if c.is_control() => return None,
+            c if c.is_numeric() => Self::Digit,
+            c if c.is_alphabetic() => Self::Letter,
+            _ => Self::Punctuation,
+        };
+        Some(class)
+    }
+
+    // The directory of the sample pool, relative to the audio path
+    fn dir(self) -> &'static str {
+        match self {
+            Self::Letter => "letters",
+            Self::Digit => "digits",
+            Self::Whitespace => "whitespace",
+            Self::Punctuation => "punctuation",
+            Self::Newline => "newline",
+        }
+    }
+}
+
+/// Sample pools by character class
+struct Classes<T> {
+    pools: HashMap<CharClass, Vec<T>>,
+}
+
+impl<T> Classes<T> {
+    fn new() -> Self {
+        Self { pools: HashMap::new() }
+    }
+
+    fn insert(&mut self, class: CharClass, pool: Vec<T>) {
+        if !pool.is_empty() {
+            self.pools.insert(class, pool);
+        }
+    }
+
+    // A random sample for the class of the grapheme,
+    // `None` if there are no samples for the class
+    fn pick(&self, s: &str, rand: &mut Random) -> Option<&T> {
+        let pool = self.pools.get(&CharClass::of(s)?)?;
+        let index = rand.next(pool.len() as u64) as usize;
+        Some(&pool[index])
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Sync -
+// -----------------------------------------------------------------------------
+/// Schedules the keystroke sounds relative to the glyphs.
+///
+/// With a positive offset the sound of the next glyph is scheduled when the
+/// current glyph is typed, `offset` before the next glyph is expected.
+/// A sound can't be scheduled further ahead than that, or before the glyph
+/// it comes after, so a larger offset than the pause between glyphs plays the
+/// sound as the previous glyph is typed.
+pub struct SoundSync {
+    offset: i64,
+    now: Duration,
+    // Sounds and when to play them
+    pending: Vec<(Duration, String)>,
+    // The sound of the glyph about to be typed is already scheduled
+    scheduled_next: bool,
+}
+
+impl SoundSync {
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            now: Duration::ZERO,
+            pending: vec![],
+            scheduled_next: false,
+        }
+    }
+
+    pub fn tick(&mut self, dt: Duration) {
+        self.now += dt;
+    }
+
+    pub fn typed(&mut self, typed: &str, next: Option<&str>, until_next: Duration) {
+        if self.offset <= 0 {
+            let delay = Duration::from_millis(self.offset.unsigned_abs());
+            self.pending.push((self.now + delay, typed.into()));
+            return;
+        }
+
+        if !std::mem::take(&mut self.scheduled_next) {
+            self.pending.push((self.now, typed.into()));
+        }
+
+        if let Some(next) = next {
+            let ahead = until_next.saturating_sub(Duration::from_millis(self.offset as u64));
+            self.pending.push((self.now + ahead, next.into()));
+            self.scheduled_next = true;
+        }
+    }
+
+    pub fn set_offset(&mut self, ms: i64) {
+        self.offset = ms;
+    }
+
+    /// Sounds that are due, in the order they were scheduled
+    pub fn due(&mut self) -> Vec<String> {
+        let mut due = vec![];
+        self.pending.retain(|(at, name)| {
+            if *at > self.now {
+                return true;
+            }
+            due.push(name.clone());
+            false
+        });
+        due
+    }
+}
+
+fn to_decibels(amplitude: f32) -> Decibels {
+    if amplitude <= 0.001 {
+        return Decibels::SILENCE;
+    }
+    Decibels(20.0 * amplitude.log10())
+}
+
+// -----------------------------------------------------------------------------
+//   - Ducking -
+// -----------------------------------------------------------------------------
+/// Volume envelope for the music.
+/// The music is lowered by `depth` while a keystroke sample is playing,
+/// and restored once the sample has finished.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Duck {
+    depth: f32,
+    attack: Duration,
+    release: Duration,
+    // Time remaining of the currently playing keystroke sample
+    hold: Duration,
+    // Current reduction in volume, between zero and `depth`
+    level: f32,
+}
+
+impl Duck {
+    const OFF: Self = Self {
+        depth: 0.0,
+        attack: Duration::ZERO,
+        release: Duration::ZERO,
+        hold: Duration::ZERO,
+        level: 0.0,
+    };
+
+    fn new(percent: u8, attack: Duration, release: Duration) -> Self {
+        Self {
+            depth: percent.min(100) as f32 / 100.0,
+            attack,
+            release,
+            ..Self::OFF
+        }
+    }
+
+    fn trigger(&mut self, sample_duration: Duration) {
+        if self.depth == 0.0 {
+            return;
+        }
+        self.hold = self.hold.max(sample_duration);
+    }
+
+    fn tick(&mut self, dt: Duration) {
+        let attack = dt.min(self.hold);
+        self.hold -= attack;
+        self.level = step(self.level, self.depth, self.depth, attack, self.attack);
+
+        let release = dt - attack;
+        self.level = step(self.level, 0.0, self.depth, release, self.release);
+    }
+
+    fn volume(&self) -> f32 {
+        1.0 - self.level
+    }
+}
+
+// Move `from` towards `to` at a rate where covering `distance` takes `time`
+fn step(from: f32, to: f32, distance: f32, dt: Duration, time: Duration) -> f32 {
+    if dt.is_zero() {
+        return from;
+    }
+
+    if time.is_zero() {
+        return to;
+    }
+
+    let delta = distance * (dt.as_secs_f32() / time.as_secs_f32());
+    match from < to {
+        true => (from + delta).min(to),
+        false => (from - delta).max(to),
+    }
+}
+
+// A short tone that fades out, unlike any keystroke
+fn click() -> StaticSoundData {
+    const SAMPLE_RATE: u32 = 44_100;
+    const FREQUENCY: f32 = 1_500.0;
+
+    let len = SAMPLE_RATE as usize / 50;
+    let frames = (0..len).map(|i| {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        let fade = 1.0 - i as f32 / len as f32;
+        Frame::from_mono((t * FREQUENCY * std::f32::consts::TAU).sin() * fade * 0.5)
+    });
+
+    StaticSoundData {
+        sample_rate: SAMPLE_RATE,
+        frames: frames.collect(),
+        settings: StaticSoundSettings::default(),
+        slice: None,
+    }
+}
+
 fn load_sounds(path: PathBuf) -> Result<HashMap<String, StaticSoundData>> {
     let mut entries = read_dir(&path).map_err(|_| Error::FilePath(path))?;
     let mut hm = HashMap::new();
@@ -91,3 +439,174 @@ fn load_sounds(path: PathBuf) -> Result<HashMap<String, StaticSoundData>> {
 
     Ok(hm)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn click_is_short_and_fades_out() {
+        let click = click();
+        assert!(click.duration() < Duration::from_millis(50));
+        assert!(click.frames.iter().any(|frame| frame.left.abs() > 0.1));
+        assert!(click.frames.last().unwrap().left.abs() < 0.01);
+    }
+
+    fn sync(offset: i64) -> SoundSync {
+        let mut sync = SoundSync::new();
+        sync.set_offset(offset);
+        sync
+    }
+
+    #[test]
+    fn sound_with_glyph() {
+        let mut sync = sync(0);
+        sync.typed("a", Some("b"), Duration::from_millis(100));
+        assert_eq!(sync.due(), ["a"]);
+    }
+
+    #[test]
+    fn sound_after_glyph() {
+        let mut sync = sync(-40);
+        sync.typed("a", Some("b"), Duration::from_millis(100));
+        sync.tick(Duration::from_millis(39));
+        assert!(sync.due().is_empty());
+        sync.tick(Duration::from_millis(1));
+        assert_eq!(sync.due(), ["a"]);
+    }
+
+    #[test]
+    fn sound_before_glyph() {
+        let mut sync = sync(40);
+
+        // The first sound can't be played any earlier
+        sync.typed("a", Some("b"), Duration::from_millis(100));
+        assert_eq!(sync.due(), ["a"]);
+        sync.tick(Duration::from_millis(59));
+        assert!(sync.due().is_empty());
+        sync.tick(Duration::from_millis(1));
+        assert_eq!(sync.due(), ["b"]);
+
+        // "b" is already scheduled
+        sync.tick(Duration::from_millis(40));
+        sync.typed("b", None, Duration::from_millis(100));
+        sync.tick(Duration::from_millis(100));
+        assert!(sync.due().is_empty());
+
+        // An offset larger than the pause plays the sound with the previous glyph
+        sync.typed("c", Some("d"), Duration::from_millis(30));
+        assert_eq!(sync.due(), ["c", "d"]);
+    }
+
+    #[test]
+    fn char_classes() {
+        assert_eq!(CharClass::of("a"), Some(CharClass::Letter));
+        assert_eq!(CharClass::of("Å"), Some(CharClass::Letter));
+        assert_eq!(CharClass::of("7"), Some(CharClass::Digit));
+        assert_eq!(CharClass::of(" "), Some(CharClass::Whitespace));
+        assert_eq!(CharClass::of("    "), Some(CharClass::Whitespace));
+        assert_eq!(CharClass::of("\t"), Some(CharClass::Whitespace));
+        assert_eq!(CharClass::of("\n"), Some(CharClass::Newline));
+        assert_eq!(CharClass::of(";"), Some(CharClass::Punctuation));
+        assert_eq!(CharClass::of("🐇"), Some(CharClass::Punctuation));
+        assert_eq!(CharClass::of(""), None);
+    }
+
+    #[test]
+    fn pick_from_class_pool() {
+        let mut classes = Classes::new();
+        classes.insert(CharClass::Letter, vec!["l1", "l2", "l3"]);
+        classes.insert(CharClass::Digit, vec!["d1"]);
+        classes.insert(CharClass::Newline, vec![]);
+
+        let mut rand = Random::with_seed(3);
+        let picks = (0..20).map(|_| *classes.pick("x", &mut rand).unwrap()).collect::<Vec<_>>();
+        assert!(picks.iter().all(|pick| pick.starts_with('l')));
+
+        // The same seed picks the same samples
+        let mut rand = Random::with_seed(3);
+        let again = (0..20).map(|_| *classes.pick("x", &mut rand).unwrap()).collect::<Vec<_>>();
+        assert_eq!(picks, again);
+
+        assert_eq!(classes.pick("1", &mut rand), Some(&"d1"));
+
+        // Missing and empty pools fall back to the generic pool
+        assert_eq!(classes.pick(";", &mut rand), None);
+        assert_eq!(classes.pick("\n", &mut rand), None);
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn assert_volume(duck: &Duck, expected: f32) {
+        let diff = (duck.volume() - expected).abs();
+        assert!(diff < 0.0001, "expected volume {expected}, found {}", duck.volume());
+    }
+
+    #[test]
+    fn duck_attack_and_release() {
+        let mut duck = Duck::new(50, ms(100), ms(200));
+        duck.trigger(ms(300));
+
+        // Half way through the attack
+        duck.tick(ms(50));
+        assert_volume(&duck, 0.75);
+
+        // Fully ducked and holding while the sample plays
+        duck.tick(ms(100));
+        assert_volume(&duck, 0.5);
+        duck.tick(ms(150));
+        assert_volume(&duck, 0.5);
+
+        // Half way through the release
+        duck.tick(ms(100));
+        assert_volume(&duck, 0.75);
+
+        // Fully restored
+        duck.tick(ms(500));
+        assert_volume(&duck, 1.0);
+    }
+
+    #[test]
+    fn duck_tick_straddling_end_of_sample() {
+        let mut duck = Duck::new(40, Duration::ZERO, ms(100));
+        duck.trigger(ms(10));
+
+        // 10ms attack (instant), then 50ms of release
+        duck.tick(ms(60));
+        assert_volume(&duck, 0.8);
+    }
+
+    #[test]
+    fn duck_retrigger_extends_hold() {
+        let mut duck = Duck::new(100, Duration::ZERO, ms(100));
+        duck.trigger(ms(50));
+        duck.tick(ms(40));
+        duck.trigger(ms(50));
+        duck.tick(ms(40));
+        assert_volume(&duck, 0.0);
+    }
+
+    #[test]
+    fn duck_zero_percent_is_disabled() {
+        let mut duck = Duck::new(0, ms(10), ms(10));
+        duck.trigger(ms(100));
+        duck.tick(ms(50));
+        assert_volume(&duck, 1.0);
+        assert_eq!(duck.hold, Duration::ZERO);
+    }
+
+    #[test]
+    fn duck_percent_is_clamped() {
+        let duck = Duck::new(250, ms(10), ms(10));
+        assert_eq!(duck.depth, 1.0);
+    }
+
+    #[test]
+    fn amplitude_to_decibels() {
+        assert_eq!(to_decibels(1.0), Decibels(0.0));
+        assert_eq!(to_decibels(0.0), Decibels::SILENCE);
+        assert!((to_decibels(0.5).0 + 6.0206).abs() < 0.001);
+    }
+}
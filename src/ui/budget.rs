@@ -0,0 +1,201 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::error::{Error, Result};
+use super::estimate::Estimator;
+use super::instructions::Instruction;
+use super::syntax_cache::Load;
+
+// -----------------------------------------------------------------------------
+//   - Budget -
+// -----------------------------------------------------------------------------
+/// A section of instructions between `budget <seconds>` and `budget end`
+#[derive(Debug, PartialEq)]
+pub struct Budget {
+    /// The line of the `budget` instruction
+    pub line: usize,
+    pub limit: Duration,
+    pub estimated: Duration,
+}
+
+impl Budget {
+    /// How much longer than the limit the section is estimated to be
+    pub fn overshoot(&self) -> Option<Duration> {
+        self.estimated.checked_sub(self.limit).filter(|d| !d.is_zero())
+    }
+}
+
+/// Estimate the length of every budgeted section, in the order they start.
+pub fn budgets(instructions: &[Instruction]) -> Result<Vec<Budget>> {
+    let mut estimator = Estimator::new();
+    let mut budgets = vec![];
+    let mut open = vec![];
+
+    for inst in instructions {
+        match inst {
+            &Instruction::Budget { line, limit } => {
+                open.push((budgets.len(), estimator.elapsed()));
+                budgets.push(Budget {
+                    line,
+                    limit,
+                    estimated: Duration::ZERO,
+                });
+            }
+            Instruction::BudgetEnd => {
+                let Some((index, start)) = open.pop() else { return Err(Error::UnmatchedBudgetEnd) };
+                budgets[index].estimated = estimator.elapsed() - start;
+            }
+            Instruction::Every { body, .. } => {
+                if let Some(&Instruction::Budget { line, .. }) =
+                    body.iter().find(|inst| matches!(inst, Instruction::Budget { .. }))
+                {
+                    return Err(Error::BudgetInEvery(line));
+                }
+                if body.iter().any(|inst| matches!(inst, Instruction::BudgetEnd)) {
+                    return Err(Error::UnmatchedBudgetEnd);
+                }
+            }
+            inst => estimator.apply(inst),
+        }
+    }
+
+    match open.pop() {
+        Some((index, _)) => Err(Error::UnclosedBudget(budgets[index].line)),
+        None => Ok(budgets),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Timings -
+// -----------------------------------------------------------------------------
+/// The actual time spent in each budgeted section, in the order they start,
+/// and how long it took to load the syntaxes and themes.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    inner: Arc<Mutex<TimingsInner>>,
+}
+
+#[derive(Debug, Default)]
+struct TimingsInner {
+    open: Vec<(usize, Duration)>,
+    actual: Vec<Option<Duration>>,
+    load: Option<Load>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&self, now: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let index = inner.actual.len();
+        inner.open.push((index, now));
+        inner.actual.push(None);
+    }
+
+    pub fn end(&self, now: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((index, start)) = inner.open.pop() {
+            inner.actual[index] = Some(now.saturating_sub(start));
+        }
+    }
+
+    /// The actual time of each section, `None` if the section never finished
+    pub fn actual(&self) -> Vec<Option<Duration>> {
+        self.inner.lock().unwrap().actual.clone()
+    }
+
+    pub fn loaded(&self, load: Load) {
+        self.inner.lock().unwrap().load = Some(load);
+    }
+
+    /// `None` if the syntaxes and themes weren't done loading
+    pub fn load(&self) -> Option<Load> {
+        self.inner.lock().unwrap().load
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn budget(line: usize, secs: u64) -> Instruction {
+        Instruction::Budget {
+            line,
+            limit: Duration::from_secs(secs),
+        }
+    }
+
+    fn wait(secs: u64) -> Instruction {
+        Instruction::Wait(Duration::from_secs(secs))
+    }
+
+    fn estimated(budgets: &[Budget]) -> Vec<(usize, u64)> {
+        budgets.iter().map(|b| (b.line, b.estimated.as_secs())).collect()
+    }
+
+    #[test]
+    fn nested_budgets() {
+        let instructions = vec![
+            budget(1, 10),
+            wait(2),
+            budget(3, 1),
+            wait(3),
+            Instruction::BudgetEnd,
+            wait(1),
+            Instruction::BudgetEnd,
+            budget(8, 5),
+            wait(1),
+            Instruction::BudgetEnd,
+        ];
+        let budgets = budgets(&instructions).unwrap();
+        assert_eq!(estimated(&budgets), vec![(1, 6), (3, 3), (8, 1)]);
+
+        assert_eq!(budgets[0].overshoot(), None);
+        assert_eq!(budgets[1].overshoot(), Some(Duration::from_secs(2)));
+        assert_eq!(budgets[2].overshoot(), None);
+    }
+
+    #[test]
+    fn typing_follows_speed_and_line_pause() {
+        let instructions = vec![
+            Instruction::SetJitter(0),
+            Instruction::Speed(Duration::from_millis(100)),
+            Instruction::LinePause(Duration::from_millis(500)),
+            budget(4, 1),
+            // 4 characters, one of them a newline, and a marker that is never typed
+            Instruction::LoadTypeBuffer("ab\n// @marker\nc".into()),
+            Instruction::BudgetEnd,
+        ];
+        let budgets = budgets(&instructions).unwrap();
+        assert_eq!(budgets[0].estimated, Duration::from_millis(900));
+    }
+
+    #[test]
+    fn unbalanced_budgets_are_rejected() {
+        let unclosed = vec![budget(1, 10), budget(2, 10), Instruction::BudgetEnd];
+        assert!(matches!(budgets(&unclosed), Err(Error::UnclosedBudget(1))));
+
+        let unmatched = vec![budget(1, 10), Instruction::BudgetEnd, Instruction::BudgetEnd];
+        assert!(matches!(budgets(&unmatched), Err(Error::UnmatchedBudgetEnd)));
+
+        let every = vec![Instruction::Every {
+            interval: Duration::from_secs(1),
+            body: vec![budget(2, 1), Instruction::BudgetEnd],
+        }];
+        assert!(matches!(budgets(&every), Err(Error::BudgetInEvery(2))));
+    }
+
+    #[test]
+    fn timings() {
+        let timings = Timings::new();
+        timings.start(Duration::from_secs(1));
+        timings.start(Duration::from_secs(2));
+        timings.end(Duration::from_secs(5));
+        timings.start(Duration::from_secs(6));
+
+        let expected = vec![None, Some(Duration::from_secs(3)), None];
+        assert_eq!(timings.actual(), expected);
+    }
+}
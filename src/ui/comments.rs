@@ -0,0 +1,43 @@
+/// The leader for extensions that aren't in the table
+pub const FALLBACK: &str = "//";
+
+const LEADERS: &[(&str, &[&str])] = &[
+    ("//", &["rs", "js", "jsx", "ts", "tsx", "c", "h", "cpp", "hpp", "cs", "go", "java", "kt", "swift", "zig"]),
+    ("#", &["py", "sh", "bash", "zsh", "fish", "toml", "yaml", "yml", "rb", "pl", "r", "nix", "conf"]),
+    ("--", &["lua", "sql", "hs", "elm"]),
+    (";", &["lisp", "el", "clj", "cljs", "scm", "rkt", "fnl", "asm", "ini"]),
+    ("%", &["tex", "erl", "m"]),
+    ("\"", &["vim"]),
+];
+
+/// The line comment leader for files with the extension, if it's in the table
+pub fn leader(extension: &str) -> Option<&'static str> {
+    LEADERS
+        .iter()
+        .find(|(_, extensions)| extensions.iter().any(|ext| ext.eq_ignore_ascii_case(extension)))
+        .map(|(leader, _)| *leader)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn leaders() {
+        for ext in ["rs", "js", "c"] {
+            assert_eq!(leader(ext), Some("//"));
+        }
+        for ext in ["py", "sh", "toml"] {
+            assert_eq!(leader(ext), Some("#"));
+        }
+        for ext in ["lua", "sql"] {
+            assert_eq!(leader(ext), Some("--"));
+        }
+        for ext in ["lisp", "clj", "el"] {
+            assert_eq!(leader(ext), Some(";"));
+        }
+        assert_eq!(leader("RS"), Some("//"));
+        assert_eq!(leader("txt"), None);
+        assert_eq!(leader(""), None);
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::{HashMap, VecDeque};
+use std::env::VarError;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anathema::geometry::Size;
@@ -5,135 +8,1108 @@ use unicode_width::UnicodeWidthStr;
 
 pub use super::context::Context;
 use super::error::{Error, Result};
+use super::{exec, filters};
 use super::instructions::Instruction;
-use crate::parser::{Dest, Source};
+use super::loader::{Files, FsLoader, Loader};
+use super::margins::Margins;
+use super::optimize::optimize;
+use super::presets::is_setting;
+use super::splash::{DEFAULT_HOLD, Splash};
+use crate::parser::{Dest, Expr, Lines, Source, Variable};
 
-pub fn compile(parsed_instructions: crate::parser::Instructions) -> Result<Vec<Instruction>> {
-    let mut context = Context::new();
-    let mut instructions = vec![];
+/// Compile with paths relative to the directory of `script`, or to the working directory without one.
+///
+/// Instructions are compiled as they are taken, so files are only loaded once
+/// the instructions before them are done. See [`compile_all`] for all of them at once.
+pub fn compile(parsed_instructions: crate::parser::Instructions, script: Option<&Path>) -> CompiledInstructions<'static> {
+    CompiledInstructions::new(parsed_instructions, Files::new(&FsLoader, base_dir(script)), HashMap::new())
+}
 
-    for inst in parsed_instructions {
-        match inst {
-            crate::parser::Instruction::Load(path, key) => {
-                let content = std::fs::read_to_string(&path).map_err(|_| Error::Import(path))?;
-                context.set(key, content);
+/// Compile everything up front, failing on the first error
+pub fn compile_all(parsed_instructions: crate::parser::Instructions, script: Option<&Path>) -> Result<Vec<Instruction>> {
+    compile(parsed_instructions, script).collect()
+}
+
+/// How [`compile_with_options`] compiles a script
+#[derive(Debug, Default, Clone)]
+pub struct CompileOptions {
+    /// Paths are relative to the directory of the script, or to the working directory without one
+    pub script: Option<PathBuf>,
+    /// Merge waits and drop instructions that do nothing, which changes the number of instructions
+    pub optimize: bool,
+    /// Variables set before the script runs, which win over `set` and `let` in the script
+    pub variables: HashMap<String, Variable>,
+}
+
+pub fn compile_with_options(
+    parsed_instructions: crate::parser::Instructions,
+    options: &CompileOptions,
+) -> Result<Vec<Instruction>> {
+    let files = Files::new(&FsLoader, base_dir(options.script.as_deref()));
+    let compiled = CompiledInstructions::new(parsed_instructions, files, options.variables.clone());
+    let mut instructions = compiled.collect::<Result<Vec<_>>>()?;
+
+    // Set first so templates see them even if the script never does
+    let mut variables = options.variables.iter().collect::<Vec<_>>();
+    variables.sort_by_key(|(name, _)| *name);
+    let variables = variables.into_iter().map(|(name, value)| Instruction::SetVariable(name.clone(), value.clone()));
+    instructions.splice(0..0, variables);
+
+    match options.optimize {
+        true => Ok(optimize(instructions)),
+        false => Ok(instructions),
+    }
+}
+
+/// The directory relative paths in the script are resolved against
+pub(super) fn base_dir(script: Option<&Path>) -> &Path {
+    script.and_then(Path::parent).unwrap_or(Path::new(""))
+}
+
+/// Compile with `load` and `include` read through `loader`, relative to `base_dir`
+pub fn compile_with_loader(
+    parsed_instructions: crate::parser::Instructions,
+    loader: &dyn Loader,
+    base_dir: &Path,
+) -> Result<Vec<Instruction>> {
+    CompiledInstructions::new(parsed_instructions, Files::new(loader, base_dir), HashMap::new()).collect()
+}
+
+// -----------------------------------------------------------------------------
+//   - Compiled instructions -
+// -----------------------------------------------------------------------------
+/// The instructions of a script, compiled one parsed instruction at a time.
+///
+/// An `include` is compiled as its instructions are reached rather than all at once.
+/// Nothing is compiled after an error.
+pub struct CompiledInstructions<'a> {
+    files: Files<'a>,
+    // The script and the files it's including, innermost last, each with its own context
+    frames: Vec<(std::vec::IntoIter<crate::parser::Instruction>, Context)>,
+    compiled: VecDeque<Instruction>,
+}
+
+impl<'a> CompiledInstructions<'a> {
+    fn new(
+        parsed_instructions: crate::parser::Instructions,
+        files: Files<'a>,
+        overrides: HashMap<String, Variable>,
+    ) -> Self {
+        Self {
+            files,
+            frames: vec![(parsed_instructions.into_iter(), Context::with_overrides(overrides))],
+            compiled: VecDeque::new(),
+        }
+    }
+
+    // Compile the next parsed instruction, which may not compile to anything
+    fn step(&mut self) -> Result<()> {
+        let Some((parsed, context)) = self.frames.last_mut() else { return Ok(()) };
+        let Some(inst) = parsed.next() else {
+            self.frames.pop();
+            // The script itself was never entered
+            if !self.frames.is_empty() {
+                self.files.leave();
             }
-            crate::parser::Instruction::Find { needle, count } => instructions.push(Instruction::FindInCurrentLine {
-                needle,
-                end_of_word: false,
-                count,
-            }),
-            crate::parser::Instruction::FindEnd { needle, count } => {
-                instructions.push(Instruction::FindInCurrentLine {
-                    needle,
-                    end_of_word: true,
-                    count,
-                })
+            return Ok(());
+        };
+
+        if let crate::parser::Instruction::Include { path, line } = inst {
+            let src = include(&mut self.files, &path, line)?;
+            let included = crate::parser::parse(&src).map_err(Error::Parse)?;
+            let context = context.include();
+            self.frames.push((included.into_iter(), context));
+            return Ok(());
+        }
+
+        let mut compiled = vec![];
+        compile_one(inst, context, &mut self.files, &mut compiled)?;
+        self.compiled.extend(compiled);
+        Ok(())
+    }
+}
+
+impl Iterator for CompiledInstructions<'_> {
+    type Item = Result<Instruction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(inst) = self.compiled.pop_front() {
+                return Some(Ok(inst));
             }
-            crate::parser::Instruction::Goto(dest) => {
-                let inst = match dest {
-                    Dest::Relative { row, col } => Instruction::Jump((col, row).into()),
-                    Dest::Marker(name) => Instruction::JumpToMarker(name),
-                };
-                instructions.push(inst);
+            if self.frames.is_empty() {
+                return None;
             }
-            crate::parser::Instruction::Select { width, height } => {
-                instructions.push(Instruction::Select(Size::new(width, height)))
+            if let Err(e) = self.step() {
+                self.frames.clear();
+                return Some(Err(e));
             }
-            crate::parser::Instruction::Delete => instructions.push(Instruction::Delete),
-            crate::parser::Instruction::Type {
-                source,
-                trim_trailing_newline,
-                prefix_newline,
-            } => {
-                let mut content = match source {
-                    Source::Str(content) => content,
-                    Source::Ident(key) => context.load(key)?,
-                };
-
-                if trim_trailing_newline && content.ends_with('\n') {
-                    _ = content.pop();
-                }
+        }
+    }
+}
 
-                if prefix_newline {
-                    instructions.push(Instruction::Insert("\n".into()));
-                }
-                instructions.push(Instruction::LoadTypeBuffer(content));
-            }
-            crate::parser::Instruction::Command(source) => {
-                let cmd = match source {
-                    Source::Str(cmd) => cmd,
-                    Source::Ident(key) => context.load(key)?,
-                };
-                instructions.push(Instruction::LoadCommandBuffer(cmd));
-                instructions.push(Instruction::ClearCommandWait);
-                instructions.push(Instruction::ClearCommandBuffer);
+// Enter the included file, saying where it was included if it can't be loaded
+fn include(files: &mut Files<'_>, path: &Path, line: usize) -> Result<String> {
+    files.enter(path).map_err(|e| match e {
+        Error::Import(path) => Error::Include { path, line },
+        e => e,
+    })
+}
+
+// Every file gets its own context, with the variables set so far
+fn compile_file(
+    parsed_instructions: crate::parser::Instructions,
+    files: &mut Files<'_>,
+    context: &Context,
+) -> Result<Vec<Instruction>> {
+    compile_block(parsed_instructions, &mut context.include(), files)
+}
+
+fn compile_block(
+    parsed_instructions: crate::parser::Instructions,
+    context: &mut Context,
+    files: &mut Files<'_>,
+) -> Result<Vec<Instruction>> {
+    let mut instructions = vec![];
+    for inst in parsed_instructions {
+        compile_one(inst, context, files, &mut instructions)?;
+    }
+    Ok(instructions)
+}
+
+fn compile_one(
+    inst: crate::parser::Instruction,
+    context: &mut Context,
+    files: &mut Files<'_>,
+    instructions: &mut Vec<Instruction>,
+) -> Result<()> {
+    let start = instructions.len();
+    match inst {
+        crate::parser::Instruction::Load(path, key, lines) => {
+            let content = files.load(&path)?;
+            let content = match lines {
+                Some(lines) => slice_lines(content, lines, path)?,
+                None => content,
+            };
+            context.set(key, content);
+        }
+        crate::parser::Instruction::Exec { command, timeout, key } => {
+            let command = context.substitute(&command)?;
+            let output = exec::run(&command, timeout.unwrap_or(exec::DEFAULT_TIMEOUT))?;
+            context.set(key, output);
+        }
+        crate::parser::Instruction::Find { needle, count, reverse } => {
+            instructions.push(Instruction::FindInCurrentLine {
+                needle,
+                end_of_word: false,
+                reverse,
+                count,
+                dependents: None,
+            })
+        }
+        crate::parser::Instruction::FindEnd { needle, count, reverse } => {
+            instructions.push(Instruction::FindInCurrentLine {
+                needle,
+                end_of_word: true,
+                reverse,
+                count,
+                dependents: None,
+            })
+        }
+        crate::parser::Instruction::FindMiss(policy) => instructions.push(Instruction::FindMiss(policy)),
+        crate::parser::Instruction::StrictMarkers(strict) => instructions.push(Instruction::StrictMarkers(strict)),
+        crate::parser::Instruction::SetMarker(name) => instructions.push(Instruction::SetMarker(name)),
+        crate::parser::Instruction::RemoveMarker(name) => instructions.push(Instruction::RemoveMarker(name)),
+        crate::parser::Instruction::VirtualText { anchor, text, below } => {
+            instructions.push(Instruction::VirtualText { anchor, text, below })
+        }
+        crate::parser::Instruction::ClearVirtualText(row) => instructions.push(Instruction::ClearVirtualText(row)),
+        crate::parser::Instruction::Splash { lines, duration, styles } => instructions.push(Instruction::Splash(Splash {
+            lines,
+            styles,
+            hold: duration.unwrap_or(DEFAULT_HOLD),
+        })),
+        crate::parser::Instruction::Goto(dest) => {
+            let inst = match dest {
+                Dest::Relative { row, col } => Instruction::Jump((col, row).into()),
+                Dest::Absolute { row, col } => Instruction::JumpAbsolute((col as i32, row as i32 - 1).into()),
+                Dest::Start => Instruction::JumpStart,
+                Dest::End => Instruction::JumpEnd,
+                Dest::Marker { name, row, col } => Instruction::JumpToMarker {
+                    name,
+                    offset: (col, row).into(),
+                },
+            };
+            instructions.push(inst);
+        }
+        crate::parser::Instruction::Select { width, height } => {
+            let size = Size::new(number(context, &width)?, number(context, &height)?);
+            instructions.push(Instruction::Select(size))
+        }
+        crate::parser::Instruction::SelectEol => instructions.push(Instruction::SelectEol),
+        crate::parser::Instruction::SelectMarkers { start, end } => {
+            instructions.push(Instruction::SelectMarkers(start, end))
+        }
+        crate::parser::Instruction::Delete => instructions.push(Instruction::Delete),
+        crate::parser::Instruction::Backspace(count) => instructions.push(Instruction::Backspace(count)),
+        crate::parser::Instruction::DeleteLine(count) => instructions.push(Instruction::DeleteLine(count)),
+        crate::parser::Instruction::DuplicateLine(count) => instructions.push(Instruction::DuplicateLine(count)),
+        crate::parser::Instruction::MoveLine { up, count } => instructions.push(Instruction::MoveLine { up, count }),
+        crate::parser::Instruction::DeleteWord => instructions.push(Instruction::DeleteWord),
+        crate::parser::Instruction::ClearEol => instructions.push(Instruction::ClearEol),
+        crate::parser::Instruction::Snapshot(name) => instructions.push(Instruction::Snapshot(name)),
+        crate::parser::Instruction::Restore(name) => instructions.push(Instruction::Restore(name)),
+        crate::parser::Instruction::Yank => instructions.push(Instruction::Yank),
+        crate::parser::Instruction::Paste { above } => instructions.push(Instruction::Paste { above }),
+        crate::parser::Instruction::Join(count) => instructions.push(Instruction::Join(count)),
+        crate::parser::Instruction::SplitLine => instructions.push(Instruction::SplitLine),
+        crate::parser::Instruction::Align { c, lines, instant } => {
+            instructions.push(Instruction::Align { c, lines, instant })
+        }
+        crate::parser::Instruction::AutoIndent(autoindent) => instructions.push(Instruction::AutoIndent(autoindent)),
+        crate::parser::Instruction::Indent { lines, levels } => {
+            instructions.push(Instruction::Indent { lines, levels })
+        }
+        crate::parser::Instruction::Dedent { lines, levels } => {
+            instructions.push(Instruction::Dedent { lines, levels })
+        }
+        crate::parser::Instruction::IndentUnit(unit) => instructions.push(Instruction::IndentUnit(unit)),
+        crate::parser::Instruction::TabWidth(width) => instructions.push(Instruction::TabWidth(width)),
+        crate::parser::Instruction::ToggleComment { lines, instant } => {
+            instructions.push(Instruction::ToggleComment { lines, instant })
+        }
+        crate::parser::Instruction::CommentLeader(leader) => instructions.push(Instruction::CommentLeader(leader)),
+        crate::parser::Instruction::CommentPrefix(prefix) => instructions.push(Instruction::CommentPrefix(prefix)),
+        crate::parser::Instruction::Type {
+            source,
+            filters,
+            trim_trailing_newline,
+            prefix_newline,
+            keep_selection,
+        } => {
+            let content = match source {
+                Source::Str(content) => context.substitute(&content)?,
+                Source::Ident(key) => context.load(key)?,
+                Source::Env { name, default } => env(name, default)?,
+            };
+            let mut content = filters::apply(content, filters)?;
+
+            if trim_trailing_newline && content.ends_with('\n') {
+                _ = content.pop();
             }
-            crate::parser::Instruction::Insert(source) => {
-                let inst = match source {
-                    Source::Str(content) => Instruction::Insert(content),
-                    Source::Ident(key) => {
-                        let content = context.load(key)?;
-                        Instruction::Insert(content)
-                    }
-                };
-                instructions.push(inst);
+
+            if prefix_newline {
+                instructions.push(Instruction::Insert("\n".into()));
             }
-            crate::parser::Instruction::Replace { src, replacement } => {
-                let width = src.width() as u16;
-                instructions.push(Instruction::FindInCurrentLine {
-                    needle: src,
-                    end_of_word: false,
-                    count: 1,
-                });
-                instructions.push(Instruction::Select(Size::new(width, 1)));
-                instructions.push(Instruction::Delete);
-                let inst = match replacement {
-                    Source::Str(content) => Instruction::LoadTypeBuffer(content),
-                    Source::Ident(key) => {
-                        let content = context.load(key)?;
-                        Instruction::LoadTypeBuffer(content)
-                    }
-                };
-                instructions.push(inst);
+            match keep_selection {
+                true => instructions.push(Instruction::LoadTypeBufferKeepSelection(content)),
+                false => instructions.push(Instruction::LoadTypeBuffer(content)),
             }
-            crate::parser::Instruction::Wait(seconds) => {
-                instructions.push(Instruction::Wait(Duration::from_secs(seconds)))
+        }
+        crate::parser::Instruction::Command(source) => {
+            let cmd = resolve(context, source)?;
+            instructions.push(Instruction::LoadCommandBuffer(cmd));
+            instructions.push(Instruction::ClearCommandWait);
+            instructions.push(Instruction::ClearCommandBuffer);
+        }
+        crate::parser::Instruction::Insert { source, filters } => {
+            let content = match source {
+                Source::Str(content) => context.substitute(&content)?,
+                Source::Ident(key) => context.load(key)?,
+                Source::Env { name, default } => env(name, default)?,
+            };
+            instructions.push(Instruction::Insert(filters::apply(content, filters)?));
+        }
+        crate::parser::Instruction::Replace { src, replacement } => {
+            let width = src.width() as u16;
+            // The select and type only make sense if the find hits.
+            // Typing over the selection replaces it.
+            instructions.push(Instruction::FindInCurrentLine {
+                needle: src,
+                end_of_word: false,
+                reverse: false,
+                count: 1,
+                dependents: Some(2),
+            });
+            instructions.push(Instruction::Select(Size::new(width, 1)));
+            let inst = match replacement {
+                Source::Str(content) => Instruction::LoadTypeBuffer(content),
+                Source::Ident(key) => {
+                    let content = context.load(key)?;
+                    Instruction::LoadTypeBuffer(content)
+                }
+                Source::Env { name, default } => Instruction::LoadTypeBuffer(env(name, default)?),
+            };
+            instructions.push(inst);
+        }
+        crate::parser::Instruction::ReplaceAll { needle, replacement } => {
+            let replacement = match replacement {
+                Source::Str(content) => content,
+                Source::Ident(key) => context.load(key)?,
+                Source::Env { name, default } => env(name, default)?,
+            };
+            instructions.push(Instruction::ReplaceAll { needle, replacement });
+        }
+        crate::parser::Instruction::Wait(duration) => instructions.push(Instruction::Wait(duration)),
+        crate::parser::Instruction::WaitRange(min, max) => instructions.push(Instruction::WaitRange(min, max)),
+        crate::parser::Instruction::WaitVariable { name, unit } => {
+            let duration = unit * number::<u32>(context, &Expr::Variable(name))?;
+            instructions.push(Instruction::Wait(duration))
+        }
+        crate::parser::Instruction::Speed(instructions_per_second) => {
+            let ips = instructions_per_second as f64;
+            let micros = (1_000_000.0 / ips) as u64;
+            instructions.push(Instruction::Speed(Duration::from_micros(micros)))
+        }
+        crate::parser::Instruction::CommandSpeed(chars_per_second) => {
+            let micros = 1_000_000 / chars_per_second;
+            instructions.push(Instruction::CommandSpeed(Some(Duration::from_micros(micros))))
+        }
+        crate::parser::Instruction::LinePause(pause) => instructions.push(Instruction::LinePause(pause)),
+        crate::parser::Instruction::SetTitle(title) => {
+            instructions.push(Instruction::SetTitle(context.substitute(&title)?))
+        }
+        crate::parser::Instruction::SetExtension(ext) => instructions.push(Instruction::SetExtension(ext)),
+        crate::parser::Instruction::ShowLineNumbers(show) => instructions.push(Instruction::ShowLineNumbers(show)),
+        crate::parser::Instruction::Scroll(rows) => instructions.push(Instruction::Scroll(rows)),
+        crate::parser::Instruction::HScroll(policy) => instructions.push(Instruction::HScroll(policy)),
+        crate::parser::Instruction::BreakLigatures(break_sequences) => {
+            instructions.push(Instruction::BreakLigatures(break_sequences))
+        }
+        crate::parser::Instruction::AddLigature(seq) => instructions.push(Instruction::AddLigature(seq)),
+        crate::parser::Instruction::ColorColumn(columns) => instructions.push(Instruction::ColorColumn(columns)),
+        crate::parser::Instruction::ScrollOff { rows, columns } => {
+            instructions.push(Instruction::ScrollOff { rows, columns })
+        }
+        crate::parser::Instruction::Wrap(indicator) => instructions.push(Instruction::Wrap(indicator)),
+        crate::parser::Instruction::Margins {
+            top,
+            right,
+            bottom,
+            left,
+        } => instructions.push(Instruction::Margins(Margins {
+            top,
+            right,
+            bottom,
+            left,
+        })),
+        crate::parser::Instruction::Jitter(jitter) => {
+            instructions.push(Instruction::SetJitter(jitter.as_millis() as u64))
+        }
+        crate::parser::Instruction::JitterProfile(profile) => instructions.push(Instruction::JitterProfile(profile)),
+        crate::parser::Instruction::BurstPause { min, max } => instructions.push(Instruction::BurstPause { min, max }),
+        crate::parser::Instruction::AutoTypo { rate, max_per_line } => {
+            instructions.push(Instruction::AutoTypo { rate, max_per_line })
+        }
+        crate::parser::Instruction::SetTheme(theme) => instructions.push(Instruction::SetTheme(theme)),
+        crate::parser::Instruction::LoadAudio(path) => instructions.push(Instruction::LoadAudio(files.resolve(&path))),
+        crate::parser::Instruction::AudioClasses(classes) => instructions.push(Instruction::AudioClasses(classes)),
+        crate::parser::Instruction::AudioOffset(ms) => instructions.push(Instruction::AudioOffset(ms)),
+        crate::parser::Instruction::KeyOverlay(show) => instructions.push(Instruction::KeyOverlay(show)),
+        crate::parser::Instruction::KeyOverlayHold(hold) => instructions.push(Instruction::KeyOverlayHold(hold)),
+        crate::parser::Instruction::Hyperlinks(show) => instructions.push(Instruction::Hyperlinks(show)),
+        crate::parser::Instruction::Link { text, url } => {
+            instructions.push(Instruction::Link { text: text.clone(), url });
+            instructions.push(Instruction::LoadTypeBuffer(text));
+        }
+        crate::parser::Instruction::Duck {
+            percent,
+            attack,
+            release,
+        } => instructions.push(Instruction::Duck {
+            percent,
+            attack,
+            release,
+        }),
+        crate::parser::Instruction::Clear => instructions.push(Instruction::Clear),
+        crate::parser::Instruction::Scene { name, theme, extension } => {
+            instructions.push(Instruction::Scene(name.clone()));
+            if context.scene_clear() {
+                instructions.push(Instruction::Clear);
             }
-            crate::parser::Instruction::Speed(instructions_per_second) => {
-                let ips = instructions_per_second as f64;
-                let micros = (1000_000.0 / ips) as u64;
-                instructions.push(Instruction::Speed(Duration::from_micros(micros)))
+            instructions.push(Instruction::SetTitle(name));
+            if let Some(theme) = theme {
+                instructions.push(Instruction::SetTheme(theme));
             }
-            crate::parser::Instruction::LinePause(millis) => {
-                instructions.push(Instruction::LinePause(Duration::from_millis(millis)))
+            if let Some(ext) = extension {
+                instructions.push(Instruction::SetExtension(ext));
             }
-            crate::parser::Instruction::SetTitle(title) => instructions.push(Instruction::SetTitle(title)),
-            crate::parser::Instruction::SetExtension(ext) => instructions.push(Instruction::SetExtension(ext)),
-            crate::parser::Instruction::ShowLineNumbers(show) => instructions.push(Instruction::ShowLineNumbers(show)),
-            crate::parser::Instruction::Jitter(jitter) => instructions.push(Instruction::SetJitter(jitter)),
-            crate::parser::Instruction::SetTheme(theme) => instructions.push(Instruction::SetTheme(theme)),
-            crate::parser::Instruction::LoadAudio(path) => instructions.push(Instruction::LoadAudio(path)),
-            crate::parser::Instruction::Clear => instructions.push(Instruction::Clear),
-            crate::parser::Instruction::Popup(Source::Str(msg)) => instructions.push(Instruction::Popup(msg)),
-            crate::parser::Instruction::Popup(Source::Ident(ident)) => {
-                let msg = context.load(ident)?;
-                instructions.push(Instruction::Popup(msg))
+        }
+        crate::parser::Instruction::SceneDefaults { clear } => context.set_scene_clear(clear),
+        crate::parser::Instruction::Transition(kind, duration) => {
+            instructions.push(Instruction::Transition(kind, duration))
+        }
+        crate::parser::Instruction::ResetCounters => instructions.push(Instruction::ResetCounters),
+        crate::parser::Instruction::Popup {
+            source,
+            close_after,
+            position,
+            title,
+        } => {
+            let title = title.map(|title| context.substitute(&title)).transpose()?;
+            instructions.push(Instruction::Popup {
+                message: resolve(context, source)?,
+                close_after,
+                position,
+                title,
+            })
+        }
+        crate::parser::Instruction::PopupAt(position) => instructions.push(Instruction::PopupAt(position)),
+        crate::parser::Instruction::PopupFile(path) => instructions.push(Instruction::popup(files.load(&path)?)),
+        crate::parser::Instruction::PopupScroll(lines) => instructions.push(Instruction::PopupScroll(lines)),
+        crate::parser::Instruction::Loop { count, pause } => instructions.push(Instruction::Loop { count, pause }),
+        crate::parser::Instruction::Exit => instructions.push(Instruction::Exit),
+        crate::parser::Instruction::ExitOnEnd(after) => instructions.push(Instruction::ExitOnEnd(after)),
+        crate::parser::Instruction::PopupAutoscroll(speed) => instructions.push(Instruction::PopupAutoscroll(speed)),
+        crate::parser::Instruction::Menu {
+            items,
+            choice,
+            step,
+            then_type,
+        } => {
+            if choice >= items.len() {
+                return Err(Error::InvalidMenuChoice { choice, len: items.len() });
             }
-            crate::parser::Instruction::ClosePopup => instructions.push(Instruction::ClosePopup),
-            crate::parser::Instruction::WriteBuffer(path) => instructions.push(Instruction::WriteBuffer(path)),
-            crate::parser::Instruction::CommandClearTimeout(timeout) => {
-                instructions.push(Instruction::CommandClearTimeout(Duration::from_millis(timeout)))
+            instructions.push(Instruction::Menu {
+                items,
+                choice,
+                step,
+                then_type,
+            })
+        }
+        crate::parser::Instruction::ClosePopup => instructions.push(Instruction::ClosePopup),
+        crate::parser::Instruction::WriteBuffer(path) => instructions.push(Instruction::WriteBuffer(files.resolve(&path))),
+        crate::parser::Instruction::CommandClearTimeout(timeout) => {
+            instructions.push(Instruction::CommandClearTimeout(timeout))
+        }
+        crate::parser::Instruction::SetVariable(name, variable) => {
+            let variable = context.set_variable(name.clone(), variable);
+            instructions.push(Instruction::SetVariable(name, variable))
+        }
+        crate::parser::Instruction::Let { name, value } => {
+            let variable = Variable::Int(context.eval(&value)?);
+            let variable = context.set_variable(name.clone(), variable);
+            instructions.push(Instruction::SetVariable(name, variable))
+        }
+        crate::parser::Instruction::Every { interval, body } => instructions.push(Instruction::Every {
+            interval,
+            body: compile_block(body, context, files)?,
+        }),
+        crate::parser::Instruction::EveryStop => instructions.push(Instruction::EveryStop),
+        // Compiled here rather than where it's called, so variables have their values as of the `def`
+        crate::parser::Instruction::Def { name, body } => {
+            context.start_definition(name.clone());
+            let body = compile_block(body, context, files)?;
+            context.define(name, body);
+        }
+        crate::parser::Instruction::Call(name) => instructions.extend(context.call(&name)?),
+        crate::parser::Instruction::If {
+            condition,
+            then,
+            otherwise,
+        } => {
+            let branch = match context.bool(&condition)? {
+                true => then,
+                false => otherwise,
+            };
+            instructions.extend(compile_block(branch, context, files)?);
+        }
+        crate::parser::Instruction::Repeat { count, body } => {
+            if count > 0 {
+                let body = compile_block(body, context, files)?;
+                for _ in 0..count {
+                    instructions.extend(body.iter().cloned());
+                }
             }
-            crate::parser::Instruction::SetVariable(name, variable) => {
-                instructions.push(Instruction::SetVariable(name, variable))
+        }
+        crate::parser::Instruction::Budget { limit, line } => instructions.push(Instruction::Budget { line, limit }),
+        crate::parser::Instruction::BudgetEnd => instructions.push(Instruction::BudgetEnd),
+        crate::parser::Instruction::Include { path, line } => {
+            let src = include(files, &path, line)?;
+            let included = crate::parser::parse(&src).map_err(Error::Parse)?;
+            instructions.extend(compile_file(included, files, context)?);
+            files.leave();
+        }
+        crate::parser::Instruction::PresetDefine { name, body } => define_preset(name, body, context, files)?,
+        crate::parser::Instruction::PresetUse(name) => instructions.extend(context.presets().apply(&name)?),
+        crate::parser::Instruction::PresetPop => instructions.extend(context.presets().pop()?),
+        crate::parser::Instruction::PresetInclude(path) => {
+            let src = files.enter(&path)?;
+            let included = crate::parser::parse(&src).map_err(Error::Parse)?;
+            for inst in included {
+                match inst {
+                    crate::parser::Instruction::PresetDefine { name, body } => {
+                        define_preset(name, body, context, files)?
+                    }
+                    _ => return Err(Error::PresetFile(path)),
+                }
             }
-            crate::parser::Instruction::Include(i) => instructions.extend(compile(i)?),
+            files.leave();
         }
     }
 
-    Ok(instructions)
+    // Whatever the settings were before a preset is what `preset pop` restores
+    for inst in &instructions[start..] {
+        context.presets().track(inst);
+    }
+
+    Ok(())
+}
+
+// Only settings, compiled on their own so they don't count as changed until the preset is used
+fn define_preset(
+    name: String,
+    body: crate::parser::Instructions,
+    context: &mut Context,
+    files: &mut Files<'_>,
+) -> Result<()> {
+    let body = body.into_iter().collect::<Vec<_>>();
+    if !body.iter().all(is_setting) {
+        return Err(Error::NotASetting(name));
+    }
+
+    let settings = compile_block(crate::parser::Instructions::new(body), &mut Context::new(), files)?;
+    context.presets().define(name, settings);
+    Ok(())
+}
+
+// The value of the expression, for an instruction that takes a smaller or unsigned number
+fn number<T: TryFrom<i64>>(context: &Context, expr: &Expr) -> Result<T> {
+    let value = context.eval(expr)?;
+    T::try_from(value).map_err(|_| Error::OutOfRange { expr: expr.to_string(), value })
+}
+
+// The lines of the content, with their line breaks
+fn slice_lines(content: String, lines: Lines, path: std::path::PathBuf) -> Result<String> {
+    let len = content.lines().count();
+    let end = lines.end.unwrap_or(len);
+    if lines.start > len || end > len {
+        return Err(Error::LoadLines { path, lines, len });
+    }
+
+    Ok(content.split_inclusive('\n').skip(lines.start - 1).take(end + 1 - lines.start).collect())
+}
+
+// The text of a source, with the variables of a string substituted
+fn resolve(context: &Context, source: Source) -> Result<String> {
+    match source {
+        Source::Str(content) => context.substitute(&content),
+        Source::Ident(key) => context.load(key),
+        Source::Env { name, default } => env(name, default),
+    }
+}
+
+// The value of an environment variable, or the default if it's unset
+fn env(name: String, default: Option<String>) -> Result<String> {
+    match std::env::var(&name) {
+        Ok(value) => Ok(value),
+        Err(VarError::NotUnicode(_)) => Err(Error::EnvNotUtf8(name)),
+        Err(VarError::NotPresent) => default.ok_or(Error::UnsetEnv(name)),
+    }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::ui::estimate::FRAME_TIME;
+    use crate::ui::loader::MemoryLoader;
+    use crate::ui::markers;
+
+    fn compile(src: &str) -> Vec<Instruction> {
+        super::compile_all(crate::parser::parse(src).unwrap(), None).unwrap()
+    }
+
+    fn compile_files(loader: &MemoryLoader, base_dir: &str, src: &str) -> Result<Vec<Instruction>> {
+        compile_with_loader(crate::parser::parse(src).unwrap(), loader, Path::new(base_dir))
+    }
+
+    #[test]
+    fn compiled_as_taken() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("demo/inc.echo", "insert \"b\"\nload \"missing.rs\" as m");
+        let parsed = crate::parser::parse("insert \"a\"\ninclude \"inc.echo\"\ninsert \"c\"").unwrap();
+        let mut compiled = CompiledInstructions::new(parsed, Files::new(&loader, Path::new("demo")), HashMap::new());
+
+        assert!(matches!(compiled.next(), Some(Ok(Instruction::Insert(s))) if s == "a"));
+        assert!(matches!(compiled.next(), Some(Ok(Instruction::Insert(s))) if s == "b"));
+        assert!(matches!(compiled.next(), Some(Err(Error::Import(_)))));
+        assert!(compiled.next().is_none());
+    }
+
+    #[test]
+    fn menu_choice_out_of_range() {
+        let parsed = crate::parser::parse("menu \"a\" \"b\" choose 2").unwrap();
+        let err = super::compile_all(parsed, None).unwrap_err();
+        assert!(matches!(err, Error::InvalidMenuChoice { choice: 2, len: 2 }));
+    }
+
+    #[test]
+    fn paths_relative_to_the_script() {
+        let dir = std::env::temp_dir().join(format!("mimic-script-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("demos")).unwrap();
+        std::fs::write(dir.join("demos/code.rs"), "fn main() {}").unwrap();
+
+        let src = format!(
+            "load \"code.rs\" as a\ninsert a\nload \"{}\" as b\naudio \"sounds\"\nwrite \"out.txt\"\nwrite \"/tmp/out.txt\"",
+            dir.join("demos/code.rs").display()
+        );
+        let script = dir.join("demos/intro.echo");
+        let output = super::compile_all(crate::parser::parse(&src).unwrap(), Some(&script));
+        let relative = super::compile_all(crate::parser::parse(&src).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let output = output.unwrap();
+        assert!(matches!(&output[0], Instruction::Insert(s) if s == "fn main() {}"));
+        assert!(matches!(&output[1], Instruction::LoadAudio(path) if *path == dir.join("demos/sounds")));
+        assert!(matches!(&output[2], Instruction::WriteBuffer(path) if *path == dir.join("demos/out.txt")));
+        // Absolute paths stay as they are
+        assert!(matches!(&output[3], Instruction::WriteBuffer(path) if path == Path::new("/tmp/out.txt")));
+
+        // Without the script it's the working directory, where there is no code.rs
+        assert!(matches!(relative, Err(Error::Import(path)) if path == Path::new("code.rs")));
+    }
+
+    #[test]
+    fn optimize_is_opt_in() {
+        let src = "speed 10\nspeed 20\nwait 1s\nwait 1s\nwait 0s\ninsert \"a\"";
+        let plain = compile_with_options(crate::parser::parse(src).unwrap(), &CompileOptions::default()).unwrap();
+        assert_eq!(plain.len(), 6);
+
+        let options = CompileOptions {
+            optimize: true,
+            ..Default::default()
+        };
+        let optimized = compile_with_options(crate::parser::parse(src).unwrap(), &options).unwrap();
+        assert!(matches!(&optimized[..], [
+            Instruction::Speed(speed),
+            Instruction::Wait(wait),
+            Instruction::Insert(_),
+        ] if *speed == Duration::from_millis(50) && *wait == Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn variables_win_over_the_script() {
+        let options = CompileOptions {
+            variables: HashMap::from([
+                ("name".to_string(), Variable::Str("cli".into())),
+                ("n".to_string(), Variable::Int(5)),
+                ("extra".to_string(), Variable::Bool(true)),
+            ]),
+            ..Default::default()
+        };
+        let src = "set name \"script\"\nlet n = 1\nlet m = n + 1\ninsert \"${name} ${n} ${m} ${extra}\"";
+        let output = compile_with_options(crate::parser::parse(src).unwrap(), &options).unwrap();
+        assert!(matches!(&output[..3], [
+            Instruction::SetVariable(extra, Variable::Bool(true)),
+            Instruction::SetVariable(n, Variable::Int(5)),
+            Instruction::SetVariable(name, Variable::Str(_)),
+        ] if extra == "extra" && n == "n" && name == "name"));
+        assert!(matches!(&output[4], Instruction::SetVariable(_, Variable::Int(5))));
+        assert!(matches!(output.last(), Some(Instruction::Insert(s)) if s == "cli 5 6 true"));
+    }
+
+    #[test]
+    fn load_through_loader() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.rs", "fn main() {}");
+        let output = compile_files(&loader, "", "load \"a.rs\" as a\ninsert a").unwrap();
+        assert!(matches!(&output[..], [Instruction::Insert(s)] if s == "fn main() {}"));
+    }
+
+    #[test]
+    fn load_lines() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.rs", "1\n2\n// @m\n4\n5");
+        let output = compile_files(&loader, "", "load \"a.rs\":2..4 as a\ninsert a").unwrap();
+        let [Instruction::Insert(content)] = &output[..] else { panic!("{output:?}") };
+        assert_eq!(content, "2\n// @m\n4\n");
+
+        let (content, markers) = markers::generate(content.clone());
+        assert_eq!(content, "2\n4\n");
+        assert!(markers.is_some_and(|markers| markers.get("m").is_some()));
+
+        let output = compile_files(&loader, "", "load \"a.rs\":4.. as a\ninsert a").unwrap();
+        assert!(matches!(&output[..], [Instruction::Insert(s)] if s == "4\n5"));
+
+        let err = compile_files(&loader, "", "load \"a.rs\":4..6 as a").unwrap_err();
+        assert_eq!(err.to_string(), "can't load lines 4..6 of \"a.rs\", it has 5 lines");
+        let err = compile_files(&loader, "", "load \"a.rs\":6.. as a").unwrap_err();
+        assert!(matches!(err, Error::LoadLines { len: 5, .. }));
+    }
+
+    #[test]
+    fn exec_output() {
+        let output = compile("set name \"mimic\"\nexec \"echo ${name}\" as out\ninsert out");
+        assert!(matches!(&output[1], Instruction::Insert(s) if s == "mimic\n"));
+
+        let err = super::compile_all(crate::parser::parse("exec \"exit 2\" as out").unwrap(), None).unwrap_err();
+        assert!(matches!(err, Error::Exec(_, _)));
+    }
+
+    #[test]
+    fn env_sources() {
+        // Cargo sets it for the tests
+        let output = compile("type env(\"CARGO_PKG_NAME\")\ninsert env_or(\"MIMIC_TEST_ENV_UNSET\", \"demo\")");
+        assert!(matches!(&output[0], Instruction::LoadTypeBuffer(s) if s == "mimic"));
+        assert!(matches!(&output[1], Instruction::Insert(s) if s == "demo"));
+
+        let src = "command env(\"MIMIC_TEST_ENV_UNSET\")";
+        let err = super::compile_all(crate::parser::parse(src).unwrap(), None).unwrap_err();
+        assert_eq!(err.to_string(), "environment variable `MIMIC_TEST_ENV_UNSET` is not set");
+    }
+
+    #[test]
+    fn let_variables() {
+        let src = "set width 3\nlet total = width + 4\nwait total\nwait total ms\nselect width 1\ninsert total";
+        let output = compile(src);
+        assert!(matches!(&output[1], Instruction::SetVariable(name, Variable::Int(7)) if name == "total"));
+        assert!(matches!(output[2], Instruction::Wait(d) if d == Duration::from_secs(7)));
+        assert!(matches!(output[3], Instruction::Wait(d) if d == Duration::from_millis(7)));
+        assert!(matches!(output[4], Instruction::Select(size) if size == Size::new(3, 1)));
+        assert!(matches!(&output[5], Instruction::Insert(s) if s == "7"));
+
+        let compile_err = |src| super::compile_all(crate::parser::parse(src).unwrap(), None).unwrap_err();
+        let err = compile_err("let total = width + 4");
+        assert!(matches!(err, Error::UnknownVariable(name) if name == "width"));
+        let err = compile_err("set width 0\nlet total = 4 / width");
+        assert_eq!(err.to_string(), "`4 / width` divides by zero");
+        let err = compile_err("let width = 0 - 2\nselect width 1");
+        assert_eq!(err.to_string(), "`width` is -2, which is out of range here");
+    }
+
+    #[test]
+    fn missing_file() {
+        let loader = MemoryLoader::new();
+        let err = compile_files(&loader, "", "load \"a.rs\" as a").unwrap_err();
+        assert!(matches!(err, Error::Import(path) if path == Path::new("a.rs")));
+
+        let err = compile_files(&loader, "", "insert \"a\"\ninclude \"b.echo\"").unwrap_err();
+        assert!(matches!(&err, Error::Include { path, line: 2 } if path == Path::new("b.echo")));
+        assert_eq!(err.to_string(), "failed to include \"b.echo\" on line 2");
+    }
+
+    #[test]
+    fn paths_relative_to_base_dir() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("demo/a.rs", "a");
+        loader.insert("/abs/b.rs", "b");
+        loader.insert("demo/inc.echo", "load \"a.rs\" as a\ninsert a");
+
+        let output = compile_files(&loader, "demo", "load \"a.rs\" as a\ninsert a").unwrap();
+        assert!(matches!(&output[..], [Instruction::Insert(s)] if s == "a"));
+
+        let output = compile_files(&loader, "demo", "load \"/abs/b.rs\" as b\ninsert b").unwrap();
+        assert!(matches!(&output[..], [Instruction::Insert(s)] if s == "b"));
+
+        let output = compile_files(&loader, "demo", "include \"inc.echo\"").unwrap();
+        assert!(matches!(&output[..], [Instruction::Insert(s)] if s == "a"));
+    }
+
+    #[test]
+    fn filters_on_loaded_content() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("list.txt", "  grüße\n\n  日本語\n");
+        let src = "load \"list.txt\" as list
+type list | dedent | prefix \"- \" | upper
+type list | trim | indent 2 | suffix \";\" nonl
+insert list | lower | indent 1";
+        let output = compile_files(&loader, "", src).unwrap();
+        let payloads = output
+            .iter()
+            .map(|inst| match inst {
+                Instruction::LoadTypeBuffer(content) | Instruction::Insert(content) => content.as_str(),
+                inst => panic!("unexpected {inst:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        // Left to right: the prefix on the empty line is upper cased too
+        let expected = ["- GRÜSSE\n- \n- 日本語\n", "  grüße;\n;\n    日本語;", "   grüße\n\n   日本語\n"];
+        assert_eq!(payloads, expected);
+    }
+
+    #[test]
+    fn filter_errors() {
+        let parsed = crate::parser::parse("wait 1\ntype \"a\" | indent 2 | shout").unwrap();
+        let err = super::compile_all(parsed, None).unwrap_err();
+        assert_eq!(err.to_string(), "unknown filter `shout` on line 2");
+
+        let parsed = crate::parser::parse("insert \"a\" | prefix 2").unwrap();
+        let err = super::compile_all(parsed, None).unwrap_err();
+        assert_eq!(err.to_string(), "filter `prefix` on line 1 takes a string");
+    }
+
+    fn inserts(instructions: &[Instruction]) -> Vec<&str> {
+        instructions
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Insert(content) => Some(content.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeat_keeps_the_order() {
+        let output = compile("insert \"a\"\nrepeat 2 {\n    insert \"b\"\n    insert \"c\"\n}\ninsert \"d\"");
+        assert_eq!(inserts(&output), ["a", "b", "c", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn nested_repeat() {
+        let src = "repeat 2 {\n    insert \"x\"\n    repeat 3 { insert \"y\" }\n    insert \"z\"\n}";
+        let output = compile(src);
+        assert_eq!(inserts(&output), ["x", "y", "y", "y", "z", "x", "y", "y", "y", "z"]);
+    }
+
+    #[test]
+    fn repeat_zero_times() {
+        assert!(compile("repeat 0 {\n    insert \"a\"\n}").is_empty());
+        assert_eq!(inserts(&compile("repeat 3 { repeat 0 { insert \"a\" } }\ninsert \"b\"")), ["b"]);
+    }
+
+    #[test]
+    fn heredocs() {
+        let output = compile("type <<EOF\n    a\n\tb \\n\nEOF\ntype <<EOF nonl\nc\nEOF\ninsert <<END\nEND");
+        let payloads = output
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::LoadTypeBuffer(content) | Instruction::Insert(content) => Some(content.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(payloads, ["    a\n\tb \\n\n", "c", ""]);
+
+        // Variables aren't substituted either
+        let output = compile("set HOME \"/home\"\ninsert <<EOF\necho ${HOME} ${PATH} \\${x} \\\\\nEOF");
+        assert_eq!(inserts(&output), ["echo ${HOME} ${PATH} \\${x} \\\\\n"]);
+    }
+
+    #[test]
+    fn substitute_variables() {
+        let src = "set name \"mimic\"\nset n 2\ntype \"hi ${name}\" nonl\ninsert \"${n}\\${n}\"\ncommand \":e ${name}\"\npopup \"${name}!\"\ntitle \"${name} ${n}\"";
+        let output = compile(src);
+        let strings = output
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::LoadTypeBuffer(s)
+                | Instruction::Insert(s)
+                | Instruction::LoadCommandBuffer(s)
+                | Instruction::Popup { message: s, .. }
+                | Instruction::SetTitle(s) => Some(s.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(strings, ["hi mimic", "2${n}", ":e mimic", "mimic!", "mimic 2"]);
+
+        // An escaped `$` is a `$`, and an escaped `\` doesn't escape the `${` after it
+        let src = "set name \"mimic\"\ninsert \"cost \\$5\"\ninsert \"\\\\${name}\"\ninsert \"\\\\\\${name}\"";
+        assert_eq!(inserts(&compile(src)), ["cost $5", "\\mimic", "\\${name}"]);
+
+        let parsed = crate::parser::parse("type \"${name}\"\nset name \"late\"").unwrap();
+        let err = super::compile_all(parsed, None).unwrap_err();
+        assert!(matches!(err, Error::UnknownVariable(name) if name == "name"));
+    }
+
+    #[test]
+    fn if_else() {
+        let src = "set long true
+set demo false
+if long {
+    insert \"a\"
+    if demo { insert \"b\" } else { insert \"c\" }
+} else {
+    insert \"d\"
+}
+if demo { insert \"e\" }
+insert \"f\"";
+        assert_eq!(inserts(&compile(src)), ["a", "c", "f"]);
+    }
+
+    #[test]
+    fn if_errors() {
+        let err = super::compile_all(crate::parser::parse("if long {}").unwrap(), None).unwrap_err();
+        assert!(matches!(err, Error::UnknownVariable(name) if name == "long"));
+
+        let err = super::compile_all(crate::parser::parse("set long \"yes\"\nif long {}").unwrap(), None).unwrap_err();
+        assert!(matches!(err, Error::NotABool(name) if name == "long"));
+    }
+
+    #[test]
+    fn macros() {
+        let src = "def save {
+    insert \"w\"
+    insert \"q\"
+}
+insert \"a\"
+call save
+def save { insert \"x\" }
+def twice {
+    call save
+    call save
+}
+call twice";
+        assert_eq!(inserts(&compile(src)), ["a", "w", "q", "x", "x"]);
+
+        // Variables are as they were at the `def`
+        let src = "set n 1\ndef show { insert \"${n}\" }\nset n 2\ncall show\ninsert \"${n}\"";
+        assert_eq!(inserts(&compile(src)), ["1", "2"]);
+    }
+
+    #[test]
+    fn macro_errors() {
+        let err = super::compile_all(crate::parser::parse("call save\ndef save {}").unwrap(), None).unwrap_err();
+        assert!(matches!(&err, Error::UnknownMacro(name) if name == "save"));
+        assert_eq!(err.to_string(), "`call save` before `def save`");
+
+        let err = super::compile_all(crate::parser::parse("def a {\n    call a\n}").unwrap(), None).unwrap_err();
+        assert!(matches!(err, Error::RecursiveMacro(name) if name == "a"));
+
+        let err = super::compile_all(crate::parser::parse("def a {\n    def b { call a }\n}").unwrap(), None).unwrap_err();
+        assert!(matches!(err, Error::RecursiveMacro(name) if name == "a"));
+    }
+
+    #[test]
+    fn include_has_own_context() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.rs", "a");
+        loader.insert("inc.echo", "insert a");
+        let err = compile_files(&loader, "", "load \"a.rs\" as a\ninclude \"inc.echo\"").unwrap_err();
+        assert!(matches!(err, Error::LoadValue(key) if key == "a"));
+    }
+
+    #[test]
+    fn include_relative_to_the_including_file() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("demo/intro/intro.echo", "load \"name.txt\" as name\ninsert name\ninclude \"parts/end.echo\"");
+        loader.insert("demo/intro/name.txt", "name");
+        loader.insert("demo/intro/parts/end.echo", "insert \"end\"");
+        let output = compile_files(&loader, "demo", "include \"intro/intro.echo\"").unwrap();
+        assert_eq!(inserts(&output), ["name", "end"]);
+    }
+
+    #[test]
+    fn variables_set_before_an_include() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("inc.echo", "insert \"a\"");
+        let output = compile_files(&loader, "", "set name \"x\"\ninclude \"inc.echo\"").unwrap();
+        assert!(matches!(&output[..], [Instruction::SetVariable(name, _), Instruction::Insert(_)] if name == "name"));
+
+        // They can be used in it, in a block as well, and the overrides still win
+        loader.insert("name.echo", "insert \"${name} ${who}\"");
+        let src = "set name \"x\"\nset who \"me\"\ninclude \"name.echo\"\nrepeat 1 {\n    include \"name.echo\"\n}";
+        let parsed = crate::parser::parse(src).unwrap();
+        let files = Files::new(&loader, Path::new(""));
+        let overrides = HashMap::from([("who".to_string(), Variable::Str("you".into()))]);
+        let output = CompiledInstructions::new(parsed, files, overrides).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(inserts(&output), ["x you", "x you"]);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.echo", "insert \"a\"\ninclude \"b.echo\"");
+        loader.insert("b.echo", "include \"a.echo\"");
+        let err = compile_files(&loader, "", "include \"a.echo\"").unwrap_err();
+        let expected = ["a.echo", "b.echo", "a.echo"].map(PathBuf::from);
+        assert!(matches!(err, Error::IncludeCycle(cycle) if cycle == expected));
+
+        // Including the same file twice is fine
+        loader.insert("c.echo", "insert \"c\"");
+        let output = compile_files(&loader, "", "include \"c.echo\"\ninclude \"c.echo\"").unwrap();
+        assert_eq!(output.len(), 2);
+    }
+
+    #[test]
+    fn scene_preamble() {
+        let output = compile("scene \"main\" theme \"togglebit\" ext \"rs\"");
+        assert!(matches!(
+            &output[..],
+            [
+                Instruction::Scene(name),
+                Instruction::Clear,
+                Instruction::SetTitle(title),
+                Instruction::SetTheme(theme),
+                Instruction::SetExtension(ext),
+            ] if name == "main" && title == "main" && theme == "togglebit" && ext == "rs"
+        ));
+    }
+
+    #[test]
+    fn scene_keep_buffer() {
+        let output = compile("scene_defaults keep_buffer\nscene \"a\"\nscene_defaults clear\nscene \"b\"");
+        assert!(matches!(
+            &output[..],
+            [
+                Instruction::Scene(_),
+                Instruction::SetTitle(_),
+                Instruction::Scene(_),
+                Instruction::Clear,
+                Instruction::SetTitle(_),
+            ]
+        ));
+    }
+
+    fn themes(output: &[Instruction]) -> Vec<&str> {
+        output
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::SetTheme(theme) => Some(theme.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn preset_use_and_pop() {
+        let src = "
+preset define a
+    theme \"a\"
+end
+preset define b
+    theme \"b\"
+    speed 10
+end
+theme \"base\"
+preset use a
+preset use b
+preset pop
+preset pop
+";
+        let output = compile(src);
+        assert_eq!(themes(&output), ["base", "a", "b", "a", "base"]);
+        // The speed was never set, so popping b puts it back to the default
+        let speeds = output.iter().filter_map(|inst| match inst {
+            Instruction::Speed(frame_time) => Some(*frame_time),
+            _ => None,
+        });
+        assert_eq!(speeds.collect::<Vec<_>>(), [Duration::from_millis(100), FRAME_TIME]);
+    }
+
+    #[test]
+    fn preset_errors() {
+        let loader = MemoryLoader::new();
+        let err = compile_files(&loader, "", "preset define a\n    type \"x\"\nend").unwrap_err();
+        assert!(matches!(err, Error::NotASetting(name) if name == "a"));
+
+        let err = compile_files(&loader, "", "preset use a").unwrap_err();
+        assert!(matches!(err, Error::UnknownPreset(name) if name == "a"));
+
+        let err = compile_files(&loader, "", "preset pop").unwrap_err();
+        assert!(matches!(err, Error::UnmatchedPresetPop));
+    }
+
+    #[test]
+    fn preset_include() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("common.echo", "preset define a\n    theme \"a\"\nend");
+        loader.insert("bad.echo", "preset define a\n    theme \"a\"\nend\ntheme \"b\"");
+
+        let output = compile_files(&loader, "", "preset include \"common.echo\"\npreset use a\npreset pop").unwrap();
+        assert_eq!(themes(&output), ["a", "togglebit"]);
+
+        let err = compile_files(&loader, "", "preset include \"bad.echo\"").unwrap_err();
+        assert!(matches!(err, Error::PresetFile(path) if path == Path::new("bad.echo")));
+    }
+}
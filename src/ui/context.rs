@@ -1,21 +1,241 @@
 use std::collections::HashMap;
 
 use super::error::{Error, Result};
+use super::instructions::Instruction;
+use super::presets::Presets;
+use crate::parser::{Expr, Op, Variable};
 
 pub struct Context {
     data: HashMap<String, String>,
+    // Values given to `set`, for `${name}` in strings and `if`
+    variables: HashMap<String, Variable>,
+    // Values given from outside the script, which `set` and `let` can't change
+    overrides: HashMap<String, Variable>,
+    macros: HashMap<String, Vec<Instruction>>,
+    // Macros being defined, innermost last
+    defining: Vec<String>,
+    scene_clear: bool,
+    presets: Presets,
 }
 impl Context {
     pub(crate) fn new() -> Self {
-        Self { data: HashMap::new() }
+        Self::with_overrides(HashMap::new())
+    }
+
+    /// A context with variables that win over the ones set in the script
+    pub(crate) fn with_overrides(overrides: HashMap<String, Variable>) -> Self {
+        Self {
+            data: HashMap::new(),
+            variables: overrides.clone(),
+            overrides,
+            macros: HashMap::new(),
+            defining: vec![],
+            scene_clear: true,
+            presets: Presets::default(),
+        }
+    }
+
+    /// The context of an included file, which sees the variables set so far but not what was loaded
+    pub(crate) fn include(&self) -> Self {
+        Self {
+            variables: self.variables.clone(),
+            ..Self::with_overrides(self.overrides.clone())
+        }
+    }
+
+    pub fn presets(&mut self) -> &mut Presets {
+        &mut self.presets
+    }
+
+    /// Whether a new scene clears the buffer
+    pub fn scene_clear(&self) -> bool {
+        self.scene_clear
+    }
+
+    pub fn set_scene_clear(&mut self, clear: bool) {
+        self.scene_clear = clear;
     }
 
     pub fn set(&mut self, key: String, value: String) {
         self.data.insert(key, value);
     }
 
+    /// The content loaded `as key`, or the value of the variable as a string
     pub fn load(&self, key: impl AsRef<str>) -> Result<String> {
         let key = key.as_ref();
-        self.data.get(key).cloned().ok_or_else(|| Error::LoadValue(key.into()))
+        match self.data.get(key) {
+            Some(content) => Ok(content.clone()),
+            None => self.variables.get(key).map(Variable::to_string).ok_or_else(|| Error::LoadValue(key.into())),
+        }
+    }
+
+    /// Set a variable and return the value it has now, which is the override if there is one
+    pub fn set_variable(&mut self, name: String, value: Variable) -> Variable {
+        let value = self.overrides.get(&name).cloned().unwrap_or(value);
+        self.variables.insert(name, value.clone());
+        value
+    }
+
+    /// The value given to `set name`, which has to be `true` or `false`
+    pub fn bool(&self, name: &str) -> Result<bool> {
+        match self.variables.get(name) {
+            Some(Variable::Bool(b)) => Ok(*b),
+            Some(_) => Err(Error::NotABool(name.into())),
+            None => Err(Error::UnknownVariable(name.into())),
+        }
+    }
+
+    /// The value given to `set name` or `let name`, which has to be an integer
+    pub fn int(&self, name: &str) -> Result<i64> {
+        match self.variables.get(name) {
+            Some(Variable::Int(i)) => Ok(*i),
+            Some(_) => Err(Error::NotAnInt(name.into())),
+            None => Err(Error::UnknownVariable(name.into())),
+        }
+    }
+
+    /// Work out the expression with the int variables set so far
+    pub fn eval(&self, expr: &Expr) -> Result<i64> {
+        let (lhs, op, rhs) = match expr {
+            Expr::Int(i) => return Ok(*i),
+            Expr::Variable(name) => return self.int(name),
+            Expr::Binary(lhs, op, rhs) => (self.eval(lhs)?, op, self.eval(rhs)?),
+        };
+
+        let value = match op {
+            Op::Add => lhs.checked_add(rhs),
+            Op::Sub => lhs.checked_sub(rhs),
+            Op::Mul => lhs.checked_mul(rhs),
+            Op::Div if rhs == 0 => return Err(Error::DivisionByZero(expr.to_string())),
+            Op::Div => lhs.checked_div(rhs),
+        };
+        value.ok_or_else(|| Error::Overflow(expr.to_string()))
+    }
+
+    /// Calls to the macro are rejected until [`Context::define`]
+    pub fn start_definition(&mut self, name: String) {
+        self.defining.push(name);
+    }
+
+    /// Defining a macro again replaces it
+    pub fn define(&mut self, name: String, body: Vec<Instruction>) {
+        self.defining.retain(|defining| *defining != name);
+        self.macros.insert(name, body);
+    }
+
+    pub fn call(&self, name: &str) -> Result<Vec<Instruction>> {
+        if self.defining.iter().any(|defining| defining == name) {
+            return Err(Error::RecursiveMacro(name.into()));
+        }
+        self.macros.get(name).cloned().ok_or_else(|| Error::UnknownMacro(name.into()))
+    }
+
+    /// Replace every `${name}` with the value given to `set name`, or loaded `as name`.
+    /// `\$` is a `$` that doesn't start one, and `\\` is a `\`, like the lexer leaves them.
+    pub fn substitute(&self, s: &str) -> Result<String> {
+        let mut output = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(start) = rest.find(['\\', '$']) {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            if let Some(escaped @ ('\\' | '$')) = rest.strip_prefix('\\').and_then(|rest| rest.chars().next()) {
+                output.push(escaped);
+                rest = &rest[2..];
+                continue;
+            }
+
+            if let Some(after) = rest.strip_prefix("${")
+                && let Some(len) = after.find('}')
+            {
+                let name = &after[..len];
+                let value = match self.variables.get(name) {
+                    Some(variable) => variable.to_string(),
+                    None => self.load(name).map_err(|_| Error::UnknownVariable(name.into()))?,
+                };
+                output.push_str(&value);
+                rest = &after[len + 1..];
+                continue;
+            }
+
+            // A `\` or a `$` on its own
+            output.push_str(&rest[..1]);
+            rest = &rest[1..];
+        }
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context() -> Context {
+        let mut context = Context::new();
+        context.set_variable("name".into(), Variable::Str("world".into()));
+        context.set_variable("n".into(), Variable::Int(3));
+        context.set_variable("long".into(), Variable::Bool(true));
+        context.set("code".into(), "fn main() {}".into());
+        context
+    }
+
+    #[test]
+    fn substitute() {
+        let context = context();
+        assert_eq!(context.substitute("hello ${name}!").unwrap(), "hello world!");
+        assert_eq!(context.substitute("${n}${n} ${code}").unwrap(), "33 fn main() {}");
+        assert_eq!(context.substitute("nothing to see").unwrap(), "nothing to see");
+    }
+
+    #[test]
+    fn escaped_and_unclosed() {
+        let context = context();
+        assert_eq!(context.substitute(r"\${name} is ${name}").unwrap(), "${name} is world");
+        assert_eq!(context.substitute(r"\\${name} \$5 \n").unwrap(), r"\world $5 \n");
+        assert_eq!(context.substitute("${name").unwrap(), "${name");
+    }
+
+    #[test]
+    fn bools() {
+        let context = context();
+        assert!(context.bool("long").unwrap());
+        assert!(matches!(context.bool("n"), Err(Error::NotABool(name)) if name == "n"));
+        assert!(matches!(context.bool("code"), Err(Error::UnknownVariable(name)) if name == "code"));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let context = context();
+        let eval = |src: &str| {
+            let parsed = crate::parser::parse(&format!("let x = {src}")).unwrap().take_instructions();
+            let [crate::parser::Instruction::Let { value, .. }] = &parsed[..] else { panic!("{parsed:?}") };
+            context.eval(value)
+        };
+
+        assert_eq!(eval("n + 4").unwrap(), 7);
+        assert_eq!(eval("2 + n * 4 - 10 / n").unwrap(), 11);
+        assert_eq!(eval("n -4").unwrap(), -1);
+
+        let err = eval("10 / n - 3 / 0").unwrap_err();
+        assert_eq!(err.to_string(), "`3 / 0` divides by zero");
+        assert!(matches!(eval("n * nope"), Err(Error::UnknownVariable(name)) if name == "nope"));
+        assert!(matches!(eval("name + 1"), Err(Error::NotAnInt(name)) if name == "name"));
+        assert!(matches!(eval("9223372036854775807 + n"), Err(Error::Overflow(_))));
+    }
+
+    #[test]
+    fn variables_as_content() {
+        let context = context();
+        assert_eq!(context.load("n").unwrap(), "3");
+        assert_eq!(context.load("code").unwrap(), "fn main() {}");
+        assert!(matches!(context.load("nope"), Err(Error::LoadValue(_))));
+    }
+
+    #[test]
+    fn unknown_variable() {
+        let err = context().substitute("${nope}").unwrap_err();
+        assert!(matches!(&err, Error::UnknownVariable(name) if name == "nope"));
+        assert_eq!(err.to_string(), "`${nope}` is not set or loaded, it can also be given with `--var nope=<value>`");
     }
 }
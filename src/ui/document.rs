@@ -3,29 +3,51 @@ use std::ops::Range;
 use anathema::geometry::{Pos, Region};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use super::markers::{Marker, Markers};
+use super::anchor::{Anchored, Shift};
+use super::markers::{Collision, Marker, Markers};
+use super::virtual_text::VirtualText;
 
 #[derive(Debug)]
 pub(crate) struct Document {
     pub markers: Markers,
+    pub virtual_text: VirtualText,
     text: String,
+    // Shifts not yet taken by anchors outside of the document
+    shifts: Vec<Shift>,
 }
 
 impl Document {
     pub fn new(text: impl Into<String>) -> Self {
         let (text, markers) = super::markers::generate(text);
         let markers = markers.unwrap_or_else(Markers::new);
-        Self { text, markers }
+        Self {
+            text,
+            markers,
+            virtual_text: VirtualText::new(),
+            shifts: vec![],
+        }
     }
 
-    pub fn add_markers(&mut self, row: usize, markers: Markers) {
-        self.markers.merge(row, markers);
+    pub fn add_markers(&mut self, row: usize, markers: Markers) -> Vec<Collision> {
+        self.markers.merge(row, markers)
     }
 
     pub fn text(&self) -> &str {
         &self.text
     }
 
+    // Move everything anchored to rows along with them
+    fn shift(&mut self, shift: Shift) {
+        self.markers.shift(shift);
+        self.virtual_text.shift(shift);
+        self.shifts.push(shift);
+    }
+
+    /// The row changes since the last time, for anchors kept outside of the document
+    pub fn take_shifts(&mut self) -> Vec<Shift> {
+        std::mem::take(&mut self.shifts)
+    }
+
     pub fn lookup_marker(&self, key: &str) -> Option<&Marker> {
         self.markers.get(key)
     }
@@ -58,64 +80,472 @@ impl Document {
         // If the string contains a newline character then offset all the markers by one
         let newlines = s.chars().filter(|c| *c == '\n').count();
         if newlines > 0 {
-            self.markers.offset_after(pos.y as usize, newlines);
+            self.shift(Shift::Down {
+                from: pos.y as usize,
+                count: newlines,
+            });
         }
     }
 
-    // Get the byte position in the string
-    pub(crate) fn get_byte_offset(&self, pos: Pos, mut width: usize) -> Range<usize> {
-        let start = self.byte_offset(pos);
-        let line = &self.text[start..];
-
-        let mut end = start;
-        for (i, c) in line.char_indices() {
-            if c == '\n' {
-                end = start + i;
-                break;
+    /// The bytes of the characters covering any of the `width` columns starting at `pos`.
+    /// A wide character with only one of its cells in the columns is included in its entirety,
+    /// as are the zero width characters after an included character.
+    pub(crate) fn get_byte_offset(&self, pos: Pos, width: usize) -> Range<usize> {
+        let line = self.line_range(pos.y as usize);
+        let from = pos.x.max(0) as usize;
+        let to = from + width;
+
+        let mut range = None::<Range<usize>>;
+        let mut included = false;
+        let mut col = 0;
+        for (i, c) in self.text[line.clone()].char_indices() {
+            let start = line.start + i;
+            let c_width = c.width().unwrap_or(0);
+            if c_width > 0 {
+                included = col < to && col + c_width > from;
             }
-
-            width = width.saturating_sub(c.width().unwrap_or(0));
-
-            if width == 0 {
-                end = start + i;
-                break;
+            col += c_width;
+
+            match &mut range {
+                Some(range) if included => range.end = start + c.len_utf8(),
+                Some(_) => break,
+                None if included => range = Some(start..start + c.len_utf8()),
+                None if col > from => range = Some(start..start),
+                None => {}
             }
         }
 
-        start..end
+        range.unwrap_or(line.end..line.end)
     }
 
+    /// Remove every character covering a cell of the region, see [`Document::get_byte_offset`]
     pub(crate) fn delete(&mut self, region: Region) {
         for y in region.from.y..region.to.y {
             let pos = Pos::new(region.from.x, y);
-            let width = 1 + region.to.x - region.from.x;
-            _ = self.text.drain(self.get_byte_offset(pos, width as usize));
+            let width = region.to.x - region.from.x;
+            _ = self.text.drain(self.get_byte_offset(pos, width.max(0) as usize));
         }
     }
 
-    pub(crate) fn find(&self, cursor: Pos, needle: &str, mut count: usize) -> Option<usize> {
-        let line_offset = self.byte_offset(Pos::new(0, cursor.y));
-        let text = &self.text[line_offset..];
+    /// The characters covering the cells of the region, a line for every row of it
+    pub(crate) fn copy(&self, region: Region) -> String {
+        let width = (region.to.x - region.from.x).max(0) as usize;
+        let rows = region.from.y..region.to.y;
+        let rows = rows.map(|y| &self.text[self.get_byte_offset(Pos::new(region.from.x, y), width)]);
+        rows.collect::<Vec<_>>().join("\n")
+    }
+
+    /// The lines, each with a line break, including the last line of the document
+    pub(crate) fn copy_lines(&self, rows: Range<usize>) -> String {
+        let rows = rows.start..rows.end.min(self.line_count());
+        rows.map(|row| format!("{}\n", &self.text[self.line_range(row)])).collect()
+    }
+
+    /// Put `count` copies of the line below it, all of the line wherever the cursor is.
+    /// Anything anchored below the line moves down with the lines after it.
+    pub(crate) fn duplicate_line(&mut self, row: usize, count: usize) {
+        let line = self.line_range(row);
+        // The copies go after the end of the line, so the last line needs no line break of its own
+        let copies = format!("\n{}", &self.text[line.clone()]).repeat(count);
+        self.text.insert_str(line.end, &copies);
+        self.shift(Shift::Down { from: row + 1, count });
+    }
+
+    /// Swap the line with the one below it, along with anything anchored to them.
+    /// The last line has no line below it and stays where it is.
+    pub(crate) fn swap_lines(&mut self, row: usize) {
+        if row + 1 >= self.line_count() {
+            return;
+        }
+        let first = self.line_range(row);
+        let second = self.line_range(row + 1);
+        let swapped = format!("{}\n{}", &self.text[second.clone()], &self.text[first.clone()]);
+        self.text.replace_range(first.start..second.end, &swapped);
+        self.shift(Shift::Swap { row });
+    }
+
+    /// Remove the lines along with their line breaks. Anything anchored to the
+    /// removed lines ends up on the line that takes their place.
+    pub(crate) fn delete_lines(&mut self, rows: Range<usize>) {
+        let start = self.byte_offset(Pos::new(0, rows.start as i32));
+        let range = match rows.end < self.line_count() {
+            true => start..self.byte_offset(Pos::new(0, rows.end as i32)),
+            // Up to the end, and the line break before the first line instead
+            false => start.saturating_sub(1)..self.text.len(),
+        };
+        self.text.drain(range);
+        self.shift(Shift::Up {
+            row: rows.start,
+            count: rows.len(),
+        });
+    }
+
+    /// Remove the word under the position: a run of alphanumeric characters and `_`,
+    /// or a single character of anything else. Whitespace and the end of the line are
+    /// not words.
+    ///
+    /// Returns the column the word started at, or `None` if there was no word.
+    pub(crate) fn delete_word(&mut self, pos: Pos) -> Option<i32> {
+        let line = self.line_range(pos.y as usize);
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+        // The byte offset and the columns of every character
+        let mut col = 0;
+        let chars = self.text[line.clone()]
+            .char_indices()
+            .map(|(i, c)| {
+                let start = col;
+                col += c.width().unwrap_or(0) as i32;
+                (line.start + i, start..col, c)
+            })
+            .collect::<Vec<_>>();
+
+        let at = chars.iter().position(|(_, cols, _)| cols.contains(&pos.x))?;
+        let (first, last) = match chars[at].2 {
+            c if c.is_whitespace() => return None,
+            c if is_word(c) => {
+                let first = chars[..at].iter().rposition(|&(.., c)| !is_word(c)).map_or(0, |i| i + 1);
+                let last = chars[at..].iter().position(|&(.., c)| !is_word(c)).map_or(chars.len(), |i| at + i);
+                (first, last)
+            }
+            _ => (at, at + 1),
+        };
+
+        let end = chars.get(last).map_or(line.end, |&(i, ..)| i);
+        self.text.drain(chars[first].0..end);
+        Some(chars[first].1.start)
+    }
+
+    /// Replace every occurrence of `needle` in one go. Anything anchored to the rows
+    /// after an occurrence moves along when the replacement has more or fewer lines.
+    pub(crate) fn replace_all(&mut self, needle: &str, replacement: &str) {
+        let needle_lines = needle.matches('\n').count();
+        let replacement_lines = replacement.matches('\n').count();
+
+        // The row of every occurrence, as the rows are once the ones before it are replaced
+        let mut shifts = vec![];
+        if needle_lines != replacement_lines {
+            let (mut row, mut last) = (0, 0);
+            for (i, _) in self.text.match_indices(needle) {
+                row += self.text[last..i].matches('\n').count();
+                last = i + needle.len();
+                let shift = match replacement_lines > needle_lines {
+                    true => Shift::Down {
+                        from: row + needle_lines + 1,
+                        count: replacement_lines - needle_lines,
+                    },
+                    false => Shift::Up {
+                        row: row + replacement_lines,
+                        count: needle_lines - replacement_lines,
+                    },
+                };
+                shifts.push(shift);
+                row += replacement_lines;
+            }
+        }
+
+        self.text = self.text.replace(needle, replacement);
+        shifts.into_iter().for_each(|shift| self.shift(shift));
+    }
+
+    // Byte range of a line, excluding the newline character
+    fn line_range(&self, row: usize) -> Range<usize> {
+        let start = self.byte_offset(Pos::new(0, row as i32));
+        let len = self.text[start..].find('\n').unwrap_or(self.text.len() - start);
+        start..start + len
+    }
+
+    /// Remove everything from the position to the end of the line, keeping the line break.
+    /// A wide character the position is in the middle of is removed too.
+    /// Returns the column the removed text started at, if there was any.
+    pub(crate) fn clear_eol(&mut self, pos: Pos) -> Option<i32> {
+        let line = self.line_range(pos.y as usize);
+        let start = self.get_byte_offset(pos, 1).start;
+        if start == line.end {
+            return None;
+        }
+        let x = char_width(&self.text[line.start..start]);
+        self.text.drain(start..line.end);
+        Some(x)
+    }
+
+    /// Remove up to `count` characters before the position, without joining it to the line above.
+    /// Returns the width of what was removed.
+    pub(crate) fn backspace(&mut self, pos: Pos, count: usize) -> i32 {
+        let line = self.line_range(pos.y as usize);
+        let end = self.byte_offset(pos).clamp(line.start, line.end);
+        let start = self.text[line.start..end]
+            .char_indices()
+            .rev()
+            .take(count)
+            .last()
+            .map_or(end, |(i, _)| line.start + i);
+        char_width(&self.text.drain(start..end).collect::<String>())
+    }
+
+    /// There is always at least one line, an empty document has one empty line
+    pub(crate) fn line_count(&self) -> usize {
+        self.text.split('\n').count()
+    }
+
+    /// Width of the line, zero for rows past the end
+    pub(crate) fn line_width(&self, row: usize) -> i32 {
+        char_width(&self.text[self.line_range(row)])
+    }
+
+    /// The last row with more than whitespace on it, or the first row if there is none
+    pub(crate) fn last_non_empty_line(&self) -> usize {
+        let lines = self.text.split('\n').enumerate();
+        lines.filter(|(_, line)| !line.trim().is_empty()).last().map_or(0, |(row, _)| row)
+    }
+
+    /// The closest position on a line of the document, at most at the end of the line
+    pub(crate) fn clamp(&self, pos: Pos) -> Pos {
+        let y = pos.y.clamp(0, self.line_count() as i32 - 1);
+        let x = pos.x.clamp(0, self.line_width(y as usize));
+        Pos::new(x, y)
+    }
+
+    /// Prefix the lines with `levels` of the indent `unit`.
+    /// Empty lines are not indented.
+    pub(crate) fn indent(&mut self, rows: Range<usize>, unit: &str, levels: usize) {
+        let prefix = unit.repeat(levels);
+        let last = self.line_count().min(rows.end);
+
+        for row in (rows.start..last).rev() {
+            let line = self.line_range(row);
+            if line.is_empty() {
+                continue;
+            }
+            self.text.insert_str(line.start, &prefix);
+        }
+    }
+
+    /// Remove up to `levels` of the indent `unit` from the start of the lines.
+    /// Only leading whitespace is removed. A tab counts as `tab_width` spaces, and is removed
+    /// in its entirety even if that removes more than the requested levels.
+    pub(crate) fn dedent(&mut self, rows: Range<usize>, unit: &str, levels: usize, tab_width: usize) {
+        let unit_width = unit.chars().map(|c| if c == '\t' { tab_width } else { 1 }).sum::<usize>();
+        let max_width = unit_width * levels;
+        let last = self.line_count().min(rows.end);
+
+        for row in (rows.start..last).rev() {
+            let line = self.line_range(row);
+            let mut width = 0;
+            let mut end = line.start;
+            for c in self.text[line].chars() {
+                if width >= max_width {
+                    break;
+                }
+                match c {
+                    '\t' => width += tab_width,
+                    ' ' => width += 1,
+                    _ => break,
+                }
+                end += c.len_utf8();
+            }
+
+            let start = self.line_range(row).start;
+            self.text.replace_range(start..end, "");
+        }
+    }
+
+    /// The padding that lines up the first `c` on each of `count` lines, starting at `row`.
+    /// Lines without `c` are skipped.
+    ///
+    /// Returns the position of each `c` that has to move, and the number of spaces to insert before it.
+    pub(crate) fn align(&self, row: usize, count: usize, c: char) -> Vec<(Pos, usize)> {
+        let last = self.line_count().min(row + count);
+        let columns = (row..last)
+            .filter_map(|row| {
+                let line = &self.text[self.line_range(row)];
+                let col = char_width(&line[..line.find(c)?]);
+                Some(Pos::new(col, row as i32))
+            })
+            .collect::<Vec<_>>();
+
+        let Some(max) = columns.iter().map(|pos| pos.x).max() else { return vec![] };
+        columns
+            .into_iter()
+            .filter(|pos| pos.x < max)
+            .map(|pos| (pos, (max - pos.x) as usize))
+            .collect()
+    }
+
+    /// Whether the non-blank lines of the `count` lines from `row` all start with `leader`
+    /// after their indentation, so toggling removes the leaders rather than adding more.
+    pub(crate) fn is_commented(&self, row: usize, count: usize, leader: &str) -> bool {
+        let last = self.line_count().min(row + count);
+        let mut lines = (row..last)
+            .map(|row| self.text[self.line_range(row)].trim_start())
+            .filter(|line| !line.is_empty())
+            .peekable();
+        lines.peek().is_some() && lines.all(|line| line.starts_with(leader))
+    }
+
+    /// Put `leader` and a space after the indentation of the line, or remove the
+    /// leader and one space after it. Blank lines are left alone.
+    ///
+    /// Markers with a column after the indentation move with the text.
+    /// Returns the column of the change and the change in width.
+    pub(crate) fn comment_line(&mut self, row: usize, leader: &str, comment: bool) -> Option<(i32, i32)> {
+        if row >= self.line_count() {
+            return None;
+        }
 
-        let end = text.bytes().take_while(|b| *b != b'\n').count();
-        let offset = text[..cursor.x as usize].width();
-        let line = &text[cursor.x as usize..end];
+        let line = self.line_range(row);
+        let content = &self.text[line.clone()];
+        let indent = content.len() - content.trim_start().len();
+        let rest = &content[indent..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let col = char_width(&content[..indent]);
+        let start = line.start + indent;
+        let delta = match comment {
+            true => {
+                let prefix = format!("{leader} ");
+                self.text.insert_str(start, &prefix);
+                char_width(&prefix)
+            }
+            false => {
+                let after = rest.strip_prefix(leader)?;
+                let len = leader.len() + after.starts_with(' ') as usize;
+                let width = char_width(&self.text[start..start + len]);
+                self.text.replace_range(start..start + len, "");
+                -width
+            }
+        };
+        self.markers.shift_columns(row, col as usize, delta);
+        Some((col, delta))
+    }
+
+    /// Join the line below `row` onto the end of `row`.
+    /// Leading whitespace of the joined line is collapsed into a single space,
+    /// unless the line ends with an open bracket (or whitespace), or the joined line is empty.
+    ///
+    /// Returns the column of the join point, or `None` if there is no line below.
+    pub(crate) fn join_line(&mut self, row: usize) -> Option<usize> {
+        let line = self.line_range(row);
+        if line.end == self.text.len() {
+            return None;
+        }
 
-        let mut byte_pos = line.find(&needle)?;
+        let next = self.line_range(row + 1);
+        let indent = self.text[next.clone()].len() - self.text[next.clone()].trim_start().len();
+        let current = &self.text[line.clone()];
+        let joined = &self.text[next.start + indent..next.end];
+
+        let separator = match (current.chars().last(), joined.chars().next()) {
+            (None, _) | (_, None | Some(')' | ']' | '}')) => "",
+            (Some('(' | '[' | '{'), _) => "",
+            (Some(c), _) if c.is_whitespace() => "",
+            _ => " ",
+        };
+
+        let col = current.width();
+        self.text.replace_range(line.end..next.start + indent, separator);
+        self.shift(Shift::Up { row, count: 1 });
+
+        Some(col)
+    }
+
+    /// Remove the line break at the end of `row`, joining the line below onto it as it is.
+    /// Returns the column of the join point.
+    pub(crate) fn remove_line_break(&mut self, row: usize) -> usize {
+        let line = self.line_range(row);
+        if line.end < self.text.len() {
+            self.text.remove(line.end);
+            self.shift(Shift::Up { row, count: 1 });
+        }
+        char_width(&self.text[line]) as usize
+    }
+
+    /// Split the line at the position, moving the remainder onto a new line.
+    /// If `autoindent` is set the new line will have the same indentation as the original line.
+    ///
+    /// Returns the column on the new line where the remainder starts.
+    pub(crate) fn split_line(&mut self, pos: Pos, autoindent: bool) -> usize {
+        let line = self.line_range(pos.y as usize);
+        let split = self.byte_offset(pos).min(line.end);
+
+        let mut insert = String::from("\n");
+        let mut remove = 0;
+
+        if autoindent {
+            let text = &self.text[line.clone()];
+            let indent = &text[..text.len() - text.trim_start().len()];
+            let remainder = &self.text[split..line.end];
+            remove = remainder.len() - remainder.trim_start().len();
+            insert.push_str(indent);
+        }
+
+        let col = insert[1..].width();
+        self.text.replace_range(split..split + remove, &insert);
+
+        // Splitting at the start of the line moves the entire line down
+        let row = pos.y as usize;
+        let row = match split == line.start {
+            true => row,
+            false => row + 1,
+        };
+        self.shift(Shift::Down { from: row, count: 1 });
+
+        col
+    }
+
+    /// The column of the `count`th `needle` on the line, at or after the cursor
+    pub(crate) fn find(&self, cursor: Pos, needle: &str, mut count: usize) -> Option<usize> {
+        let cursor = self.clamp(cursor);
+        let line = self.line_range(cursor.y as usize);
+        // The cursor is a column, which isn't the byte offset once there are wide or multi-byte chars
+        let start = self.byte_offset(cursor).min(line.end);
+        let offset = self.text[line.start..start].width();
+        let line = &self.text[start..line.end];
+
+        let mut byte_pos = line.find(needle)?;
 
         while count > 1 {
             byte_pos += 1;
-            byte_pos += &line[byte_pos..].find(&needle)?;
+            byte_pos += &line[byte_pos..].find(needle)?;
             count -= 1;
         }
 
         Some(line[..byte_pos].width() + offset)
     }
 
+    /// The column of the nth match that starts before the cursor, counting back from the cursor
+    pub(crate) fn rfind(&self, cursor: Pos, needle: &str, count: usize) -> Option<usize> {
+        let cursor = self.clamp(cursor);
+        let line = self.line_range(cursor.y as usize);
+        let start = self.byte_offset(cursor).min(line.end) - line.start;
+        let line = &self.text[line.clone()];
+
+        // A match can run past the cursor as long as it starts before it
+        let mut matches = line.rmatch_indices(needle).filter(|(byte_pos, _)| *byte_pos < start);
+        let (byte_pos, _) = matches.nth(count.max(1) - 1)?;
+        Some(char_width(&line[..byte_pos]) as usize)
+    }
+
     pub(crate) fn clear(&mut self) {
-        self.markers.clear();
+        self.shift(Shift::Clear);
         self.text.clear();
     }
+
+    /// Put back text and markers from before. Everything else anchored to the
+    /// current text is gone, the same as with [`Document::clear`].
+    pub(crate) fn restore(&mut self, text: String, markers: Markers) {
+        self.clear();
+        self.text = text;
+        self.markers = markers;
+    }
+}
+
+// Width of a string the same way the cursor sees it.
+// Unlike `UnicodeWidthStr::width` this gives control characters zero width.
+fn char_width(s: &str) -> i32 {
+    s.chars().map(|c| c.width().unwrap_or(0) as i32).sum()
 }
 
 #[cfg(test)]
@@ -146,6 +576,419 @@ abcdefg";
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn delete_rounds_out_to_whole_wide_chars() {
+        // a: 0, 界: 1-2, b: 3, 😀: 4-5, c: 6
+        let deleted = |x, width| {
+            let mut doc = Document::new("a界b😀c");
+            doc.delete(Region::from((Pos::new(x, 0), Size::new(width, 1))));
+            doc.text().to_string()
+        };
+
+        assert_eq!(deleted(1, 1), "ab😀c");
+        assert_eq!(deleted(2, 1), "ab😀c");
+        assert_eq!(deleted(2, 2), "a😀c");
+        assert_eq!(deleted(3, 2), "a界c");
+        assert_eq!(deleted(5, 1), "a界bc");
+        assert_eq!(deleted(0, 7), "");
+        assert_eq!(deleted(7, 2), "a界b😀c");
+
+        // Combining marks go with the character before them
+        let mut doc = Document::new("e\u{301}xy");
+        doc.delete(Region::from((Pos::new(0, 0), Size::new(1, 1))));
+        assert_eq!(doc.text(), "xy");
+        doc.delete(Region::from((Pos::new(1, 0), Size::new(1, 1))));
+        assert_eq!(doc.text(), "x");
+    }
+
+    #[test]
+    fn delete_wide_chars_at_every_offset() {
+        let lines = ["界界界", "😀a😀b", "a界b😀c", "ab"];
+        for line in lines {
+            for x in 0..9 {
+                for width in 1..5 {
+                    // Every character with a cell in the columns goes
+                    let mut col = 0;
+                    let kept = line
+                        .chars()
+                        .filter(|c| {
+                            let start = col;
+                            col += c.width().unwrap();
+                            col <= x || start >= x + width
+                        })
+                        .collect::<String>();
+
+                    let mut doc = Document::new(format!("{line}\n{line}"));
+                    doc.delete(Region::from((Pos::new(x as i32, 0), Size::new(width as u16, 2))));
+                    assert_eq!(doc.text(), format!("{kept}\n{kept}"), "{line:?} from {x}, {width} wide");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn delete_lines() {
+        let mut doc = Document::new("a\nb\nc\nd");
+        doc.delete_lines(1..3);
+        assert_eq!(doc.text(), "a\nd");
+        doc.delete_lines(1..2);
+        assert_eq!(doc.text(), "a");
+        doc.delete_lines(0..1);
+        assert_eq!(doc.text(), "");
+    }
+
+    #[test]
+    fn delete_words() {
+        let mut doc = Document::new("a.b_c(界x)  ");
+        assert_eq!(doc.delete_word(Pos::new(4, 0)), Some(2));
+        assert_eq!(doc.text(), "a.(界x)  ");
+        assert_eq!(doc.delete_word(Pos::new(1, 0)), Some(1));
+        assert_eq!(doc.text(), "a(界x)  ");
+        // The second cell of a wide character
+        assert_eq!(doc.delete_word(Pos::new(3, 0)), Some(2));
+        assert_eq!(doc.text(), "a()  ");
+
+        assert_eq!(doc.delete_word(Pos::new(3, 0)), None);
+        assert_eq!(doc.delete_word(Pos::new(5, 0)), None);
+        assert_eq!(doc.delete_word(Pos::new(0, 3)), None);
+        assert_eq!(doc.text(), "a()  ");
+    }
+
+    #[test]
+    fn clear_to_the_end_of_the_line() {
+        let mut doc = Document::new("fn a() -> u8 {\n}");
+        assert_eq!(doc.clear_eol(Pos::new(7, 0)), Some(7));
+        assert_eq!(doc.text(), "fn a() \n}");
+
+        // The second cell of a wide character
+        let mut doc = Document::new("a界b\nc");
+        assert_eq!(doc.clear_eol(Pos::new(2, 0)), Some(1));
+        assert_eq!(doc.text(), "a\nc");
+
+        assert_eq!(doc.clear_eol(Pos::new(1, 0)), None);
+        assert_eq!(doc.clear_eol(Pos::new(0, 5)), None);
+        assert_eq!(doc.text(), "a\nc");
+        assert_eq!(doc.clear_eol(Pos::new(0, 1)), Some(0));
+        assert_eq!(doc.text(), "a\n");
+    }
+
+    #[test]
+    fn copy_regions_and_lines() {
+        let doc = Document::new("ab界c\nde\nf");
+        assert_eq!(doc.copy(Region::new(Pos::new(1, 0), Pos::new(3, 2))), "b界\ne");
+        assert_eq!(doc.copy(Region::new(Pos::new(0, 2), Pos::new(0, 2))), "");
+        assert_eq!(doc.copy_lines(1..3), "de\nf\n");
+        assert_eq!(doc.copy_lines(2..5), "f\n");
+    }
+
+    #[test]
+    fn rfind() {
+        // 界: 0-1, ü: 2, a: 4, 😀: 6-7, a: 8, é: 10, a: 12
+        let doc = Document::new("x\n界ü a 😀a é a");
+        let end = Pos::new(13, 1);
+        assert_eq!(doc.rfind(end, "a", 1), Some(12));
+        assert_eq!(doc.rfind(end, "a", 2), Some(8));
+        assert_eq!(doc.rfind(end, "a", 3), Some(4));
+        assert_eq!(doc.rfind(end, "a", 4), None);
+        assert_eq!(doc.rfind(end, "界ü", 1), Some(0));
+
+        // Only matches that start before the cursor
+        assert_eq!(doc.rfind(Pos::new(12, 1), "a", 1), Some(8));
+        assert_eq!(doc.rfind(Pos::new(4, 1), "a", 1), None);
+        assert_eq!(doc.rfind(Pos::new(1, 0), "x", 1), Some(0));
+
+        // Even when they end after it
+        let doc = Document::new("abcabc");
+        assert_eq!(doc.rfind(Pos::new(4, 0), "abc", 1), Some(3));
+        assert_eq!(doc.rfind(Pos::new(3, 0), "abc", 1), Some(0));
+        assert_eq!(doc.rfind(Pos::new(1, 0), "abc", 2), None);
+    }
+
+    #[test]
+    fn last_non_empty_line() {
+        assert_eq!(Document::new("").last_non_empty_line(), 0);
+        assert_eq!(Document::new("\n \n").last_non_empty_line(), 0);
+        assert_eq!(Document::new("a\nb\n\t\n").last_non_empty_line(), 1);
+        assert_eq!(Document::new("a\n\nb").last_non_empty_line(), 2);
+    }
+
+    #[test]
+    fn clamp() {
+        let empty = Document::new("");
+        assert_eq!(empty.line_count(), 1);
+        assert_eq!(empty.clamp(Pos::new(3, 2)), Pos::ZERO);
+        assert_eq!(empty.clamp(Pos::new(-1, -1)), Pos::ZERO);
+
+        let doc = Document::new("ab\n界");
+        assert_eq!(doc.line_width(1), 2);
+        assert_eq!(doc.line_width(5), 0);
+        assert_eq!(doc.clamp(Pos::new(5, 0)), Pos::new(2, 0));
+        assert_eq!(doc.clamp(Pos::new(5, 5)), Pos::new(2, 1));
+    }
+
+    #[test]
+    fn backspace() {
+        let mut doc = Document::new("ab\nc界d");
+        assert_eq!(doc.backspace(Pos::new(3, 1), 2), 3);
+        assert_eq!(doc.text(), "ab\nd");
+
+        // Never past the start of the line
+        assert_eq!(doc.backspace(Pos::new(1, 1), 5), 1);
+        assert_eq!(doc.text(), "ab\n");
+        assert_eq!(doc.backspace(Pos::new(0, 1), 1), 0);
+        assert_eq!(doc.text(), "ab\n");
+    }
+
+    #[test]
+    fn join_lines() {
+        let mut doc = Document::new("fn main() {\n    let a = 1;\n}");
+
+        assert_eq!(doc.join_line(0), Some(11));
+        assert_eq!(doc.text(), "fn main() {let a = 1;\n}");
+
+        assert_eq!(doc.join_line(0), Some(21));
+        assert_eq!(doc.text(), "fn main() {let a = 1;}");
+    }
+
+    #[test]
+    fn join_collapses_whitespace() {
+        let mut doc = Document::new("a\n\t   b\nc");
+        assert_eq!(doc.join_line(0), Some(1));
+        assert_eq!(doc.text(), "a b\nc");
+    }
+
+    #[test]
+    fn join_empty_lines() {
+        let mut doc = Document::new("\nb\n");
+        assert_eq!(doc.join_line(0), Some(0));
+        assert_eq!(doc.text(), "b\n");
+
+        assert_eq!(doc.join_line(0), Some(1));
+        assert_eq!(doc.text(), "b");
+    }
+
+    #[test]
+    fn join_last_line() {
+        let mut doc = Document::new("a\nb");
+        assert_eq!(doc.join_line(1), None);
+        assert_eq!(doc.join_line(5), None);
+        assert_eq!(doc.text(), "a\nb");
+    }
+
+    #[test]
+    fn join_wide_chars() {
+        let mut doc = Document::new("🐇🐇\n  x");
+        assert_eq!(doc.join_line(0), Some(4));
+        assert_eq!(doc.text(), "🐇🐇 x");
+    }
+
+    #[test]
+    fn join_offsets_markers() {
+        let text = "a
+b
+// @one
+c
+// @two
+d";
+        let mut doc = Document::new(text);
+        doc.join_line(0);
+
+        assert_eq!(doc.text(), "a b\nc\nd");
+        assert_eq!(doc.lookup_marker("one").unwrap().row, 1);
+        assert_eq!(doc.lookup_marker("two").unwrap().row, 2);
+    }
+
+    #[test]
+    fn replace_all() {
+        let text = "let a = old;
+// @one
+old(old);
+// @two
+end";
+        let mut doc = Document::new(text);
+        doc.replace_all("old", "new");
+        assert_eq!(doc.text(), "let a = new;\nnew(new);\nend");
+        assert_eq!(doc.lookup_marker("one").unwrap().row, 1);
+        assert_eq!(doc.lookup_marker("two").unwrap().row, 2);
+    }
+
+    #[test]
+    fn replace_all_with_more_lines() {
+        let text = "a;
+// @one
+b; c;
+// @two
+d";
+        let mut doc = Document::new(text);
+        doc.replace_all(";", ";\n//");
+        assert_eq!(doc.text(), "a;\n//\nb;\n// c;\n//\nd");
+        assert_eq!(doc.lookup_marker("one").unwrap().row, 2);
+        assert_eq!(doc.lookup_marker("two").unwrap().row, 5);
+
+        // And back
+        doc.replace_all(";\n//", ";");
+        assert_eq!(doc.text(), "a;\nb; c;\nd");
+        assert_eq!(doc.lookup_marker("one").unwrap().row, 1);
+        assert_eq!(doc.lookup_marker("two").unwrap().row, 2);
+    }
+
+    #[test]
+    fn split_lines() {
+        let mut doc = Document::new("    let a = 1;");
+        assert_eq!(doc.split_line(Pos::new(11, 0), false), 0);
+        assert_eq!(doc.text(), "    let a =\n 1;");
+
+        let mut doc = Document::new("    let a = 1;");
+        assert_eq!(doc.split_line(Pos::new(11, 0), true), 4);
+        assert_eq!(doc.text(), "    let a =\n    1;");
+    }
+
+    #[test]
+    fn split_at_column_zero() {
+        let mut doc = Document::new("    a\nb");
+        assert_eq!(doc.split_line(Pos::new(0, 0), false), 0);
+        assert_eq!(doc.text(), "\n    a\nb");
+
+        let mut doc = Document::new("    a\nb");
+        assert_eq!(doc.split_line(Pos::new(0, 0), true), 4);
+        assert_eq!(doc.text(), "\n    a\nb");
+    }
+
+    #[test]
+    fn split_past_end_of_line() {
+        let mut doc = Document::new("ab\ncd");
+        doc.split_line(Pos::new(10, 0), false);
+        assert_eq!(doc.text(), "ab\n\ncd");
+    }
+
+    #[test]
+    fn split_offsets_markers() {
+        let text = "a
+// @one
+b
+// @two
+c";
+        let mut doc = Document::new(text);
+        doc.split_line(Pos::new(0, 1), false);
+
+        assert_eq!(doc.text(), "a\n\nb\nc");
+        assert_eq!(doc.lookup_marker("one").unwrap().row, 2);
+        assert_eq!(doc.lookup_marker("two").unwrap().row, 3);
+
+        doc.split_line(Pos::new(1, 0), false);
+        assert_eq!(doc.text(), "a\n\n\nb\nc");
+        assert_eq!(doc.lookup_marker("one").unwrap().row, 3);
+        assert_eq!(doc.lookup_marker("two").unwrap().row, 4);
+    }
+
+    #[test]
+    fn indent_lines() {
+        let mut doc = Document::new("a\n\n  b\nc");
+        doc.indent(0..3, "    ", 1);
+        assert_eq!(doc.text(), "    a\n\n      b\nc");
+
+        let mut doc = Document::new("a\nb");
+        doc.indent(1..6, "\t", 2);
+        assert_eq!(doc.text(), "a\n\t\tb");
+    }
+
+    #[test]
+    fn dedent_lines() {
+        let mut doc = Document::new("        a\n    b\nc");
+        doc.dedent(0..3, "    ", 1, 4);
+        assert_eq!(doc.text(), "    a\nb\nc");
+    }
+
+    #[test]
+    fn dedent_mixed_tabs_and_spaces() {
+        let mut doc = Document::new("\t\ta\n  \tb\n\t  c");
+        doc.dedent(0..3, "    ", 1, 4);
+        assert_eq!(doc.text(), "\ta\nb\n  c");
+
+        let mut doc = Document::new("\t\ta\n    b\n  c");
+        doc.dedent(0..3, "\t", 1, 4);
+        assert_eq!(doc.text(), "\ta\nb\nc");
+
+        // A tab as wide as two spaces
+        let mut doc = Document::new("\t\ta\n\t  \tb");
+        doc.dedent(0..2, "    ", 1, 2);
+        assert_eq!(doc.text(), "a\n\tb");
+    }
+
+    #[test]
+    fn dedent_lines_shorter_than_unit() {
+        let mut doc = Document::new("  a\n b\n\n   ");
+        doc.dedent(0..4, "    ", 2, 4);
+        assert_eq!(doc.text(), "a\nb\n\n");
+    }
+
+    #[test]
+    fn dedent_does_not_remove_text() {
+        let mut doc = Document::new("abc\n  x y");
+        doc.dedent(0..2, "    ", 3, 4);
+        assert_eq!(doc.text(), "abc\nx y");
+    }
+
+    #[test]
+    fn comment_lines() {
+        let mut doc = Document::new("fn a() {\n    b();\n\n}");
+        assert!(!doc.is_commented(0, 4, "//"));
+        for row in 0..4 {
+            doc.comment_line(row, "//", true);
+        }
+        assert_eq!(doc.text(), "// fn a() {\n    // b();\n\n// }");
+        assert!(doc.is_commented(0, 4, "//"));
+
+        assert_eq!(doc.comment_line(1, "//", false), Some((4, -3)));
+        assert_eq!(doc.comment_line(2, "//", false), None);
+        assert_eq!(doc.text(), "// fn a() {\n    b();\n\n// }");
+    }
+
+    #[test]
+    fn uncomment_without_space() {
+        let mut doc = Document::new("  #x\n#  y\nz");
+        assert!(doc.is_commented(0, 2, "#"));
+        assert!(!doc.is_commented(0, 3, "#"));
+        assert_eq!(doc.comment_line(0, "#", false), Some((2, -1)));
+        // Only one space goes with the leader
+        assert_eq!(doc.comment_line(1, "#", false), Some((0, -2)));
+        assert_eq!(doc.comment_line(2, "#", false), None);
+        assert_eq!(doc.text(), "  x\n y\nz");
+    }
+
+    #[test]
+    fn blank_lines_are_not_commented() {
+        let doc = Document::new("\n   \n");
+        assert!(!doc.is_commented(0, 3, "--"));
+    }
+
+    #[test]
+    fn align_lines() {
+        let doc = Document::new("a = 1\nlong = 2\nnothing\nab = 3\nfoo = 4");
+        let pads = doc.align(0, 4, '=');
+        assert_eq!(pads, vec![(Pos::new(2, 0), 3), (Pos::new(3, 3), 2)]);
+    }
+
+    #[test]
+    fn align_wide_chars() {
+        let mut doc = Document::new("日本 = 1\nab = 2\nx: 3");
+        let pads = doc.align(0, 3, '=');
+        assert_eq!(pads, vec![(Pos::new(3, 1), 2)]);
+
+        for (pos, width) in pads {
+            doc.insert_str(pos, " ".repeat(width));
+        }
+        assert_eq!(doc.text(), "日本 = 1\nab   = 2\nx: 3");
+    }
+
+    #[test]
+    fn align_nothing() {
+        let doc = Document::new("a = 1\nb = 2\nc");
+        assert!(doc.align(0, 3, '=').is_empty());
+        assert!(doc.align(2, 1, '=').is_empty());
+        assert!(doc.align(5, 1, '=').is_empty());
+    }
+
     #[test]
     fn insert_offsets_marker() {
         static NEWLINES: usize = 4;
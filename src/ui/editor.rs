@@ -1,60 +1,134 @@
-use std::collections::VecDeque;
-use std::io::Write;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use anathema::component::*;
 use anathema::default_widgets::{Canvas, CanvasBuffer};
-use anathema::geometry::{LocalPos, Pos, Region, Size};
+use anathema::geometry::{LocalPos, Pos, Size};
+use anathema::widgets::Style;
 use anathema::widgets::query::Elements;
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+use super::anchor::Anchored;
 use super::audio::AudioShell;
-use super::document::Document;
+use super::budget::Timings;
+#[cfg(test)]
+use super::events::Event;
+use super::events::Events;
+use super::guides::{ColorColumns, GUIDE_COLOR};
 use super::instructions::Instruction;
-use super::markers::generate;
+use super::keys::{BACKSPACE, KeyOverlay};
+use super::ligatures::Ligatures;
+use super::links::Links;
+use super::margins::Margins;
+use super::menu::Menu;
+use super::mirror::{Grid, Mirror};
+use super::overlays::{Layer, Overlays, Paint, Target};
+use super::popup::Popup;
+use super::progress::{Left, Progress};
+use super::queue::{Queue, ScriptItem};
 use super::random::Random;
+use super::schedule::Schedule;
+use super::session::{Effect, Session};
+use super::shutdown::Shutdown;
+use super::splash::Splash;
 use super::syntax::{Highlighter, InactiveScratch};
-use super::textbuffer::TextBuffer;
+use super::transition::Transition;
+use super::typo::AutoTypo;
+use super::virtual_text::ScreenRow;
+use super::wrap::{self, wrap_rows};
+use crate::parser::{HScroll, JitterProfile, LoopCount, PopupPosition, TransitionKind};
 
 // -----------------------------------------------------------------------------
 //   - Frame timer -
 // -----------------------------------------------------------------------------
+pub(crate) const DEFAULT_JITTER_MS: u64 = 20;
+pub(crate) const DEFAULT_BURST_PAUSE: (Duration, Duration) = (Duration::from_millis(80), Duration::from_millis(220));
+
 struct Timer {
     frame_time: Duration,
+    // The frame time while the command line is typed, if it's not `frame_time`
+    command_frame_time: Option<Duration>,
+    typing_command: bool,
     accumulator: Duration,
     wait: Duration,
     jitter: Duration,
     jitter_ms: u64,
+    profile: JitterProfile,
+    burst_pause: (Duration, Duration),
     rand: Random,
+    // Script time per real time
+    scale: f64,
 }
 
 impl Timer {
     pub fn new(frame_time: Duration) -> Self {
         Self {
             frame_time,
+            command_frame_time: None,
+            typing_command: false,
             accumulator: Duration::ZERO,
             wait: Duration::ZERO,
             jitter: Duration::ZERO,
-            jitter_ms: 20,
+            jitter_ms: DEFAULT_JITTER_MS,
+            profile: JitterProfile::Uniform,
+            burst_pause: DEFAULT_BURST_PAUSE,
             rand: Random::new(),
+            scale: 1.0,
+        }
+    }
+
+    // The time between frames of what's being typed
+    fn interval(&self) -> Duration {
+        match self.typing_command {
+            true => self.command_frame_time.unwrap_or(self.frame_time),
+            false => self.frame_time,
         }
     }
 
+    fn set_scale(&mut self, scale: f64) {
+        self.scale = scale.clamp(*TIME_SCALE.start(), *TIME_SCALE.end());
+    }
+
     fn apply_jitter(&mut self) {
         self.wait += self.jitter;
-        self.jitter = Duration::from_millis(self.rand.next(self.jitter_ms));
+        self.jitter = match self.profile {
+            JitterProfile::Uniform if self.jitter_ms > 0 => Duration::from_millis(self.rand.next(self.jitter_ms)),
+            _ => Duration::ZERO,
+        };
+    }
+
+    fn set_profile(&mut self, profile: JitterProfile) {
+        self.profile = profile;
+        self.jitter = Duration::ZERO;
     }
 
-    fn tick(&mut self, mut dt: Duration) -> usize {
+    // The pause before typing `upcoming`.
+    // In burst mode words are typed without jitter, with a pause before every space or newline
+    fn pause_before(&mut self, upcoming: Option<&str>) -> Duration {
+        match (self.profile, upcoming) {
+            (JitterProfile::Burst, Some(s)) if s.starts_with(char::is_whitespace) => {
+                let (min, max) = self.burst_pause;
+                self.between(min, max)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn tick(&mut self, dt: Duration) -> usize {
+        // Everything the timer waits for is in script time
+        let mut dt = dt.mul_f64(self.scale);
         if !self.wait.is_zero() {
             match self.wait.checked_sub(dt) {
-                Some(wait) => {
+                Some(wait) if !wait.is_zero() => {
                     self.wait = wait;
                     return 0;
                 }
-                None => {
-                    self.wait = Duration::ZERO;
+                // The rest of the tick counts towards the next frame
+                _ => {
                     dt -= self.wait;
+                    self.wait = Duration::ZERO;
                 }
             }
         } else {
@@ -63,18 +137,195 @@ impl Timer {
 
         self.accumulator += dt;
 
+        let interval = self.interval();
         let mut count = 0;
-        while self.accumulator >= self.frame_time {
-            self.accumulator = self.accumulator.saturating_sub(self.frame_time);
+        while self.accumulator >= interval {
+            self.accumulator = self.accumulator.saturating_sub(interval);
             count += 1;
         }
 
         count
     }
 
+    // A random duration from `min` to `max`, to the millisecond
+    fn between(&mut self, min: Duration, max: Duration) -> Duration {
+        let range = (max - min).as_millis() as u64;
+        min + Duration::from_millis(self.rand.next(range + 1))
+    }
+
     fn wait(&mut self, wait: Duration) {
         self.wait = wait;
     }
+
+    // Frames that were due but not used, they are due again after a wait
+    fn owe(&mut self, frames: usize) {
+        self.accumulator += self.interval() * frames as u32;
+    }
+
+    // Roughly how long until the next frame in real time, ignoring jitter
+    fn until_next(&self) -> Duration {
+        (self.wait + self.interval().saturating_sub(self.accumulator)).div_f64(self.scale)
+    }
+}
+
+/// How much faster than written a script can be played
+pub const TIME_SCALE: RangeInclusive<f64> = 0.1..=20.0;
+
+// The time scales the `+` and `-` keys step through
+const TIME_SCALE_STEPS: [f64; 14] = [0.1, 0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 20.0];
+
+// The next step up or down from `scale`, which doesn't have to be one of the steps
+fn step_time_scale(scale: f64, up: bool) -> f64 {
+    const EPSILON: f64 = 1e-9;
+    let next = match up {
+        true => TIME_SCALE_STEPS.iter().find(|&&step| step > scale + EPSILON),
+        false => TIME_SCALE_STEPS.iter().rev().find(|&&step| step < scale - EPSILON),
+    };
+    next.copied().unwrap_or(scale)
+}
+
+// -----------------------------------------------------------------------------
+//   - Typing rate -
+// -----------------------------------------------------------------------------
+/// Exponential moving average of the number of characters typed per second
+struct TypingRate {
+    average: f64,
+    time_constant: Duration,
+}
+
+impl TypingRate {
+    fn new(time_constant: Duration) -> Self {
+        Self {
+            average: 0.0,
+            time_constant,
+        }
+    }
+
+    fn update(&mut self, chars: usize, dt: Duration) -> f64 {
+        if dt.is_zero() {
+            return self.average;
+        }
+
+        let dt = dt.as_secs_f64();
+        let rate = chars as f64 / dt;
+        let alpha = 1.0 - (-dt / self.time_constant.as_secs_f64()).exp();
+        self.average += alpha * (rate - self.average);
+        self.average
+    }
+
+    fn reset(&mut self) {
+        self.average = 0.0;
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Stepping -
+// -----------------------------------------------------------------------------
+/// How far a key press moves the script along in step mode
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum StepBy {
+    /// One instruction, and all the text it types
+    #[default]
+    Instruction,
+    /// One typed character, or one instruction of any kind.
+    /// Loading the text to type is a step of its own, before its first character
+    Char,
+}
+
+// Holds the editor until a key press allows the next step
+#[derive(Default)]
+struct Stepping {
+    enabled: bool,
+    by: StepBy,
+    allowed: bool,
+    held: bool,
+}
+
+impl Stepping {
+    fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.allowed = false;
+        self.held = false;
+    }
+
+    fn allow(&mut self) {
+        self.allowed = true;
+        self.held = false;
+    }
+
+    // Whether the editor can go on at the start of a step of `by`, holding it if not
+    fn take(&mut self, by: StepBy) -> bool {
+        if !self.enabled || self.by != by {
+            return true;
+        }
+        self.held = !self.allowed;
+        std::mem::take(&mut self.allowed)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Seek -
+// -----------------------------------------------------------------------------
+/// Where to start playing at the normal speed, with everything before it applied at once
+#[derive(Debug, Clone, PartialEq)]
+pub enum Seek {
+    /// Once the marker is set
+    Marker(String),
+    /// Once the scene starts
+    Scene(String),
+    /// At this instruction of the script, counting from 1
+    Instruction(usize),
+}
+
+impl Seek {
+    /// A marker from `@name`, and a scene from any other name
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg.strip_prefix('@') {
+            Some("") => None,
+            Some(marker) => Some(Seek::Marker(marker.into())),
+            None if arg.is_empty() => None,
+            None => Some(Seek::Scene(arg.into())),
+        }
+    }
+}
+
+impl std::fmt::Display for Seek {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Seek::Marker(name) => write!(f, "@{name}"),
+            Seek::Scene(name) => write!(f, "scene \"{name}\""),
+            Seek::Instruction(index) => write!(f, "instruction {index}"),
+        }
+    }
+}
+
+// Plays the script again once it ends
+struct Looping {
+    count: LoopCount,
+    pause: Duration,
+    // Plays that have finished
+    played: u32,
+    // Waiting out the pause before the next play
+    pausing: bool,
+}
+
+impl Looping {
+    fn new(count: LoopCount, pause: Duration) -> Self {
+        Self {
+            count,
+            pause,
+            played: 0,
+            pausing: false,
+        }
+    }
+
+    // Whether the script plays again after the current play
+    fn again(&self) -> bool {
+        match self.count {
+            LoopCount::Times(times) => self.played + 1 < times,
+            LoopCount::Forever => true,
+        }
+    }
 }
 
 // -----------------------------------------------------------------------------
@@ -84,6 +335,8 @@ enum RenderAction {
     NextInstruction,
     Skip,
     NextFrame,
+    // Waiting doesn't use up a frame, the wait is all the time it takes
+    Wait,
 }
 
 // -----------------------------------------------------------------------------
@@ -103,9 +356,37 @@ pub struct DocState {
     debug: Value<String>,
     show_line_numbers: Value<bool>,
     popup: Value<String>,
+    popup_title: Value<String>,
+    // `top`, `bottom` or `centre` for an aligned popup, empty for one at `popup_x` and `popup_y`
+    popup_align: Value<String>,
+    popup_x: Value<i32>,
+    popup_y: Value<i32>,
     command_buffer: Value<String>,
+    command_height: Value<u16>,
     show_cursor: Value<bool>,
     ctx: Value<Map<Box<dyn State>>>,
+    chars_typed_total: Value<i32>,
+    chars_per_second_ewma: Value<i32>,
+    last_change_row: Value<i32>,
+    recent_keys: Value<String>,
+    show_menu: Value<bool>,
+    menu: Value<List<String>>,
+    menu_selected: Value<i32>,
+    margin_top: Value<u16>,
+    margin_right: Value<u16>,
+    margin_bottom: Value<u16>,
+    margin_left: Value<u16>,
+    line_numbers: Value<List<String>>,
+    paused: Value<bool>,
+    // Like `2x` when the time scale isn't 1, otherwise empty
+    time_scale: Value<String>,
+    stepping: Value<bool>,
+    // The number of instructions taken from the script, and how many there are, or 0 if that isn't known
+    instruction_index: Value<i32>,
+    instruction_total: Value<i32>,
+    // From 0 to 1, and the estimated time left like `1:05`, when the playback tracks its progress
+    progress: Value<f32>,
+    remaining: Value<String>,
 }
 
 impl DocState {
@@ -115,33 +396,13 @@ impl DocState {
             ..Default::default()
         }
     }
-}
-
-// -----------------------------------------------------------------------------
-//   - Visual rang -
-// -----------------------------------------------------------------------------
-#[derive(Debug)]
-struct VisualRange {
-    region: Region,
-}
-
-impl VisualRange {
-    fn new(pos: Pos, size: Size) -> Self {
-        Self {
-            region: Region::from((pos, size)),
-        }
-    }
-}
-
-trait OptVisualRange {
-    fn contains(&self, pos: Pos) -> bool;
-}
 
-impl OptVisualRange for Option<VisualRange> {
-    fn contains(&self, pos: Pos) -> bool {
-        match self {
-            None => false,
-            Some(range) => range.region.contains(pos),
+    /// The error that stopped the script, if any
+    pub fn error(&self) -> Option<String> {
+        let error = self.error.to_ref();
+        match error.is_empty() {
+            true => None,
+            false => Some(error.clone()),
         }
     }
 }
@@ -150,320 +411,719 @@ impl OptVisualRange for Option<VisualRange> {
 //   - Virtual editor -
 // -----------------------------------------------------------------------------
 pub struct Editor {
-    doc: Document,
-    cursor: Pos,
-    offset: Pos,
-    selected_range: Option<VisualRange>,
-    instructions: VecDeque<Instruction>,
-    type_buffer: TextBuffer,
-    type_command_buffer: TextBuffer,
+    session: Session,
     highlighter: Highlighter,
     buffer: CanvasBuffer,
     lines: InactiveScratch,
-    line_pause: Duration,
-    extension: String,
-    theme: String,
     audio: AudioShell,
+    // Click as each glyph is typed
+    metronome: bool,
     frame_timer: Timer,
+    // The frame time before the script changes it
+    frame_time: Duration,
+    // Every play draws the same random numbers when set
+    seed: Option<u64>,
     size: Size,
-    command_clear_timeout: Duration,
+    color_columns: ColorColumns,
+    shutdown: Shutdown,
+    typing_rate: TypingRate,
+    typed_this_tick: usize,
+    schedule: Schedule,
+    next_transition: Option<(TransitionKind, Duration)>,
+    transition: Option<Transition>,
+    hscroll: HScroll,
+    // Rows and columns kept around the cursor
+    scrolloff: (i32, i32),
+    // Long lines wrap when set, with the indicator at the start of the rows they continue on
+    wrap: Option<String>,
+    ligatures: Ligatures,
+    timings: Timings,
+    clock: Duration,
+    mirror: Option<Mirror>,
+    overlays: Overlays,
+    events: Events,
+    command_height: u16,
+    key_overlay: KeyOverlay,
+    links: Links,
+    menu: Option<Menu>,
+    splash: Option<Splash>,
+    margins: Margins,
+    popup: Popup,
+    auto_typo: AutoTypo,
+    paused: bool,
+    stepping: Stepping,
+    looping: Option<Looping>,
+    // Quit once the script has ended for this long
+    exit_on_end: Option<Duration>,
+    // How long the script has ended for
+    ended_for: Duration,
+    exiting: bool,
+    // Set when the script quits by itself, rather than being stopped
+    completed: Arc<AtomicBool>,
+    // Where every play starts, and whether the current one has yet to skip ahead to it
+    from: Option<Seek>,
+    seeking: bool,
+    // The last scene the script got to
+    scene: Option<String>,
+    progress: Option<Progress>,
 }
 
 impl Editor {
-    pub fn new(instructions: Vec<Instruction>, highlighter: Highlighter, frame_time: Duration) -> Self {
+    pub fn new(
+        instructions: impl IntoIterator<Item: ScriptItem, IntoIter: 'static>,
+        highlighter: Highlighter,
+        frame_time: Duration,
+        shutdown: Shutdown,
+        timings: Timings,
+    ) -> Self {
         Self {
-            doc: Document::new(String::new()),
-            cursor: Pos::ZERO,
-            offset: Pos::ZERO,
-            selected_range: None,
-            instructions: instructions.into(),
-            type_buffer: TextBuffer::new(),
-            type_command_buffer: TextBuffer::new(),
+            session: Session::new(instructions),
             highlighter,
             buffer: CanvasBuffer::default(),
             lines: InactiveScratch::new(),
-            line_pause: Duration::ZERO,
-            extension: "txt".into(),
-            theme: String::from("togglebit"),
             audio: AudioShell::new(),
+            metronome: false,
             frame_timer: Timer::new(frame_time),
+            frame_time,
+            seed: None,
             size: Size::ZERO,
-            command_clear_timeout: Duration::from_secs(1),
+            color_columns: ColorColumns::new(),
+            shutdown,
+            typing_rate: TypingRate::new(Duration::from_secs(1)),
+            typed_this_tick: 0,
+            schedule: Schedule::new(),
+            next_transition: None,
+            transition: None,
+            hscroll: HScroll::default(),
+            scrolloff: SCROLLOFF,
+            wrap: None,
+            ligatures: Ligatures::new(),
+            timings,
+            clock: Duration::ZERO,
+            mirror: None,
+            overlays: Overlays::new(),
+            events: Events::default(),
+            command_height: 0,
+            key_overlay: KeyOverlay::new(),
+            links: Links::new(),
+            menu: None,
+            splash: None,
+            margins: Margins::default(),
+            popup: Popup::new(),
+            auto_typo: AutoTypo::default(),
+            paused: false,
+            stepping: Stepping::default(),
+            looping: None,
+            exit_on_end: None,
+            ended_for: Duration::ZERO,
+            exiting: false,
+            completed: Arc::new(AtomicBool::new(false)),
+            from: None,
+            seeking: false,
+            scene: None,
+            progress: None,
+        }
+    }
+
+    /// Send every drawn frame to the attached viewers
+    pub fn set_mirror(&mut self, mirror: Mirror) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Report document stats and markers as they change
+    pub fn set_events(&mut self, events: Events) {
+        self.events = events;
+    }
+
+    /// Everything reported so far, when the events are kept in memory
+    #[cfg(test)]
+    pub fn take_events(&mut self) -> Vec<Event> {
+        self.events.take()
+    }
+
+    #[cfg(test)]
+    pub fn text(&self) -> &str {
+        self.session.doc.text()
+    }
+
+    /// Play the script `scale` times faster (or slower) than written, waits included.
+    /// The speeds set by the script stay as they are
+    pub fn set_time_scale(&mut self, scale: f64, state: &mut DocState) {
+        self.frame_timer.set_scale(scale);
+        let scale = self.frame_timer.scale;
+        state.time_scale.set(match scale == 1.0 {
+            true => String::new(),
+            false => format!("{scale}x"),
+        });
+        self.update_progress(state);
+    }
+
+    /// Stop or continue the playback. Nothing advances while paused, including waits
+    pub fn toggle_pause(&mut self, state: &mut DocState) {
+        self.paused = !self.paused;
+        state.paused.set(self.paused);
+    }
+
+    /// Turn step mode on or off: the script only moves on by one step per [`Editor::step`].
+    /// Waits are skipped while stepping
+    pub fn toggle_stepping(&mut self, state: &mut DocState) {
+        self.stepping.toggle();
+        state.stepping.set(self.stepping.enabled);
+        self.update_position(state);
+    }
+
+    pub fn set_step_by(&mut self, by: StepBy) {
+        self.stepping.by = by;
+    }
+
+    /// Take the next step in step mode
+    pub fn step(&mut self) {
+        if self.stepping.enabled {
+            self.stepping.allow();
         }
     }
 
+    /// Play the script `count` times. This wins over a `loop` in the script
+    pub fn set_loop(&mut self, count: LoopCount) {
+        self.looping = Some(Looping::new(count, Duration::ZERO));
+    }
+
+    /// Apply everything before `to` at once when the editor is ready, and again whenever it starts over.
+    /// Nothing is timed, waited for or heard until then
+    pub fn seek(&mut self, to: Seek) {
+        self.from = Some(to);
+        self.seeking = true;
+    }
+
+    /// Show how far through the script the playback is, and the estimated time left
+    pub fn set_progress(&mut self, progress: Progress, state: &mut DocState) {
+        self.progress = Some(progress);
+        self.update_progress(state);
+    }
+
+    /// Set once the script quits by itself with `exit` or `exit_on_end`, rather than being stopped
+    pub fn completed(&self) -> Arc<AtomicBool> {
+        self.completed.clone()
+    }
+
+    // `exit` was reached, or the script has ended for as long as `exit_on_end` waits
+    fn should_exit(&self) -> bool {
+        self.exiting || self.exit_on_end.is_some_and(|after| self.ended_for >= after)
+    }
+
+    /// Play the script again from the start, as if it was just started.
+    /// The speed, the loop and whether it's paused or stepping stay as they are
+    pub fn restart(&mut self, state: &mut DocState) {
+        let mut instructions = std::mem::replace(&mut self.session.instructions, Queue::new(Vec::<Instruction>::new()));
+        instructions.rewind();
+        self.session = Session::new(Vec::<Instruction>::new());
+        self.session.instructions = instructions;
+
+        let scale = self.frame_timer.scale;
+        self.frame_timer = Timer::new(self.frame_time);
+        self.frame_timer.scale = scale;
+        if let Some(seed) = self.seed {
+            self.frame_timer.rand = Random::with_seed(seed);
+        }
+
+        self.size = self.margins.outer(self.size);
+        self.margins = Margins::default();
+        self.audio.restart();
+        self.metronome = false;
+        self.color_columns = ColorColumns::new();
+        self.typing_rate.reset();
+        self.typed_this_tick = 0;
+        self.schedule = Schedule::new();
+        self.next_transition = None;
+        self.transition = None;
+        self.hscroll = HScroll::default();
+        self.scrolloff = SCROLLOFF;
+        self.wrap = None;
+        self.ligatures = Ligatures::new();
+        self.overlays = Overlays::new();
+        self.key_overlay = KeyOverlay::new();
+        self.links = Links::new();
+        self.menu = None;
+        self.splash = None;
+        self.popup = Popup::new();
+        self.auto_typo = AutoTypo::default();
+        self.exit_on_end = None;
+        self.ended_for = Duration::ZERO;
+        self.exiting = false;
+        self.seeking = self.from.is_some();
+        self.scene = None;
+
+        state.title.set(String::new());
+        state.error.set(String::new());
+        state.debug.set(String::new());
+        state.show_line_numbers.set(false);
+        state.command_buffer.set(String::new());
+        state.show_cursor.set(true);
+        state.chars_typed_total.set(0);
+        state.chars_per_second_ewma.set(0);
+        state.last_change_row.set(0);
+        state.recent_keys.set(String::new());
+        state.margin_top.set(0);
+        state.margin_right.set(0);
+        state.margin_bottom.set(0);
+        state.margin_left.set(0);
+        self.update_command_height(state);
+        self.update_menu(state);
+        self.update_popup(state);
+        self.update_position(state);
+        self.update_progress(state);
+    }
+
+    /// Use the same random numbers on every run, for jitter and transitions
+    pub fn seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.frame_timer.rand = Random::with_seed(seed);
+    }
+
+    /// The script is never started over, by a key or a loop, so what's played doesn't have to be kept
+    pub fn forget_history(&mut self) {
+        self.session.instructions.forget_history();
+    }
+
+    /// Set the size of the canvas when there is no runtime to lay it out
+    pub fn resize(&mut self, size: Size) {
+        self.size = size;
+    }
+
+    /// Nothing left to type, apply or wait for
+    pub fn is_done(&mut self) -> bool {
+        self.exiting
+            || self.session.instructions.is_empty()
+            && self.session.type_buffer.peek().is_none()
+            && self.session.type_command_buffer.peek().is_none()
+            && self.session.backspaces == 0
+            && self.menu.is_none()
+            && self.transition.is_none()
+            && !self.looping.as_ref().is_some_and(Looping::again)
+    }
+
+    pub fn cursor(&self) -> Pos {
+        self.session.cursor
+    }
+
+    /// Draw the current frame into a grid rather than a canvas
+    pub fn render_grid(&mut self) -> super::error::Result<Grid> {
+        let mut grid = Grid::new(self.size);
+        self.render(&mut grid)?;
+        Ok(grid)
+    }
+
     fn error(&mut self, state: &mut DocState, msg: impl Into<String>) {
-        self.instructions.clear();
+        self.session.instructions.clear();
+        self.schedule.stop();
+        self.looping = None;
         state.error.set(msg.into());
     }
 
+    // The script has ended: start it over after the pause, if it loops
+    fn play_again(&mut self, state: &mut DocState) -> RenderAction {
+        let Some(looping) = self.looping.as_mut().filter(|looping| looping.again()) else { return RenderAction::Skip };
+        if !looping.pausing && !looping.pause.is_zero() {
+            looping.pausing = true;
+            self.frame_timer.wait(looping.pause);
+            return RenderAction::Wait;
+        }
+        looping.pausing = false;
+        looping.played += 1;
+        self.restart(state);
+        RenderAction::NextInstruction
+    }
+
     fn apply(&mut self, state: &mut DocState) -> RenderAction {
-        if let Some(s) = self.type_command_buffer.next() {
+        if self.transition.as_ref().is_some_and(Transition::is_blocking) || self.menu.is_some() {
+            return RenderAction::Skip;
+        }
+
+        // Stepping by characters holds every pass, so instructions are held as well
+        if !self.stepping.take(StepBy::Char) {
+            return RenderAction::Skip;
+        }
+
+        if let Some(s) = self.session.type_command_buffer.next() {
             state.command_buffer.to_mut().push_str(s);
+            self.typed_this_tick += s.chars().count();
+            self.key_overlay.push(s, true);
+            state.recent_keys.set(self.key_overlay.text());
+            self.update_command_height(state);
             return RenderAction::NextFrame;
         }
 
+        // A typo is typed as instructions, along with the rest of the buffer
+        let row = self.session.cursor.y;
+        let buffer = &mut self.session.type_buffer;
+        if let Some(typo) = self.auto_typo.next(buffer, row, &mut self.frame_timer.rand) {
+            typo.into_iter().rev().for_each(|inst| self.session.instructions.push_front(inst));
+        }
+
         // If we have something to type then do that.
         // otherwise load the next instruction
-        if let Some(s) = self.type_buffer.next() {
-            self.doc.insert_str(self.cursor, s);
+        if let Some((typed, mut pause)) = self.session.type_next() {
+            self.typed_this_tick += typed.chars().count();
+            state.last_change_row.set(row);
 
-            self.audio.play(s);
+            self.key_overlay.push(&typed, false);
+            state.recent_keys.set(self.key_overlay.text());
 
-            if s == "\n" {
-                self.cursor.x = 0;
-                self.cursor.y += 1;
+            pause += self.frame_timer.pause_before(self.session.type_buffer.peek());
+            if !pause.is_zero() {
+                self.frame_timer.wait(pause);
+            }
 
-                if self.line_pause > Duration::ZERO {
-                    self.frame_timer.wait(self.line_pause);
-                    return RenderAction::NextFrame;
-                }
-            } else {
-                self.cursor.x += s.width() as i32;
+            if self.metronome {
+                self.audio.click();
             }
+            // The sound of the next glyph might be due before the next frame
+            self.audio.typed(&typed, self.session.type_buffer.peek(), self.frame_timer.until_next());
 
             return RenderAction::NextFrame;
         }
 
-        let instruction = self.instructions.pop_front();
-        match instruction {
-            None => return RenderAction::Skip,
-            Some(instruction) => {
-                match instruction {
-                    Instruction::LoadCommandBuffer(content) => {
-                        state.show_cursor.set(false);
-                        self.type_command_buffer.push(content);
-                    }
-                    Instruction::LoadTypeBuffer(content) => {
-                        // Make markers and all that what what
-                        let (content, markers) = generate(content);
-                        self.type_buffer.push(content);
-
-                        if let Some(markers) = markers {
-                            self.instructions.push_front(Instruction::AddMarkers {
-                                row: self.cursor.y as usize,
-                                markers,
-                            });
-                        }
-                    }
-                    Instruction::Insert(content) => {
-                        let (content, markers) = generate(content);
-                        self.cursor.x = 0;
-                        self.doc.insert_str(self.cursor, &content);
-                        if let Some(markers) = markers {
-                            self.instructions.push_front(Instruction::AddMarkers {
-                                row: self.cursor.y as usize,
-                                markers,
-                            });
-                        }
-                    }
-                    Instruction::AddMarkers { row, markers } => self.doc.add_markers(row, markers),
-                    Instruction::Jump(pos) => {
-                        self.cursor += pos;
-                        // Don't move the cursor past zero
-                        self.cursor.x = self.cursor.x.max(0);
-                        self.cursor.y = self.cursor.y.max(0);
-                    }
-                    Instruction::JumpToMarker(name) => {
-                        let Some(row) = self.doc.lookup_marker(&name).map(|m| m.row) else {
-                            self.error(state, format!("marker \"{name}\" does not exist"));
-                            return RenderAction::NextFrame;
-                        };
-                        self.cursor.y = row as i32;
-                        self.cursor.x = 0;
-                    }
-                    Instruction::Select(size) if size == Size::ZERO => return RenderAction::NextInstruction,
-                    Instruction::Select(size) => {
-                        let visual_range = VisualRange::new(self.cursor, size);
-                        self.cursor = visual_range.region.to - Pos::new(1, 1);
-                        self.selected_range = Some(visual_range);
-                    }
-                    Instruction::Delete => match self.selected_range.take() {
-                        Some(range) => {
-                            self.cursor = range.region.from;
-                            self.doc.delete(range.region);
-                        }
-                        None => self.doc.delete(Region::from((self.cursor, Size::new(1, 1)))),
-                    },
-                    Instruction::Wait(dur) => {
-                        self.frame_timer.wait(dur);
-                        return RenderAction::NextFrame;
-                    }
-                    Instruction::Speed(dur) => self.frame_timer.frame_time = dur,
-                    Instruction::FindInCurrentLine { needle, .. } if needle.is_empty() => (),
-                    Instruction::FindInCurrentLine {
-                        needle,
-                        end_of_word,
-                        count,
-                    } => {
-                        let Some(x) = self.doc.find(self.cursor, &needle, count) else { return RenderAction::NextInstruction };
-                        self.cursor.x = x as i32;
-                        if end_of_word {
-                            self.cursor.x += needle.width() as i32 - 1;
-                        }
-                    }
-                    Instruction::LinePause(duration) => self.line_pause = duration,
-                    Instruction::SetTitle(title) => state.title.set(title),
-                    Instruction::SetJitter(jitter) => self.frame_timer.jitter_ms = jitter,
-                    Instruction::ShowLineNumbers(show) => state.show_line_numbers.set(show),
-                    Instruction::Clear => {
-                        self.doc.clear();
-                        self.offset = Pos::ZERO;
-                        self.cursor = Pos::ZERO;
-                    }
-                    Instruction::SetExtension(ext) => self.extension = ext,
-                    Instruction::SetTheme(theme) => self.theme = theme,
-                    Instruction::LoadAudio(path) => {
-                        if let Err(e) = self.audio.load(path) {
-                            self.error(state, e.to_string());
-                        }
-                    }
-                    Instruction::Popup(message) => state.popup.set(message),
-                    Instruction::ClosePopup => state.popup.set(String::new()),
-                    Instruction::WriteBuffer(path_buf) if path_buf.exists() => {
-                        self.error(state, format!("can't write to {path_buf:?}, file already exists"));
-                    }
-                    Instruction::WriteBuffer(path_buf) => match std::fs::File::create(&path_buf) {
-                        Err(e) => self.error(state, format!("failed to create {path_buf:?} : {e}")),
-                        Ok(mut file) => {
-                            if let Err(e) = file.write_all(self.doc.text().as_bytes()) {
-                                self.error(state, format!("failed to write {path_buf:?} : {e}"));
-                            }
-                        }
-                    },
-                    Instruction::ClearCommandBuffer => {
-                        state.command_buffer.to_mut().clear();
-                        state.show_cursor.set(true);
-                    }
-                    Instruction::CommandClearTimeout(duration) => self.command_clear_timeout = duration,
-                    Instruction::ClearCommandWait => self
-                        .instructions
-                        .push_front(Instruction::Wait(self.command_clear_timeout)),
-                    Instruction::SetVariable(name, variable) => {
-                        let value: Box<dyn State> = match variable {
-                            crate::parser::Variable::Bool(var) => Box::new(var),
-                            crate::parser::Variable::Str(var) => Box::new(var),
-                            crate::parser::Variable::Int(var) => Box::new(var),
-                        };
-                        state.ctx.to_mut().insert(name, value);
-                    }
+        if self.session.backspace_next() {
+            state.last_change_row.set(self.session.cursor.y);
+            self.key_overlay.push(BACKSPACE, false);
+            state.recent_keys.set(self.key_overlay.text());
+            let next = (self.session.backspaces > 0).then_some(BACKSPACE);
+            self.audio.typed(BACKSPACE, next, self.frame_timer.until_next());
+            return RenderAction::NextFrame;
+        }
+
+        // Recurring tasks are only spliced in between instructions,
+        // never in the middle of typing
+        if let Some(body) = self.schedule.poll(self.session.instructions.taken()) {
+            body.into_iter().rev().for_each(|inst| self.session.instructions.push_front(inst));
+        }
+
+        if !self.stepping.take(StepBy::Instruction) {
+            return RenderAction::Skip;
+        }
+
+        let Some(instruction) = self.session.instructions.pop_front() else { return self.play_again(state) };
+        if self.stepping.enabled {
+            self.update_position(state);
+        }
+        let effect = self.session.apply(instruction);
+        self.apply_effect(effect, state)
+    }
+
+    fn apply_effect(&mut self, effect: Effect, state: &mut DocState) -> RenderAction {
+        match effect {
+            Effect::Next => {}
+            Effect::Changed(row) => state.last_change_row.set(row),
+            Effect::Wait(_) if self.stepping.enabled => {}
+            Effect::Wait(duration) => {
+                self.frame_timer.wait(duration);
+                return RenderAction::Wait;
+            }
+            Effect::Error(msg) => {
+                self.error(state, msg);
+                return RenderAction::NextFrame;
+            }
+            Effect::Warning(msg) => state.debug.set(msg),
+            Effect::Cleared => {
+                if let Some((kind, duration)) = self.next_transition.take() {
+                    let transition = Transition::new(kind, duration, self.size, &mut self.frame_timer.rand);
+                    self.transition = Some(transition);
+                }
+                self.schedule.stop();
+            }
+            Effect::HideCursor => state.show_cursor.set(false),
+            Effect::ClearCommandBuffer => {
+                state.command_buffer.to_mut().clear();
+                self.update_command_height(state);
+                state.show_cursor.set(true);
+            }
+            Effect::Write(path, text) => {
+                self.events.written(&text);
+                if let Err(e) = self.shutdown.write(path, text) {
+                    self.error(state, e);
                 }
             }
+            Effect::Ui(instruction) => self.apply_ui(instruction, state),
         }
 
         RenderAction::NextInstruction
     }
 
-    fn update_cursor(&mut self, state: &mut DocState) {
-        static PADDING: i32 = 7;
+    // Apply the script up to `to` without typing, waiting or playing a sound,
+    // returning false if it ended before getting there
+    fn fast_forward(&mut self, to: &Seek, state: &mut DocState) -> bool {
+        loop {
+            while let Some(s) = self.session.type_command_buffer.next() {
+                state.command_buffer.to_mut().push_str(s);
+            }
+            while self.session.type_next().is_some() {}
+            while self.session.backspace_next() {}
+            if let Some(text) = self.menu.take().and_then(Menu::then_type) {
+                self.session.type_buffer.push(text);
+                continue;
+            }
+            self.transition = None;
 
-        let height = self.size.height as i32 - 1 - PADDING;
-        let width = self.size.width as i32 - 1;
+            let reached = match to {
+                Seek::Marker(name) => self.session.doc.markers.get(name).is_some(),
+                Seek::Scene(name) => self.scene.as_ref() == Some(name),
+                Seek::Instruction(index) => self.session.instructions.position() + 1 >= *index,
+            };
+            if reached {
+                return true;
+            }
 
-        let y = self.cursor.y + self.offset.y;
-        if y > height {
-            self.offset.y = height - self.cursor.y;
-        } else if y < 0 {
-            self.offset.y -= self.cursor.y + self.offset.y;
+            let Some(instruction) = self.session.instructions.pop_front() else { return false };
+            match self.session.apply(instruction) {
+                Effect::Wait(_) => {}
+                effect => _ = self.apply_effect(effect, state),
+            }
+            for shift in self.session.doc.take_shifts() {
+                self.overlays.shift(shift);
+            }
         }
+    }
 
-        let x = self.cursor.x + self.offset.x;
-        if x > width {
-            self.offset.x = width - self.cursor.x;
-        } else if x < 0 {
-            self.offset.x -= self.cursor.x + self.offset.x;
+    // Instructions that only concern the presentation, timing and audio
+    fn apply_ui(&mut self, instruction: Instruction, state: &mut DocState) {
+        match instruction {
+            Instruction::Speed(dur) => self.frame_timer.frame_time = dur,
+            Instruction::CommandSpeed(dur) => self.frame_timer.command_frame_time = dur,
+            Instruction::SetTitle(title) => state.title.set(title),
+            Instruction::SetJitter(jitter) => self.frame_timer.jitter_ms = jitter,
+            Instruction::JitterProfile(profile) => self.frame_timer.set_profile(profile),
+            Instruction::BurstPause { min, max } => self.frame_timer.burst_pause = (min, max),
+            Instruction::AutoTypo { rate, max_per_line } => self.auto_typo = AutoTypo::new(rate, max_per_line),
+            Instruction::ShowLineNumbers(show) => state.show_line_numbers.set(show),
+            Instruction::HScroll(policy) => self.hscroll = policy,
+            Instruction::ScrollOff { rows, columns } => {
+                self.scrolloff.0 = rows as i32;
+                if let Some(columns) = columns {
+                    self.scrolloff.1 = columns as i32;
+                }
+            }
+            Instruction::Wrap(indicator) => self.wrap = indicator,
+            // The count is only taken the first time, unless it was given as an option
+            Instruction::Loop { count, pause } => match &mut self.looping {
+                Some(looping) => looping.pause = pause,
+                None => self.looping = Some(Looping::new(count, pause)),
+            },
+            Instruction::Exit => {
+                self.session.instructions.clear();
+                self.looping = None;
+                self.exiting = true;
+            }
+            Instruction::ExitOnEnd(after) => self.exit_on_end = Some(after),
+            Instruction::BreakLigatures(break_sequences) => self.ligatures.set_break(break_sequences),
+            Instruction::AddLigature(seq) => self.ligatures.add(&seq),
+            Instruction::ColorColumn(columns) => self.color_columns.set(columns),
+            Instruction::Margins(margins) => {
+                // The canvas is what's inside the current margins
+                let total = self.margins.outer(self.size);
+                let Some(size) = margins.inner(total) else {
+                    let msg = format!("margins ({margins}) don't fit in {}x{}", total.width, total.height);
+                    return self.error(state, msg);
+                };
+                self.margins = margins;
+                self.size = size;
+                state.margin_top.set(margins.top);
+                state.margin_right.set(margins.right);
+                state.margin_bottom.set(margins.bottom);
+                state.margin_left.set(margins.left);
+            }
+            Instruction::Every { interval, body } => self.schedule.add(interval, body),
+            Instruction::EveryStop => self.schedule.stop(),
+            Instruction::Budget { .. } => self.timings.start(self.clock),
+            Instruction::BudgetEnd => self.timings.end(self.clock),
+            Instruction::Scene(name) => {
+                if let Err(e) = self.events.scene(&name) {
+                    return self.error(state, format!("failed to write events: {e}"));
+                }
+                self.scene = Some(name);
+            }
+            Instruction::WaitRange(min, max) => {
+                let wait = self.frame_timer.between(min, max);
+                self.session.instructions.push_front(Instruction::Wait(wait));
+            }
+            Instruction::Transition(kind, duration) => self.next_transition = Some((kind, duration)),
+            Instruction::ResetCounters => {
+                self.typing_rate.reset();
+                state.chars_typed_total.set(0);
+                state.chars_per_second_ewma.set(0);
+                state.last_change_row.set(0);
+            }
+            Instruction::LoadAudio(path) => {
+                if let Err(e) = self.audio.load(path) {
+                    self.error(state, e.to_string());
+                }
+            }
+            Instruction::AudioClasses(classes) => self.audio.set_classes(classes),
+            Instruction::AudioOffset(ms) => self.audio.set_offset(ms),
+            Instruction::Metronome(on) => self.metronome = on,
+            Instruction::KeyOverlay(show) => {
+                self.key_overlay.set_enabled(show);
+                state.recent_keys.set(self.key_overlay.text());
+            }
+            Instruction::KeyOverlayHold(hold) => self.key_overlay.set_hold(hold),
+            Instruction::Hyperlinks(show) => self.links.set_enabled(show),
+            Instruction::Link { text, url } => self.links.add(self.session.cursor, text, url),
+            Instruction::Duck {
+                percent,
+                attack,
+                release,
+            } => self.audio.duck(percent, attack, release),
+            Instruction::Popup {
+                message,
+                close_after,
+                position,
+                title,
+            } => {
+                self.popup.set(&message);
+                if let Some(duration) = close_after {
+                    self.popup.close_after(duration);
+                }
+                self.popup.set_position(position);
+                self.popup.set_title(title);
+                self.update_popup(state);
+            }
+            Instruction::PopupAt(position) => {
+                self.popup.set_position(position);
+                self.update_popup(state);
+            }
+            Instruction::PopupScroll(lines) => {
+                self.popup.scroll(lines, self.popup_height());
+                self.update_popup(state);
+            }
+            Instruction::PopupAutoscroll(speed) => self.popup.set_autoscroll(speed),
+            Instruction::Menu {
+                items,
+                choice,
+                step,
+                then_type,
+            } => {
+                self.menu = Some(Menu::new(items, choice, step, then_type));
+                self.update_menu(state);
+            }
+            Instruction::ClosePopup => {
+                self.popup.clear();
+                self.update_popup(state);
+            }
+            Instruction::Splash(splash) => {
+                self.splash = Some(splash);
+                state.show_cursor.set(false);
+            }
+            Instruction::HideSplash => {
+                self.splash = None;
+                state.show_cursor.set(true);
+            }
+            Instruction::SetVariable(name, variable) => {
+                let value: Box<dyn State> = match variable {
+                    crate::parser::Variable::Bool(var) => Box::new(var),
+                    crate::parser::Variable::Str(var) => Box::new(var),
+                    crate::parser::Variable::Int(var) => Box::new(var),
+                };
+                state.ctx.to_mut().insert(name, value);
+            }
+            // The session applies everything else, so getting one here is a bug rather than a bad script
+            inst => self.error(state, format!("{inst:?} can't be applied by the editor")),
         }
-
-        state.screen_cursor_x.set(self.cursor.x + self.offset.x);
-        state.screen_cursor_y.set(self.cursor.y + self.offset.y);
-        state.cursor_x.set(self.cursor.x);
-        state.cursor_y.set(self.cursor.y);
-        state.offset_x.set(self.offset.x);
-        state.offset_y.set(self.offset.y);
     }
 
-    fn draw(&mut self, mut elements: Elements<'_, '_, '_>, state: &mut DocState) {
-        elements.by_tag("canvas").first(|el, _| {
-            let canvas = el.to::<Canvas>();
-            canvas.clear();
+    /// Instructions are held until the size of the canvas is known
+    pub fn ready(&self) -> bool {
+        self.size != Size::ZERO
+    }
 
-            let mut y = self.offset.y;
+    // Advance the editor by `dt`, returning true if the editor should be drawn.
+    // Nothing advances until the editor is ready, so no time is owed once it is,
+    // or while it's paused.
+    pub fn update(&mut self, state: &mut DocState, dt: Duration) -> bool {
+        if !self.ready() || self.paused || self.stepping.held {
+            return false;
+        }
 
-            // re-highlight the content
-            let scratch = unsafe { self.lines.activate(self.doc.text()) };
-            let res = scratch.with(|lines, code| {
-                self.highlighter.highlight(&self.theme, code, &self.extension, lines)?;
+        state.height.set(self.size.height);
 
-                let skip = (y < 0).then_some(y.abs() as usize).unwrap_or(0);
-                y = 0;
-                for spans in lines.iter().skip(skip) {
-                    let mut x = self.offset.x;
-                    for span in spans {
-                        for c in span.src.chars() {
-                            if x >= 0 {
-                                let pos: LocalPos = (x, y).into();
-                                let mut style = span.style();
-                                // if we have a selected range
-                                // then set the background of the style to red
-                                // but only if the pos is inside the selected range
-                                if self.selected_range.contains(pos.into()) {
-                                    style.bg = Some(Color::Red);
-                                }
-                                canvas.put(c, style, pos);
-                            }
-                            x += c.width().unwrap_or(0) as i32;
-                        }
-                    }
+        self.audio.tick(dt);
+        self.schedule.tick(dt);
+        self.clock += dt;
 
-                    y += 1;
-                }
+        if self.key_overlay.tick(dt) {
+            state.recent_keys.set(self.key_overlay.text());
+        }
 
-                Ok::<_, super::error::Error>(())
-            });
+        if let Some(e) = self.shutdown.take_error() {
+            self.error(state, e);
+        }
 
-            if let Err(e) = res {
-                self.error(state, e.to_string());
+        let mut render = false;
+        if std::mem::take(&mut self.seeking)
+            && let Some(to) = self.from.clone()
+        {
+            let reached = self.fast_forward(&to, state);
+            if let Some(e) = self.session.instructions.take_error() {
+                self.error(state, e);
+            } else if !reached && state.error().is_none() {
+                self.error(state, format!("the script ended before {to}"));
             }
-        });
-    }
-}
-
-impl Component for Editor {
-    type Message = Instruction;
-    type State = DocState;
+            self.update_command_height(state);
+            self.update_menu(state);
+            self.update_popup(state);
+            self.update_position(state);
+            self.update_progress(state);
+            render = true;
+        }
 
-    fn on_key(&mut self, key: KeyEvent, _: &mut Self::State, _: Children<'_, '_>, _: Context<'_, '_, Self::State>) {
-        match key.code {
-            // KeyCode::Char('h') => self.instructions.push_back(Instruction::Jump(Pos::new(-1, 0))),
-            // KeyCode::Char('j') => self.instructions.push_back(Instruction::Jump(Pos::new(0, 1))),
-            // KeyCode::Char('k') => self.instructions.push_back(Instruction::Jump(Pos::new(0, -1))),
-            // KeyCode::Char('l') => self.instructions.push_back(Instruction::Jump(Pos::new(1, 0))),
-            // KeyCode::Char('d') => self.instructions.push_back(Instruction::Jump(Pos::new(0, 9))),
-            _ => {}
+        if let Some(transition) = &mut self.transition {
+            transition.tick(dt);
+            render = true;
         }
-    }
 
-    fn on_tick(
-        &mut self,
-        state: &mut Self::State,
-        mut children: Children<'_, '_>,
-        _: Context<'_, '_, Self::State>,
-        dt: Duration,
-    ) {
-        if self.size == Size::ZERO {
-            let Some(size) = children.elements().by_tag("canvas").first(|el, _| el.size()) else { return };
-            self.size = size;
+        if self.popup.tick(dt, self.popup_height()) {
+            self.update_popup(state);
+            render = true;
         }
 
-        state.height.set(self.size.height);
+        if let Some(menu) = &mut self.menu {
+            if menu.tick(dt) {
+                render = true;
+            }
+            if menu.is_done()
+                && let Some(text) = self.menu.take().and_then(Menu::then_type)
+            {
+                self.session.type_buffer.push(text);
+            }
+            self.update_menu(state);
+        }
 
+        self.frame_timer.typing_command = self.session.type_command_buffer.peek().is_some();
         let mut count = self.frame_timer.tick(dt);
-        let mut render = false;
 
+        // Instructions that don't take a frame are applied in the same tick,
+        // so there is no idle frame between them and the typing that follows
+        let mut free = 0;
         while count > 0 {
-            match self.apply(state) {
-                RenderAction::NextInstruction => render = true,
+            let action = self.apply(state);
+            for shift in self.session.doc.take_shifts() {
+                self.overlays.shift(shift);
+            }
+            if let Err(e) = self.events.observe(&self.session.doc) {
+                self.error(state, format!("failed to write events: {e}"));
+            }
+            match action {
+                RenderAction::NextInstruction => {
+                    render = true;
+                    free += 1;
+                    if free == MAX_FREE_INSTRUCTIONS {
+                        break;
+                    }
+                }
                 RenderAction::Skip => break,
+                RenderAction::Wait => {
+                    self.frame_timer.owe(count);
+                    render = true;
+                    break;
+                }
                 RenderAction::NextFrame => {
                     count -= 1;
                     render = true;
@@ -471,10 +1131,519 @@ impl Component for Editor {
             }
         }
 
-        if render {
-            self.update_cursor(state);
+        // A script that is compiled as it's taken can fail part of the way through
+        if let Some(e) = self.session.instructions.take_error() {
+            self.error(state, e);
+            render = true;
+        }
+
+        // Typing and waiting move the progress along as well
+        self.update_progress(state);
+
+        let typed = std::mem::take(&mut self.typed_this_tick);
+        if typed > 0 {
+            *state.chars_typed_total.to_mut() += typed as i32;
+        }
+        let chars_per_second = self.typing_rate.update(typed, dt).round() as i32;
+        if chars_per_second != *state.chars_per_second_ewma.to_ref() {
+            state.chars_per_second_ewma.set(chars_per_second);
+        }
+
+        // An error is left on screen rather than counting as the end
+        match self.is_done() && state.error().is_none() {
+            true => self.ended_for += dt,
+            false => self.ended_for = Duration::ZERO,
+        }
+
+        render
+    }
+
+    // The estimate is of the script as written, so it's scaled like the playback
+    fn update_progress(&self, state: &mut DocState) {
+        let Some(progress) = &self.progress else { return };
+        let taken = self.session.instructions.position();
+        let buffers = [&self.session.type_buffer, &self.session.type_command_buffer];
+        let left = match buffers.into_iter().find(|buffer| buffer.peek().is_some()) {
+            Some(buffer) => Left::Typing(buffer.left()),
+            None => Left::Wait(self.frame_timer.wait),
+        };
+
+        let fraction = progress.fraction(taken, left);
+        if fraction != *state.progress.to_ref() {
+            state.progress.set(fraction);
+        }
+        let secs = progress.remaining(taken, left).div_f64(self.frame_timer.scale).as_secs();
+        let remaining = format!("{}:{:02}", secs / 60, secs % 60);
+        if remaining != *state.remaining.to_ref() {
+            state.remaining.set(remaining);
+        }
+    }
+
+    fn update_position(&self, state: &mut DocState) {
+        state.instruction_index.set(self.session.instructions.position() as i32);
+        state.instruction_total.set(self.session.instructions.total().unwrap_or(0) as i32);
+    }
+
+    // The popup is drawn in a border, under its title if it has one, and can't be taller than what's below it
+    fn popup_height(&self) -> usize {
+        let y = match self.popup.position() {
+            PopupPosition::Cursor => self.screen_cursor().1,
+            PopupPosition::At { row, .. } => row as i32,
+            PopupPosition::Top | PopupPosition::Bottom | PopupPosition::Center => 0,
+        };
+        let title = self.popup.title().is_some() as i32;
+        (self.text_height() as i32 - y - 2 - title).max(1) as usize
+    }
+
+    fn update_popup(&self, state: &mut DocState) {
+        let visible = self.popup.visible(self.popup_height());
+        if *state.popup.to_ref() != visible {
+            state.popup.set(visible);
+        }
+
+        let title = self.popup.title().unwrap_or_default();
+        if *state.popup_title.to_ref() != title {
+            state.popup_title.set(title.to_string());
+        }
+
+        let (align, x, y) = match self.popup.position() {
+            PopupPosition::Cursor => {
+                let (x, y) = self.screen_cursor();
+                ("", x, y)
+            }
+            PopupPosition::Top => ("top", 0, 0),
+            PopupPosition::Bottom => ("bottom", 0, 0),
+            PopupPosition::Center => ("centre", 0, 0),
+            PopupPosition::At { row, col } => ("", col as i32, row as i32),
+        };
+        if *state.popup_align.to_ref() != align {
+            state.popup_align.set(align.to_string());
+        }
+        state.popup_x.set(x);
+        state.popup_y.set(y);
+    }
+
+    fn update_menu(&self, state: &mut DocState) {
+        let Some(menu) = &self.menu else {
+            state.show_menu.set(false);
+            while state.menu.pop().is_some() {}
+            return;
+        };
+
+        let (first, items) = menu.window();
+        let view = menu.view();
+        let selected = match view.highlight {
+            true => (view.selected - first) as i32,
+            false => -1,
+        };
+        state.menu_selected.set(selected);
+
+        let current = state.menu.to_ref().iter().map(|item| item.to_ref().clone()).collect::<Vec<_>>();
+        if current != items {
+            while state.menu.pop().is_some() {}
+            items.iter().for_each(|item| state.menu.push(item.clone()));
+        }
+        state.show_menu.set(true);
+    }
+
+    // The cursor on screen. Virtual text below a line and wrapped lines push the rows after
+    // them down, so the vertical offset is in screen rows rather than document rows.
+    fn screen_cursor(&self) -> (i32, i32) {
+        let cursor = self.session.cursor;
+        let offset = self.session.offset;
+        match &self.wrap {
+            Some(indicator) => {
+                let (x, y) = wrap::cursor(&self.screen_rows(), cursor, indicator.width());
+                (x, y + offset.y)
+            }
+            None => {
+                let y = self.session.doc.virtual_text.screen_row(cursor.y as usize) as i32;
+                (cursor.x + offset.x, y + offset.y)
+            }
+        }
+    }
+
+    // What is on each row of the screen before scrolling
+    fn screen_rows(&self) -> Vec<ScreenRow<'_>> {
+        let doc = &self.session.doc;
+        let rows = doc.virtual_text.screen_rows(doc.line_count());
+        match &self.wrap {
+            Some(indicator) => {
+                let width = self.size.width as usize;
+                wrap_rows(rows, doc.text(), width, indicator.width(), self.session.cursor)
+            }
+            None => rows,
+        }
+    }
+
+    // The rows of the canvas that aren't covered by the command line
+    fn text_height(&self) -> u16 {
+        self.size.height.saturating_sub(self.command_height)
+    }
+
+    fn update_command_height(&mut self, state: &mut DocState) {
+        let height = command_height(&state.command_buffer.to_ref(), self.size.width);
+        if height != self.command_height {
+            self.command_height = height;
+            state.command_height.set(height);
+        }
+    }
+
+    fn update_cursor(&mut self, state: &mut DocState) {
+        let height = self.text_height() as i32;
+        let width = self.size.width as i32 - 1;
+
+        let (rows, columns) = self.scrolloff;
+        let (_, y) = self.screen_cursor();
+        self.session.offset.y = vertical_offset(self.session.offset.y, y, height, rows);
+        let x = self.session.cursor.x;
+        self.session.offset.x = match self.wrap {
+            // Nothing goes past the edge
+            Some(_) => 0,
+            None => horizontal_offset(self.hscroll, self.session.offset.x, x, width, columns),
+        };
+
+        let (x, y) = self.screen_cursor();
+        state.screen_cursor_x.set(x);
+        state.screen_cursor_y.set(y);
+        state.cursor_x.set(self.session.cursor.x);
+        state.cursor_y.set(self.session.cursor.y);
+        state.offset_x.set(self.session.offset.x);
+        state.offset_y.set(self.session.offset.y);
+        self.update_line_numbers(state);
+        self.update_popup(state);
+    }
+
+    // Line numbers for the rows on screen, where rows of virtual text and the rest of wrapped lines have none
+    fn update_line_numbers(&self, state: &mut DocState) {
+        let skip = self.session.offset.y.unsigned_abs() as usize;
+        let height = self.text_height() as usize;
+        let line_count = self.session.doc.line_count();
+        let rows = self.screen_rows();
+
+        let mut next = line_count;
+        let line_numbers = (skip..skip + height)
+            .map(|y| match rows.get(y) {
+                Some(ScreenRow::Line(row)) => (row + 1).to_string(),
+                Some(ScreenRow::Virtual(..) | ScreenRow::Wrapped(..)) => String::new(),
+                // Past the end of the document
+                None => {
+                    next += 1;
+                    next.to_string()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let current = state.line_numbers.to_ref().iter().map(|n| n.to_ref().clone()).collect::<Vec<_>>();
+        if current != line_numbers {
+            while state.line_numbers.pop().is_some() {}
+            line_numbers.into_iter().for_each(|n| state.line_numbers.push(n));
+        }
+    }
+
+    fn draw(&mut self, mut elements: Elements<'_, '_, '_>, state: &mut DocState) {
+        elements.by_tag("canvas").first(|el, _| {
+            let canvas = el.to::<Canvas>();
+            if let Some(transition) = &mut self.transition {
+                transition.snapshot(canvas);
+            }
+            canvas.clear();
+
+            if let Err(e) = self.render(canvas) {
+                self.error(state, e.to_string());
+                return;
+            }
+
+            if let Some(transition) = &self.transition {
+                transition.cover(canvas);
+            }
+
+            if let Some(mirror) = &mut self.mirror {
+                mirror.capture(canvas, self.size);
+            }
+        });
+    }
+
+    // The selection and the column guides as they are now
+    fn update_overlays(&mut self) {
+        let selection = self.session.selection.as_ref();
+        let selection = selection.map(|range| (Target::Region(range.region), Paint::Highlight(Color::Red)));
+        self.overlays.set_layer(Layer::Selection, selection);
+
+        let offset = self.session.offset.x;
+        let guides = self
+            .color_columns
+            .screen_columns(offset, self.size.width)
+            .map(|x| (Target::Column(x as i32 - offset), Paint::Backdrop(GUIDE_COLOR)));
+        self.overlays.set_layer(Layer::ColumnGuide, guides);
+    }
+
+    // Draw the document and the guides, or only the splash while it's shown
+    fn render(&mut self, surface: &mut impl Surface) -> super::error::Result<()> {
+        if let Some(splash) = &self.splash {
+            let (x, y) = splash.origin(self.size);
+            let style = splash.style();
+            for (i, line) in splash.lines.iter().enumerate() {
+                let y = y + i as i32;
+                if y >= 0 {
+                    put_str(surface, line, style, x, y);
+                }
+            }
+            return Ok(());
+        }
+
+        self.update_overlays();
+
+        let skip = self.session.offset.y.min(0).unsigned_abs() as usize;
+        let virtual_text = &self.session.doc.virtual_text;
+        let indicator = self.wrap.as_deref().unwrap_or_default();
+        let indent = indicator.width() as i32;
+
+        // re-highlight the content
+        let scratch = unsafe { self.lines.activate(self.session.doc.text()) };
+        scratch.with(|lines, code| {
+            self.highlighter.highlight(&self.session.settings.theme, code, &self.session.settings.extension, lines)?;
+
+            let mut virtual_style = Style::new();
+            virtual_style.set_dim(true);
+
+            let mut screen_rows = virtual_text.screen_rows(lines.line_count());
+            if self.wrap.is_some() {
+                let width = self.size.width as usize;
+                screen_rows = wrap_rows(screen_rows, code, width, indent as usize, self.session.cursor);
+            }
+
+            let mut cells = vec![];
+            let mut cells_row = None;
+            for (y, screen_row) in screen_rows.iter().skip(skip).enumerate() {
+                // Where the line continues on the next row, if it does
+                let end = match screen_rows.get(skip + y + 1) {
+                    Some(&ScreenRow::Wrapped(_, end)) => end,
+                    _ => usize::MAX,
+                };
+
+                let y = y as i32;
+                if y >= self.size.height as i32 {
+                    break;
+                }
+
+                // The columns of the line on this row, and where the first of them is drawn
+                let (row, start, origin) = match *screen_row {
+                    ScreenRow::Line(row) => (row, 0, self.session.offset.x),
+                    ScreenRow::Wrapped(row, start) => {
+                        put_str(surface, indicator, virtual_style, 0, y);
+                        (row, start, indent)
+                    }
+                    ScreenRow::Virtual(_, text) => {
+                        put_str(surface, text, virtual_style, self.session.offset.x, y);
+                        continue;
+                    }
+                };
+
+                // The rows of a wrapped line share the cells
+                if cells_row != Some(row) {
+                    let spans = lines.line(row);
+                    cells.clear();
+                    for span in spans {
+                        let style = span.style();
+                        cells.extend(span.src.chars().map(|c| (c, style)));
+                    }
+                    self.ligatures.apply(&mut cells);
+                    self.links.apply(row, &mut cells);
+                    cells_row = Some(row);
+                }
+
+                let mut col = 0;
+                for &(c, style) in &cells {
+                    let x = origin + col as i32 - start as i32;
+                    if (start..end).contains(&col) && x >= 0 {
+                        surface.put(c, style, (x, y).into());
+                    }
+                    col += c.width().unwrap_or(0);
+                }
+
+                if end != usize::MAX {
+                    continue;
+                }
+
+                // Virtual text at the end of the line, one space after it
+                let mut x = origin + col as i32 - start as i32;
+                for text in virtual_text.eol(row) {
+                    x = put_str(surface, text, virtual_style, x + 1, y);
+                }
+            }
+
+            // Overlays go on top of the syntax highlighting, in a single pass.
+            // The cells of a wrapped row are the columns of the line from where the row starts.
+            for y in 0..self.size.height {
+                let (row, offset, first) = match screen_rows.get(skip + y as usize) {
+                    Some(ScreenRow::Line(row)) => (Some(*row), self.session.offset.x, 0),
+                    Some(ScreenRow::Wrapped(row, start)) => (Some(*row), indent - *start as i32, indent),
+                    Some(ScreenRow::Virtual(..)) | None => (None, self.session.offset.x, 0),
+                };
+                for x in 0..self.size.width {
+                    // The wrap indicator isn't part of the line
+                    let row = row.filter(|_| x as i32 >= first);
+                    self.overlays.paint(surface, LocalPos::new(x, y), x as i32 - offset, row);
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+// Rows above and below the cursor, and columns to either side of it, until `scrolloff` says otherwise
+const SCROLLOFF: (i32, i32) = (7, 4);
+
+// The most instructions applied in a single tick without taking a frame
+const MAX_FREE_INSTRUCTIONS: usize = 256;
+
+// The command line grows with its content up to this many rows
+const MAX_COMMAND_HEIGHT: u16 = 5;
+
+// Rows taken by the command line in a canvas `width` wide. The cursor is drawn
+// next to the text, so it wraps a column early. Nothing to show takes no rows.
+fn command_height(command: &str, width: u16) -> u16 {
+    if command.is_empty() {
+        return 0;
+    }
+
+    let width = (width as usize).saturating_sub(1).max(1);
+    let rows: usize = command.split('\n').map(|line| line.width().div_ceil(width).max(1)).sum();
+    rows.min(MAX_COMMAND_HEIGHT as usize) as u16
+}
+
+// The vertical offset (zero or negative) that keeps `padding` rows above and below the cursor,
+// where `y` is the row of the cursor on screen and `height` is the number of rows in view.
+// More padding than fits keeps the cursor in the middle.
+fn vertical_offset(offset: i32, y: i32, height: i32, padding: i32) -> i32 {
+    let padding = padding.min((height - 1) / 2).max(0);
+    let last = (height - 1 - padding).max(padding);
+    let offset = match y {
+        y if y > last => offset + last - y,
+        y if y < padding => offset + padding - y,
+        _ => return offset,
+    };
+
+    // Never scroll past the start of the document
+    offset.min(0)
+}
+
+// The horizontal offset (zero or negative) that keeps the cursor in view,
+// where `width` is the last visible column, with `margin` columns to either side in smooth scrolling
+fn horizontal_offset(policy: HScroll, offset: i32, cursor: i32, width: i32, margin: i32) -> i32 {
+    let margin = margin.min(width / 2);
+    let x = cursor + offset;
+    let visible = (0..=width).contains(&x);
+
+    let offset = match policy {
+        HScroll::Jump | HScroll::Keep if visible => return offset,
+        HScroll::Smooth if (margin..=width - margin).contains(&x) => return offset,
+        HScroll::Jump => width / 2 - cursor,
+        HScroll::Smooth | HScroll::Keep if x < margin => margin - cursor,
+        HScroll::Smooth | HScroll::Keep => width - margin - cursor,
+    };
+
+    // Never scroll past the start of the line
+    offset.min(0)
+}
+
+// Where the editor draws: the canvas, or a grid when running headless
+pub trait Surface {
+    fn put(&mut self, c: char, style: Style, pos: LocalPos);
+    fn get_mut(&mut self, pos: LocalPos) -> Option<(&mut char, &mut Style)>;
+}
+
+impl Surface for Canvas {
+    fn put(&mut self, c: char, style: Style, pos: LocalPos) {
+        Canvas::put(self, c, style, pos);
+    }
+
+    fn get_mut(&mut self, pos: LocalPos) -> Option<(&mut char, &mut Style)> {
+        Canvas::get_mut(self, pos)
+    }
+}
+
+impl Surface for Grid {
+    fn put(&mut self, c: char, style: Style, pos: LocalPos) {
+        Grid::put(self, c, style, pos);
+    }
+
+    fn get_mut(&mut self, pos: LocalPos) -> Option<(&mut char, &mut Style)> {
+        Grid::get_mut(self, pos)
+    }
+}
+
+// Put a string on a row from column `x`, clipped on the left, and return the column after it
+fn put_str(surface: &mut impl Surface, text: &str, style: Style, mut x: i32, y: i32) -> i32 {
+    for c in text.chars() {
+        if x >= 0 {
+            surface.put(c, style, (x, y).into());
+        }
+        x += c.width().unwrap_or(0) as i32;
+    }
+    x
+}
+
+impl Component for Editor {
+    type Message = Instruction;
+    type State = DocState;
+
+    fn on_key(
+        &mut self,
+        key: KeyEvent,
+        state: &mut Self::State,
+        mut children: Children<'_, '_>,
+        mut context: Context<'_, '_, Self::State>,
+    ) {
+        if matches!(key.state, KeyState::Release) {
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('+') => self.set_time_scale(step_time_scale(self.frame_timer.scale, true), state),
+            KeyCode::Char('-') => self.set_time_scale(step_time_scale(self.frame_timer.scale, false), state),
+            KeyCode::Char('0') => self.set_time_scale(1.0, state),
+            KeyCode::Char('q') | KeyCode::Esc => context.stop_runtime(),
+            KeyCode::Char(' ') => self.toggle_pause(state),
+            KeyCode::Char('s') => self.toggle_stepping(state),
+            KeyCode::Char('n') | KeyCode::Enter => self.step(),
+            KeyCode::Char('r') if self.ready() => {
+                self.restart(state);
+                self.update_cursor(state);
+                self.draw(children.elements(), state);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_tick(
+        &mut self,
+        state: &mut Self::State,
+        mut children: Children<'_, '_>,
+        mut context: Context<'_, '_, Self::State>,
+        dt: Duration,
+    ) {
+        if !self.ready()
+            && let Some(size) = children.elements().by_tag("canvas").first(|el, _| el.size())
+        {
+            self.size = size;
+        }
+
+        if self.update(state, dt) {
+            self.update_cursor(state);
             self.draw(children.elements(), state);
         }
+
+        if self.should_exit() {
+            self.completed.store(true, Ordering::Relaxed);
+            context.stop_runtime();
+        }
+
+        if self.transition.as_ref().is_some_and(Transition::is_done) {
+            self.transition = None;
+        }
     }
 
     fn on_mount(&mut self, _: &mut Self::State, mut children: Children<'_, '_>, _: Context<'_, '_, Self::State>) {
@@ -497,6 +1666,925 @@ impl Component for Editor {
         if let Some(size) = children.elements().by_tag("canvas").first(|el, _| el.size()) {
             self.size = size;
             state.height.set(size.height);
+            self.update_popup(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ui::audio::SoundSync;
+    use crate::ui::document::Document;
+    use crate::ui::textbuffer::TextBuffer;
+
+    fn editor(instructions: Vec<Instruction>) -> Editor {
+        let mut editor = Editor::new(instructions, Highlighter::plain_text(), FRAME, Shutdown::new(), Timings::new());
+        editor.frame_timer.set_profile(JitterProfile::Off);
+        editor
+    }
+
+    #[test]
+    fn hold_instructions_until_ready() {
+        let mut editor = editor(vec![
+            Instruction::SetTitle("hold".into()),
+            Instruction::LoadTypeBuffer("abc".into()),
+        ]);
+        let mut state = DocState::new();
+
+        // The canvas has no size for the first ticks
+        for _ in 0..10 {
+            assert!(!editor.ready());
+            assert!(!editor.update(&mut state, FRAME));
+        }
+        assert_eq!(editor.session.instructions.taken(), Some(0));
+        assert_eq!(editor.session.doc.text(), "");
+
+        // Once ready, no time is owed from the ticks before
+        editor.size = Size::new(80, 24);
+        assert!(editor.ready());
+        assert!(editor.update(&mut state, FRAME));
+        assert_eq!(editor.session.doc.text(), "a");
+
+        assert!(editor.update(&mut state, FRAME));
+        assert_eq!(editor.session.doc.text(), "ab");
+    }
+
+    #[test]
+    fn ui_instructions_change_the_editor() {
+        let mut editor = editor(vec![
+            Instruction::SetTitle("title".into()),
+            Instruction::Speed(Duration::from_millis(7)),
+            Instruction::ShowLineNumbers(true),
+            Instruction::Wrap(Some("> ".into())),
+            Instruction::ScrollOff {
+                rows: 2,
+                columns: Some(3),
+            },
+            Instruction::ExitOnEnd(Duration::from_secs(1)),
+        ]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        editor.update(&mut state, FRAME);
+
+        assert_eq!(*state.title.to_ref(), "title");
+        assert_eq!(editor.frame_timer.frame_time, Duration::from_millis(7));
+        assert!(*state.show_line_numbers.to_ref());
+        assert_eq!(editor.wrap.as_deref(), Some("> "));
+        assert_eq!(editor.scrolloff, (2, 3));
+        assert_eq!(editor.exit_on_end, Some(Duration::from_secs(1)));
+        assert!(state.error().is_none());
+    }
+
+    #[test]
+    fn session_instructions_are_not_applied_by_the_editor() {
+        let mut editor = editor(vec![]);
+        let mut state = DocState::new();
+        editor.apply_ui(Instruction::LoadTypeBuffer("a".into()), &mut state);
+        assert!(state.error().is_some_and(|e| e.contains("LoadTypeBuffer")), "{:?}", state.error());
+        assert_eq!(editor.text(), "");
+    }
+
+    #[test]
+    fn pause_holds_waits() {
+        let mut editor = editor(vec![
+            Instruction::Wait(Duration::from_secs(1)),
+            Instruction::LoadTypeBuffer("a".into()),
+        ]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        let tick = |editor: &mut Editor, state: &mut DocState, millis| {
+            for _ in 0..millis / 10 {
+                editor.update(state, Duration::from_millis(10));
+            }
+        };
+
+        tick(&mut editor, &mut state, 500);
+        editor.toggle_pause(&mut state);
+        assert!(*state.paused.to_ref());
+        tick(&mut editor, &mut state, 5000);
+        assert_eq!(editor.text(), "");
+
+        editor.toggle_pause(&mut state);
+        assert!(!*state.paused.to_ref());
+        tick(&mut editor, &mut state, 400);
+        assert_eq!(editor.text(), "");
+        tick(&mut editor, &mut state, 200);
+        assert_eq!(editor.text(), "a");
+    }
+
+    #[test]
+    fn compile_errors_show_when_they_are_reached() {
+        let parsed = crate::parser::parse("type \"a\"\nload \"no/such/file\" as code\ntype \"b\"").unwrap();
+        let script = crate::ui::compile(parsed, None);
+        let mut editor = Editor::new(script, Highlighter::plain_text(), FRAME, Shutdown::new(), Timings::new());
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME);
+        }
+        assert_eq!(editor.text(), "a");
+        assert!(state.error().is_some_and(|e| e.contains("no/such/file")), "{:?}", state.error());
+    }
+
+    #[test]
+    fn exit_when_the_script_ends() {
+        let mut exit = editor(vec![Instruction::Exit, Instruction::LoadTypeBuffer("a".into())]);
+        let mut editor = editor(vec![
+            Instruction::ExitOnEnd(Duration::from_secs(1)),
+            Instruction::LoadTypeBuffer("a".into()),
+        ]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        editor.update(&mut state, FRAME);
+        assert!(editor.is_done());
+        assert!(!editor.should_exit());
+        for _ in 0..60 {
+            editor.update(&mut state, FRAME);
+        }
+        assert!(editor.should_exit());
+
+        // `exit` quits right away, without the rest of the script
+        exit.size = Size::new(80, 24);
+        exit.update(&mut state, FRAME);
+        assert!(exit.is_done());
+        assert!(exit.should_exit());
+        assert_eq!(exit.text(), "");
+    }
+
+    #[test]
+    fn seek_applies_everything_before_at_once() {
+        let script = || {
+            vec![
+                Instruction::LoadTypeBuffer("abc\n".into()),
+                Instruction::Wait(Duration::from_secs(10)),
+                Instruction::LoadCommandBuffer(":w".into()),
+                Instruction::ClearCommandBuffer,
+                Instruction::SetMarker("here".into()),
+                Instruction::LoadTypeBuffer("d".into()),
+            ]
+        };
+        let mut by_index = editor(script());
+        let mut missing = editor(script());
+        let mut editor = editor(script());
+        editor.size = Size::new(80, 24);
+        editor.seek(Seek::Marker("here".into()));
+        let mut state = DocState::new();
+        editor.update(&mut state, Duration::ZERO);
+        assert_eq!(editor.text(), "abc\n");
+        assert_eq!(editor.cursor(), Pos::new(0, 1));
+        assert_eq!(*state.command_buffer.to_ref(), "");
+        editor.update(&mut state, FRAME);
+        assert_eq!(editor.text(), "abc\nd");
+
+        // Instructions count from 1, so this starts at the second `type`
+        by_index.size = Size::new(80, 24);
+        by_index.seek(Seek::Instruction(6));
+        let mut state = DocState::new();
+        by_index.update(&mut state, Duration::ZERO);
+        assert_eq!(by_index.text(), "abc\n");
+        assert_eq!(state.error(), None);
+
+        missing.size = Size::new(80, 24);
+        missing.seek(Seek::Marker("nowhere".into()));
+        let mut state = DocState::new();
+        missing.update(&mut state, Duration::ZERO);
+        assert_eq!(state.error().as_deref(), Some("the script ended before @nowhere"));
+    }
+
+    #[test]
+    fn seek_from_arg() {
+        assert_eq!(Seek::from_arg("@setup"), Some(Seek::Marker("setup".into())));
+        assert_eq!(Seek::from_arg("intro"), Some(Seek::Scene("intro".into())));
+        assert_eq!(Seek::from_arg("@"), None);
+        assert_eq!(Seek::from_arg(""), None);
+    }
+
+    #[test]
+    fn restart_keeps_only_the_time_scale() {
+        let mut editor = editor(vec![Instruction::JitterProfile(JitterProfile::Burst)]);
+        editor.size = Size::new(80, 24);
+        editor.seed(7);
+        let first = editor.frame_timer.rand.next(1000);
+        editor.frame_timer.set_scale(2.0);
+        let mut state = DocState::new();
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME);
+        }
+        assert_eq!(editor.frame_timer.profile, JitterProfile::Burst);
+
+        // The profile came from the script, so it goes back to the default
+        editor.restart(&mut state);
+        assert_eq!(editor.frame_timer.profile, JitterProfile::Uniform);
+        assert_eq!(editor.frame_timer.scale, 2.0);
+        // and a seeded run draws the same numbers again
+        assert_eq!(editor.frame_timer.rand.next(1000), first);
+    }
+
+    #[test]
+    fn seek_to_a_scene() {
+        let script = || {
+            vec![
+                Instruction::Scene("intro".into()),
+                Instruction::LoadTypeBuffer("a".into()),
+                Instruction::Wait(Duration::from_secs(10)),
+                Instruction::Scene("main".into()),
+                Instruction::Clear,
+                Instruction::LoadTypeBuffer("b".into()),
+            ]
+        };
+        let mut missing = editor(script());
+        let mut editor = editor(script());
+        editor.size = Size::new(80, 24);
+        editor.seek(Seek::Scene("main".into()));
+        let mut state = DocState::new();
+        editor.update(&mut state, Duration::ZERO);
+        // The scene starts, and the rest of it plays as usual
+        assert_eq!(editor.text(), "a");
+        assert_eq!(editor.scene.as_deref(), Some("main"));
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME);
+        }
+        assert_eq!(editor.text(), "b");
+
+        // Starting over seeks to the scene again
+        editor.restart(&mut state);
+        assert_eq!(editor.scene, None);
+        editor.update(&mut state, Duration::ZERO);
+        assert_eq!(editor.text(), "a");
+
+        missing.size = Size::new(80, 24);
+        missing.seek(Seek::Scene("outro".into()));
+        let mut state = DocState::new();
+        missing.update(&mut state, Duration::ZERO);
+        assert_eq!(state.error().as_deref(), Some("the script ended before scene \"outro\""));
+    }
+
+    #[test]
+    fn progress_as_instructions_are_applied() {
+        let script = vec![
+            Instruction::Wait(Duration::from_secs(120)),
+            Instruction::Wait(Duration::from_secs(60)),
+        ];
+        let progress = Progress::new(&script);
+        let mut editor = editor(script);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        editor.set_progress(progress, &mut state);
+        assert_eq!(*state.progress.to_ref(), 0.0);
+        assert_eq!(*state.remaining.to_ref(), "3:00");
+
+        // The wait that's under way counts as left, and moves the progress along while it lasts
+        editor.update(&mut state, FRAME);
+        assert_eq!(*state.progress.to_ref(), 0.0);
+        assert_eq!(*state.remaining.to_ref(), "3:00");
+        editor.update(&mut state, Duration::from_secs(60));
+        assert_eq!(*state.progress.to_ref(), 0.25);
+        assert_eq!(*state.remaining.to_ref(), "2:00");
+
+        editor.set_time_scale(2.0, &mut state);
+        assert_eq!(*state.remaining.to_ref(), "1:00");
+    }
+
+    #[test]
+    fn progress_while_typing() {
+        let script = vec![
+            Instruction::LoadTypeBuffer("abcd".into()),
+            Instruction::Wait(Duration::from_secs(1)),
+        ];
+        let progress = Progress::new(&script);
+        let mut editor = editor(script);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        editor.set_progress(progress, &mut state);
+
+        // Every character typed counts, not just the whole text at once
+        let mut fractions = vec![];
+        while editor.text() != "abcd" {
+            editor.update(&mut state, FRAME);
+            fractions.push(*state.progress.to_ref());
+        }
+        assert_eq!(fractions, [0.2, 0.4, 0.6, 0.8]);
+    }
+
+    #[test]
+    fn time_scale_skips_through_waits() {
+        let mut editor = editor(vec![
+            Instruction::Wait(Duration::from_secs(2)),
+            Instruction::LoadTypeBuffer("a".into()),
+        ]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        editor.set_time_scale(4.0, &mut state);
+        assert_eq!(*state.time_scale.to_ref(), "4x");
+
+        for _ in 0..60 {
+            editor.update(&mut state, Duration::from_millis(10));
+        }
+        assert_eq!(editor.text(), "a");
+
+        editor.set_time_scale(0.75, &mut state);
+        assert_eq!(*state.time_scale.to_ref(), "0.75x");
+        editor.set_time_scale(1.0, &mut state);
+        assert_eq!(*state.time_scale.to_ref(), "");
+    }
+
+    #[test]
+    fn step_an_instruction_at_a_time() {
+        let script = || {
+            vec![
+                Instruction::LoadTypeBuffer("ab".into()),
+                Instruction::Wait(Duration::from_secs(10)),
+                Instruction::LoadTypeBuffer("c".into()),
+            ]
+        };
+        let mut by_char = editor(script());
+        let mut editor = editor(script());
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        editor.toggle_stepping(&mut state);
+        assert_eq!(*state.instruction_total.to_ref(), 3);
+
+        let run = |editor: &mut Editor, state: &mut DocState| {
+            for _ in 0..30 {
+                editor.update(state, FRAME);
+            }
+            editor.text().to_string()
+        };
+        assert_eq!(run(&mut editor, &mut state), "");
+        editor.step();
+        assert_eq!(run(&mut editor, &mut state), "ab");
+        assert_eq!(*state.instruction_index.to_ref(), 1);
+        // The wait is skipped
+        editor.step();
+        assert_eq!(run(&mut editor, &mut state), "ab");
+        editor.step();
+        assert_eq!(run(&mut editor, &mut state), "abc");
+        assert_eq!(*state.instruction_index.to_ref(), 3);
+
+        // A character at a time
+        by_char.size = Size::new(80, 24);
+        by_char.set_step_by(StepBy::Char);
+        by_char.toggle_stepping(&mut state);
+        by_char.step();
+        run(&mut by_char, &mut state);
+        by_char.step();
+        assert_eq!(run(&mut by_char, &mut state), "a");
+
+        // Without step mode it all runs
+        by_char.toggle_stepping(&mut state);
+        assert_eq!(run(&mut by_char, &mut state), "ab");
+    }
+
+    #[test]
+    fn loop_plays_the_script_again() {
+        let script = |pause| {
+            vec![
+                Instruction::Loop {
+                    count: LoopCount::Times(3),
+                    pause,
+                },
+                Instruction::LoadTypeBuffer("ab".into()),
+            ]
+        };
+        let play = |editor: &mut Editor| {
+            editor.size = Size::new(80, 24);
+            let mut state = DocState::new();
+            let mut texts = vec![];
+            while !editor.is_done() {
+                editor.update(&mut state, FRAME);
+                texts.push(editor.text().to_string());
+            }
+            texts
+        };
+
+        let mut editor = editor(script(Duration::from_secs(1)));
+        let mut texts = play(&mut editor);
+        // Two pauses of a second between the three plays
+        assert!(texts.iter().filter(|text| *text == "ab").count() > 2 * 60);
+        texts.dedup();
+        assert_eq!(texts, ["a", "ab", "a", "ab", "a", "ab"]);
+
+        // Given as an option the count wins over the script
+        let highlighter = Highlighter::plain_text();
+        let mut editor = Editor::new(script(Duration::ZERO), highlighter, FRAME, Shutdown::new(), Timings::new());
+        editor.set_loop(LoopCount::Times(2));
+        let mut texts = play(&mut editor);
+        texts.dedup();
+        assert_eq!(texts, ["a", "ab", "a", "ab"]);
+    }
+
+    #[test]
+    fn backspace_one_frame_at_a_time() {
+        let mut editor = editor(vec![
+            Instruction::LoadTypeBuffer("ab\ncd".into()),
+            Instruction::Backspace(3),
+            Instruction::LoadTypeBuffer("!".into()),
+        ]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+
+        let mut texts = vec![];
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME);
+            texts.push(editor.session.doc.text().to_string());
+        }
+        texts.dedup();
+        assert_eq!(texts, ["a", "ab", "ab\n", "ab\nc", "ab\ncd", "ab\nc", "ab\n", "ab", "ab!"]);
+        assert_eq!(editor.session.cursor, Pos::new(3, 0));
+    }
+
+    #[test]
+    fn wait_range_is_seeded() {
+        let wait = |seed| {
+            let range = Instruction::WaitRange(Duration::from_millis(100), Duration::from_millis(900));
+            let mut editor = editor(vec![range]);
+            editor.seed(seed);
+            editor.size = Size::new(80, 24);
+            let mut state = DocState::new();
+            while !matches!(editor.apply(&mut state), RenderAction::Wait) {}
+            editor.frame_timer.wait
+        };
+
+        let waits = (1..10).map(wait).collect::<Vec<_>>();
+        assert_eq!(waits, (1..10).map(wait).collect::<Vec<_>>());
+        assert!(waits.iter().all(|wait| (100..=900).contains(&wait.as_millis())));
+        assert!(waits.iter().any(|wait| *wait != waits[0]));
+    }
+
+    #[test]
+    fn margins() {
+        let margins = |top, right, bottom, left| {
+            Instruction::Margins(Margins {
+                top,
+                right,
+                bottom,
+                left,
+            })
+        };
+        let mut editor = editor(vec![margins(1, 2, 3, 4), margins(0, 0, 1, 0), margins(12, 0, 12, 0)]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+
+        editor.update(&mut state, FRAME);
+        assert_eq!(editor.size, Size::new(80, 23));
+        assert_eq!(*state.margin_bottom.to_ref(), 1);
+        assert_eq!(*state.margin_left.to_ref(), 0);
+        assert_eq!(state.error.to_ref().as_str(), "margins (12 0 12 0) don't fit in 80x24");
+    }
+
+    #[test]
+    fn command_heights() {
+        assert_eq!(command_height("", 10), 0);
+        assert_eq!(command_height(":w", 10), 1);
+        assert_eq!(command_height("123456789", 10), 1);
+        assert_eq!(command_height("1234567890", 10), 2);
+        assert_eq!(command_height("a\n\nb", 10), 3);
+        assert_eq!(command_height("日本語日本", 10), 2);
+        assert_eq!(command_height(&"a\n".repeat(20), 10), MAX_COMMAND_HEIGHT);
+        assert_eq!(command_height("ab", 0), 2);
+    }
+
+    #[test]
+    fn command_line_grows_while_typing() {
+        let mut editor = editor(vec![
+            Instruction::LoadCommandBuffer("a".repeat(20)),
+            Instruction::ClearCommandBuffer,
+        ]);
+        editor.size = Size::new(10, 8);
+        let mut state = DocState::new();
+
+        let mut heights = vec![];
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME);
+            heights.push(*state.command_height.to_ref());
+        }
+        heights.dedup();
+        assert_eq!(heights, [1, 2, 3, 0]);
+        assert_eq!(editor.text_height(), 8);
+    }
+
+    #[test]
+    fn command_speed() {
+        // The time it takes to type ten characters of code and ten of a command
+        let elapsed = |command_speed| {
+            let mut editor = editor(vec![
+                Instruction::Speed(Duration::from_millis(100)),
+                Instruction::CommandSpeed(command_speed),
+                Instruction::LoadTypeBuffer("a".repeat(10)),
+                Instruction::LoadCommandBuffer("b".repeat(10)),
+                Instruction::ClearCommandBuffer,
+            ]);
+            editor.size = Size::new(20, 8);
+            let mut state = DocState::new();
+            let mut frames = 0;
+            while !editor.is_done() {
+                editor.update(&mut state, FRAME);
+                frames += 1;
+            }
+            editor.update(&mut state, FRAME);
+            assert!(state.command_buffer.to_ref().is_empty());
+            FRAME * frames
+        };
+
+        let same = elapsed(None);
+        assert!(same >= Duration::from_millis(1900), "{same:?}");
+        let faster = elapsed(Some(Duration::from_millis(20)));
+        assert!(faster < Duration::from_millis(1400), "{faster:?}");
+        assert!(faster >= Duration::from_millis(1150), "{faster:?}");
+    }
+
+    #[test]
+    fn cursor_stays_above_the_command_line() {
+        let mut editor = editor(vec![
+            Instruction::LoadTypeBuffer("a\n".repeat(11)),
+            Instruction::LoadCommandBuffer(":wq\nplease".into()),
+        ]);
+        editor.size = Size::new(10, 20);
+        let mut state = DocState::new();
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME);
+            editor.update_cursor(&mut state);
+        }
+
+        // The command takes two of the twenty rows, and the cursor keeps its padding above it
+        assert_eq!(editor.text_height(), 18);
+        assert_eq!(*state.screen_cursor_y.to_ref(), 10);
+        assert_eq!(*state.offset_y.to_ref(), -1);
+    }
+
+    #[test]
+    fn scroll_keeps_the_cursor() {
+        let mut editor = editor(vec![]);
+        editor.size = Size::new(10, 20);
+        editor.session.doc = Document::new("a\n".repeat(29));
+        let mut state = DocState::new();
+        let mut scroll = |editor: &mut Editor, rows: i32, cursor: i32| {
+            editor.session.cursor.y = cursor;
+            editor.session.apply(Instruction::Scroll(rows));
+            editor.update_cursor(&mut state);
+            editor.session.offset.y
+        };
+
+        // Twenty rows less the padding above and below leaves the cursor free between rows 7 and 12
+        assert_eq!(scroll(&mut editor, 5, 10), -3);
+        assert_eq!(scroll(&mut editor, -2, 10), -1);
+        // Never above the first line
+        assert_eq!(scroll(&mut editor, -10, 12), 0);
+        assert_eq!(scroll(&mut editor, 8, 3), 0);
+
+        // The cursor at the edge of the padding keeps it from scrolling up
+        assert_eq!(scroll(&mut editor, 5, 20), -8);
+        assert_eq!(scroll(&mut editor, -1, 20), -8);
+        assert_eq!(scroll(&mut editor, 4, 20), -12);
+
+        // The padding holds at the last line too
+        assert_eq!(scroll(&mut editor, 100, 29), -22);
+        assert_eq!(*state.screen_cursor_y.to_ref(), 7);
+    }
+
+    #[test]
+    fn wrap_long_lines() {
+        let mut editor = Editor::new(Vec::<Instruction>::new(), Highlighter::bundled(), FRAME, Shutdown::new(), Timings::new());
+        editor.size = Size::new(6, 4);
+        editor.session.doc = Document::new("abcdefghij\nxy");
+        let mut state = DocState::new();
+        editor.apply_ui(Instruction::Wrap(Some(">".into())), &mut state);
+        editor.session.cursor = Pos::new(4, 0);
+        editor.session.apply(Instruction::Select(Size::new(3, 1)));
+        editor.update_cursor(&mut state);
+
+        // The cursor is on the g, the first cell after the indicator
+        assert_eq!(*state.screen_cursor_x.to_ref(), 1);
+        assert_eq!(*state.screen_cursor_y.to_ref(), 1);
+        assert_eq!(*state.offset_x.to_ref(), 0);
+        let line_numbers = state.line_numbers.to_ref().iter().map(|n| n.to_ref().clone()).collect::<Vec<_>>();
+        assert_eq!(line_numbers, ["1", "", "2", "3"]);
+
+        let grid = editor.render_grid().unwrap();
+        let cell = |x, y| grid.get(LocalPos::new(x, y));
+        let rows = (0..4)
+            .map(|y| (0..6).filter_map(|x| cell(x, y)).map(|(c, _)| c).filter(|c| !c.is_control()).collect::<String>())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, ["abcdef", ">ghij", "xy", ""]);
+
+        // The selection follows the line onto the next row, but not the indicator
+        let selected = |x, y| cell(x, y).is_some_and(|(_, style)| style.bg.is_some());
+        assert!(!selected(3, 0));
+        assert!(selected(4, 0) && selected(5, 0));
+        assert!(!selected(0, 1));
+        assert!(selected(1, 1));
+        assert!(!selected(2, 1));
+    }
+
+    // The pause after typing each string of `input`
+    fn burst_pauses(input: &str) -> Vec<u64> {
+        let mut timer = Timer::new(FRAME);
+        timer.rand = Random::with_seed(7);
+        timer.set_profile(JitterProfile::Burst);
+
+        let mut buffer = TextBuffer::new();
+        buffer.push(input);
+        let mut pauses = vec![];
+        while buffer.next().is_some() {
+            pauses.push(timer.pause_before(buffer.peek()).as_millis() as u64);
+        }
+        pauses
+    }
+
+    // Type `text` at `frame_time`, returning when each glyph appeared and when each sound played
+    fn sound_sync(frame_time: Duration, offset: i64, text: &str) -> (Vec<Duration>, Vec<Duration>) {
+        let mut timer = Timer::new(frame_time);
+        timer.set_profile(JitterProfile::Off);
+        let mut sync = SoundSync::new();
+        sync.set_offset(offset);
+
+        let chars = text.chars().map(String::from).collect::<Vec<_>>();
+        let mut typed = 0;
+        let mut now = Duration::ZERO;
+        let mut glyphs = vec![];
+        let mut sounds = vec![];
+
+        while sounds.len() < chars.len() {
+            now += FRAME;
+            sync.tick(FRAME);
+            sounds.extend(sync.due().iter().map(|_| now));
+
+            for _ in 0..timer.tick(FRAME) {
+                let Some(glyph) = chars.get(typed) else { break };
+                typed += 1;
+                glyphs.push(now);
+                sync.typed(glyph, chars.get(typed).map(String::as_str), timer.until_next());
+                sounds.extend(sync.due().iter().map(|_| now));
+            }
+        }
+
+        (glyphs, sounds)
+    }
+
+    #[test]
+    fn sound_offset_across_frame_times() {
+        for ms in [30, 50, 70, 120, 250] {
+            let frame_time = Duration::from_millis(ms);
+            for offset in [-40, 0, 25] {
+                let (glyphs, sounds) = sound_sync(frame_time, offset, "abcdefghij");
+
+                // The first sound can't be played ahead of the first glyph
+                let skip = if offset > 0 { 1 } else { 0 };
+                for (glyph, sound) in glyphs.iter().zip(&sounds).skip(skip) {
+                    let lead = glyph.as_secs_f64() - sound.as_secs_f64();
+                    let error = (lead - offset as f64 / 1000.0).abs();
+                    assert!(error <= FRAME.as_secs_f64(), "{ms}ms frames, {offset}ms offset: {lead}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn menu_then_type() {
+        let items = vec!["src/main.rs".to_string(), "src/lib.rs".into(), "Cargo.toml".into()];
+        let step = Duration::from_millis(100);
+        let mut editor = editor(vec![
+            Instruction::Menu {
+                items,
+                choice: 1,
+                step,
+                then_type: true,
+            },
+            Instruction::LoadTypeBuffer("!".into()),
+        ]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+
+        // Nothing else runs while the menu is open
+        let open = Menu::duration(1, step);
+        let mut elapsed = Duration::ZERO;
+        editor.update(&mut state, FRAME);
+        while elapsed + FRAME < open {
+            assert!(*state.show_menu.to_ref());
+            assert_eq!(editor.session.doc.text(), "");
+            editor.update(&mut state, FRAME);
+            elapsed += FRAME;
+        }
+
+        for _ in 0..100 {
+            editor.update(&mut state, FRAME);
+        }
+        assert!(!*state.show_menu.to_ref());
+        assert_eq!(editor.session.doc.text(), "src/lib.rs!");
+    }
+
+    #[test]
+    fn burst_pauses_before_spaces_and_newlines() {
+        let pauses = burst_pauses("let a = 1;\nb");
+        assert_eq!(pauses, burst_pauses("let a = 1;\nb"));
+
+        // Pause before " ", " ", " " and "\n", no pause after the last character
+        let paused = pauses.iter().map(|&p| p > 0).collect::<Vec<_>>();
+        let expected = [false, false, true, false, true, false, true, false, false, true, false, false];
+        assert_eq!(paused, expected);
+        assert!(pauses.iter().filter(|&&p| p > 0).all(|p| (80..=220).contains(p)));
+    }
+
+    // The number of frames in `seconds` of ticks
+    fn frames(timer: &mut Timer, seconds: u32) -> usize {
+        (0..seconds * 60).map(|_| timer.tick(FRAME)).sum()
+    }
+
+    #[test]
+    fn time_scale() {
+        let frame_time = Duration::from_millis(100);
+        let mut timer = Timer::new(frame_time);
+        timer.set_profile(JitterProfile::Off);
+        assert_eq!(frames(&mut timer, 10), 100);
+
+        timer.set_scale(4.0);
+        assert_eq!(frames(&mut timer, 10), 400);
+        assert_eq!(timer.until_next(), Duration::from_millis(25) - timer.accumulator.div_f64(4.0));
+
+        timer.set_scale(0.5);
+        assert_eq!(frames(&mut timer, 10), 50);
+
+        // Waits are scaled too
+        timer.set_scale(4.0);
+        timer.wait(Duration::from_secs(4));
+        assert_eq!(frames(&mut timer, 1), 0);
+        assert_eq!(frames(&mut timer, 1), 40);
+
+        timer.set_scale(100.0);
+        assert_eq!(timer.scale, 20.0);
+        timer.set_scale(0.0);
+        assert_eq!(timer.scale, 0.1);
+    }
+
+    #[test]
+    fn time_scale_steps() {
+        assert_eq!(step_time_scale(1.0, true), 1.5);
+        assert_eq!(step_time_scale(1.0, false), 0.75);
+        assert_eq!(step_time_scale(2.2, true), 3.0);
+        assert_eq!(step_time_scale(2.2, false), 2.0);
+        assert_eq!(step_time_scale(20.0, true), 20.0);
+        assert_eq!(step_time_scale(0.1, false), 0.1);
+    }
+
+    #[test]
+    fn jitter_profiles() {
+        let mut timer = Timer::new(FRAME);
+        timer.rand = Random::with_seed(7);
+        timer.jitter_ms = 50;
+
+        timer.apply_jitter();
+        assert!(!timer.jitter.is_zero());
+        assert_eq!(timer.pause_before(Some(" ")), Duration::ZERO);
+
+        // No jitter within words in burst mode, and switching takes effect immediately
+        timer.set_profile(JitterProfile::Burst);
+        assert!(timer.jitter.is_zero());
+        timer.apply_jitter();
+        assert!(timer.jitter.is_zero());
+
+        timer.set_profile(JitterProfile::Off);
+        timer.apply_jitter();
+        assert!(timer.jitter.is_zero());
+        assert_eq!(timer.pause_before(Some(" ")), Duration::ZERO);
+
+        timer.burst_pause = (Duration::from_millis(100), Duration::from_millis(100));
+        timer.set_profile(JitterProfile::Burst);
+        assert_eq!(timer.pause_before(Some("\n")), Duration::from_millis(100));
+        assert_eq!(timer.pause_before(Some("    ")), Duration::from_millis(100));
+        assert_eq!(timer.pause_before(Some("a")), Duration::ZERO);
+    }
+
+    // Move the cursor through `cursors`, returning the offset after each move
+    fn scroll(policy: HScroll, width: i32, cursors: &[i32]) -> Vec<i32> {
+        let mut offset = 0;
+        cursors
+            .iter()
+            .map(|&cursor| {
+                offset = horizontal_offset(policy, offset, cursor, width, SCROLLOFF.1);
+                offset
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scrolloff() {
+        // Move the cursor through the rows of `cursors`, returning the offset after each move
+        let scroll = |height: i32, padding: i32, cursors: &[i32]| {
+            let mut offset = 0;
+            cursors
+                .iter()
+                .map(|&cursor| {
+                    offset = vertical_offset(offset, cursor + offset, height, padding);
+                    offset
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let cases: &[(i32, i32, &[i32], &[i32])] = &[
+            (10, 0, &[9, 10, 15, 6, 5, 0], &[0, -1, -6, -6, -5, 0]),
+            (10, 2, &[7, 8, 10, 5, 4, 1], &[0, -1, -3, -3, -2, 0]),
+            // More padding than fits keeps the cursor in the middle, or the middle two rows
+            (9, 100, &[4, 5, 6, 5, 4], &[0, -1, -2, -1, 0]),
+            (10, 7, &[4, 5, 6, 5, 4, 3], &[0, 0, -1, -1, 0, 0]),
+            (2, 1, &[1, 2, 1, 0], &[0, -1, -1, 0]),
+            (1, 3, &[1, 2, 1, 0], &[-1, -2, -1, 0]),
+        ];
+
+        for (height, padding, cursors, expected) in cases {
+            let offsets = scroll(*height, *padding, cursors);
+            assert_eq!(&offsets, expected, "height {height} padding {padding} cursors {cursors:?}");
+        }
+
+        // Going back and forth over the same rows doesn't move the view back and forth
+        let offsets = scroll(6, 3, &[10, 11, 10, 11, 10]);
+        assert_eq!(offsets, [-7, -8, -8, -8, -8]);
+    }
+
+    #[test]
+    fn hscroll() {
+        let cases: &[(HScroll, i32, &[i32], &[i32])] = &[
+            // Visible cursors never move the view
+            (HScroll::Keep, 20, &[0, 10, 20], &[0, 0, 0]),
+            (HScroll::Keep, 20, &[10, 30, 25, 5, 3, 30], &[0, -14, -14, -1, -1, -14]),
+            (HScroll::Keep, 20, &[40, 0], &[-24, 0]),
+            (HScroll::Jump, 20, &[10, 30, 25, 5, 60], &[0, -20, -20, 0, -50]),
+            (HScroll::Smooth, 20, &[10, 17, 18, 30, 26, 12, 2], &[0, -1, -2, -14, -14, -8, 0]),
+            // The margin shrinks with the view
+            (HScroll::Smooth, 4, &[2, 3, 5], &[0, -1, -3]),
+            (HScroll::Keep, 0, &[0, 1, 0], &[0, -1, 0]),
+        ];
+
+        for (policy, width, cursors, expected) in cases {
+            let offsets = scroll(*policy, *width, cursors);
+            assert_eq!(&offsets, expected, "{policy:?} width {width} cursors {cursors:?}");
+        }
+    }
+
+    const FRAME: Duration = Duration::from_micros(16_667);
+
+    // Simulate `seconds` worth of frames, typing `chars` every `every` frames
+    fn simulate(rate: &mut TypingRate, seconds: u32, chars: usize, every: u32) -> f64 {
+        let mut average = 0.0;
+        for frame in 0..seconds * 60 {
+            let typed = if frame % every == 0 { chars } else { 0 };
+            average = rate.update(typed, FRAME);
+        }
+        average
+    }
+
+    #[test]
+    fn typing_rate_steady() {
+        let mut rate = TypingRate::new(Duration::from_secs(1));
+        // One char every third frame: 20 chars per second
+        let average = simulate(&mut rate, 10, 1, 3);
+        assert!((average - 20.0).abs() < 1.0, "{average}");
+    }
+
+    #[test]
+    fn typing_rate_bursts() {
+        let mut rate = TypingRate::new(Duration::from_secs(1));
+        // Five chars every 15th frame: also 20 chars per second
+        let average = simulate(&mut rate, 10, 5, 15);
+        assert!((average - 20.0).abs() < 3.0, "{average}");
+    }
+
+    #[test]
+    fn typing_rate_idle_gap() {
+        let mut rate = TypingRate::new(Duration::from_secs(1));
+        simulate(&mut rate, 10, 1, 3);
+
+        // One time constant of idle time should decay to ~37%
+        let average = simulate(&mut rate, 1, 0, 1);
+        assert!((average - 20.0 * (-1.0f64).exp()).abs() < 1.0, "{average}");
+
+        let average = simulate(&mut rate, 10, 0, 1);
+        assert!(average < 0.01, "{average}");
+    }
+
+    #[test]
+    fn typing_rate_zero_dt() {
+        let mut rate = TypingRate::new(Duration::from_secs(1));
+        assert_eq!(rate.update(10, Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn stepping_by_chars_holds_instructions_too() {
+        let mut editor = editor(vec![
+            Instruction::LoadTypeBuffer("ab".into()),
+            Instruction::Insert("!".into()),
+            Instruction::LoadTypeBuffer("c".into()),
+        ]);
+        editor.size = Size::new(80, 24);
+        let mut state = DocState::new();
+        editor.set_step_by(StepBy::Char);
+        editor.toggle_stepping(&mut state);
+        let mut texts = vec![];
+        for _ in 0..6 {
+            editor.step();
+            editor.update(&mut state, FRAME);
+            texts.push(editor.text().to_string());
         }
+        assert_eq!(texts, ["", "a", "ab", "!ab", "!ab", "c!ab"]);
     }
 }
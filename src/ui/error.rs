@@ -5,20 +5,63 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     Import(PathBuf),
+    Include { path: PathBuf, line: usize },
     LoadValue(String),
+    Exec(String, String),
+    ExecTimeout(String, std::time::Duration),
+    LoadLines { path: PathBuf, lines: crate::parser::Lines, len: usize },
     FilePath(PathBuf),
     Anathema(anathema::runtime::Error),
     Syntect(syntect::Error),
     Kira(kira::backend::cpal::Error),
     InvalidTheme(String),
     NoDefaultSound,
+    UnclosedBudget(usize),
+    UnmatchedBudgetEnd,
+    BudgetInEvery(usize),
+    Mirror(std::io::Error),
+    Preview(std::io::Error),
+    Parse(crate::parser::error::Error),
+    IncludeCycle(Vec<PathBuf>),
+    InvalidMenuChoice { choice: usize, len: usize },
+    Script(String),
+    Unfinished(std::time::Duration),
+    NotASetting(String),
+    UnknownPreset(String),
+    UnmatchedPresetPop,
+    PresetFile(PathBuf),
+    UnknownFilter { name: String, line: usize },
+    FilterArgs { name: String, expected: &'static str, line: usize },
+    UnknownVariable(String),
+    UnsetEnv(String),
+    EnvNotUtf8(String),
+    NotABool(String),
+    NotAnInt(String),
+    DivisionByZero(String),
+    Overflow(String),
+    OutOfRange { expr: String, value: i64 },
+    UnknownMacro(String),
+    RecursiveMacro(String),
+    Json(String),
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::Import(path) => write!(f, "failed to load \"{}\"", path.to_str().unwrap_or("<path>")),
+            Error::Include { path, line } => {
+                write!(f, "failed to include \"{}\" on line {line}", path.to_str().unwrap_or("<path>"))
+            }
             Error::LoadValue(key) => write!(f, "\"{key}\" does not exist"),
+            Error::Exec(command, reason) => write!(f, "`{command}` failed: {reason}"),
+            Error::ExecTimeout(command, timeout) => {
+                write!(f, "`{command}` still running after {}s", timeout.as_secs_f64())
+            }
+            Error::LoadLines { path, lines, len } => {
+                let end = lines.end.map(|end| end.to_string()).unwrap_or_default();
+                let path = path.to_str().unwrap_or("<path>");
+                write!(f, "can't load lines {}..{end} of \"{path}\", it has {len} lines", lines.start)
+            }
             Error::FilePath(path_buf) => write!(f, "file does not exist: {}", path_buf.to_str().unwrap_or("<path>")),
             Error::Anathema(error) => write!(f, "{error}"),
             Error::Syntect(error) => write!(f, "{error}"),
@@ -28,6 +71,46 @@ impl std::fmt::Display for Error {
                 f,
                 "default sound missing. there has to be a default.mp3 in the root of the sound dir"
             ),
+            Error::UnclosedBudget(line) => write!(f, "budget on line {line} is never closed"),
+            Error::UnmatchedBudgetEnd => write!(f, "`budget end` without a budget"),
+            Error::BudgetInEvery(line) => write!(f, "budget on line {line} is inside of `every`"),
+            Error::Mirror(error) => write!(f, "mirror: {error}"),
+            Error::Preview(error) => write!(f, "preview: {error}"),
+            Error::Parse(error) => write!(f, "{error}"),
+            Error::InvalidMenuChoice { choice, len } => {
+                write!(f, "menu has {len} items, can't choose item {choice}")
+            }
+            Error::Script(msg) => write!(f, "script failed: {msg}"),
+            Error::Unfinished(duration) => write!(f, "script still running after {}s", duration.as_secs()),
+            Error::NotASetting(preset) => write!(
+                f,
+                "preset `{preset}` can only contain settings: speed, command_speed, jitter, jitter_profile, burst_pause, \
+                 auto_typo, line_pause, command_clear_timeout, theme, extension, numbers, audio_classes and audio_offset"
+            ),
+            Error::UnknownPreset(preset) => write!(f, "no preset named `{preset}`"),
+            Error::UnmatchedPresetPop => write!(f, "`preset pop` without a `preset use`"),
+            Error::PresetFile(path) => {
+                write!(f, "\"{}\" can only define presets", path.to_str().unwrap_or("<path>"))
+            }
+            Error::UnknownFilter { name, line } => write!(f, "unknown filter `{name}` on line {line}"),
+            Error::FilterArgs { name, expected, line } => write!(f, "filter `{name}` on line {line} takes {expected}"),
+            Error::UnknownVariable(name) => {
+                write!(f, "`${{{name}}}` is not set or loaded, it can also be given with `--var {name}=<value>`")
+            }
+            Error::UnsetEnv(name) => write!(f, "environment variable `{name}` is not set"),
+            Error::EnvNotUtf8(name) => write!(f, "environment variable `{name}` is not valid UTF-8"),
+            Error::NotABool(name) => write!(f, "`{name}` has to be true or false"),
+            Error::NotAnInt(name) => write!(f, "`{name}` has to be an integer"),
+            Error::DivisionByZero(expr) => write!(f, "`{expr}` divides by zero"),
+            Error::Overflow(expr) => write!(f, "`{expr}` is too large"),
+            Error::OutOfRange { expr, value } => write!(f, "`{expr}` is {value}, which is out of range here"),
+            Error::UnknownMacro(name) => write!(f, "`call {name}` before `def {name}`"),
+            Error::RecursiveMacro(name) => write!(f, "`{name}` calls itself"),
+            Error::Json(reason) => write!(f, "invalid instructions: {reason}"),
+            Error::IncludeCycle(cycle) => {
+                let cycle = cycle.iter().map(|p| p.to_str().unwrap_or("<path>")).collect::<Vec<_>>();
+                write!(f, "include cycle: {}", cycle.join(" -> "))
+            }
         }
     }
 }
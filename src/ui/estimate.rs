@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use super::instructions::Instruction;
+use super::markers::generate;
+use super::menu::Menu;
+use super::session::ALIGN_STEP;
+use super::textbuffer::TextBuffer;
+use crate::parser::JitterProfile;
+
+/// The frame time the editor starts out with
+pub const FRAME_TIME: Duration = Duration::from_millis(70);
+
+/// The estimated start time of every scene
+pub fn scenes(instructions: &[Instruction]) -> Vec<(String, Duration)> {
+    let mut estimator = Estimator::new();
+    let mut scenes = vec![];
+    for inst in instructions {
+        if let Instruction::Scene(name) = inst {
+            scenes.push((name.clone(), estimator.elapsed()));
+        }
+        estimator.apply(inst);
+    }
+    scenes
+}
+
+/// The estimated time it takes to run all of the instructions
+pub fn duration(instructions: &[Instruction]) -> Duration {
+    let mut estimator = Estimator::new();
+    instructions.iter().for_each(|inst| estimator.apply(inst));
+    estimator.elapsed()
+}
+
+// -----------------------------------------------------------------------------
+//   - Estimator -
+// -----------------------------------------------------------------------------
+// Follows the timing of the editor closely enough for an estimate.
+// Jitter is counted as its average, and recurring instructions are not counted.
+pub struct Estimator {
+    elapsed: Duration,
+    frame_time: Duration,
+    command_frame_time: Option<Duration>,
+    line_pause: Duration,
+    jitter: Duration,
+    profile: JitterProfile,
+    burst_pause: Duration,
+    command_clear_timeout: Duration,
+}
+
+impl Estimator {
+    pub fn new() -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            frame_time: FRAME_TIME,
+            command_frame_time: None,
+            line_pause: Duration::ZERO,
+            jitter: Duration::from_millis(10),
+            profile: JitterProfile::Uniform,
+            burst_pause: Duration::from_millis(150),
+            command_clear_timeout: Duration::from_secs(1),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn apply(&mut self, inst: &Instruction) {
+        match inst {
+            Instruction::LoadTypeBuffer(content) | Instruction::LoadTypeBufferKeepSelection(content) => {
+                let (content, _) = generate(content.as_str());
+                let mut buffer = TextBuffer::new();
+                buffer.push(content);
+                while let Some(s) = buffer.next() {
+                    self.elapsed += self.frame_time;
+                    if s == "\n" {
+                        self.elapsed += self.line_pause;
+                    }
+                    match self.profile {
+                        JitterProfile::Uniform => self.elapsed += self.jitter,
+                        JitterProfile::Burst if buffer.peek().is_some_and(|s| s.starts_with(char::is_whitespace)) => {
+                            self.elapsed += self.burst_pause
+                        }
+                        JitterProfile::Burst | JitterProfile::Off => {}
+                    }
+                }
+            }
+            Instruction::Backspace(count) => self.elapsed += (self.frame_time + self.jitter) * *count as u32,
+            Instruction::LoadCommandBuffer(content) => {
+                let frame_time = self.command_frame_time.unwrap_or(self.frame_time);
+                self.elapsed += (frame_time + self.jitter) * content.chars().count() as u32;
+            }
+            Instruction::ClearCommandWait => self.elapsed += self.command_clear_timeout,
+            Instruction::CommandClearTimeout(timeout) => self.command_clear_timeout = *timeout,
+            Instruction::Wait(duration) => self.elapsed += *duration,
+            Instruction::WaitRange(min, max) => self.elapsed += (*min + *max) / 2,
+            Instruction::Speed(frame_time) => self.frame_time = *frame_time,
+            Instruction::CommandSpeed(frame_time) => self.command_frame_time = *frame_time,
+            Instruction::LinePause(line_pause) => self.line_pause = *line_pause,
+            Instruction::SetJitter(jitter) => self.jitter = Duration::from_millis(*jitter) / 2,
+            Instruction::JitterProfile(profile) => self.profile = *profile,
+            Instruction::BurstPause { min, max } => self.burst_pause = (*min + *max) / 2,
+            Instruction::Transition(_, duration) => self.elapsed += *duration,
+            Instruction::Splash(splash) => self.elapsed += splash.hold,
+            // At most, lines that are already lined up are not animated
+            Instruction::Align { lines, instant: false, .. } => self.elapsed += ALIGN_STEP * lines.saturating_sub(1) as u32,
+            // Without a number of lines it's the selection, which isn't known here
+            Instruction::ToggleComment { lines, instant: false } => {
+                self.elapsed += ALIGN_STEP * lines.unwrap_or(1).saturating_sub(1) as u32
+            }
+            // At most, a line can't move past the start or the end
+            Instruction::MoveLine { count, .. } => self.elapsed += ALIGN_STEP * count.saturating_sub(1) as u32,
+            Instruction::Menu {
+                items,
+                choice,
+                step,
+                then_type,
+            } => {
+                self.elapsed += Menu::duration(*choice, *step);
+                if *then_type {
+                    self.apply(&Instruction::LoadTypeBuffer(items[*choice].clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scene_start_times() {
+        let instructions = vec![
+            Instruction::Scene("intro".into()),
+            Instruction::Wait(Duration::from_secs(2)),
+            Instruction::Scene("main".into()),
+            Instruction::Wait(Duration::from_secs(3)),
+            Instruction::Scene("outro".into()),
+        ];
+        let expected = vec![
+            ("intro".to_string(), Duration::ZERO),
+            ("main".to_string(), Duration::from_secs(2)),
+            ("outro".to_string(), Duration::from_secs(5)),
+        ];
+        assert_eq!(scenes(&instructions), expected);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+
+use super::document::Document;
+
+// -----------------------------------------------------------------------------
+//   - Event -
+// -----------------------------------------------------------------------------
+/// What a tool following the run can know about the document.
+/// Events are written as one JSON object per line, with the keys in a fixed order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// After every change to the document. `dirty` is set until the document is written.
+    DocumentStats { lines: usize, chars: usize, dirty: bool },
+    /// Every marker and its row, whenever one is added, moved or removed.
+    /// Sorted by row, then name.
+    Markers(Vec<(String, usize)>),
+    /// When a scene starts
+    Scene(String),
+}
+
+impl Event {
+    pub fn to_json(&self) -> String {
+        match self {
+            Event::DocumentStats { lines, chars, dirty } => {
+                format!(r#"{{"event":"document_stats","lines":{lines},"chars":{chars},"dirty":{dirty}}}"#)
+            }
+            Event::Markers(markers) => {
+                let markers = markers
+                    .iter()
+                    .map(|(name, row)| format!(r#"{{"name":{},"row":{row}}}"#, json_string(name)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!(r#"{{"event":"markers","markers":[{markers}]}}"#)
+            }
+            Event::Scene(name) => format!(r#"{{"event":"scene","name":{}}}"#, json_string(name)),
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// -----------------------------------------------------------------------------
+//   - Events -
+// -----------------------------------------------------------------------------
+enum Sink {
+    #[cfg(test)]
+    Memory(Vec<Event>),
+    Writer(Box<dyn Write + Send>),
+}
+
+/// Emits an event whenever the document stats or the markers differ from the last time they were observed,
+/// and when a scene starts
+#[derive(Default)]
+pub struct Events {
+    sink: Option<Sink>,
+    stats: Option<(usize, usize, bool)>,
+    markers: Option<Vec<(String, usize)>>,
+    // The text as it was last written, or empty before that
+    written: u64,
+}
+
+impl Events {
+    pub fn to_writer(writer: impl Write + Send + 'static) -> Self {
+        Self::with_sink(Sink::Writer(Box::new(writer)))
+    }
+
+    #[cfg(test)]
+    pub fn memory() -> Self {
+        Self::with_sink(Sink::Memory(vec![]))
+    }
+
+    fn with_sink(sink: Sink) -> Self {
+        Self {
+            sink: Some(sink),
+            written: hash(""),
+            ..Default::default()
+        }
+    }
+
+    /// The document was written, so it's no longer dirty
+    pub fn written(&mut self, text: &str) {
+        self.written = hash(text);
+    }
+
+    pub fn observe(&mut self, doc: &Document) -> std::io::Result<()> {
+        if self.sink.is_none() {
+            return Ok(());
+        }
+
+        let text = doc.text();
+        let stats = (doc.line_count(), text.chars().count(), hash(text) != self.written);
+        if self.stats != Some(stats) {
+            self.stats = Some(stats);
+            let (lines, chars, dirty) = stats;
+            self.emit(Event::DocumentStats { lines, chars, dirty })?;
+        }
+
+        let mut markers = doc.markers.rows().map(|(name, row)| (name.to_string(), row)).collect::<Vec<_>>();
+        markers.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        if self.markers.as_ref() != Some(&markers) {
+            self.markers = Some(markers.clone());
+            self.emit(Event::Markers(markers))?;
+        }
+
+        Ok(())
+    }
+
+    /// A scene started
+    pub fn scene(&mut self, name: &str) -> std::io::Result<()> {
+        self.emit(Event::Scene(name.into()))
+    }
+
+    /// Everything emitted so far, when the events are kept in memory
+    #[cfg(test)]
+    pub fn take(&mut self) -> Vec<Event> {
+        match &mut self.sink {
+            Some(Sink::Memory(events)) => std::mem::take(events),
+            _ => vec![],
+        }
+    }
+
+    fn emit(&mut self, event: Event) -> std::io::Result<()> {
+        match &mut self.sink {
+            None => Ok(()),
+            #[cfg(test)]
+            Some(Sink::Memory(events)) => {
+                events.push(event);
+                Ok(())
+            }
+            Some(Sink::Writer(writer)) => writeln!(writer, "{}", event.to_json()),
+        }
+    }
+}
+
+fn hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json() {
+        let stats = Event::DocumentStats {
+            lines: 3,
+            chars: 20,
+            dirty: true,
+        };
+        assert_eq!(stats.to_json(), r#"{"event":"document_stats","lines":3,"chars":20,"dirty":true}"#);
+
+        let markers = Event::Markers(vec![("setup".into(), 1), ("a\"b".into(), 4)]);
+        assert_eq!(
+            markers.to_json(),
+            r#"{"event":"markers","markers":[{"name":"setup","row":1},{"name":"a\"b","row":4}]}"#
+        );
+        assert_eq!(Event::Markers(vec![]).to_json(), r#"{"event":"markers","markers":[]}"#);
+        assert_eq!(Event::Scene("intro".into()).to_json(), r#"{"event":"scene","name":"intro"}"#);
+    }
+
+    #[test]
+    fn scenes_are_always_emitted() {
+        let mut events = Events::memory();
+        events.scene("intro").unwrap();
+        events.scene("intro").unwrap();
+        assert_eq!(events.take(), [Event::Scene("intro".into()), Event::Scene("intro".into())]);
+    }
+
+    #[test]
+    fn only_changes_are_emitted() {
+        let mut events = Events::memory();
+        let mut doc = Document::new("a\n// @x\nb");
+        events.observe(&doc).unwrap();
+        events.observe(&doc).unwrap();
+        assert_eq!(events.take().len(), 2);
+
+        doc.insert_str(Default::default(), "c");
+        events.observe(&doc).unwrap();
+        assert_eq!(events.take(), [Event::DocumentStats { lines: 2, chars: 4, dirty: true }]);
+
+        events.written(doc.text());
+        events.observe(&doc).unwrap();
+        assert_eq!(events.take(), [Event::DocumentStats { lines: 2, chars: 4, dirty: false }]);
+    }
+
+    #[test]
+    fn nothing_without_a_sink() {
+        let mut events = Events::default();
+        events.observe(&Document::new("a")).unwrap();
+        assert!(events.take().is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use super::error::{Error, Result};
+
+/// How long `exec` waits for a command without a timeout of its own
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL: Duration = Duration::from_millis(10);
+
+/// Run a command with `sh -c` and return what it wrote to stdout.
+/// A command that fails or runs past the timeout is an error.
+pub fn run(command: &str, timeout: Duration) -> Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Exec(command.into(), e.to_string()))?;
+
+    // Read on other threads so a chatty command can't fill the pipe and block
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                _ = child.kill();
+                _ = child.wait();
+                return Err(Error::ExecTimeout(command.into(), timeout));
+            }
+            Ok(None) => std::thread::sleep(POLL),
+            Err(e) => return Err(Error::Exec(command.into(), e.to_string())),
+        }
+    };
+
+    // Something the command started in the background can keep the pipes open after it's done
+    let read = |output: Receiver<String>| match output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => Ok(output),
+        Err(RecvTimeoutError::Timeout) => Err(Error::ExecTimeout(command.into(), timeout)),
+        Err(RecvTimeoutError::Disconnected) => Ok(String::new()),
+    };
+    let stdout = read(stdout)?;
+    let stderr = read(stderr)?;
+    match status.success() {
+        true => Ok(stdout),
+        false => {
+            let status = match status.code() {
+                Some(code) => format!("exit status {code}"),
+                None => "killed by a signal".into(),
+            };
+            Err(Error::Exec(command.into(), format!("{status}\n{}", stderr.trim_end())))
+        }
+    }
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut bytes = vec![];
+        if let Some(mut pipe) = pipe {
+            _ = pipe.read_to_end(&mut bytes);
+        }
+        _ = sender.send(String::from_utf8_lossy(&bytes).into_owned());
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn captures_stdout() {
+        let output = run("echo hello; echo oops >&2", DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(output, "hello\n");
+
+        let output = run("echo oops >&2", DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(output, "");
+        let output = run("echo oops 2>&1", DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(output, "oops\n");
+    }
+
+    #[test]
+    fn failure_has_status_and_stderr() {
+        let err = run("echo out; echo broken >&2; exit 3", DEFAULT_TIMEOUT).unwrap_err();
+        assert_eq!(err.to_string(), "`echo out; echo broken >&2; exit 3` failed: exit status 3\nbroken");
+    }
+
+    #[test]
+    fn timeout() {
+        let start = Instant::now();
+        let err = run("sleep 5", Duration::from_millis(100)).unwrap_err();
+        assert!(matches!(err, Error::ExecTimeout(_, _)));
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn timeout_with_the_output_still_open() {
+        let start = Instant::now();
+        let err = run("sleep 5 & echo started", Duration::from_millis(200)).unwrap_err();
+        assert!(matches!(err, Error::ExecTimeout(_, _)));
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,204 @@
+use super::error::{Error, Result};
+use super::markers::is_marker;
+use crate::parser::Variable;
+
+// -----------------------------------------------------------------------------
+//   - Filter -
+// -----------------------------------------------------------------------------
+/// A change to the content of a source before it's typed or inserted
+#[derive(Debug, PartialEq)]
+enum Filter {
+    /// Put `n` spaces in front of every line that isn't empty
+    Indent(usize),
+    /// Remove the indentation every line that isn't empty has in common
+    Dedent,
+    Prefix(String),
+    Suffix(String),
+    /// Every line but marker comments, or the marker names would change
+    Upper,
+    Lower,
+    /// Whitespace at the start and the end
+    Trim,
+    /// Whitespace at the end
+    TrimEnd,
+    /// The first `n` lines
+    Head(usize),
+    /// The last `n` lines
+    Tail(usize),
+}
+
+impl Filter {
+    fn new(filter: crate::parser::Filter) -> Result<Self> {
+        let crate::parser::Filter { name, args, line } = filter;
+        let filter = match (name.as_str(), &args[..]) {
+            ("indent", [Variable::Int(n @ 0..)]) => Self::Indent(*n as usize),
+            ("dedent", []) => Self::Dedent,
+            ("prefix", [Variable::Str(prefix)]) => Self::Prefix(prefix.clone()),
+            ("suffix", [Variable::Str(suffix)]) => Self::Suffix(suffix.clone()),
+            ("upper", []) => Self::Upper,
+            ("lower", []) => Self::Lower,
+            ("trim", []) => Self::Trim,
+            ("trim_end", []) => Self::TrimEnd,
+            ("head", [Variable::Int(n @ 0..)]) => Self::Head(*n as usize),
+            ("tail", [Variable::Int(n @ 0..)]) => Self::Tail(*n as usize),
+            _ => {
+                let Some(expected) = expected_args(&name) else { return Err(Error::UnknownFilter { name, line }) };
+                return Err(Error::FilterArgs { name, expected, line });
+            }
+        };
+        Ok(filter)
+    }
+
+    fn apply(&self, content: &str) -> String {
+        match self {
+            Self::Indent(n) => {
+                let indent = " ".repeat(*n);
+                lines(content, |line| match line.trim().is_empty() {
+                    true => line.to_string(),
+                    false => format!("{indent}{line}"),
+                })
+            }
+            Self::Dedent => {
+                // The indentation is compared as it is, so a tab and four spaces have nothing in common
+                let common = content
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+                    .reduce(|common, indent| {
+                        let len = common.bytes().zip(indent.bytes()).take_while(|(a, b)| a == b).count();
+                        &common[..len]
+                    })
+                    .unwrap_or("");
+                lines(content, |line| line.strip_prefix(common).unwrap_or(line.trim_start()).to_string())
+            }
+            Self::Prefix(prefix) => lines(content, |line| format!("{prefix}{line}")),
+            Self::Suffix(suffix) => lines(content, |line| format!("{line}{suffix}")),
+            Self::Upper => lines(content, |line| match is_marker(line) {
+                true => line.to_string(),
+                false => line.to_uppercase(),
+            }),
+            Self::Lower => lines(content, |line| match is_marker(line) {
+                true => line.to_string(),
+                false => line.to_lowercase(),
+            }),
+            Self::Trim => content.trim().to_string(),
+            Self::TrimEnd => content.trim_end().to_string(),
+            Self::Head(n) => content.split_inclusive('\n').take(*n).collect(),
+            Self::Tail(n) => {
+                let len = content.split_inclusive('\n').count();
+                content.split_inclusive('\n').skip(len.saturating_sub(*n)).collect()
+            }
+        }
+    }
+}
+
+// What the filter takes, for filters that exist
+fn expected_args(name: &str) -> Option<&'static str> {
+    let args = match name {
+        "indent" => "a number of spaces",
+        "head" | "tail" => "a number of lines",
+        "prefix" | "suffix" => "a string",
+        "dedent" | "upper" | "lower" | "trim" | "trim_end" => "no arguments",
+        _ => return None,
+    };
+    Some(args)
+}
+
+// Every line without its line break, put back together with the line breaks
+fn lines(content: &str, mut f: impl FnMut(&str) -> String) -> String {
+    content
+        .split_inclusive('\n')
+        .map(|line| match line.strip_suffix('\n') {
+            Some(line) => f(line) + "\n",
+            None => f(line),
+        })
+        .collect()
+}
+
+/// Run the content through the filters, left to right
+pub fn apply(content: String, filters: Vec<crate::parser::Filter>) -> Result<String> {
+    filters
+        .into_iter()
+        .try_fold(content, |content, filter| Ok(Filter::new(filter)?.apply(&content)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filter(name: &str, args: Vec<Variable>) -> crate::parser::Filter {
+        crate::parser::Filter {
+            name: name.into(),
+            args,
+            line: 3,
+        }
+    }
+
+    fn apply_one(content: &str, name: &str, args: Vec<Variable>) -> String {
+        apply(content.into(), vec![filter(name, args)]).unwrap()
+    }
+
+    #[test]
+    fn indent() {
+        let output = apply_one("a\n\n  b\n", "indent", vec![Variable::Int(2)]);
+        assert_eq!(output, "  a\n\n    b\n");
+    }
+
+    #[test]
+    fn dedent() {
+        let output = apply_one("    a\n\n      b\n    c", "dedent", vec![]);
+        assert_eq!(output, "a\n\n  b\nc");
+        assert_eq!(apply_one("a\n  b", "dedent", vec![]), "a\n  b");
+    }
+
+    #[test]
+    fn dedent_tabs_and_spaces() {
+        assert_eq!(apply_one("\t\ta\n\tb", "dedent", vec![]), "\ta\nb");
+        assert_eq!(apply_one("\ta\n    b", "dedent", vec![]), "\ta\n    b");
+        assert_eq!(apply_one("  \ta\n  b", "dedent", vec![]), "\ta\nb");
+    }
+
+    #[test]
+    fn prefix_and_suffix() {
+        assert_eq!(apply_one("a\nb\n", "prefix", vec![Variable::Str("- ".into())]), "- a\n- b\n");
+        assert_eq!(apply_one("a\nb", "suffix", vec![Variable::Str(";".into())]), "a;\nb;");
+    }
+
+    #[test]
+    fn case() {
+        assert_eq!(apply_one("Straße", "upper", vec![]), "STRASSE");
+        assert_eq!(apply_one("ÅÄÖ", "lower", vec![]), "åäö");
+
+        // Marker names stay as they are
+        let output = apply_one("fn main() {\n    // @Setup col=4\n    // @@Not\n}", "upper", vec![]);
+        assert_eq!(output, "FN MAIN() {\n    // @Setup col=4\n    // @@NOT\n}");
+        assert_eq!(apply_one("# @Setup\nA", "lower", vec![]), "# @Setup\na");
+    }
+
+    #[test]
+    fn trim() {
+        assert_eq!(apply_one("\n  a\nb  \n", "trim", vec![]), "a\nb");
+        assert_eq!(apply_one("\n  a\nb  \n", "trim_end", vec![]), "\n  a\nb");
+    }
+
+    #[test]
+    fn head_and_tail() {
+        let content = "// license\n// more license\nfn main() {}\n";
+        assert_eq!(apply_one(content, "head", vec![Variable::Int(1)]), "// license\n");
+        assert_eq!(apply_one(content, "tail", vec![Variable::Int(1)]), "fn main() {}\n");
+        assert_eq!(apply_one(content, "tail", vec![Variable::Int(9)]), content);
+        assert_eq!(apply_one(content, "head", vec![Variable::Int(0)]), "");
+    }
+
+    #[test]
+    fn errors() {
+        let unknown = apply("a".into(), vec![filter("reverse", vec![])]).unwrap_err();
+        assert_eq!(unknown.to_string(), "unknown filter `reverse` on line 3");
+
+        let arity = apply("a".into(), vec![filter("indent", vec![])]).unwrap_err();
+        assert_eq!(arity.to_string(), "filter `indent` on line 3 takes a number of spaces");
+
+        let negative = apply("a".into(), vec![filter("indent", vec![Variable::Int(-1)])]);
+        assert!(matches!(negative, Err(Error::FilterArgs { .. })));
+    }
+}
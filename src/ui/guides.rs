@@ -0,0 +1,67 @@
+use anathema::state::Color;
+
+pub static GUIDE_COLOR: Color = Color::Rgb(0x30, 0x30, 0x30);
+
+// -----------------------------------------------------------------------------
+//   - Column guides -
+// -----------------------------------------------------------------------------
+/// Columns that should be tinted on every row, like vim's `colorcolumn`.
+/// Columns are one based.
+pub struct ColorColumns {
+    columns: Vec<u16>,
+}
+
+impl ColorColumns {
+    pub fn new() -> Self {
+        Self { columns: vec![] }
+    }
+
+    pub fn set(&mut self, columns: Vec<u16>) {
+        self.columns = columns;
+    }
+
+    /// The canvas columns of the guides that are visible given the
+    /// horizontal offset and the width of the canvas.
+    pub fn screen_columns(&self, offset_x: i32, width: u16) -> impl Iterator<Item = u16> + '_ {
+        self.columns
+            .iter()
+            .map(move |col| *col as i32 - 1 + offset_x)
+            .filter(move |x| (0..width as i32).contains(x))
+            .map(|x| x as u16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn screen_columns(columns: &[u16], offset_x: i32, width: u16) -> Vec<u16> {
+        let mut guides = ColorColumns::new();
+        guides.set(columns.to_vec());
+        guides.screen_columns(offset_x, width).collect()
+    }
+
+    #[test]
+    fn guides_without_offset() {
+        assert_eq!(screen_columns(&[1, 80, 100], 0, 120), vec![0, 79, 99]);
+    }
+
+    #[test]
+    fn guides_outside_of_canvas() {
+        assert_eq!(screen_columns(&[80, 100], 0, 90), vec![79]);
+        assert!(screen_columns(&[80], 0, 79).is_empty());
+    }
+
+    #[test]
+    fn guides_with_horizontal_offset() {
+        // Scrolled 30 columns to the right
+        assert_eq!(screen_columns(&[20, 80, 100], -30, 60), vec![49]);
+        assert_eq!(screen_columns(&[31], -30, 60), vec![0]);
+        assert!(screen_columns(&[30], -30, 60).is_empty());
+    }
+
+    #[test]
+    fn guides_off() {
+        assert!(screen_columns(&[], 0, 100).is_empty());
+    }
+}
@@ -0,0 +1,594 @@
+use std::time::Duration;
+
+use anathema::geometry::{LocalPos, Size};
+use anathema::state::Color;
+use anathema::widgets::{Attributes, Style};
+use unicode_width::UnicodeWidthChar;
+
+use super::budget::Timings;
+use super::editor::{DocState, Editor};
+use super::error::{Error, Result};
+use super::estimate::FRAME_TIME;
+use super::instructions::Instruction;
+use super::mirror::Grid;
+use super::shutdown::{self, Shutdown};
+use super::syntax::Highlighter;
+
+// Scripts that are still going after this much simulated time are assumed to never end
+const MAX_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Run the instructions without a terminal and render frames as text.
+///
+/// The final frame is always included. With `every` set, every nth drawn frame
+/// before it is included as well. Only the editor canvas is rendered: the status
+/// bar, command line and popups are part of the template, and transitions only
+/// exist on a real canvas.
+pub fn frames(instructions: Vec<Instruction>, size: Size, seed: u64, every: Option<usize>) -> Result<Vec<String>> {
+    let mut frames = vec![];
+    let (mut editor, drawn) = play(instructions, size, seed, |editor, drawn| {
+        if every.is_some_and(|every| every > 0 && drawn % every == 0) {
+            frames.push(frame(editor, drawn)?);
+        }
+        Ok(())
+    })?;
+
+    // The final frame might already be there
+    if every.is_none_or(|every| every == 0 || drawn % every != 0) {
+        frames.push(frame(&mut editor, drawn)?);
+    }
+    Ok(frames)
+}
+
+/// Run the instructions without a terminal and render the final frame
+pub fn final_grid(instructions: Vec<Instruction>, size: Size, seed: u64) -> Result<Grid> {
+    let (mut editor, _) = play(instructions, size, seed, |_, _| Ok(()))?;
+    editor.render_grid()
+}
+
+// Run the editor to the end, calling `on_drawn` with the number of frames drawn so far every time one is drawn
+fn play(
+    instructions: Vec<Instruction>,
+    size: Size,
+    seed: u64,
+    mut on_drawn: impl FnMut(&mut Editor, usize) -> Result<()>,
+) -> Result<(Editor, usize)> {
+    let shutdown = Shutdown::new();
+    // Without keys only a loop starts it over
+    let loops = instructions.iter().any(|inst| matches!(inst, Instruction::Loop { .. }));
+    let mut editor = Editor::new(instructions, Highlighter::bundled(), FRAME_TIME, shutdown.clone(), Timings::new());
+    editor.seed(seed);
+    editor.resize(size);
+    if !loops {
+        editor.forget_history();
+    }
+
+    let mut state = DocState::new();
+    let mut drawn = 0;
+    let mut elapsed = Duration::ZERO;
+
+    while !editor.is_done() {
+        if elapsed >= MAX_DURATION {
+            return Err(Error::Unfinished(MAX_DURATION));
+        }
+        elapsed += FRAME_TIME;
+
+        if !editor.update(&mut state, FRAME_TIME) {
+            continue;
+        }
+
+        drawn += 1;
+        if let Some(msg) = state.error() {
+            return Err(Error::Script(msg));
+        }
+        on_drawn(&mut editor, drawn)?;
+    }
+
+    shutdown.finish(shutdown::DEADLINE);
+    if let Some(msg) = state.error() {
+        return Err(Error::Script(msg));
+    }
+    Ok((editor, drawn))
+}
+
+fn frame(editor: &mut Editor, index: usize) -> Result<String> {
+    let grid = editor.render_grid()?;
+    let cursor = editor.cursor();
+    Ok(format!("-- frame {index}, cursor {}:{} --\n{}", cursor.y, cursor.x, ansi(&grid)))
+}
+
+/// Every row of the grid as a line of text, with SGR sequences where the style changes.
+/// Trailing empty cells are left out.
+pub fn ansi(grid: &Grid) -> String {
+    let size = grid.size();
+    let mut out = String::new();
+
+    for y in 0..size.height {
+        // Line endings are drawn too, but they don't show up in a terminal
+        let cells = (0..size.width)
+            .map(|x| grid.get(LocalPos::new(x, y)).filter(|(c, _)| !c.is_control()))
+            .collect::<Vec<_>>();
+        let len = cells.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+
+        let mut current = None;
+        let mut x = 0;
+        while x < len {
+            let (c, style) = match cells[x] {
+                Some((c, style)) => (c, Some(style)),
+                None => (' ', None),
+            };
+
+            let key = style.map(|style| (style.fg, style.bg, style.attributes));
+            if key != current {
+                out.push_str(&sgr(style));
+                current = key;
+            }
+
+            out.push(c);
+            // The cell after a wide char is covered by it
+            x += c.width().unwrap_or(1).max(1);
+        }
+
+        if current.is_some() {
+            out.push_str(&sgr(None));
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Every row of the grid as a line of HTML, with a `span` where the style changes.
+/// Trailing empty cells are left out, like [`ansi`].
+pub fn html(grid: &Grid) -> String {
+    let size = grid.size();
+    let mut out = String::new();
+
+    for y in 0..size.height {
+        let cells = (0..size.width)
+            .map(|x| grid.get(LocalPos::new(x, y)).filter(|(c, _)| !c.is_control()))
+            .collect::<Vec<_>>();
+        let len = cells.iter().rposition(Option::is_some).map_or(0, |last| last + 1);
+
+        let mut current = None;
+        let mut x = 0;
+        while x < len {
+            let (c, style) = match cells[x] {
+                Some((c, style)) => (c, Some(style)),
+                None => (' ', None),
+            };
+
+            // Unstyled text needs no span
+            let key = style.map(css).filter(|css| !css.is_empty());
+            if key != current {
+                if current.is_some() {
+                    out.push_str("</span>");
+                }
+                if let Some(css) = &key {
+                    out.push_str(&format!("<span style=\"{css}\">"));
+                }
+                current = key;
+            }
+
+            match c {
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '&' => out.push_str("&amp;"),
+                c => out.push(c),
+            }
+            x += c.width().unwrap_or(1).max(1);
+        }
+
+        if current.is_some() {
+            out.push_str("</span>");
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+fn css(style: Style) -> String {
+    let mut css = vec![];
+    css.extend(style.fg.and_then(css_color).map(|color| format!("color:{color}")));
+    css.extend(style.bg.and_then(css_color).map(|color| format!("background:{color}")));
+    for (attribute, property) in [
+        (Attributes::BOLD, "font-weight:bold"),
+        (Attributes::DIM, "opacity:0.6"),
+        (Attributes::ITALIC, "font-style:italic"),
+        (Attributes::UNDERLINED, "text-decoration:underline"),
+        (Attributes::CROSSED_OUT, "text-decoration:line-through"),
+    ] {
+        if style.attributes.contains(attribute) {
+            css.push(property.into());
+        }
+    }
+    css.join(";")
+}
+
+// The colours of the default xterm palette. `None` leaves the page's own colour.
+fn css_color(color: Color) -> Option<String> {
+    const BASIC: [Color; 16] = [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::Grey,
+        Color::DarkGrey,
+        Color::LightRed,
+        Color::LightGreen,
+        Color::LightYellow,
+        Color::LightBlue,
+        Color::LightMagenta,
+        Color::LightCyan,
+        Color::White,
+    ];
+
+    let (r, g, b) = match color {
+        Color::Reset => return None,
+        Color::Black => (0, 0, 0),
+        Color::Red => (205, 0, 0),
+        Color::Green => (0, 205, 0),
+        Color::Yellow => (205, 205, 0),
+        Color::Blue => (0, 0, 238),
+        Color::Magenta => (205, 0, 205),
+        Color::Cyan => (0, 205, 205),
+        Color::Grey => (229, 229, 229),
+        Color::DarkGrey => (127, 127, 127),
+        Color::LightRed => (255, 0, 0),
+        Color::LightGreen => (0, 255, 0),
+        Color::LightYellow => (255, 255, 0),
+        Color::LightBlue => (92, 92, 255),
+        Color::LightMagenta => (255, 0, 255),
+        Color::LightCyan => (0, 255, 255),
+        Color::White => (255, 255, 255),
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::AnsiVal(n @ 0..16) => return css_color(BASIC[n as usize]),
+        // The 6x6x6 cube, then the greys
+        Color::AnsiVal(n @ 16..232) => {
+            let level = |i: u8| if i == 0 { 0 } else { 55 + i * 40 };
+            let n = n - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        Color::AnsiVal(n) => {
+            let grey = 8 + (n - 232) * 10;
+            (grey, grey, grey)
+        }
+    };
+    Some(format!("#{r:02x}{g:02x}{b:02x}"))
+}
+
+// Select graphic rendition: always reset first, so a sequence only depends on its own style
+fn sgr(style: Option<Style>) -> String {
+    let Some(style) = style else { return "\x1b[0m".into() };
+
+    let mut codes = vec![String::from("0")];
+    for (attribute, code) in [
+        (Attributes::BOLD, "1"),
+        (Attributes::DIM, "2"),
+        (Attributes::ITALIC, "3"),
+        (Attributes::UNDERLINED, "4"),
+        (Attributes::REVERSED, "7"),
+        (Attributes::CROSSED_OUT, "9"),
+    ] {
+        if style.attributes.contains(attribute) {
+            codes.push(code.into());
+        }
+    }
+
+    codes.extend(style.fg.map(|color| color_code(color, 0)));
+    codes.extend(style.bg.map(|color| color_code(color, 10)));
+
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+// Foreground codes, background codes are offset by 10
+fn color_code(color: Color, offset: u8) -> String {
+    let code = match color {
+        Color::Reset => 39,
+        Color::Black => 30,
+        Color::Red => 31,
+        Color::Green => 32,
+        Color::Yellow => 33,
+        Color::Blue => 34,
+        Color::Magenta => 35,
+        Color::Cyan => 36,
+        Color::Grey => 37,
+        Color::DarkGrey => 90,
+        Color::LightRed => 91,
+        Color::LightGreen => 92,
+        Color::LightYellow => 93,
+        Color::LightBlue => 94,
+        Color::LightMagenta => 95,
+        Color::LightCyan => 96,
+        Color::White => 97,
+        Color::Rgb(r, g, b) => return format!("{};2;{r};{g};{b}", 38 + offset),
+        Color::AnsiVal(n) => return format!("{};5;{n}", 38 + offset),
+    };
+    (code + offset).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ui::events::{Event, Events};
+    use crate::ui::splash::Splash;
+
+    #[test]
+    fn styles() {
+        let mut grid = Grid::new(Size::new(6, 2));
+        let mut red = Style::new();
+        red.fg = Some(Color::Red);
+        red.set_bold(true);
+        grid.put('a', red, LocalPos::new(0, 0));
+        grid.put('b', red, LocalPos::new(1, 0));
+        grid.put('c', Style::new(), LocalPos::new(3, 0));
+
+        let mut bg = Style::new();
+        bg.bg = Some(Color::Rgb(1, 2, 3));
+        grid.put('界', bg, LocalPos::new(0, 1));
+        grid.put('d', bg, LocalPos::new(2, 1));
+
+        assert_eq!(ansi(&grid), "\x1b[0;1;31mab\x1b[0m \x1b[0mc\x1b[0m\n\x1b[0;48;2;1;2;3m界d\x1b[0m\n");
+    }
+
+    #[test]
+    fn html_styles() {
+        let mut grid = Grid::new(Size::new(6, 2));
+        let mut red = Style::new();
+        red.fg = Some(Color::Rgb(255, 0, 0));
+        red.set_bold(true);
+        grid.put('<', red, LocalPos::new(0, 0));
+        grid.put('b', red, LocalPos::new(1, 0));
+        grid.put('&', Style::new(), LocalPos::new(3, 0));
+        grid.put('界', Style::new(), LocalPos::new(0, 1));
+        grid.put('d', Style::new(), LocalPos::new(2, 1));
+
+        let expected = "<span style=\"color:#ff0000;font-weight:bold\">&lt;b</span> &amp;\n界d\n";
+        assert_eq!(html(&grid), expected);
+        assert_eq!(css_color(Color::AnsiVal(1)), css_color(Color::Red));
+        assert_eq!(css_color(Color::AnsiVal(196)).unwrap(), "#ff0000");
+        assert_eq!(css_color(Color::AnsiVal(232)).unwrap(), "#080808");
+    }
+
+    #[test]
+    fn frames_are_stable() {
+        let instructions = || {
+            vec![
+                Instruction::SetJitter(40),
+                Instruction::LoadTypeBuffer("fn main() {}".into()),
+            ]
+        };
+        let size = Size::new(20, 4);
+        let first = frames(instructions(), size, 3, Some(5)).unwrap();
+        let second = frames(instructions(), size, 3, Some(5)).unwrap();
+        assert_eq!(first, second);
+        assert!(first.len() > 1);
+        assert!(first.last().unwrap().contains("fn main() {}"));
+    }
+
+    #[test]
+    fn restart_plays_it_again() {
+        let instructions = vec![
+            Instruction::SetTitle("demo".into()),
+            Instruction::LoadTypeBuffer("fn main() {\n}".into()),
+            Instruction::Jump(anathema::geometry::Pos::new(-1, 0)),
+            Instruction::LoadTypeBuffer("    x\n".into()),
+            Instruction::popup("hi".into()),
+            Instruction::LoadCommandBuffer(":w".into()),
+        ];
+        // Not through `play`, which doesn't keep what was played without a loop
+        let mut editor = Editor::new(instructions, Highlighter::bundled(), FRAME_TIME, Shutdown::new(), Timings::new());
+        editor.resize(Size::new(40, 10));
+        let mut state = DocState::new();
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME_TIME);
+        }
+        let first = editor.text().to_string();
+        assert!(first.contains("x"));
+
+        editor.restart(&mut state);
+        assert_eq!(editor.text(), "");
+        assert_eq!(editor.cursor(), anathema::geometry::Pos::ZERO);
+
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME_TIME);
+        }
+        assert_eq!(editor.text(), first);
+        assert_eq!(state.error(), None);
+    }
+
+    #[test]
+    fn auto_typos_leave_the_text_as_written() {
+        use crate::ui::random::Random;
+
+        let chars = "abcdefghijklmnopqrstuvwxyz    ABCXYZ\n\n(),._=12é界";
+        let chars = chars.chars().collect::<Vec<_>>();
+        let mut slower = 0;
+        for seed in 1..=20 {
+            let mut rand = Random::with_seed(seed);
+            let len = 20 + rand.next(100) as usize;
+            let text = (0..len).map(|_| chars[rand.next(chars.len() as u64) as usize]).collect::<String>();
+
+            let play_at = |rate| {
+                let instructions = vec![
+                    Instruction::AutoTypo {
+                        rate,
+                        max_per_line: None,
+                    },
+                    Instruction::LoadTypeBuffer(text.clone()),
+                ];
+                let (editor, drawn) = play(instructions, Size::new(40, 10), seed, |_, _| Ok(())).unwrap();
+                assert_eq!(editor.text(), text, "seed {seed}");
+                drawn
+            };
+
+            // Typos take longer, but that's all they do
+            let plain = play_at(0);
+            if play_at(50) > plain {
+                slower += 1;
+            }
+        }
+        // Not every text has a word in it that can get a typo
+        assert!(slower >= 10, "{slower}");
+    }
+
+    // When the cursor moves, ticking like the runtime does
+    fn emitted(instructions: Vec<Instruction>) -> Vec<Duration> {
+        let mut instructions = instructions;
+        instructions.insert(0, Instruction::JitterProfile(crate::parser::JitterProfile::Off));
+        let mut editor = Editor::new(instructions, Highlighter::plain_text(), FRAME_TIME, Shutdown::new(), Timings::new());
+        editor.seed(1);
+        editor.resize(Size::new(20, 4));
+
+        let mut state = DocState::new();
+        let tick = Duration::from_millis(10);
+        let mut now = Duration::ZERO;
+        let mut cursor = editor.cursor();
+        let mut times = vec![];
+        while !editor.is_done() {
+            now += tick;
+            editor.update(&mut state, tick);
+            if editor.cursor() != cursor {
+                cursor = editor.cursor();
+                times.push(now);
+            }
+        }
+        times
+    }
+
+    fn typing(text: &str) -> Instruction {
+        Instruction::LoadTypeBuffer(text.into())
+    }
+
+    #[test]
+    fn no_idle_frame_between_type_instructions() {
+        let speed = Instruction::Speed(Duration::from_millis(100));
+        let whole = emitted(vec![speed.clone(), typing("abcdef")]);
+        let split = emitted(vec![
+            speed,
+            typing("ab"),
+            typing("cd"),
+            Instruction::SetTitle("title".into()),
+            Instruction::SetJitter(20),
+            typing("ef"),
+        ]);
+        assert_eq!(whole.len(), 6);
+        assert_eq!(whole, split);
+    }
+
+    #[test]
+    fn waits_stay_in_place() {
+        let speed = || Instruction::Speed(Duration::from_millis(100));
+        let pause = Duration::from_millis(300);
+        let plain = emitted(vec![speed(), typing("ab"), typing("cd")]);
+        let waited = emitted(vec![speed(), typing("ab"), Instruction::Wait(pause), typing("cd")]);
+
+        assert_eq!(plain[..2], waited[..2]);
+        for (plain, waited) in plain[2..].iter().zip(&waited[2..]) {
+            assert_eq!(*waited - *plain, pause);
+        }
+
+        let paused = emitted(vec![speed(), Instruction::LinePause(pause), typing("a\nb")]);
+        assert_eq!(paused[2] - paused[1], pause + Duration::from_millis(100));
+    }
+
+    #[test]
+    fn free_instructions_are_capped() {
+        let mut instructions = vec![Instruction::SetTitle("title".into()); 1000];
+        instructions.push(typing("a"));
+        let capped = emitted(instructions);
+        let direct = emitted(vec![typing("a")]);
+        assert_eq!(capped.len(), 1);
+        assert!(capped[0] > direct[0]);
+    }
+
+    #[test]
+    fn splash_holds() {
+        let hold = Duration::from_millis(1500);
+        let splash = Instruction::Splash(Splash {
+            lines: vec!["Mimic".into()],
+            styles: vec![],
+            hold,
+        });
+        let plain = emitted(vec![typing("ab")]);
+        let splashed = emitted(vec![splash, typing("ab")]);
+        assert_eq!(splashed.len(), plain.len());
+        for (plain, splashed) in plain.iter().zip(&splashed) {
+            assert_eq!(*splashed - *plain, hold);
+        }
+    }
+
+    #[test]
+    fn splash_is_centred_then_cleared() {
+        let splash = Instruction::Splash(Splash {
+            lines: vec!["ab".into(), "cde".into()],
+            styles: vec![],
+            hold: Duration::from_secs(1),
+        });
+        let mut editor = Editor::new(vec![splash], Highlighter::bundled(), FRAME_TIME, Shutdown::new(), Timings::new());
+        editor.resize(Size::new(7, 4));
+        let mut state = DocState::new();
+
+        editor.update(&mut state, FRAME_TIME);
+        let grid = ansi(&editor.render_grid().unwrap());
+        assert_eq!(grid, "\n  \x1b[0mab\x1b[0m\n  \x1b[0mcde\x1b[0m\n\n");
+
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME_TIME);
+        }
+        assert_eq!(ansi(&editor.render_grid().unwrap()), "\n\n\n\n");
+    }
+
+    #[test]
+    fn document_stats_and_markers() {
+        use anathema::geometry::Pos;
+
+        let instructions = vec![
+            Instruction::Speed(Duration::from_millis(1)),
+            typing("a\n// @main\nb"),
+            // Delete the line above the marker
+            Instruction::Jump(Pos::new(-1, -1)),
+            Instruction::Select(Size::new(1, 1)),
+            Instruction::Delete,
+            Instruction::Join(1),
+            Instruction::Clear,
+        ];
+        let mut editor = Editor::new(instructions, Highlighter::plain_text(), FRAME_TIME, Shutdown::new(), Timings::new());
+        editor.set_events(Events::memory());
+        editor.resize(Size::new(20, 4));
+        let mut state = DocState::new();
+        while !editor.is_done() {
+            editor.update(&mut state, FRAME_TIME);
+        }
+
+        let stats = |lines, chars, dirty| Event::DocumentStats { lines, chars, dirty };
+        let markers = |markers: &[(&str, usize)]| {
+            Event::Markers(markers.iter().map(|(name, row)| (name.to_string(), *row)).collect())
+        };
+        let expected = vec![
+            stats(1, 0, false),
+            markers(&[]),
+            stats(1, 1, true),
+            stats(2, 2, true),
+            stats(2, 3, true),
+            markers(&[("main", 1)]),
+            stats(2, 2, true),
+            stats(1, 1, true),
+            markers(&[("main", 0)]),
+            // Empty is how the document started out, so there is nothing to write
+            stats(1, 0, false),
+            markers(&[]),
+        ];
+        assert_eq!(editor.take_events(), expected);
+    }
+
+    #[test]
+    fn script_errors() {
+        let instructions = vec![Instruction::SetTheme("no such theme".into()), Instruction::LoadTypeBuffer("a".into())];
+        let err = frames(instructions, Size::new(10, 2), 1, None).unwrap_err();
+        assert!(matches!(err, Error::InvalidTheme(_)));
+    }
+}
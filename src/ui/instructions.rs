@@ -2,57 +2,277 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use anathema::geometry::{Pos, Size};
+use serde::{Deserialize, Serialize};
 
+use super::json::{millis, pos, size};
+use super::margins::Margins;
 use super::markers::Markers;
-use crate::parser::Variable;
+use super::splash::Splash;
+use crate::parser::{Anchor, FindMiss, HScroll, JitterProfile, LoopCount, PopupPosition, TransitionKind, Variable};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Instruction {
     // Relative jump
-    Jump(Pos),
-    JumpToMarker(String),
-    Select(Size),
+    Jump(#[serde(with = "pos")] Pos),
+    // To a row and column of the document, or as close to it as the document goes
+    JumpAbsolute(#[serde(with = "pos")] Pos),
+    // To the first line, or to the last line that isn't empty, as the document is by then
+    JumpStart,
+    JumpEnd,
+    // Rows to scroll the view by, positive is down. The cursor stays where it is.
+    Scroll(i32),
+    // To the marker and then `offset` from there, without stopping at the marker
+    JumpToMarker {
+        name: String,
+        #[serde(with = "pos")]
+        offset: Pos,
+    },
+    Select(#[serde(with = "size")] Size),
+    // From the cursor to the end of the line, as wide as the line is by then
+    SelectEol,
+    // Whole lines, from the row of the first marker up to the row of the second
+    SelectMarkers(String, String),
 
     // -----------------------------------------------------------------------------
     //   - Modifying instructions -
     // -----------------------------------------------------------------------------
     // * Require new highlighting
     // * If the `content` contains a newline then offset all the subsequent markers
+    // Typing replaces the selection, unless it's kept
     LoadTypeBuffer(String),
+    LoadTypeBufferKeepSelection(String),
+    // Typed as is, without looking for markers. Queued by auto typos.
+    LoadTypeBufferRaw(String),
     LoadCommandBuffer(String),
     ClearCommandBuffer,
     ClearCommandWait,
-    CommandClearTimeout(Duration),
+    CommandClearTimeout(#[serde(with = "millis")] Duration),
+    // The frame time of the command line, `None` types it at the speed of the code
+    CommandSpeed(#[serde(with = "millis::option")] Option<Duration>),
     // Inserts all the content at once, unlike Type which types the content out
     // character by character
     Insert(String),
     // Remove all character in the highlighted range of the editor, or
     // if no selection exists: remove the character under the cursor
     Delete,
-    Wait(Duration),
-    Speed(Duration),
-    LinePause(Duration),
+    // Remove `count` lines, starting at the cursor, along with their line breaks
+    DeleteLine(usize),
+    // Remove the word under the cursor, see `Document::delete_word`
+    DeleteWord,
+    // Put `count` copies of the cursor's line below it, and move the cursor to the last one
+    DuplicateLine(usize),
+    // Swap the cursor's line with the one above or below it, `count` times, a step at a time
+    MoveLine {
+        up: bool,
+        count: usize,
+    },
+    // From the cursor to the end of the line, see `Document::clear_eol`
+    ClearEol,
+    // Keep the text, markers and cursor to restore later
+    Snapshot(String),
+    Restore(String),
+    // Copy the selection, or the cursor's line, into the register
+    Yank,
+    // The register, at the cursor or on lines of its own below or above the cursor's line
+    Paste {
+        above: bool,
+    },
+    // Every occurrence in the document at once, see `Document::replace_all`
+    ReplaceAll {
+        needle: String,
+        replacement: String,
+    },
+    // Remove the characters before the cursor one frame at a time,
+    // joining the line onto the line above at the start of a line
+    Backspace(usize),
+    // Join the next `n` lines onto the current line
+    Join(usize),
+    // Split the line at the cursor
+    SplitLine,
+    // Line up the first `c` on the next `lines` lines
+    Align {
+        c: char,
+        lines: usize,
+        instant: bool,
+    },
+    // Insert `width` spaces at the position, queued by `Align`
+    Pad {
+        #[serde(with = "pos")]
+        pos: Pos,
+        width: usize,
+    },
+    AutoIndent(bool),
+    // The number of lines from the cursor, or the selected lines, or the cursor's line
+    Indent {
+        lines: Option<usize>,
+        levels: usize,
+    },
+    Dedent {
+        lines: Option<usize>,
+        levels: usize,
+    },
+    IndentUnit(String),
+    TabWidth(usize),
+    // Comment out the next `lines` lines, or else the selected lines or the cursor's line,
+    // or uncomment them if they all are
+    ToggleComment {
+        lines: Option<usize>,
+        instant: bool,
+    },
+    // Comment or uncomment a single line, queued by `ToggleComment`
+    CommentLine {
+        row: usize,
+        leader: String,
+        comment: bool,
+    },
+    // Use this rather than the leader for the extension
+    CommentLeader(String),
+    // The leader for extensions that have none of their own
+    CommentPrefix(String),
+    Wait(#[serde(with = "millis")] Duration),
+    // A random wait between the two, picked by the editor so a seed makes it the same every run
+    WaitRange(#[serde(with = "millis")] Duration, #[serde(with = "millis")] Duration),
+    // Run the body every `interval` alongside the other instructions
+    Every {
+        #[serde(with = "millis")]
+        interval: Duration,
+        body: Vec<Instruction>,
+    },
+    EveryStop,
+    Budget {
+        line: usize,
+        #[serde(with = "millis")]
+        limit: Duration,
+    },
+    BudgetEnd,
+    Speed(#[serde(with = "millis")] Duration),
+    LinePause(#[serde(with = "millis")] Duration),
 
     FindInCurrentLine {
         needle: String,
         end_of_word: bool,
+        // From the cursor towards the start of the line
+        reverse: bool,
         count: usize,
+        // The instructions to drop on a miss with `find_miss skip`, rather than the
+        // count of the policy. Set when the find belongs to a `replace`.
+        dependents: Option<usize>,
     },
+    FindMiss(FindMiss),
 
     SetTitle(String),
     SetExtension(String),
     SetJitter(u64),
+    JitterProfile(JitterProfile),
+    BurstPause {
+        #[serde(with = "millis")]
+        min: Duration,
+        #[serde(with = "millis")]
+        max: Duration,
+    },
+    AutoTypo {
+        rate: u8,
+        max_per_line: Option<usize>,
+    },
     SetTheme(String),
     ShowLineNumbers(bool),
+    HScroll(HScroll),
+    BreakLigatures(bool),
+    AddLigature(String),
+    ColorColumn(Vec<u16>),
+    Margins(Margins),
+    // Leaves the columns as they are without any
+    ScrollOff {
+        rows: u16,
+        columns: Option<u16>,
+    },
+    Wrap(Option<String>),
     AddMarkers {
         row: usize,
         markers: Markers,
     },
+    StrictMarkers(bool),
+    // On the row of the cursor, replacing a marker with the same name
+    SetMarker(String),
+    RemoveMarker(String),
+    VirtualText {
+        anchor: Anchor,
+        text: String,
+        below: bool,
+    },
+    // All of it, or the text of a row
+    ClearVirtualText(Option<usize>),
+    // Shown on its own for its hold time
+    Splash(Splash),
+    HideSplash,
     LoadAudio(PathBuf),
-    Popup(String),
+    AudioClasses(bool),
+    AudioOffset(i64),
+    // A click as each glyph is typed, to hear how far off the keystroke sounds are
+    Metronome(bool),
+    KeyOverlay(bool),
+    KeyOverlayHold(#[serde(with = "millis")] Duration),
+    Hyperlinks(bool),
+    // Text typed next carries this target
+    Link {
+        text: String,
+        url: String,
+    },
+    Duck {
+        percent: u8,
+        #[serde(with = "millis")]
+        attack: Duration,
+        #[serde(with = "millis")]
+        release: Duration,
+    },
+    Popup {
+        message: String,
+        // Closes itself after the duration, without holding up what comes next
+        #[serde(with = "millis::option")]
+        close_after: Option<Duration>,
+        position: PopupPosition,
+        title: Option<String>,
+    },
+    // Move the open popup
+    PopupAt(PopupPosition),
+    PopupScroll(i32),
+    PopupAutoscroll(u32),
+    Menu {
+        items: Vec<String>,
+        choice: usize,
+        #[serde(with = "millis")]
+        step: Duration,
+        then_type: bool,
+    },
     ClosePopup,
     Clear,
+    // The start of a scene
+    Scene(String),
+    // Applies to the next `Clear`
+    Transition(TransitionKind, #[serde(with = "millis")] Duration),
+    ResetCounters,
+    // Play the script again once it ends
+    Loop {
+        count: LoopCount,
+        #[serde(with = "millis")]
+        pause: Duration,
+    },
+    Exit,
+    // Quit once nothing has happened for this long at the end of the script
+    ExitOnEnd(#[serde(with = "millis")] Duration),
 
     WriteBuffer(PathBuf),
-    SetVariable(String, Variable)
+    SetVariable(String, Variable),
+}
+
+impl Instruction {
+    /// A popup at the cursor that stays until it's closed
+    pub fn popup(message: String) -> Self {
+        Self::Popup {
+            message,
+            close_after: None,
+            position: PopupPosition::Cursor,
+            title: None,
+        }
+    }
 }
@@ -0,0 +1,176 @@
+use super::error::{Error, Result};
+use super::instructions::Instruction;
+
+/// The compiled instructions as JSON, to load with [`instructions_from_json`].
+/// Durations are in milliseconds.
+pub fn instructions_to_json(instructions: &[Instruction]) -> String {
+    serde_json::to_string_pretty(instructions).expect("instructions only hold values JSON has")
+}
+
+/// Instructions written by [`instructions_to_json`]
+pub fn instructions_from_json(json: &str) -> Result<Vec<Instruction>> {
+    serde_json::from_str(json).map_err(|e| Error::Json(e.to_string()))
+}
+
+// Durations as milliseconds, with a fraction for anything shorter
+pub(super) mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_nanos() as f64 / 1_000_000.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let millis = f64::deserialize(deserializer)?;
+        if !millis.is_finite() || millis < 0.0 {
+            return Err(serde::de::Error::custom(format!("{millis} is not a duration in milliseconds")));
+        }
+        Ok(Duration::from_nanos((millis * 1_000_000.0).round() as u64))
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+            match duration {
+                Some(duration) => super::serialize(duration, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+            #[derive(Deserialize)]
+            struct Millis(#[serde(with = "super")] Duration);
+
+            let millis = Option::<Millis>::deserialize(deserializer)?;
+            Ok(millis.map(|Millis(duration)| duration))
+        }
+    }
+}
+
+// anathema's positions and sizes, as objects with their field names
+pub(super) mod pos {
+    use anathema::geometry::Pos;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Xy {
+        x: i32,
+        y: i32,
+    }
+
+    pub fn serialize<S: Serializer>(pos: &Pos, serializer: S) -> Result<S::Ok, S::Error> {
+        Xy { x: pos.x, y: pos.y }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pos, D::Error> {
+        let Xy { x, y } = Xy::deserialize(deserializer)?;
+        Ok(Pos::new(x, y))
+    }
+}
+
+pub(super) mod size {
+    use anathema::geometry::Size;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct WidthHeight {
+        width: u16,
+        height: u16,
+    }
+
+    pub fn serialize<S: Serializer>(size: &Size, serializer: S) -> Result<S::Ok, S::Error> {
+        WidthHeight {
+            width: size.width,
+            height: size.height,
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Size, D::Error> {
+        let WidthHeight { width, height } = WidthHeight::deserialize(deserializer)?;
+        Ok(Size::new(width, height))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use anathema::geometry::{Pos, Size};
+
+    use super::*;
+    use crate::parser::{FindMiss, PopupPosition, TextStyle, TransitionKind, Variable};
+    use crate::ui::markers::generate;
+    use crate::ui::splash::Splash;
+
+    fn debug(instructions: &[Instruction]) -> Vec<String> {
+        instructions.iter().map(|inst| format!("{inst:?}")).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (_, markers) = generate("// @a\nfn a() {}\n// @b\n");
+        let instructions = vec![
+            Instruction::Jump(Pos::new(-1, 2)),
+            Instruction::Select(Size::new(3, 1)),
+            Instruction::Speed(Duration::from_micros(33_333)),
+            Instruction::CommandSpeed(None),
+            Instruction::WaitRange(Duration::from_millis(100), Duration::from_secs(2)),
+            Instruction::AddMarkers {
+                row: 4,
+                markers: markers.unwrap(),
+            },
+            Instruction::FindMiss(FindMiss::Skip(2)),
+            Instruction::Popup {
+                message: "hi".into(),
+                close_after: Some(Duration::from_millis(1500)),
+                position: PopupPosition::At { row: 1, col: 2 },
+                title: None,
+            },
+            Instruction::Splash(Splash {
+                lines: vec!["intro".into()],
+                styles: vec![TextStyle::Bold],
+                hold: Duration::from_secs(3),
+            }),
+            Instruction::Every {
+                interval: Duration::from_secs(1),
+                body: vec![Instruction::Insert("a".into())],
+            },
+            Instruction::Transition(TransitionKind::Dissolve, Duration::from_millis(400)),
+            Instruction::LoadAudio("sounds/keys".into()),
+            Instruction::SetVariable("x".into(), Variable::Int(1)),
+        ];
+
+        let json = instructions_to_json(&instructions);
+        let loaded = instructions_from_json(&json).unwrap();
+        assert_eq!(debug(&loaded), debug(&instructions));
+    }
+
+    #[test]
+    fn compiled_script_round_trip() {
+        let src = "speed 30\ntype \"// @start\\nfn main() {}\"\ngoto @start\nwait 250ms\nreplace \"main\" \"run\"";
+        let instructions = crate::ui::compile_all(crate::parser::parse(src).unwrap(), None).unwrap();
+        let loaded = instructions_from_json(&instructions_to_json(&instructions)).unwrap();
+        assert_eq!(debug(&loaded), debug(&instructions));
+    }
+
+    #[test]
+    fn durations_in_millis() {
+        let json = instructions_to_json(&[Instruction::Wait(Duration::from_millis(250)), Instruction::JumpStart]);
+        assert_eq!(json.split_whitespace().collect::<String>(), r#"[{"Wait":250.0},"JumpStart"]"#);
+
+        let loaded = instructions_from_json(r#"[{"Wait": 1500}, {"Speed": 0.5}]"#).unwrap();
+        assert!(matches!(loaded[..], [Instruction::Wait(wait), Instruction::Speed(speed)]
+            if wait == Duration::from_millis(1500) && speed == Duration::from_micros(500)));
+    }
+
+    #[test]
+    fn invalid_json() {
+        assert!(matches!(instructions_from_json("[{\"Wait\": -1}]"), Err(Error::Json(_))));
+        assert!(matches!(instructions_from_json("[\"Jump\"]"), Err(Error::Json(_))));
+        assert!(matches!(instructions_from_json("{}"), Err(Error::Json(_))));
+    }
+}
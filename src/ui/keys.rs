@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Number of keys shown at once
+const CAPACITY: usize = 8;
+
+/// The backspace key, which doesn't type anything
+pub const BACKSPACE: &str = "\u{8}";
+
+/// How a typed grapheme is shown in the key overlay
+pub fn token(grapheme: &str) -> String {
+    match grapheme {
+        BACKSPACE => "BS".into(),
+        " " => "SPC".into(),
+        "\n" => "⏎".into(),
+        "\t" => "TAB".into(),
+        s => s.into(),
+    }
+}
+
+/// The most recently "pressed" keys
+pub struct KeyOverlay {
+    enabled: bool,
+    hold: Duration,
+    // Keys and how long they have been shown, oldest first
+    keys: VecDeque<(String, Duration)>,
+}
+
+impl KeyOverlay {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            hold: Duration::from_millis(1500),
+            keys: VecDeque::new(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.keys.clear();
+        }
+    }
+
+    pub fn set_hold(&mut self, hold: Duration) {
+        self.hold = hold;
+    }
+
+    /// Record a typed grapheme, `command` if it was typed on the command line
+    pub fn push(&mut self, grapheme: &str, command: bool) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut key = token(grapheme);
+        if command {
+            key.insert(0, ':');
+        }
+
+        if self.keys.len() == CAPACITY {
+            _ = self.keys.pop_front();
+        }
+        self.keys.push_back((key, Duration::ZERO));
+    }
+
+    /// Expire keys that have been shown for longer than the hold time.
+    /// Returns true if any key expired.
+    pub fn tick(&mut self, dt: Duration) -> bool {
+        let len = self.keys.len();
+        self.keys.iter_mut().for_each(|(_, shown)| *shown += dt);
+        self.keys.retain(|(_, shown)| *shown < self.hold);
+        len != self.keys.len()
+    }
+
+    pub fn text(&self) -> String {
+        let keys = self.keys.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>();
+        keys.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn overlay() -> KeyOverlay {
+        let mut overlay = KeyOverlay::new();
+        overlay.set_enabled(true);
+        overlay.set_hold(Duration::from_millis(100));
+        overlay
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(token(" "), "SPC");
+        assert_eq!(token("\n"), "⏎");
+        assert_eq!(token("\t"), "TAB");
+        assert_eq!(token(BACKSPACE), "BS");
+        assert_eq!(token("a"), "a");
+        assert_eq!(token("A"), "A");
+        assert_eq!(token("é"), "é");
+    }
+
+    #[test]
+    fn command_keys() {
+        let mut overlay = overlay();
+        overlay.push("w", true);
+        overlay.push(" ", false);
+        assert_eq!(overlay.text(), ":w SPC");
+    }
+
+    #[test]
+    fn bounded() {
+        let mut overlay = overlay();
+        "abcdefghij".chars().for_each(|c| overlay.push(&c.to_string(), false));
+        assert_eq!(overlay.text(), "c d e f g h i j");
+    }
+
+    #[test]
+    fn expire() {
+        let mut overlay = overlay();
+        overlay.push("a", false);
+        assert!(!overlay.tick(Duration::from_millis(60)));
+        overlay.push("b", false);
+        assert!(overlay.tick(Duration::from_millis(40)));
+        assert_eq!(overlay.text(), "b");
+        assert!(!overlay.tick(Duration::from_millis(59)));
+        assert!(overlay.tick(Duration::from_millis(1)));
+        assert_eq!(overlay.text(), "");
+    }
+
+    #[test]
+    fn disabled() {
+        let mut overlay = overlay();
+        overlay.push("a", false);
+        overlay.set_enabled(false);
+        assert_eq!(overlay.text(), "");
+        overlay.push("b", false);
+        assert_eq!(overlay.text(), "");
+    }
+}
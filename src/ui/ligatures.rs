@@ -0,0 +1,136 @@
+use anathema::state::Color;
+use anathema::widgets::Style;
+
+const DEFAULT_SEQUENCES: &[&str] = &["->", "=>", "!=", "==", "<=", ">=", "<-", "::"];
+
+// -----------------------------------------------------------------------------
+//   - Ligatures -
+// -----------------------------------------------------------------------------
+/// Prevent the terminal from rendering character sequences as ligatures.
+///
+/// Terminals only shape runs of cells with the same style, so every other
+/// character of a sequence gets a foreground colour that is off by one.
+pub struct Ligatures {
+    break_sequences: bool,
+    sequences: Vec<Vec<char>>,
+}
+
+impl Ligatures {
+    pub fn new() -> Self {
+        let mut ligatures = Self {
+            break_sequences: false,
+            sequences: vec![],
+        };
+        DEFAULT_SEQUENCES.iter().for_each(|seq| ligatures.add(seq));
+        ligatures
+    }
+
+    pub fn set_break(&mut self, break_sequences: bool) {
+        self.break_sequences = break_sequences;
+    }
+
+    pub fn add(&mut self, seq: &str) {
+        let seq = seq.chars().collect::<Vec<_>>();
+        if self.sequences.contains(&seq) {
+            return;
+        }
+        self.sequences.push(seq);
+        // Longest match first
+        self.sequences.sort_by_key(|seq| std::cmp::Reverse(seq.len()));
+    }
+
+    /// Break up any sequence in a line of cells
+    pub fn apply(&self, cells: &mut [(char, Style)]) {
+        if !self.break_sequences {
+            return;
+        }
+
+        let mut i = 0;
+        while i < cells.len() {
+            let seq = self.sequences.iter().find(|seq| {
+                seq.len() <= cells.len() - i && seq.iter().zip(&cells[i..]).all(|(a, (b, _))| a == b)
+            });
+
+            match seq {
+                Some(seq) => {
+                    cells[i..i + seq.len()]
+                        .iter_mut()
+                        .skip(1)
+                        .step_by(2)
+                        .for_each(|(_, style)| nudge(style));
+                    i += seq.len();
+                }
+                None => i += 1,
+            }
+        }
+    }
+}
+
+fn nudge(style: &mut Style) {
+    // Only true colours can be changed without it being visible
+    if let Some(Color::Rgb(r, g, b)) = style.fg {
+        style.fg = Some(Color::Rgb(r ^ 1, g, b));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FG: Color = Color::Rgb(0x10, 0x20, 0x30);
+    const NUDGED: Color = Color::Rgb(0x11, 0x20, 0x30);
+
+    fn cells(s: &str) -> Vec<(char, Style)> {
+        let mut style = Style::new();
+        style.fg = Some(FG);
+        s.chars().map(|c| (c, style)).collect()
+    }
+
+    // The characters that were nudged, in order
+    fn broken(ligatures: &Ligatures, s: &str) -> String {
+        let mut cells = cells(s);
+        ligatures.apply(&mut cells);
+        cells
+            .iter()
+            .map(|(c, style)| if style.fg == Some(NUDGED) { *c } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn allow_by_default() {
+        let ligatures = Ligatures::new();
+        assert_eq!(broken(&ligatures, "a => b"), "......");
+    }
+
+    #[test]
+    fn break_default_sequences() {
+        let mut ligatures = Ligatures::new();
+        ligatures.set_break(true);
+        assert_eq!(broken(&ligatures, "a => b"), "...>..");
+        assert_eq!(broken(&ligatures, "x->y != z"), "..>...=..");
+        assert_eq!(broken(&ligatures, "a == b <= c >= d"), "...=....=....=..");
+        assert_eq!(broken(&ligatures, "std::io <- x"), "....:....-..");
+    }
+
+    #[test]
+    fn characters_are_unchanged() {
+        let mut ligatures = Ligatures::new();
+        ligatures.set_break(true);
+        let mut cells = cells("a => b");
+        ligatures.apply(&mut cells);
+        let text = cells.iter().map(|(c, _)| c).collect::<String>();
+        assert_eq!(text, "a => b");
+    }
+
+    #[test]
+    fn longest_sequence_first() {
+        let mut ligatures = Ligatures::new();
+        ligatures.set_break(true);
+        assert_eq!(broken(&ligatures, "<=>"), ".=.");
+
+        ligatures.add("<=>");
+        assert_eq!(broken(&ligatures, "<=>"), ".=.");
+        assert_eq!(broken(&ligatures, "a <=> b <= c"), "...=.....=..");
+        assert_eq!(broken(&ligatures, "===="), ".=.=");
+    }
+}
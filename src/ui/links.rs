@@ -0,0 +1,235 @@
+use std::ops::Range;
+
+use anathema::geometry::Pos;
+use anathema::widgets::Style;
+use unicode_width::UnicodeWidthChar;
+
+/// Find URL shaped tokens in a line: a scheme, `://` and everything up to the
+/// next whitespace. The ranges are in chars, not bytes.
+pub fn detect(line: &str) -> Vec<Range<usize>> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut ranges = vec![];
+    let mut from = 0;
+    let mut i = 0;
+
+    while i + 3 <= chars.len() {
+        if chars[i..i + 3] != [':', '/', '/'] {
+            i += 1;
+            continue;
+        }
+
+        let mut start = i;
+        while start > from && is_scheme(chars[start - 1]) {
+            start -= 1;
+        }
+        // A scheme starts with a letter
+        while start < i && !chars[start].is_ascii_alphabetic() {
+            start += 1;
+        }
+
+        let mut end = i + 3;
+        while end < chars.len() && !chars[end].is_whitespace() {
+            end += 1;
+        }
+
+        if start == i || end == i + 3 {
+            i += 3;
+            continue;
+        }
+
+        ranges.push(start..end);
+        from = end;
+        i = end;
+    }
+
+    ranges
+}
+
+fn is_scheme(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')
+}
+
+// -----------------------------------------------------------------------------
+//   - Links -
+// -----------------------------------------------------------------------------
+/// Underline links in the visible lines.
+///
+/// The canvas only holds a char and a style per cell, so there is no way to
+/// wrap a link in OSC 8 sequences; links are underlined instead.
+pub struct Links {
+    enabled: bool,
+    // Text typed with `link`, where it was typed and the target it carries
+    explicit: Vec<(Pos, String, String)>,
+    // The links of each row, and the line they were found in
+    cache: Vec<Option<(String, Vec<Range<usize>>)>>,
+}
+
+impl Links {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            explicit: vec![],
+            cache: vec![],
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.cache.clear();
+    }
+
+    /// Link the text typed at `pos`. Only that text is underlined, not the
+    /// same words elsewhere, and once it's edited or moved it's no longer a link.
+    pub fn add(&mut self, pos: Pos, text: String, url: String) {
+        self.explicit.retain(|(p, _, _)| *p != pos);
+        self.explicit.push((pos, text, url));
+        self.cache.clear();
+    }
+
+    /// The links in a row, only detected again if the line changed
+    fn ranges(&mut self, row: usize, line: String) -> &[Range<usize>] {
+        if self.cache.len() <= row {
+            self.cache.resize(row + 1, None);
+        }
+
+        let entry = &mut self.cache[row];
+        if entry.as_ref().is_none_or(|(cached, _)| *cached != line) {
+            let mut ranges = detect(&line);
+            let explicit = self.explicit.iter().filter(|(pos, ..)| pos.y as usize == row);
+            ranges.extend(explicit.filter_map(|(pos, text, _)| find_at(&line, pos.x as usize, text)));
+            *entry = Some((line, ranges));
+        }
+
+        entry.as_ref().map(|(_, ranges)| ranges.as_slice()).unwrap_or_default()
+    }
+
+    /// Underline the links in a line of cells
+    pub fn apply(&mut self, row: usize, cells: &mut [(char, Style)]) {
+        if !self.enabled {
+            return;
+        }
+
+        let line = cells.iter().map(|(c, _)| c).collect();
+        for range in self.ranges(row, line) {
+            cells[range.clone()].iter_mut().for_each(|(_, style)| style.set_underlined(true));
+        }
+    }
+}
+
+// The char range of `text` if it's in `line` at the display column `x`
+fn find_at(line: &str, x: usize, text: &str) -> Option<Range<usize>> {
+    if text.is_empty() {
+        return None;
+    }
+
+    let mut col = 0;
+    let (start, byte) = line.char_indices().enumerate().find_map(|(i, (byte, c))| {
+        let found = (col == x).then_some((i, byte));
+        col += c.width().unwrap_or(0);
+        found
+    })?;
+
+    line[byte..].starts_with(text).then(|| start..start + text.chars().count())
+}
+
+#[cfg(test)]
+mod test {
+    use anathema::widgets::Attributes;
+
+    use super::*;
+
+    fn underlined(style: &Style) -> bool {
+        style.attributes.contains(Attributes::UNDERLINED)
+    }
+
+    fn spans(links: &mut Links, row: usize, line: &str) -> Vec<(usize, usize)> {
+        links.ranges(row, line.into()).iter().map(|range| (range.start, range.end)).collect()
+    }
+
+    fn links(line: &str) -> Vec<&str> {
+        let chars = line.chars().collect::<Vec<_>>();
+        detect(line)
+            .into_iter()
+            .map(|range| {
+                let start = chars[..range.start].iter().map(|c| c.len_utf8()).sum::<usize>();
+                let end = chars[..range.end].iter().map(|c| c.len_utf8()).sum::<usize>();
+                &line[start..end]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detect_urls() {
+        assert_eq!(links("see https://example.com/a?b=c for more"), vec!["https://example.com/a?b=c"]);
+        assert_eq!(links("http://a.b ftp://c"), vec!["http://a.b", "ftp://c"]);
+        assert_eq!(links("(git+ssh://host/repo)"), vec!["git+ssh://host/repo)"]);
+        assert_eq!(links("é https://ö.se"), vec!["https://ö.se"]);
+    }
+
+    #[test]
+    fn not_urls() {
+        assert!(links("no links here").is_empty());
+        assert!(links("://missing-scheme").is_empty());
+        assert!(links("https:// nothing after").is_empty());
+        assert!(links("1://digits").is_empty());
+        assert!(links("a::b").is_empty());
+    }
+
+    #[test]
+    fn cache_until_line_changes() {
+        let mut links = Links::new();
+        links.set_enabled(true);
+        assert_eq!(spans(&mut links, 2, "x http://a"), [(2, 10)]);
+
+        // Pretend the cached result is different to see if it's used
+        let (_, ranges) = links.cache[2].as_mut().unwrap();
+        ranges.clear();
+        ranges.push(0..1);
+        assert_eq!(spans(&mut links, 2, "x http://a"), [(0, 1)]);
+
+        // Another line in the same row
+        assert_eq!(spans(&mut links, 2, "http://a"), [(0, 8)]);
+    }
+
+    #[test]
+    fn explicit_links() {
+        let mut links = Links::new();
+        links.set_enabled(true);
+        assert!(spans(&mut links, 0, "read the docs").is_empty());
+
+        links.add(Pos::new(5, 0), "the docs".into(), "https://docs.rs".into());
+        assert_eq!(spans(&mut links, 0, "read the docs"), [(5, 13)]);
+    }
+
+    #[test]
+    fn explicit_links_stay_where_they_were_typed() {
+        let mut links = Links::new();
+        links.set_enabled(true);
+        links.add(Pos::new(5, 1), "the docs".into(), "https://docs.rs".into());
+
+        // The same words somewhere else
+        assert_eq!(spans(&mut links, 1, "the docs and the docs"), []);
+        assert_eq!(spans(&mut links, 0, "read the docs"), []);
+        assert_eq!(spans(&mut links, 1, "read the docs, the docs"), [(5, 13)]);
+
+        // Columns are on screen, wide chars take two
+        links.add(Pos::new(4, 2), "the docs".into(), "https://docs.rs".into());
+        assert_eq!(spans(&mut links, 2, "日本the docs"), [(2, 10)]);
+
+        // Edited
+        assert_eq!(spans(&mut links, 1, "read the doc"), []);
+    }
+
+    #[test]
+    fn underline() {
+        let mut links = Links::new();
+        let mut cells = "a s://b c".chars().map(|c| (c, Style::new())).collect::<Vec<_>>();
+        links.apply(0, &mut cells);
+        assert!(cells.iter().all(|(_, style)| !underlined(style)));
+
+        links.set_enabled(true);
+        links.apply(0, &mut cells);
+        let flags = cells.iter().map(|(_, style)| underlined(style)).collect::<Vec<_>>();
+        assert_eq!(flags, [false, false, true, true, true, true, true, false, false]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::error::{Error, Result};
+
+/// Where the compile step gets the content of `load` and `include` from
+pub trait Loader {
+    fn load(&self, path: &Path) -> Result<String>;
+}
+
+/// Read files from disk
+pub struct FsLoader;
+
+impl Loader for FsLoader {
+    fn load(&self, path: &Path) -> Result<String> {
+        std::fs::read_to_string(path).map_err(|_| Error::Import(path.into()))
+    }
+}
+
+/// Files kept in memory, mostly useful for tests
+#[derive(Debug, Default)]
+pub struct MemoryLoader {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: impl Into<PathBuf>, content: impl Into<String>) {
+        self.files.insert(path.into(), content.into());
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self, path: &Path) -> Result<String> {
+        self.files.get(path).cloned().ok_or_else(|| Error::Import(path.into()))
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Files -
+// -----------------------------------------------------------------------------
+/// Resolves every path used by the compile step and loads it through the loader
+pub(super) struct Files<'a> {
+    loader: &'a dyn Loader,
+    base_dir: PathBuf,
+    // Includes currently being compiled, outermost first
+    including: Vec<PathBuf>,
+}
+
+impl<'a> Files<'a> {
+    pub(super) fn new(loader: &'a dyn Loader, base_dir: &Path) -> Self {
+        Self {
+            loader,
+            base_dir: base_dir.to_path_buf(),
+            including: vec![],
+        }
+    }
+
+    /// Relative to the file being included, or to the base directory outside of includes
+    pub(super) fn resolve(&self, path: &Path) -> PathBuf {
+        let dir = self.including.last().and_then(|include| include.parent()).unwrap_or(&self.base_dir);
+        dir.join(path)
+    }
+
+    pub(super) fn load(&self, path: &Path) -> Result<String> {
+        self.loader.load(&self.resolve(path))
+    }
+
+    /// Load an include, failing if it's already being included further up
+    pub(super) fn enter(&mut self, path: &Path) -> Result<String> {
+        let path = self.resolve(path);
+        if self.including.contains(&path) {
+            let mut cycle = self.including.clone();
+            cycle.push(path);
+            return Err(Error::IncludeCycle(cycle));
+        }
+
+        let content = self.loader.load(&path)?;
+        self.including.push(path);
+        Ok(content)
+    }
+
+    pub(super) fn leave(&mut self) {
+        _ = self.including.pop();
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Display;
+
+use anathema::geometry::Size;
+use serde::{Deserialize, Serialize};
+
+/// Space around the editor where nothing is drawn.
+///
+/// The template pads the editor, line numbers, command line and status bar with
+/// the margins, so the canvas the editor draws on is the area inside of them.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Margins {
+    pub top: u16,
+    pub right: u16,
+    pub bottom: u16,
+    pub left: u16,
+}
+
+impl Margins {
+    /// The area inside the margins, or `None` if the margins don't leave any room
+    pub fn inner(&self, size: Size) -> Option<Size> {
+        let width = size.width.checked_sub(self.left.saturating_add(self.right))?;
+        let height = size.height.checked_sub(self.top.saturating_add(self.bottom))?;
+        match width > 0 && height > 0 {
+            true => Some(Size::new(width, height)),
+            false => None,
+        }
+    }
+
+    /// The area the margins were taken from
+    pub fn outer(&self, size: Size) -> Size {
+        Size::new(size.width + self.left + self.right, size.height + self.top + self.bottom)
+    }
+}
+
+impl Display for Margins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {} {}", self.top, self.right, self.bottom, self.left)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn margins(top: u16, right: u16, bottom: u16, left: u16) -> Margins {
+        Margins {
+            top,
+            right,
+            bottom,
+            left,
+        }
+    }
+
+    #[test]
+    fn inner_and_outer() {
+        let margins = margins(1, 2, 3, 4);
+        let inner = margins.inner(Size::new(80, 24)).unwrap();
+        assert_eq!(inner, Size::new(74, 20));
+        assert_eq!(margins.outer(inner), Size::new(80, 24));
+    }
+
+    #[test]
+    fn replace_margins() {
+        // The canvas is the inner area of the current margins
+        let canvas = margins(2, 2, 2, 2).inner(Size::new(80, 24)).unwrap();
+        let total = margins(2, 2, 2, 2).outer(canvas);
+        assert_eq!(margins(0, 0, 1, 0).inner(total), Some(Size::new(80, 23)));
+    }
+
+    #[test]
+    fn too_large() {
+        assert_eq!(margins(0, u16::MAX, 0, u16::MAX).inner(Size::new(80, 24)), None);
+        assert_eq!(margins(12, 0, 12, 0).inner(Size::new(80, 24)), None);
+        assert_eq!(margins(0, 40, 0, 41).inner(Size::new(80, 24)), None);
+        assert_eq!(margins(0, 0, 0, 0).inner(Size::new(80, 24)), Some(Size::new(80, 24)));
+    }
+}
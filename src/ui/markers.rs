@@ -1,4 +1,11 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
 use anathema::geometry::Pos;
+use serde::{Deserialize, Serialize};
+
+use super::anchor::{Anchored, Shift};
+use super::instructions::Instruction;
 
 static SYMBOLS: &[&str] = &["//", "#", ";;", ";", "--"];
 
@@ -29,19 +36,21 @@ pub fn generate(text: impl Into<String>) -> (String, Option<Markers>) {
 // -----------------------------------------------------------------------------
 //   - Marker -
 // -----------------------------------------------------------------------------
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Marker {
     pub row: usize,
     name: String,
+    // The start of the line if there is none
+    col: Option<usize>,
 }
 
 impl From<&Marker> for Pos {
     fn from(marker: &Marker) -> Self {
-        Self::new(0, marker.row as i32)
+        Self::new(marker.col.unwrap_or(0) as i32, marker.row as i32)
     }
 }
 
-// marker: <maybe comment> @<ident>
+// marker: <maybe comment> @<ident> [col=<int>]
 
 // Possible comment syntax:
 // * //
@@ -80,12 +89,23 @@ fn marker(offset: usize, line: &str) -> Option<Marker> {
         .take_while(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
         .collect::<String>();
 
+    let col = line[marker.len()..].trim().strip_prefix("col=").and_then(|col| {
+        let digits = col.find(|c: char| !c.is_ascii_digit()).unwrap_or(col.len());
+        col[..digits].parse().ok()
+    });
+
     Some(Marker {
         row: offset,
         name: marker.to_string(),
+        col,
     })
 }
 
+/// The line is a marker comment, like `// @name`
+pub fn is_marker(line: &str) -> bool {
+    marker(0, line).is_some()
+}
+
 fn escape_marker(input: &str) -> String {
     let line = input.trim_start();
 
@@ -122,7 +142,7 @@ fn escape_marker(input: &str) -> String {
 // -----------------------------------------------------------------------------
 //   - Markers -
 // -----------------------------------------------------------------------------
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Markers {
     inner: Vec<Marker>,
 }
@@ -141,21 +161,63 @@ impl Markers {
         Self { inner: new }
     }
 
-    pub fn offset_after(&mut self, row: usize, offset: usize) {
-        let index = self.inner.partition_point(|marker| marker.row < row);
-        self.inner[index..].iter_mut().for_each(|marker| marker.row += offset);
+    /// Every marker by name and row, in row order
+    pub fn rows(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.inner.iter().map(|marker| (marker.name.as_str(), marker.row))
     }
 
     pub fn get(&self, key: &str) -> Option<&Marker> {
         self.inner.iter().find(|Marker { name, .. }| key.eq(name))
     }
 
+    /// Put a marker at the start of the row, replacing the marker with the same name
+    pub fn set(&mut self, name: String, row: usize) {
+        self.remove(&name);
+        let index = self.inner.partition_point(|marker| marker.row <= row);
+        self.inner.insert(index, Marker { row, name, col: None });
+    }
+
+    /// Move the markers on `row` from column `col` on by `delta` columns, when text goes in or out at `col`
+    pub fn shift_columns(&mut self, row: usize, col: usize, delta: i32) {
+        for marker in self.inner.iter_mut().filter(|marker| marker.row == row) {
+            if let Some(marker_col) = marker.col.as_mut().filter(|marker_col| **marker_col >= col) {
+                *marker_col = (*marker_col as i32 + delta).max(col as i32) as usize;
+            }
+        }
+    }
+
+    /// Whether there was a marker with the name
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.inner.len();
+        self.inner.retain(|marker| marker.name != name);
+        self.inner.len() != len
+    }
+
     // * offset new markers by insertion point
     // * offset current markers *after* the insertion point with N lines
-    pub fn merge(&mut self, insert_after_row: usize, mut other: Self) {
+    // * the later of two markers with the same name wins
+    pub fn merge(&mut self, insert_after_row: usize, mut other: Self) -> Vec<Collision> {
         // Offset the new rows by the insertion point (row)
         other.offset_by(insert_after_row);
 
+        let mut collisions = vec![];
+        let mut new: Vec<Marker> = vec![];
+        for marker in other {
+            if let Some(index) = new.iter().position(|m| m.name == marker.name) {
+                collisions.extend(Collision::new(&new.remove(index), &marker));
+            }
+            new.push(marker);
+        }
+
+        self.inner.retain(|old| match new.iter().find(|m| m.name == old.name) {
+            Some(marker) => {
+                collisions.extend(Collision::new(old, marker));
+                false
+            }
+            None => true,
+        });
+        let other = Self::from(new);
+
         // The last marker before the insertion point
         let index = self.inner.partition_point(|marker| marker.row < insert_after_row);
 
@@ -165,10 +227,20 @@ impl Markers {
         // Add the new markers in before joining the old ones back in
         self.inner.extend(other);
         self.inner.extend(rhs);
+
+        collisions
     }
+}
 
-    pub(crate) fn clear(&mut self) {
-        self.inner.clear();
+impl Anchored for Markers {
+    fn shift(&mut self, shift: Shift) {
+        self.inner.retain_mut(|marker| match shift.row(marker.row) {
+            Some(row) => {
+                marker.row = row;
+                true
+            }
+            None => false,
+        });
     }
 }
 
@@ -187,6 +259,107 @@ impl IntoIterator for Markers {
     }
 }
 
+// -----------------------------------------------------------------------------
+//   - Collisions -
+// -----------------------------------------------------------------------------
+/// Where a marker was defined
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub row: usize,
+    /// The content the marker came from, if known
+    pub source: Option<String>,
+}
+
+impl Display for Definition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {}", self.row + 1)?;
+        match &self.source {
+            Some(source) => write!(f, " of {source}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A marker defined twice, where the second definition wins
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    pub name: String,
+    pub first: Definition,
+    pub second: Definition,
+}
+
+impl Collision {
+    // Redefining a marker on the same row changes nothing
+    fn new(first: &Marker, second: &Marker) -> Option<Self> {
+        (first.row != second.row).then(|| Self {
+            name: second.name.clone(),
+            first: Definition {
+                row: first.row,
+                source: None,
+            },
+            second: Definition {
+                row: second.row,
+                source: None,
+            },
+        })
+    }
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "marker \"{}\" on {} is redefined on {}", self.name, self.first, self.second)
+    }
+}
+
+/// Markers that are defined more than once by inserted or typed text.
+///
+/// Only the text that is known before running is checked, and the rows are
+/// within the text the marker came from.
+pub fn marker_collisions(instructions: &[Instruction]) -> Vec<Collision> {
+    let mut defined = HashMap::<String, Definition>::new();
+    let mut collisions = vec![];
+
+    for inst in instructions {
+        let (kind, content) = match inst {
+            Instruction::Insert(content) => ("insert", content),
+            Instruction::LoadTypeBuffer(content) | Instruction::LoadTypeBufferKeepSelection(content) => ("type", content),
+            Instruction::Clear => {
+                defined.clear();
+                continue;
+            }
+            _ => continue,
+        };
+
+        let (text, Some(markers)) = generate(content.as_str()) else { continue };
+        let source = describe(kind, &text);
+        for marker in markers {
+            let definition = Definition {
+                row: marker.row,
+                source: Some(source.clone()),
+            };
+            if let Some(first) = defined.insert(marker.name.clone(), definition.clone()) {
+                collisions.push(Collision {
+                    name: marker.name,
+                    first,
+                    second: definition,
+                });
+            }
+        }
+    }
+
+    collisions
+}
+
+// The instruction and the first line of the text, e.g `insert "fn main() {"`
+fn describe(kind: &str, text: &str) -> String {
+    const MAX_WIDTH: usize = 24;
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default();
+    match line.chars().count() > MAX_WIDTH {
+        true => format!("{kind} \"{}...\"", line.chars().take(MAX_WIDTH).collect::<String>()),
+        false => format!("{kind} \"{line}\""),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -209,22 +382,36 @@ c"
         }
     }
 
+    #[test]
+    fn marker_columns() {
+        let (_, markers) = generate("// @a col=12\nx\n  # @b\ny\n// @c col=x\n// @d col=3, the end\n");
+        let markers = markers.unwrap();
+        let pos = |name| Pos::from(markers.get(name).unwrap());
+        assert_eq!(pos("a"), Pos::new(12, 0));
+        assert_eq!(pos("b"), Pos::new(0, 1));
+        assert_eq!(pos("c"), Pos::new(0, 2));
+        assert_eq!(pos("d"), Pos::new(3, 2));
+    }
+
     #[test]
     fn merge_markers() {
         let mut markers = Markers::new();
         markers.inner.push(Marker {
             row: 0,
             name: "B".to_string(),
+            col: None,
         });
         markers.inner.push(Marker {
             row: 1,
             name: "C".to_string(),
+            col: None,
         });
 
         let mut other = Markers::new();
         other.inner.push(Marker {
             row: 0,
             name: "A".to_string(),
+            col: None,
         });
 
         // Insert A before B
@@ -239,6 +426,57 @@ c"
         assert_eq!(&markers.inner[2].name, "C");
     }
 
+    fn names(markers: &Markers) -> Vec<(&str, usize)> {
+        markers.inner.iter().map(|m| (m.name.as_str(), m.row)).collect()
+    }
+
+    #[test]
+    fn merge_collisions() {
+        let (_, markers) = generate("// @setup\na\n// @main\nb\n");
+        let mut markers = markers.unwrap();
+
+        let (_, other) = generate("// @setup\nc\n");
+        let collisions = markers.merge(4, other.unwrap());
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(collisions[0].to_string(), "marker \"setup\" on row 1 is redefined on row 5");
+        // The later one wins
+        assert_eq!(names(&markers), [("main", 1), ("setup", 4)]);
+
+        // Twice in the same text
+        let (_, other) = generate("// @a\nx\n// @a\ny\n");
+        let collisions = markers.merge(0, other.unwrap());
+        assert_eq!(collisions[0].first.row, 0);
+        assert_eq!(collisions[0].second.row, 1);
+        assert_eq!(markers.get("a").unwrap().row, 1);
+
+        // Same name on the same row is not a collision
+        let (_, other) = generate("// @a\nz\n");
+        assert!(markers.merge(1, other.unwrap()).is_empty());
+    }
+
+    #[test]
+    fn compile_time_collisions() {
+        let instructions = vec![
+            Instruction::Insert("// @setup\nfn a() {}\n// @main\n".into()),
+            Instruction::LoadTypeBuffer("x\n// @setup\nfn b() {}".into()),
+            Instruction::Insert("no markers".into()),
+        ];
+        let collisions = marker_collisions(&instructions);
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(
+            collisions[0].to_string(),
+            "marker \"setup\" on row 1 of insert \"fn a() {}\" is redefined on row 2 of type \"x\""
+        );
+
+        // Clearing starts over
+        let instructions = vec![
+            Instruction::Insert("// @setup\na".into()),
+            Instruction::Clear,
+            Instruction::Insert("// @setup\nb".into()),
+        ];
+        assert!(marker_collisions(&instructions).is_empty());
+    }
+
     #[test]
     fn escape_markers() {
         let input = "  // @@escape";
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+/// Number of items visible at once, longer menus scroll
+pub const HEIGHT: usize = 8;
+
+// The chosen item blinks this many times before the menu closes
+const FLASHES: u32 = 3;
+const FLASH: Duration = Duration::from_millis(80);
+
+/// What the menu looks like at a point in time
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct View {
+    pub selected: usize,
+    pub highlight: bool,
+    pub done: bool,
+}
+
+/// A menu that walks the selection down to an item and activates it.
+///
+/// The selection moves one row per step, rests on the chosen item for one more
+/// step and then flashes before the menu closes.
+#[derive(Debug)]
+pub struct Menu {
+    items: Vec<String>,
+    choice: usize,
+    step: Duration,
+    then_type: bool,
+    elapsed: Duration,
+    view: View,
+}
+
+impl Menu {
+    pub fn new(items: Vec<String>, choice: usize, step: Duration, then_type: bool) -> Self {
+        let mut menu = Self {
+            items,
+            choice,
+            step,
+            then_type,
+            elapsed: Duration::ZERO,
+            view: View {
+                selected: 0,
+                highlight: true,
+                done: false,
+            },
+        };
+        menu.view = menu.at(Duration::ZERO);
+        menu
+    }
+
+    /// How long the menu is open
+    pub fn duration(choice: usize, step: Duration) -> Duration {
+        step * (choice as u32 + 1) + FLASH * FLASHES * 2
+    }
+
+    fn at(&self, elapsed: Duration) -> View {
+        let walk = self.step * self.choice as u32;
+        let rest = walk + self.step;
+
+        if elapsed < walk {
+            let selected = (elapsed.as_secs_f64() / self.step.as_secs_f64()) as usize;
+            return View {
+                selected,
+                highlight: true,
+                done: false,
+            };
+        }
+
+        let flash = elapsed.saturating_sub(rest).as_millis() / FLASH.as_millis();
+        View {
+            selected: self.choice,
+            highlight: flash.is_multiple_of(2),
+            done: elapsed >= Self::duration(self.choice, self.step),
+        }
+    }
+
+    /// Returns true if the menu changed
+    pub fn tick(&mut self, dt: Duration) -> bool {
+        self.elapsed += dt;
+        let view = self.at(self.elapsed);
+        let changed = view != self.view;
+        self.view = view;
+        changed
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.view.done
+    }
+
+    pub fn view(&self) -> View {
+        self.view
+    }
+
+    /// The visible items, scrolled to keep the selection in view,
+    /// and the offset of the first visible item
+    pub fn window(&self) -> (usize, &[String]) {
+        let first = (self.view.selected + 1).saturating_sub(HEIGHT);
+        let last = (first + HEIGHT).min(self.items.len());
+        (first, &self.items[first..last])
+    }
+
+    /// The text to type once the menu closes
+    pub fn then_type(self) -> Option<String> {
+        match self.then_type {
+            true => self.items.into_iter().nth(self.choice),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn menu(len: usize, choice: usize) -> Menu {
+        let items = (0..len).map(|i| i.to_string()).collect();
+        Menu::new(items, choice, Duration::from_millis(100), true)
+    }
+
+    #[test]
+    fn timing() {
+        let mut menu = menu(5, 2);
+        let ms = Duration::from_millis;
+
+        let view = |selected, highlight, done| View {
+            selected,
+            highlight,
+            done,
+        };
+        let mut views = vec![menu.view()];
+        for _ in 0..80 {
+            if menu.tick(ms(10)) {
+                views.push(menu.view());
+            }
+        }
+
+        let expected = vec![
+            view(0, true, false),
+            // walk
+            view(1, true, false),
+            view(2, true, false),
+            // rest, then flash
+            view(2, false, false),
+            view(2, true, false),
+            view(2, false, false),
+            view(2, true, false),
+            view(2, false, false),
+            // close
+            view(2, true, true),
+        ];
+        assert_eq!(views, expected);
+        assert_eq!(Menu::duration(2, ms(100)), ms(780));
+    }
+
+    #[test]
+    fn choose_first() {
+        let mut menu = menu(3, 0);
+        assert_eq!(menu.view().selected, 0);
+        menu.tick(Duration::from_millis(579));
+        assert!(!menu.is_done());
+        menu.tick(Duration::from_millis(1));
+        assert!(menu.is_done());
+        assert_eq!(menu.then_type(), Some("0".into()));
+    }
+
+    #[test]
+    fn scroll_long_menus() {
+        let mut menu = menu(20, 15);
+        let (first, items) = menu.window();
+        assert_eq!((first, items.len()), (0, HEIGHT));
+
+        menu.tick(Duration::from_millis(1000));
+        let (first, items) = menu.window();
+        assert_eq!(first, 3);
+        assert_eq!(items.last().unwrap(), "10");
+
+        menu.tick(Duration::from_millis(1000));
+        let (first, items) = menu.window();
+        assert_eq!(first, 8);
+        assert_eq!(items.last().unwrap(), "15");
+    }
+
+    #[test]
+    fn short_menus() {
+        let menu = menu(2, 1);
+        let (first, items) = menu.window();
+        assert_eq!((first, items.len()), (0, 2));
+    }
+}
@@ -0,0 +1,569 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anathema::component::*;
+use anathema::default_widgets::Canvas;
+use anathema::geometry::{LocalPos, Size};
+use anathema::widgets::{Attributes, Style};
+
+/// Bumped whenever the encoding of an update changes
+pub const VERSION: u8 = 1;
+
+// Updates larger than this are rejected rather than allocated
+const MAX_LEN: usize = 64 * 1024 * 1024;
+
+type Cell = Option<(char, Style)>;
+
+// -----------------------------------------------------------------------------
+//   - Update -
+// -----------------------------------------------------------------------------
+/// The cells that changed since the previous update.
+/// If `clear` is set the receiver starts over with an empty grid of `size`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub size: Size,
+    pub clear: bool,
+    pub changes: Vec<(LocalPos, Cell)>,
+}
+
+// -----------------------------------------------------------------------------
+//   - Grid -
+// -----------------------------------------------------------------------------
+/// A copy of the cells of the editor canvas
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    size: Size,
+    cells: Vec<Cell>,
+}
+
+impl Grid {
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            cells: vec![None; size.width as usize * size.height as usize],
+        }
+    }
+
+    fn from_canvas(canvas: &mut Canvas, size: Size) -> Self {
+        let mut grid = Self::new(size);
+        for (index, cell) in grid.cells.iter_mut().enumerate() {
+            *cell = canvas.get(grid.size.pos(index));
+        }
+        grid
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn get(&self, pos: LocalPos) -> Cell {
+        self.cells[pos.y as usize * self.size.width as usize + pos.x as usize]
+    }
+
+    fn index(&self, pos: LocalPos) -> Option<usize> {
+        match pos.x < self.size.width && pos.y < self.size.height {
+            true => Some(pos.y as usize * self.size.width as usize + pos.x as usize),
+            false => None,
+        }
+    }
+
+    /// Put a char in a cell, anything outside the grid is clipped
+    pub fn put(&mut self, c: char, style: Style, pos: LocalPos) {
+        if let Some(index) = self.index(pos) {
+            self.cells[index] = Some((c, style));
+        }
+    }
+
+    pub fn get_mut(&mut self, pos: LocalPos) -> Option<(&mut char, &mut Style)> {
+        let index = self.index(pos)?;
+        self.cells[index].as_mut().map(|(c, style)| (c, style))
+    }
+
+    /// The update that turns `self` into `next`
+    pub fn diff(&self, next: &Grid) -> Update {
+        if self.size != next.size {
+            return next.full();
+        }
+
+        let changes = self
+            .cells
+            .iter()
+            .zip(&next.cells)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(index, (_, b))| (next.size.pos(index), *b))
+            .collect();
+
+        Update {
+            size: next.size,
+            clear: false,
+            changes,
+        }
+    }
+
+    /// An update that recreates this grid from nothing
+    pub fn full(&self) -> Update {
+        let changes = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.is_some())
+            .map(|(index, cell)| (self.size.pos(index), *cell))
+            .collect();
+
+        Update {
+            size: self.size,
+            clear: true,
+            changes,
+        }
+    }
+
+    pub fn apply(&mut self, update: &Update) {
+        if update.clear || update.size != self.size {
+            *self = Self::new(update.size);
+        }
+
+        for &(pos, cell) in &update.changes {
+            if pos.x < self.size.width && pos.y < self.size.height {
+                let width = self.size.width as usize;
+                self.cells[pos.y as usize * width + pos.x as usize] = cell;
+            }
+        }
+    }
+
+    /// The cells that fit inside `size`, the rest is clipped
+    pub fn visible(&self, size: Size) -> impl Iterator<Item = (LocalPos, char, Style)> + '_ {
+        let width = self.size.width.min(size.width);
+        let height = self.size.height.min(size.height);
+        (0..height)
+            .flat_map(move |y| (0..width).map(move |x| LocalPos::new(x, y)))
+            .filter_map(|pos| self.get(pos).map(|(c, style)| (pos, c, style)))
+    }
+}
+
+trait SizeExt {
+    fn pos(&self, index: usize) -> LocalPos;
+}
+
+impl SizeExt for Size {
+    fn pos(&self, index: usize) -> LocalPos {
+        let width = self.width as usize;
+        LocalPos::new((index % width) as u16, (index / width) as u16)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Encoding -
+// -----------------------------------------------------------------------------
+// Every update is written as a big endian u32 length, followed by the
+// protocol version and the update itself.
+pub fn write_update(writer: &mut impl Write, update: &Update) -> io::Result<()> {
+    let mut buf = vec![VERSION];
+    buf.extend(update.size.width.to_be_bytes());
+    buf.extend(update.size.height.to_be_bytes());
+    buf.push(update.clear as u8);
+    buf.extend((update.changes.len() as u32).to_be_bytes());
+
+    for (pos, cell) in &update.changes {
+        buf.extend(pos.x.to_be_bytes());
+        buf.extend(pos.y.to_be_bytes());
+        match cell {
+            None => buf.push(0),
+            Some((c, style)) => {
+                buf.push(1);
+                buf.extend((*c as u32).to_be_bytes());
+                encode_color(&mut buf, style.fg);
+                encode_color(&mut buf, style.bg);
+                buf.extend(style.attributes.bits().to_be_bytes());
+            }
+        }
+    }
+
+    writer.write_all(&(buf.len() as u32).to_be_bytes())?;
+    writer.write_all(&buf)?;
+    writer.flush()
+}
+
+/// Read the next update, `None` if the stream has ended
+pub fn read_update(reader: &mut impl Read) -> io::Result<Option<Update>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_LEN {
+        return Err(invalid("update too large"));
+    }
+
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    let mut decoder = Decoder { buf: &buf };
+
+    let version = decoder.u8()?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported version {version}, expected {VERSION}")));
+    }
+
+    let size = Size::new(decoder.u16()?, decoder.u16()?);
+    let clear = decoder.u8()? != 0;
+    let count = decoder.u32()? as usize;
+
+    let mut changes = Vec::with_capacity(count.min(len));
+    for _ in 0..count {
+        let pos = LocalPos::new(decoder.u16()?, decoder.u16()?);
+        let cell = match decoder.u8()? {
+            0 => None,
+            _ => {
+                let c = char::from_u32(decoder.u32()?).ok_or_else(|| invalid("invalid char"))?;
+                let mut style = Style::new();
+                style.fg = decoder.color()?;
+                style.bg = decoder.color()?;
+                style.attributes = Attributes::from_bits_truncate(decoder.u16()?);
+                Some((c, style))
+            }
+        };
+        changes.push((pos, cell));
+    }
+
+    Ok(Some(Update { size, clear, changes }))
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+const NAMED_COLORS: [Color; 17] = [
+    Color::Reset,
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Grey,
+    Color::DarkGrey,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+];
+
+const RGB: u8 = 0xfe;
+const ANSI: u8 = 0xfd;
+const NO_COLOR: u8 = 0xff;
+
+fn encode_color(buf: &mut Vec<u8>, color: Option<Color>) {
+    match color {
+        None => buf.push(NO_COLOR),
+        Some(Color::Rgb(r, g, b)) => buf.extend([RGB, r, g, b]),
+        Some(Color::AnsiVal(val)) => buf.extend([ANSI, val]),
+        Some(color) => {
+            let index = NAMED_COLORS.iter().position(|c| *c == color).unwrap_or(0);
+            buf.push(index as u8);
+        }
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let Some((bytes, rest)) = self.buf.split_first_chunk::<N>() else { return Err(invalid("truncated update")) };
+        self.buf = rest;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        self.take::<1>().map(|[b]| b)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        self.take().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.take().map(u32::from_be_bytes)
+    }
+
+    fn color(&mut self) -> io::Result<Option<Color>> {
+        let color = match self.u8()? {
+            NO_COLOR => None,
+            RGB => {
+                let [r, g, b] = self.take()?;
+                Some(Color::Rgb(r, g, b))
+            }
+            ANSI => Some(Color::AnsiVal(self.u8()?)),
+            index => Some(*NAMED_COLORS.get(index as usize).ok_or_else(|| invalid("invalid color"))?),
+        };
+        Ok(color)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Server -
+// -----------------------------------------------------------------------------
+/// Sends the changes of the editor canvas to the attached viewers
+pub struct Mirror {
+    tx: Sender<Update>,
+    last: Grid,
+}
+
+impl Mirror {
+    /// Listen for viewers on `port`
+    pub fn serve(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let viewers = Arc::new(Mutex::new(vec![]));
+
+        let accepted = viewers.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                _ = stream.set_nodelay(true);
+                accepted.lock().unwrap().push(Viewer { stream, new: true });
+            }
+        });
+
+        let (tx, rx) = channel();
+        std::thread::spawn(move || broadcast(rx, viewers));
+
+        Ok(Self {
+            tx,
+            last: Grid::new(Size::ZERO),
+        })
+    }
+
+    /// Send whatever changed on the canvas since the last capture
+    pub fn capture(&mut self, canvas: &mut Canvas, size: Size) {
+        let grid = Grid::from_canvas(canvas, size);
+        let update = self.last.diff(&grid);
+        if update.clear || !update.changes.is_empty() {
+            _ = self.tx.send(update);
+        }
+        self.last = grid;
+    }
+}
+
+struct Viewer<W> {
+    stream: W,
+    // New viewers need the whole grid before any changes
+    new: bool,
+}
+
+fn broadcast<W: Write>(rx: Receiver<Update>, viewers: Arc<Mutex<Vec<Viewer<W>>>>) {
+    let mut grid = Grid::new(Size::ZERO);
+
+    loop {
+        let update = match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(update) => {
+                grid.apply(&update);
+                Some(update)
+            }
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let mut viewers = viewers.lock().unwrap();
+        viewers.retain_mut(|viewer| {
+            let res = match (viewer.new, &update) {
+                (true, _) => write_update(&mut viewer.stream, &grid.full()),
+                (false, Some(update)) => write_update(&mut viewer.stream, update),
+                (false, None) => Ok(()),
+            };
+            viewer.new = false;
+            res.is_ok()
+        });
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Attach -
+// -----------------------------------------------------------------------------
+/// Read-only view of a mirrored session
+pub struct Attached {
+    rx: Receiver<Update>,
+    grid: Grid,
+}
+
+impl Attached {
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(addr)?;
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            while let Ok(Some(update)) = read_update(&mut stream) {
+                if tx.send(update).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            rx,
+            grid: Grid::new(Size::ZERO),
+        })
+    }
+}
+
+impl Component for Attached {
+    type Message = ();
+    type State = ();
+
+    fn on_tick(&mut self, _: &mut Self::State, mut children: Children<'_, '_>, _: Context<'_, '_, Self::State>, _: Duration) {
+        let mut changed = false;
+        while let Ok(update) = self.rx.try_recv() {
+            self.grid.apply(&update);
+            changed = true;
+        }
+
+        if !changed {
+            return;
+        }
+
+        children.elements().by_tag("canvas").first(|el, _| {
+            let size = el.size();
+            let canvas = el.to::<Canvas>();
+            canvas.clear();
+            for (pos, c, style) in self.grid.visible(size) {
+                canvas.put(c, style, pos);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn cell(c: char, fg: Option<Color>, bg: Option<Color>) -> Cell {
+        let mut style = Style::new();
+        style.fg = fg;
+        style.bg = bg;
+        style.set_bold(true);
+        Some((c, style))
+    }
+
+    fn grid(width: u16, height: u16, text: &str) -> Grid {
+        let mut grid = Grid::new(Size::new(width, height));
+        for (index, c) in text.chars().enumerate() {
+            if c != ' ' {
+                grid.cells[index] = cell(c, Some(Color::Rgb(1, 2, 3)), None);
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn round_trip() {
+        let update = Update {
+            size: Size::new(80, 24),
+            clear: true,
+            changes: vec![
+                (LocalPos::new(0, 0), cell('a', Some(Color::Rgb(1, 2, 3)), None)),
+                (LocalPos::new(79, 23), cell('🐇', Some(Color::AnsiVal(200)), Some(Color::Red))),
+                (LocalPos::new(5, 5), cell('x', Some(Color::Reset), Some(Color::White))),
+                (LocalPos::new(1, 0), None),
+            ],
+        };
+
+        let mut stream = Cursor::new(vec![]);
+        write_update(&mut stream, &update).unwrap();
+        write_update(&mut stream, &update).unwrap();
+
+        stream.set_position(0);
+        assert_eq!(read_update(&mut stream).unwrap(), Some(update.clone()));
+        assert_eq!(read_update(&mut stream).unwrap(), Some(update));
+        assert_eq!(read_update(&mut stream).unwrap(), None);
+    }
+
+    #[test]
+    fn reject_other_versions() {
+        let update = grid(2, 1, "ab").full();
+        let mut bytes = vec![];
+        write_update(&mut bytes, &update).unwrap();
+        bytes[4] = VERSION + 1;
+        assert!(read_update(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn reject_truncated_updates() {
+        let update = grid(2, 1, "ab").full();
+        let mut bytes = vec![];
+        write_update(&mut bytes, &update).unwrap();
+        let len = bytes.len() as u32 - 4 - 3;
+        bytes[..4].copy_from_slice(&len.to_be_bytes());
+        bytes.truncate(bytes.len() - 3);
+        assert!(read_update(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn diff_and_apply() {
+        let a = grid(3, 2, "ab c  ");
+        let b = grid(3, 2, "abxc d");
+
+        let update = a.diff(&b);
+        assert!(!update.clear);
+        assert_eq!(update.changes.len(), 2);
+
+        let mut mirrored = a.clone();
+        mirrored.apply(&update);
+        assert_eq!(mirrored, b);
+
+        // A new size starts over
+        let c = grid(2, 2, "abcd");
+        let update = b.diff(&c);
+        assert!(update.clear);
+        mirrored.apply(&update);
+        assert_eq!(mirrored, c);
+    }
+
+    #[test]
+    fn clip_to_viewer_size() {
+        let grid = grid(3, 2, "abcdef");
+
+        let visible = |size| grid.visible(size).map(|(_, c, _)| c).collect::<String>();
+        assert_eq!(visible(Size::new(80, 24)), "abcdef");
+        assert_eq!(visible(Size::new(2, 24)), "abde");
+        assert_eq!(visible(Size::new(80, 1)), "abc");
+        assert_eq!(visible(Size::ZERO), "");
+    }
+
+    #[test]
+    fn broadcast_to_viewers() {
+        let (tx, rx) = channel();
+        let viewers = Arc::new(Mutex::new(vec![]));
+        let handle = {
+            let viewers = viewers.clone();
+            std::thread::spawn(move || broadcast(rx, viewers))
+        };
+
+        let first = grid(2, 1, "a ").full();
+        let second = grid(2, 1, "a ").diff(&grid(2, 1, "ab"));
+        tx.send(first).unwrap();
+
+        // A viewer that attaches late gets the whole grid first
+        viewers.lock().unwrap().push(Viewer { stream: vec![], new: true });
+        tx.send(second).unwrap();
+        drop(tx);
+        handle.join().unwrap();
+
+        let viewer = viewers.lock().unwrap().pop().unwrap();
+        let mut stream = Cursor::new(viewer.stream);
+        let mut mirrored = Grid::new(Size::ZERO);
+        while let Some(update) = read_update(&mut stream).unwrap() {
+            mirrored.apply(&update);
+        }
+        assert_eq!(mirrored, grid(2, 1, "ab"));
+    }
+}
@@ -1,29 +1,80 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
 use anathema::prelude::*;
-pub use compile::compile;
-use error::Result;
+pub use budget::{Budget, Timings, budgets};
+pub use compile::{CompileOptions, CompiledInstructions, compile, compile_all, compile_with_loader, compile_with_options};
+pub use estimate::scenes;
+pub use json::{instructions_from_json, instructions_to_json};
+use error::{Error, Result};
+pub use loader::{FsLoader, Loader, MemoryLoader};
+pub use editor::{Seek, StepBy, TIME_SCALE};
+pub use margins::Margins;
+pub use markers::{Collision, Definition, marker_collisions};
+pub use progress::{Left, Progress};
+pub use queue::ScriptItem;
+pub use syntax_cache::{Load, Source};
+pub use validate::{Diagnostic, Severity, validate, validate_with_loader};
 
+use self::estimate::FRAME_TIME;
 use self::editor::Editor;
+use self::events::Events;
 use self::instructions::Instruction;
+use self::mirror::{Attached, Mirror};
+use self::shutdown::Shutdown;
 use self::syntax::Highlighter;
+use crate::parser::{JitterProfile, LoopCount};
 use crate::ui::editor::DocState;
 
+mod anchor;
 mod audio;
+mod budget;
+mod comments;
 mod compile;
 mod context;
 mod document;
 mod editor;
 mod error;
+mod estimate;
+mod events;
+mod exec;
+mod filters;
+mod guides;
+pub(crate) mod headless;
 pub mod instructions;
+mod json;
+mod keys;
+mod ligatures;
+mod links;
+mod loader;
+mod margins;
 mod markers;
+mod menu;
+mod mirror;
+mod optimize;
+mod overlays;
+mod popup;
+mod presets;
+mod preview;
+mod progress;
+mod queue;
 mod random;
+mod schedule;
+mod session;
+mod shutdown;
+mod splash;
 pub(crate) mod syntax;
+mod syntax_cache;
 mod textbuffer;
+mod transition;
+mod typo;
+mod validate;
+mod virtual_text;
+mod wrap;
 
 pub mod setup_paths {
     use std::io::Write;
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
 
     use super::error::{Error, Result};
 
@@ -31,7 +82,17 @@ pub mod setup_paths {
     static STATUS: &[u8] = include_bytes!("../templates/status.aml");
     static ERROR: &[u8] = include_bytes!("../templates/error.aml");
     static POPUP: &[u8] = include_bytes!("../templates/popup.aml");
-    static THEME: &[u8] = include_bytes!("../themes/togglebit.tmTheme");
+    static TEMPLATES: [(&str, &[u8]); 4] =
+        [("index.aml", INDEX), ("status.aml", STATUS), ("error.aml", ERROR), ("popup.aml", POPUP)];
+    // The templates as earlier versions of mimic wrote them, which nobody has changed if they are still the same
+    static SHIPPED: [(&str, u64); 4] = [
+        // 0.1.7
+        ("index.aml", 0x0fe0c42cc35f2d3d),
+        ("status.aml", 0xd11eda29dac7ff46),
+        ("error.aml", 0x1883703f01edb3e2),
+        ("popup.aml", 0x4cae2a8174d8134a),
+    ];
+    pub(crate) static THEME: &[u8] = include_bytes!("../themes/togglebit.tmTheme");
 
     fn mimic_root() -> PathBuf {
         dirs::config_dir().unwrap().join("mimic")
@@ -45,6 +106,11 @@ pub mod setup_paths {
         mimic_root().join("syntax")
     }
 
+    /// The compiled syntaxes and themes, rebuilt whenever the files under the other roots change
+    pub fn cache_root() -> PathBuf {
+        mimic_root().join("cache")
+    }
+
     // Ensure that templates and syntax files exists
     pub fn ensure_exists() -> Result<PathBuf> {
         let template_dir = mimic_root().join("templates");
@@ -52,6 +118,9 @@ pub mod setup_paths {
         let theme_dir = theme_root();
 
         if template_dir.is_dir() {
+            for (name, content) in TEMPLATES {
+                refresh(&template_dir.join(name), name, content, &SHIPPED)?;
+            }
             return Ok(template_dir);
         }
 
@@ -63,23 +132,71 @@ pub mod setup_paths {
         _ = std::fs::create_dir_all(&syntax_dir);
         _ = std::fs::create_dir_all(&theme_dir);
 
-        for (path, content) in [
-            ("index.aml", INDEX),
-            ("status.aml", STATUS),
-            ("error.aml", ERROR),
-            ("popup.aml", POPUP),
-        ] {
-            let path = template_dir.join(path);
-            let mut file = std::fs::File::create(&path).map_err(|_| Error::FilePath(path))?;
-            file.write_all(content).expect("did you run out of disk space?");
+        for (path, content) in TEMPLATES {
+            write(&template_dir.join(path), content)?;
         }
-
-        let path = theme_root().join("togglebit.tmTheme");
-        let mut file = std::fs::File::create(&path).map_err(|_| Error::FilePath(path))?;
-        file.write_all(THEME).expect("did you run out of disk space?");
+        write(&theme_root().join("togglebit.tmTheme"), THEME)?;
 
         Ok(mimic_root())
     }
+
+    // Replace a template that an earlier version of mimic wrote with the one it ships with now,
+    // as older templates don't show what was added since. Templates that were changed are left alone.
+    fn refresh(path: &Path, name: &str, content: &[u8], shipped: &[(&str, u64)]) -> Result<()> {
+        let Ok(old) = std::fs::read(path) else { return write(path, content) };
+        let shipped = shipped.iter().any(|&(shipped, hash)| shipped == name && hash == fnv(&old));
+        match shipped && old != content {
+            true => write(path, content),
+            false => Ok(()),
+        }
+    }
+
+    // FNV-1a, which unlike the hasher in std stays the same between versions of Rust
+    fn fnv(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+
+    fn write(path: &Path, content: &[u8]) -> Result<()> {
+        let mut file = std::fs::File::create(path).map_err(|_| Error::FilePath(path.into()))?;
+        file.write_all(content).expect("did you run out of disk space?");
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn refresh_templates_nobody_changed() {
+            let dir = std::env::temp_dir().join(format!("mimic-templates-{}", std::process::id()));
+            _ = std::fs::create_dir_all(&dir);
+
+            let shipped = [("index.aml", fnv(b"old")), ("status.aml", fnv(b"old"))];
+
+            // As an earlier version wrote it
+            let index = dir.join("index.aml");
+            write(&index, b"old").unwrap();
+            refresh(&index, "index.aml", b"new", &shipped).unwrap();
+            assert_eq!(std::fs::read(&index).unwrap(), b"new");
+
+            // Changed since
+            let status = dir.join("status.aml");
+            write(&status, b"changed").unwrap();
+            refresh(&status, "status.aml", b"new", &shipped).unwrap();
+            assert_eq!(std::fs::read(&status).unwrap(), b"changed");
+
+            // Missing templates are added
+            let popup = dir.join("popup.aml");
+            refresh(&popup, "popup.aml", b"popup", &shipped).unwrap();
+            assert_eq!(std::fs::read(&popup).unwrap(), b"popup");
+
+            // The hash has to stay what it was when the templates were shipped
+            assert_eq!(fnv(b""), 0xcbf29ce484222325);
+            assert_eq!(fnv(b"a"), 0xaf63dc4c8601ec8c);
+
+            _ = std::fs::remove_dir_all(&dir);
+        }
+    }
 }
 
 pub fn print_syntaxes() {
@@ -92,9 +209,98 @@ pub fn print_themes() {
     highlighter.print_themes();
 }
 
-pub fn run(instructions: Vec<Instruction>) -> Result<()> {
-    let highlighter = Highlighter::new();
-    let editor = Editor::new(instructions, highlighter, Duration::from_millis(70));
+/// Ten characters typed on a steady beat with a click as each one appears,
+/// to find the `audio_offset` where the sounds line up with the click
+pub fn calibration(audio: PathBuf, offset: i64) -> Vec<Instruction> {
+    vec![
+        Instruction::SetTitle(format!("audio calibration: {offset}ms")),
+        Instruction::LoadAudio(audio),
+        Instruction::AudioOffset(offset),
+        Instruction::Metronome(true),
+        Instruction::JitterProfile(JitterProfile::Off),
+        Instruction::Speed(Duration::from_millis(500)),
+        Instruction::Wait(Duration::from_secs(1)),
+        Instruction::LoadTypeBuffer("1234567890".into()),
+        Instruction::Wait(Duration::from_secs(1)),
+        Instruction::popup("run again with a different --audio-offset until the sounds line up".into()),
+    ]
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Mirror the editor to viewers connecting to this port
+    pub serve: Option<u16>,
+    /// Play the script this many times faster than written, see [`TIME_SCALE`]
+    pub time_scale: Option<f64>,
+    /// Write document stats and markers to this file as they change, one JSON object per line
+    pub events: Option<PathBuf>,
+    /// Start in step mode, moving on by this much per press of `n` or enter
+    pub step: Option<StepBy>,
+    /// Play the script this many times, winning over a `loop` in the script
+    pub looping: Option<LoopCount>,
+    /// Apply the script up to here at once, then play the rest as written
+    pub from: Option<Seek>,
+    /// Show how far through the script the playback is, and the time left
+    pub progress: Option<Progress>,
+}
+
+/// How a run came to an end
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ending {
+    /// The script quit by itself, with `exit` or `exit_on_end`
+    Completed,
+    /// Stopped with `q`, escape or ctrl+c
+    Interrupted,
+}
+
+/// Run the instructions, returning how the run ended and the time spent in each budgeted section.
+///
+/// The instructions can be what [`compile`] returns, to start running before the rest of the script
+/// is compiled. An error from compiling ends the script and shows on screen once it's reached.
+pub fn run(instructions: impl IntoIterator<Item: ScriptItem, IntoIter: 'static>) -> Result<(Ending, Timings)> {
+    run_with(instructions, Options::default())
+}
+
+pub fn run_with(
+    instructions: impl IntoIterator<Item: ScriptItem, IntoIter: 'static>,
+    options: Options,
+) -> Result<(Ending, Timings)> {
+    let shutdown = Shutdown::new();
+    let timings = Timings::new();
+    let highlighter = Highlighter::background(timings.clone());
+    let mut editor = Editor::new(instructions, highlighter, FRAME_TIME, shutdown.clone(), timings.clone());
+    let completed = editor.completed();
+    let mut state = DocState::new();
+
+    if let Some(scale) = options.time_scale {
+        editor.set_time_scale(scale, &mut state);
+    }
+
+    if let Some(by) = options.step {
+        editor.set_step_by(by);
+        editor.toggle_stepping(&mut state);
+    }
+
+    if let Some(count) = options.looping {
+        editor.set_loop(count);
+    }
+
+    if let Some(to) = options.from {
+        editor.seek(to);
+    }
+
+    if let Some(progress) = options.progress {
+        editor.set_progress(progress, &mut state);
+    }
+
+    if let Some(path) = options.events {
+        let file = std::fs::File::create(&path).map_err(|_| Error::FilePath(path))?;
+        editor.set_events(Events::to_writer(std::io::LineWriter::new(file)));
+    }
+
+    if let Some(port) = options.serve {
+        editor.set_mirror(Mirror::serve(port).map_err(Error::Mirror)?);
+    }
 
     let doc = Document::new("@index");
 
@@ -110,16 +316,62 @@ pub fn run(instructions: Vec<Instruction>) -> Result<()> {
 
     let template_root = dirs::config_dir().unwrap().join("mimic").join("templates");
 
-    builder.component("index", template_root.join("index.aml"), editor, DocState::new())?;
+    builder.component("index", template_root.join("index.aml"), editor, state)?;
     builder.template("status", template_root.join("status.aml"))?;
     builder.template("error", template_root.join("error.aml"))?;
     builder.template("popup", template_root.join("popup.aml"))?;
     let res = builder.finish(&mut backend, |runtime, backend| runtime.run(backend));
 
+    // Complete or clean up any pending writes before restoring the terminal
+    let leftovers = shutdown.finish(shutdown::DEADLINE);
+    drop(backend);
+
+    if !leftovers.is_empty() {
+        eprintln!("shutdown timed out, the following files were left behind:");
+        for path in leftovers {
+            eprintln!("    {}", path.display());
+        }
+    }
+
     match res {
         Ok(()) | Err(anathema::runtime::Error::Stop) => {}
         Err(e) => return Err(e.into()),
     }
 
-    Ok(())
+    let ending = match completed.load(std::sync::atomic::Ordering::Relaxed) {
+        true => Ending::Completed,
+        false => Ending::Interrupted,
+    };
+    Ok((ending, timings))
+}
+
+/// Serve a page on `addr` with the final frame of the script, the warnings and the estimated duration.
+/// The script is run again whenever it changes.
+pub fn preview(addr: &str, path: PathBuf) -> Result<()> {
+    preview::serve(addr, path).map_err(Error::Preview)
+}
+
+/// Watch a session started with `--serve`, read-only
+pub fn attach(addr: &str) -> Result<()> {
+    let viewer = Attached::connect(addr).map_err(Error::Mirror)?;
+
+    let doc = Document::new("@viewer");
+
+    let mut backend = TuiBackend::builder()
+        .enable_alt_screen()
+        .enable_raw_mode()
+        .hide_cursor()
+        .finish()
+        .unwrap();
+    backend.finalize();
+
+    let mut builder = Runtime::builder(doc, &backend);
+    builder.component("viewer", "canvas".to_template(), viewer, ())?;
+    let res = builder.finish(&mut backend, |runtime, backend| runtime.run(backend));
+    drop(backend);
+
+    match res {
+        Ok(()) | Err(anathema::runtime::Error::Stop) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
 }
@@ -0,0 +1,181 @@
+use std::mem::discriminant;
+use std::time::Duration;
+
+use anathema::geometry::Size;
+
+use super::instructions::Instruction;
+use crate::parser::FindMiss;
+
+/// Merge waits that follow each other, and drop instructions that do nothing:
+/// zero waits, selections of nothing, and a `speed`, `line_pause` or `jitter`
+/// changed again before anything else happens.
+///
+/// A find can skip the instructions after it when it misses. Those are left
+/// exactly as they are so the find skips the same instructions.
+pub fn optimize(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut output = Vec::with_capacity(instructions.len());
+    let mut skip_on_miss = 0;
+    // Instructions still to copy as they are
+    let mut keep = 0;
+    // Nothing before this can be merged with or removed
+    let mut frozen = 0;
+
+    for inst in instructions {
+        if keep > 0 {
+            keep -= 1;
+            output.push(inst);
+            frozen = output.len();
+            continue;
+        }
+
+        match inst {
+            Instruction::Wait(Duration::ZERO) => {}
+            Instruction::Select(Size::ZERO) => {}
+            Instruction::Wait(duration) => match output[frozen..].last_mut() {
+                Some(Instruction::Wait(previous)) => *previous += duration,
+                _ => output.push(inst),
+            },
+            Instruction::Speed(_) | Instruction::LinePause(_) | Instruction::SetJitter(_) => {
+                let settings = output[frozen..].iter().rev().take_while(|inst| is_timing(inst)).count();
+                let start = output.len() - settings;
+                if let Some(index) = output[start..].iter().position(|prev| discriminant(prev) == discriminant(&inst)) {
+                    output.remove(start + index);
+                }
+                output.push(inst);
+            }
+            Instruction::Every { interval, body } => output.push(Instruction::Every {
+                interval,
+                body: optimize(body),
+            }),
+            Instruction::FindMiss(policy) => {
+                skip_on_miss = match policy {
+                    FindMiss::Skip(count) => count,
+                    FindMiss::Stay | FindMiss::Error => 0,
+                };
+                output.push(inst);
+            }
+            Instruction::FindInCurrentLine { dependents, .. } => {
+                // The instructions of a `replace` are kept whatever the policy, there are only two
+                keep = dependents.unwrap_or(skip_on_miss);
+                output.push(inst);
+                frozen = output.len();
+            }
+            inst => output.push(inst),
+        }
+    }
+
+    output
+}
+
+fn is_timing(inst: &Instruction) -> bool {
+    matches!(inst, Instruction::Speed(_) | Instruction::LinePause(_) | Instruction::SetJitter(_))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn debug(instructions: &[Instruction]) -> Vec<String> {
+        instructions.iter().map(|inst| format!("{inst:?}")).collect()
+    }
+
+    fn find(dependents: Option<usize>) -> Instruction {
+        Instruction::FindInCurrentLine {
+            needle: "a".into(),
+            end_of_word: false,
+            reverse: false,
+            count: 1,
+            dependents,
+        }
+    }
+
+    #[test]
+    fn merge_waits() {
+        let before = vec![
+            Instruction::Wait(secs(1)),
+            Instruction::Wait(secs(1)),
+            Instruction::Wait(Duration::ZERO),
+            Instruction::Wait(secs(2)),
+            Instruction::Insert("a".into()),
+            Instruction::Wait(Duration::ZERO),
+            Instruction::Select(Size::ZERO),
+            Instruction::Wait(secs(1)),
+        ];
+        let after = optimize(before);
+        assert_eq!(debug(&after), ["Wait(4s)", "Insert(\"a\")", "Wait(1s)"]);
+    }
+
+    #[test]
+    fn only_the_last_setting() {
+        let before = vec![
+            Instruction::Speed(ms(10)),
+            Instruction::LinePause(ms(100)),
+            Instruction::Speed(ms(20)),
+            Instruction::SetJitter(5),
+            Instruction::Speed(ms(30)),
+            Instruction::LoadTypeBuffer("a".into()),
+            Instruction::Speed(ms(40)),
+        ];
+        let after = optimize(before);
+        assert_eq!(debug(&after), [
+            "LinePause(100ms)",
+            "SetJitter(5)",
+            "Speed(30ms)",
+            "LoadTypeBuffer(\"a\")",
+            "Speed(40ms)",
+        ]);
+    }
+
+    #[test]
+    fn every_body() {
+        let before = vec![Instruction::Every {
+            interval: secs(1),
+            body: vec![Instruction::Wait(secs(1)), Instruction::Wait(secs(1))],
+        }];
+        let after = optimize(before);
+        assert!(matches!(&after[..], [Instruction::Every { body, .. }] if debug(body) == ["Wait(2s)"]));
+    }
+
+    #[test]
+    fn keep_what_a_find_skips() {
+        // A `replace` drops its select and type on a miss
+        let before = vec![
+            find(Some(2)),
+            Instruction::Select(Size::ZERO),
+            Instruction::Wait(secs(1)),
+            Instruction::Wait(secs(1)),
+        ];
+        let after = optimize(before);
+        assert_eq!(after.len(), 4);
+
+        // With `find_miss skip 3` the three after a find are dropped on a miss
+        let before = vec![
+            Instruction::FindMiss(FindMiss::Skip(3)),
+            Instruction::Wait(secs(1)),
+            find(None),
+            Instruction::Wait(secs(1)),
+            Instruction::Wait(Duration::ZERO),
+            Instruction::Speed(ms(10)),
+            Instruction::Speed(ms(20)),
+            Instruction::Wait(secs(1)),
+            Instruction::Wait(secs(1)),
+        ];
+        let after = optimize(before);
+        assert_eq!(debug(&after[2..]), [
+            "FindInCurrentLine { needle: \"a\", end_of_word: false, reverse: false, count: 1, dependents: None }",
+            "Wait(1s)",
+            "Wait(0ns)",
+            "Speed(10ms)",
+            "Speed(20ms)",
+            "Wait(2s)",
+        ]);
+    }
+}
@@ -0,0 +1,323 @@
+use anathema::geometry::{LocalPos, Region};
+use anathema::state::Color;
+use anathema::widgets::Style;
+use unicode_width::UnicodeWidthChar;
+
+use super::anchor::{Anchored, Shift};
+use super::editor::Surface;
+
+// -----------------------------------------------------------------------------
+//   - Layer -
+// -----------------------------------------------------------------------------
+/// Where an overlay sits: higher layers are painted over lower ones.
+/// Declared from the bottom up, so the order of the variants is the z-order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    ColumnGuide,
+    Selection,
+}
+
+/// What an overlay covers, in document coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// The cells of a rectangle of the document, moving with its rows
+    Region(Region),
+    /// A column on every row on screen, including rows of virtual text and rows past the end
+    Column(i32),
+}
+
+impl Target {
+    fn contains(&self, col: i32, row: Option<usize>) -> bool {
+        match *self {
+            Target::Region(region) => row.is_some_and(|row| region.contains((col, row as i32).into())),
+            Target::Column(column) => column == col,
+        }
+    }
+}
+
+/// How an overlay changes the background of a cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Paint {
+    /// Replace the background of the text, leaving empty cells alone
+    Highlight(Color),
+    /// Fill in the background where there is none, including empty cells
+    Backdrop(Color),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Overlay {
+    layer: Layer,
+    target: Target,
+    paint: Paint,
+}
+
+// -----------------------------------------------------------------------------
+//   - Overlays -
+// -----------------------------------------------------------------------------
+/// Everything that changes the style of the cells after syntax highlighting,
+/// painted in a single pass from the lowest layer to the highest
+#[derive(Debug, Default)]
+pub struct Overlays {
+    // Sorted by layer, and in the order they were added within a layer
+    inner: Vec<Overlay>,
+}
+
+impl Overlays {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace everything on the layer
+    pub fn set_layer(&mut self, layer: Layer, overlays: impl IntoIterator<Item = (Target, Paint)>) {
+        self.inner.retain(|overlay| overlay.layer != layer);
+        for (target, paint) in overlays {
+            self.add(layer, target, paint);
+        }
+    }
+
+    fn add(&mut self, layer: Layer, target: Target, paint: Paint) {
+        let index = self.inner.partition_point(|overlay| overlay.layer <= layer);
+        self.inner.insert(index, Overlay { layer, target, paint });
+    }
+
+    /// The paint of the overlays covering the cell, from the bottom up.
+    /// `row` is `None` for screen rows that aren't lines of the document.
+    pub fn at(&self, col: i32, row: Option<usize>) -> impl Iterator<Item = Paint> + '_ {
+        self.inner
+            .iter()
+            .filter(move |overlay| overlay.target.contains(col, row))
+            .map(|overlay| overlay.paint)
+    }
+
+    /// Paint the overlays covering the cell onto the surface.
+    /// Highlighting either cell of a wide char highlights all of it.
+    pub fn paint(&self, surface: &mut impl Surface, pos: LocalPos, col: i32, row: Option<usize>) {
+        // Only what was drawn before the overlays counts as text
+        let glyph = glyph(surface, pos);
+        for paint in self.at(col, row) {
+            match paint {
+                Paint::Highlight(color) => {
+                    if let Some(glyph) = glyph
+                        && let Some((_, style)) = surface.get_mut(glyph)
+                    {
+                        style.bg = Some(color);
+                    }
+                }
+                Paint::Backdrop(color) => backdrop(surface, pos, color),
+            }
+        }
+    }
+}
+
+impl Anchored for Overlays {
+    // Regions follow their first and last rows, so they grow when lines are inserted
+    // inside of them and shrink when lines are joined. Columns aren't anchored to rows.
+    fn shift(&mut self, shift: Shift) {
+        self.inner.retain_mut(|overlay| match &mut overlay.target {
+            Target::Region(region) => {
+                let last = region.to.y - 1;
+                match (shift.row(region.from.y as usize), shift.row(last as usize)) {
+                    // Swapped rows can turn the region upside down
+                    (Some(first), Some(last)) => {
+                        region.from.y = first.min(last) as i32;
+                        region.to.y = first.max(last) as i32 + 1;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Target::Column(_) => true,
+        });
+    }
+}
+
+// The cell of the char drawn over the cell: the cell itself,
+// or the one to the left of it when it's the second half of a wide char
+fn glyph(surface: &mut impl Surface, pos: LocalPos) -> Option<LocalPos> {
+    if surface.get_mut(pos).is_some() {
+        return Some(pos);
+    }
+
+    let left = LocalPos::new(pos.x.checked_sub(1)?, pos.y);
+    match surface.get_mut(left) {
+        Some((c, _)) if c.width() == Some(2) => Some(left),
+        _ => None,
+    }
+}
+
+// The background of an empty cell, or a cell covered by a wide char, is filled in
+fn backdrop(surface: &mut impl Surface, pos: LocalPos, color: Color) {
+    if let Some(glyph) = glyph(surface, pos)
+        && let Some((_, style)) = surface.get_mut(glyph)
+    {
+        _ = style.bg.get_or_insert(color);
+        return;
+    }
+
+    let mut style = Style::new();
+    style.bg = Some(color);
+    surface.put(' ', style, pos);
+}
+
+#[cfg(test)]
+mod test {
+    use anathema::geometry::{Pos, Size};
+
+    use super::*;
+    use crate::ui::mirror::Grid;
+
+    const RED: Color = Color::Red;
+    const GREY: Color = Color::Grey;
+
+    fn region(x: i32, y: i32, width: u16, height: u16) -> Target {
+        Target::Region(Region::from((Pos::new(x, y), Size::new(width, height))))
+    }
+
+    fn rows(overlays: &Overlays) -> Vec<(i32, i32)> {
+        overlays
+            .inner
+            .iter()
+            .filter_map(|overlay| match overlay.target {
+                Target::Region(region) => Some((region.from.y, region.to.y)),
+                Target::Column(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn layers_are_painted_bottom_up() {
+        let mut overlays = Overlays::new();
+        overlays.set_layer(Layer::Selection, [(region(0, 0, 4, 1), Paint::Highlight(RED))]);
+        overlays.set_layer(Layer::ColumnGuide, [(Target::Column(2), Paint::Backdrop(GREY))]);
+
+        let paints = overlays.at(2, Some(0)).collect::<Vec<_>>();
+        assert_eq!(paints, [Paint::Backdrop(GREY), Paint::Highlight(RED)]);
+        assert_eq!(overlays.at(3, Some(0)).collect::<Vec<_>>(), [Paint::Highlight(RED)]);
+        assert_eq!(overlays.at(2, Some(1)).collect::<Vec<_>>(), [Paint::Backdrop(GREY)]);
+        assert_eq!(overlays.at(2, None).collect::<Vec<_>>(), [Paint::Backdrop(GREY)]);
+        assert_eq!(overlays.at(5, Some(0)).count(), 0);
+    }
+
+    #[test]
+    fn later_overlays_on_a_layer_are_on_top() {
+        let mut overlays = Overlays::new();
+        overlays.set_layer(Layer::Selection, [
+            (region(0, 0, 2, 1), Paint::Highlight(RED)),
+            (region(0, 0, 2, 1), Paint::Highlight(GREY)),
+        ]);
+        assert_eq!(overlays.at(0, Some(0)).collect::<Vec<_>>(), [
+            Paint::Highlight(RED),
+            Paint::Highlight(GREY)
+        ]);
+    }
+
+    #[test]
+    fn set_layer_replaces_the_layer() {
+        let mut overlays = Overlays::new();
+        overlays.set_layer(Layer::ColumnGuide, [(Target::Column(1), Paint::Backdrop(GREY))]);
+        overlays.set_layer(Layer::Selection, [(region(0, 0, 2, 1), Paint::Highlight(RED))]);
+        overlays.set_layer(Layer::Selection, None);
+        assert_eq!(overlays.at(1, Some(0)).collect::<Vec<_>>(), [Paint::Backdrop(GREY)]);
+    }
+
+    #[test]
+    fn regions_move_with_rows() {
+        let mut overlays = Overlays::new();
+        overlays.set_layer(Layer::Selection, [
+            (region(0, 1, 2, 1), Paint::Highlight(RED)),
+            (region(0, 3, 2, 2), Paint::Highlight(RED)),
+            (Target::Column(1), Paint::Highlight(RED)),
+        ]);
+
+        // Lines inserted above both
+        overlays.shift(Shift::Down { from: 0, count: 2 });
+        assert_eq!(rows(&overlays), [(3, 4), (5, 7)]);
+
+        // Inserted inside the second, which grows
+        overlays.shift(Shift::Down { from: 6, count: 1 });
+        assert_eq!(rows(&overlays), [(3, 4), (5, 8)]);
+
+        // Inserted below both
+        overlays.shift(Shift::Down { from: 8, count: 1 });
+        assert_eq!(rows(&overlays), [(3, 4), (5, 8)]);
+
+        // The line after the first is joined onto it, the second moves up and shrinks
+        overlays.shift(Shift::Up { row: 3, count: 1 });
+        assert_eq!(rows(&overlays), [(3, 4), (4, 7)]);
+        overlays.shift(Shift::Up { row: 4, count: 1 });
+        assert_eq!(rows(&overlays), [(3, 4), (4, 6)]);
+        overlays.shift(Shift::Up { row: 4, count: 5 });
+        assert_eq!(rows(&overlays), [(3, 4), (4, 5)]);
+    }
+
+    #[test]
+    fn clear_removes_regions() {
+        let mut overlays = Overlays::new();
+        overlays.set_layer(Layer::ColumnGuide, [(Target::Column(1), Paint::Backdrop(GREY))]);
+        overlays.set_layer(Layer::Selection, [(region(0, 0, 2, 1), Paint::Highlight(RED))]);
+        overlays.shift(Shift::Clear);
+        assert!(rows(&overlays).is_empty());
+        assert_eq!(overlays.at(1, Some(0)).collect::<Vec<_>>(), [Paint::Backdrop(GREY)]);
+    }
+
+    #[test]
+    fn paint_cells() {
+        let mut grid = Grid::new(Size::new(5, 1));
+        let mut styled = Style::new();
+        styled.bg = Some(Color::Blue);
+        grid.put('a', Style::new(), LocalPos::new(0, 0));
+        grid.put('b', styled, LocalPos::new(1, 0));
+        grid.put('界', Style::new(), LocalPos::new(2, 0));
+
+        let mut overlays = Overlays::new();
+        overlays.set_layer(Layer::ColumnGuide, [
+            (Target::Column(1), Paint::Backdrop(GREY)),
+            (Target::Column(3), Paint::Backdrop(GREY)),
+            (Target::Column(4), Paint::Backdrop(GREY)),
+        ]);
+        overlays.set_layer(Layer::Selection, [(region(0, 0, 5, 1), Paint::Highlight(RED))]);
+        for x in 0..5 {
+            overlays.paint(&mut grid, LocalPos::new(x, 0), x as i32, Some(0));
+        }
+
+        let bg = |grid: &mut Grid, x| grid.get_mut(LocalPos::new(x, 0)).map(|(c, style)| (*c, style.bg));
+        assert_eq!(bg(&mut grid, 0), Some(('a', Some(RED))));
+        // The selection is painted over the guide and the text's own background
+        assert_eq!(bg(&mut grid, 1), Some(('b', Some(RED))));
+        assert_eq!(bg(&mut grid, 2), Some(('界', Some(RED))));
+        // The cell covered by the wide char and the empty cell only get the guide
+        assert_eq!(bg(&mut grid, 3), None);
+        assert_eq!(bg(&mut grid, 4), Some((' ', Some(GREY))));
+    }
+
+    #[test]
+    fn highlight_either_half_of_a_wide_char() {
+        for x in [1, 2] {
+            let mut grid = Grid::new(Size::new(3, 1));
+            grid.put('a', Style::new(), LocalPos::new(0, 0));
+            grid.put('界', Style::new(), LocalPos::new(1, 0));
+
+            let mut overlays = Overlays::new();
+            overlays.set_layer(Layer::Selection, [(region(x, 0, 1, 1), Paint::Highlight(RED))]);
+            for x in 0..3 {
+                overlays.paint(&mut grid, LocalPos::new(x, 0), x as i32, Some(0));
+            }
+
+            assert_eq!(grid.get_mut(LocalPos::new(1, 0)).unwrap().1.bg, Some(RED));
+            assert_eq!(grid.get_mut(LocalPos::new(0, 0)).unwrap().1.bg, None);
+        }
+    }
+
+    #[test]
+    fn backdrop_is_under_the_text() {
+        let mut grid = Grid::new(Size::new(1, 1));
+        let mut styled = Style::new();
+        styled.bg = Some(Color::Blue);
+        grid.put('a', styled, LocalPos::new(0, 0));
+
+        let mut overlays = Overlays::new();
+        overlays.set_layer(Layer::ColumnGuide, [(Target::Column(0), Paint::Backdrop(GREY))]);
+        overlays.paint(&mut grid, LocalPos::new(0, 0), 0, Some(0));
+        assert_eq!(grid.get_mut(LocalPos::new(0, 0)).unwrap().1.bg, Some(Color::Blue));
+    }
+}
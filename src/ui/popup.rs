@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use crate::parser::PopupPosition;
+
+/// The content of the popup, of which only as many lines as fit are shown
+pub struct Popup {
+    lines: Vec<String>,
+    scroll: usize,
+    // Lines per second, and how far into the next line the autoscroll is
+    speed: u32,
+    progress: f64,
+    // Time left until a timed popup closes itself
+    remaining: Option<Duration>,
+    position: PopupPosition,
+    title: Option<String>,
+}
+
+impl Popup {
+    pub fn new() -> Self {
+        Self {
+            lines: vec![],
+            scroll: 0,
+            speed: 0,
+            progress: 0.0,
+            remaining: None,
+            position: PopupPosition::Cursor,
+            title: None,
+        }
+    }
+
+    pub fn set(&mut self, content: &str) {
+        self.lines = content.lines().map(String::from).collect();
+        self.scroll = 0;
+        self.progress = 0.0;
+        self.remaining = None;
+    }
+
+    /// Remove the content, and put the position and the title back to their defaults
+    pub fn clear(&mut self) {
+        self.set("");
+        self.position = PopupPosition::Cursor;
+        self.title = None;
+    }
+
+    pub fn position(&self) -> PopupPosition {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: PopupPosition) {
+        self.position = position;
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn set_title(&mut self, title: Option<String>) {
+        self.title = title;
+    }
+
+    /// Close the popup once `duration` has passed
+    pub fn close_after(&mut self, duration: Duration) {
+        self.remaining = Some(duration);
+    }
+
+    pub fn set_autoscroll(&mut self, lines_per_second: u32) {
+        self.speed = lines_per_second;
+        self.progress = 0.0;
+    }
+
+    // The last offset that still fills the height, if there are enough lines
+    fn max_offset(&self, height: usize) -> usize {
+        self.lines.len().saturating_sub(height)
+    }
+
+    // The scroll position is kept as is when the height changes,
+    // and clamped when it's read
+    fn offset(&self, height: usize) -> usize {
+        self.scroll.min(self.max_offset(height))
+    }
+
+    /// Scroll by a number of lines, negative scrolls up
+    pub fn scroll(&mut self, lines: i32, height: usize) {
+        let offset = self.offset(height) as i64 + lines as i64;
+        self.scroll = offset.clamp(0, self.max_offset(height) as i64) as usize;
+    }
+
+    /// Advance the autoscroll and the timer of a timed popup.
+    /// Returns true if the visible lines changed.
+    pub fn tick(&mut self, dt: Duration, height: usize) -> bool {
+        if let Some(remaining) = self.remaining {
+            match remaining.checked_sub(dt).filter(|remaining| !remaining.is_zero()) {
+                Some(remaining) => self.remaining = Some(remaining),
+                None => {
+                    self.clear();
+                    return true;
+                }
+            }
+        }
+
+        if self.speed == 0 || self.lines.is_empty() {
+            return false;
+        }
+
+        self.progress += dt.as_secs_f64() * self.speed as f64;
+        let lines = self.progress.floor();
+        self.progress -= lines;
+        if lines == 0.0 {
+            return false;
+        }
+
+        let before = self.offset(height);
+        self.scroll(lines as i32, height);
+        before != self.offset(height)
+    }
+
+    /// The lines that fit in `height`
+    pub fn visible(&self, height: usize) -> String {
+        let offset = self.offset(height);
+        let end = (offset + height.max(1)).min(self.lines.len());
+        self.lines[offset..end].join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn popup(lines: usize) -> Popup {
+        let content = (0..lines).map(|i| i.to_string()).collect::<Vec<_>>().join("\n");
+        let mut popup = Popup::new();
+        popup.set(&content);
+        popup
+    }
+
+    #[test]
+    fn slice() {
+        let mut popup = popup(10);
+        assert_eq!(popup.visible(3), "0\n1\n2");
+        popup.scroll(2, 3);
+        assert_eq!(popup.visible(3), "2\n3\n4");
+        assert_eq!(popup.visible(20), "0\n1\n2\n3\n4\n5\n6\n7\n8\n9");
+    }
+
+    #[test]
+    fn clamp() {
+        let mut popup = popup(10);
+        popup.scroll(100, 4);
+        assert_eq!(popup.visible(4), "6\n7\n8\n9");
+        popup.scroll(-2, 4);
+        assert_eq!(popup.visible(4), "4\n5\n6\n7");
+        popup.scroll(-100, 4);
+        assert_eq!(popup.visible(4), "0\n1\n2\n3");
+    }
+
+    #[test]
+    fn resize() {
+        let mut popup = popup(10);
+        popup.scroll(6, 4);
+
+        // Taller: the bottom line stays at the end of the content
+        assert_eq!(popup.visible(6), "4\n5\n6\n7\n8\n9");
+        // And back again
+        assert_eq!(popup.visible(4), "6\n7\n8\n9");
+        // Scrolling is relative to what's visible
+        popup.scroll(-1, 6);
+        assert_eq!(popup.visible(6), "3\n4\n5\n6\n7\n8");
+    }
+
+    #[test]
+    fn autoscroll() {
+        let mut popup = popup(10);
+        popup.set_autoscroll(4);
+
+        let ms = Duration::from_millis;
+        assert!(!popup.tick(ms(200), 3));
+        assert!(popup.tick(ms(50), 3));
+        assert_eq!(popup.visible(3), "1\n2\n3");
+        assert!(popup.tick(ms(500), 3));
+        assert_eq!(popup.visible(3), "3\n4\n5");
+
+        // Stops at the end
+        assert!(popup.tick(ms(10_000), 3));
+        assert_eq!(popup.visible(3), "7\n8\n9");
+        assert!(!popup.tick(ms(1000), 3));
+    }
+
+    #[test]
+    fn new_content_starts_at_the_top() {
+        let mut popup = popup(10);
+        popup.scroll(5, 2);
+        popup.set("a\nb\nc");
+        assert_eq!(popup.visible(2), "a\nb");
+        popup.clear();
+        assert_eq!(popup.visible(2), "");
+    }
+
+    #[test]
+    fn timed() {
+        let mut popup = popup(3);
+        popup.close_after(Duration::from_secs(1));
+        assert!(!popup.tick(Duration::from_millis(600), 3));
+        assert_eq!(popup.visible(3), "0\n1\n2");
+        assert!(popup.tick(Duration::from_millis(400), 3));
+        assert_eq!(popup.visible(3), "");
+
+        // A new popup isn't closed by the timer of the old one
+        popup.close_after(Duration::from_secs(1));
+        popup.set("new");
+        assert!(!popup.tick(Duration::from_secs(5), 3));
+        assert_eq!(popup.visible(3), "new");
+    }
+
+    #[test]
+    fn clear_resets_position_and_title() {
+        let mut popup = popup(3);
+        popup.set_position(PopupPosition::Top);
+        popup.set_title(Some("Note".into()));
+        popup.clear();
+        assert_eq!(popup.position(), PopupPosition::Cursor);
+        assert_eq!(popup.title(), None);
+
+        // So does closing by the timer
+        popup.set_title(Some("Note".into()));
+        popup.close_after(Duration::from_secs(1));
+        popup.tick(Duration::from_secs(1), 3);
+        assert_eq!(popup.title(), None);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use super::editor::{DEFAULT_BURST_PAUSE, DEFAULT_JITTER_MS};
+use super::error::{Error, Result};
+use super::estimate::FRAME_TIME;
+use super::instructions::Instruction;
+use super::session::Settings;
+use crate::parser::JitterProfile;
+
+// -----------------------------------------------------------------------------
+//   - Setting -
+// -----------------------------------------------------------------------------
+/// What a preset can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Setting {
+    Speed,
+    CommandSpeed,
+    Jitter,
+    JitterProfile,
+    BurstPause,
+    AutoTypo,
+    LinePause,
+    CommandClearTimeout,
+    Theme,
+    Extension,
+    LineNumbers,
+    AudioClasses,
+    AudioOffset,
+}
+
+impl Setting {
+    fn of(inst: &Instruction) -> Option<Self> {
+        let setting = match inst {
+            Instruction::Speed(_) => Self::Speed,
+            Instruction::CommandSpeed(_) => Self::CommandSpeed,
+            Instruction::SetJitter(_) => Self::Jitter,
+            Instruction::JitterProfile(_) => Self::JitterProfile,
+            Instruction::BurstPause { .. } => Self::BurstPause,
+            Instruction::AutoTypo { .. } => Self::AutoTypo,
+            Instruction::LinePause(_) => Self::LinePause,
+            Instruction::CommandClearTimeout(_) => Self::CommandClearTimeout,
+            Instruction::SetTheme(_) => Self::Theme,
+            Instruction::SetExtension(_) => Self::Extension,
+            Instruction::ShowLineNumbers(_) => Self::LineNumbers,
+            Instruction::AudioClasses(_) => Self::AudioClasses,
+            Instruction::AudioOffset(_) => Self::AudioOffset,
+            _ => return None,
+        };
+        Some(setting)
+    }
+
+    // The value before anything changed it
+    fn default(self) -> Instruction {
+        let settings = Settings::default();
+        match self {
+            Self::Speed => Instruction::Speed(FRAME_TIME),
+            Self::CommandSpeed => Instruction::CommandSpeed(None),
+            Self::Jitter => Instruction::SetJitter(DEFAULT_JITTER_MS),
+            Self::JitterProfile => Instruction::JitterProfile(JitterProfile::default()),
+            Self::BurstPause => {
+                let (min, max) = DEFAULT_BURST_PAUSE;
+                Instruction::BurstPause { min, max }
+            }
+            Self::AutoTypo => Instruction::AutoTypo {
+                rate: 0,
+                max_per_line: None,
+            },
+            Self::LinePause => Instruction::LinePause(settings.line_pause),
+            Self::CommandClearTimeout => Instruction::CommandClearTimeout(settings.command_clear_timeout),
+            Self::Theme => Instruction::SetTheme(settings.theme),
+            Self::Extension => Instruction::SetExtension(settings.extension),
+            Self::LineNumbers => Instruction::ShowLineNumbers(false),
+            Self::AudioClasses => Instruction::AudioClasses(false),
+            Self::AudioOffset => Instruction::AudioOffset(0),
+        }
+    }
+}
+
+/// Whether the instruction can be part of a preset
+pub fn is_setting(inst: &crate::parser::Instruction) -> bool {
+    use crate::parser::Instruction as Parsed;
+
+    matches!(
+        inst,
+        Parsed::Speed(_)
+            | Parsed::CommandSpeed(_)
+            | Parsed::Jitter(_)
+            | Parsed::JitterProfile(_)
+            | Parsed::BurstPause { .. }
+            | Parsed::AutoTypo { .. }
+            | Parsed::LinePause(_)
+            | Parsed::CommandClearTimeout(_)
+            | Parsed::SetTheme(_)
+            | Parsed::SetExtension(_)
+            | Parsed::ShowLineNumbers(_)
+            | Parsed::AudioClasses(_)
+            | Parsed::AudioOffset(_)
+    )
+}
+
+// -----------------------------------------------------------------------------
+//   - Presets -
+// -----------------------------------------------------------------------------
+/// Named groups of settings, and the settings to restore when a preset is popped
+#[derive(Debug, Default)]
+pub struct Presets {
+    defined: HashMap<String, Vec<Instruction>>,
+    // The last value of every setting that was changed
+    current: HashMap<Setting, Instruction>,
+    // What `preset pop` restores, most recent last
+    stack: Vec<Vec<Instruction>>,
+}
+
+impl Presets {
+    /// Defining a preset again replaces it
+    pub fn define(&mut self, name: String, settings: Vec<Instruction>) {
+        self.defined.insert(name, settings);
+    }
+
+    /// Keep track of the settings as they change, so they can be restored
+    pub fn track(&mut self, inst: &Instruction) {
+        if let Some(setting) = Setting::of(inst) {
+            self.current.insert(setting, inst.clone());
+        }
+    }
+
+    /// The settings of the preset. What they replace is restored by the next [`Presets::pop`].
+    pub fn apply(&mut self, name: &str) -> Result<Vec<Instruction>> {
+        let settings = self.defined.get(name).ok_or_else(|| Error::UnknownPreset(name.into()))?;
+
+        let mut restore: Vec<Instruction> = vec![];
+        for setting in settings.iter().filter_map(Setting::of) {
+            if restore.iter().any(|inst| Setting::of(inst) == Some(setting)) {
+                continue;
+            }
+            let previous = self.current.get(&setting).cloned().unwrap_or_else(|| setting.default());
+            restore.push(previous);
+        }
+
+        self.stack.push(restore);
+        Ok(settings.clone())
+    }
+
+    /// The settings from before the last preset was applied
+    pub fn pop(&mut self) -> Result<Vec<Instruction>> {
+        self.stack.pop().ok_or(Error::UnmatchedPresetPop)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn speed(ms: u64) -> Instruction {
+        Instruction::Speed(Duration::from_millis(ms))
+    }
+
+    fn speeds(instructions: &[Instruction]) -> Vec<u128> {
+        instructions
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Speed(frame_time) => Some(frame_time.as_millis()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Apply or pop, tracking the output like the compiler does
+    fn apply(presets: &mut Presets, name: &str) -> Vec<Instruction> {
+        let output = presets.apply(name).unwrap();
+        output.iter().for_each(|inst| presets.track(inst));
+        output
+    }
+
+    fn pop(presets: &mut Presets) -> Vec<Instruction> {
+        let output = presets.pop().unwrap();
+        output.iter().for_each(|inst| presets.track(inst));
+        output
+    }
+
+    #[test]
+    fn nested_use_and_pop() {
+        let mut presets = Presets::default();
+        presets.define("a".into(), vec![speed(10)]);
+        presets.define("b".into(), vec![speed(20), Instruction::LinePause(Duration::from_millis(5))]);
+        presets.track(&speed(50));
+
+        assert_eq!(speeds(&apply(&mut presets, "a")), [10]);
+        assert_eq!(speeds(&apply(&mut presets, "b")), [20]);
+
+        // Back to a, with the line pause that was never set restored to the default
+        let restored = pop(&mut presets);
+        assert_eq!(speeds(&restored), [10]);
+        assert!(matches!(restored[1], Instruction::LinePause(Duration::ZERO)));
+
+        // Back to before a
+        assert_eq!(speeds(&pop(&mut presets)), [50]);
+        assert!(matches!(presets.pop(), Err(Error::UnmatchedPresetPop)));
+    }
+
+    #[test]
+    fn restore_the_value_from_before_the_preset() {
+        let mut presets = Presets::default();
+        presets.define("twice".into(), vec![speed(10), speed(20)]);
+        assert_eq!(speeds(&apply(&mut presets, "twice")), [10, 20]);
+        assert_eq!(speeds(&pop(&mut presets)), [FRAME_TIME.as_millis()]);
+    }
+
+    #[test]
+    fn unknown_preset() {
+        let mut presets = Presets::default();
+        assert!(matches!(presets.apply("nope"), Err(Error::UnknownPreset(name)) if name == "nope"));
+    }
+}
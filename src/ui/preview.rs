@@ -0,0 +1,398 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anathema::geometry::Size;
+
+use super::compile::compile_with_loader;
+use super::estimate::{duration, scenes};
+use super::headless::{final_grid, html};
+use super::instructions::Instruction;
+use super::error::Result as LoadResult;
+use super::loader::{FsLoader, Loader};
+use super::markers::marker_collisions;
+
+// The size of the rendered editor
+const SIZE: Size = Size::new(100, 30);
+const SEED: u64 = 1;
+
+/// Serve the preview of the script on `addr`, one request at a time.
+/// The script is run again whenever it, or a file it loads, changed since the last request.
+pub fn serve(addr: &str, path: PathBuf) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let mut preview = Preview::new(path);
+
+    for stream in listener.incoming() {
+        // A connection that failed before it was accepted is no reason to stop serving
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("preview: {e}");
+                continue;
+            }
+        };
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            continue;
+        }
+
+        // Skip the headers, nothing in them matters
+        let mut header = String::new();
+        while reader.read_line(&mut header).is_ok_and(|len| len > 2) {
+            header.clear();
+        }
+
+        let response = preview.respond(request_line.trim_end());
+        _ = write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            reason(response.status),
+            response.body.len(),
+            response.body
+        );
+    }
+
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Method Not Allowed",
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Routing -
+// -----------------------------------------------------------------------------
+#[derive(Debug, PartialEq)]
+enum Route {
+    /// The final frame, or the frame as of the start of the scene
+    Page { at: Option<String> },
+    NotFound,
+    MethodNotAllowed,
+}
+
+// From the request line, like `GET /?at=intro HTTP/1.1`
+fn route(request_line: &str) -> Route {
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else { return Route::NotFound };
+    if method != "GET" {
+        return Route::MethodNotAllowed;
+    }
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path != "/" {
+        return Route::NotFound;
+    }
+
+    let at = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "at")
+        .map(|(_, value)| decode(value))
+        .filter(|at| !at.is_empty());
+    Route::Page { at }
+}
+
+// Percent decoding, with `+` as a space
+fn decode(value: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => match rest.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()) {
+                Some(decoded) => {
+                    bytes.push(decoded);
+                    rest = &rest[2..];
+                }
+                None => bytes.push(byte),
+            },
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// -----------------------------------------------------------------------------
+//   - Preview -
+// -----------------------------------------------------------------------------
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    body: String,
+}
+
+struct Preview {
+    path: PathBuf,
+    // The last page, for the modification times of the script and the files it loaded,
+    // and the checkpoint it was rendered for
+    cache: Option<(Vec<(PathBuf, Option<SystemTime>)>, Option<String>, String)>,
+}
+
+impl Preview {
+    fn new(path: PathBuf) -> Self {
+        Self { path, cache: None }
+    }
+
+    fn respond(&mut self, request_line: &str) -> Response {
+        let at = match route(request_line) {
+            Route::Page { at } => at,
+            Route::NotFound => return Response { status: 404, body: "not found".into() },
+            Route::MethodNotAllowed => return Response { status: 405, body: "only GET".into() },
+        };
+
+        if let Some((files, cached_at, body)) = &self.cache
+            && files.iter().all(|(path, cached)| modified(path) == *cached)
+            && *cached_at == at
+        {
+            return Response { status: 200, body: body.clone() };
+        }
+
+        let loader = Watched::new();
+        let body = match std::fs::read_to_string(&self.path) {
+            Ok(src) => page(&src, self.path.parent().unwrap_or(Path::new("")), at.as_deref(), &loader),
+            Err(e) => page_with(&Err(format!("failed to read {}: {e}", self.path.display())), at.as_deref()),
+        };
+        let files = std::iter::once(self.path.clone()).chain(loader.loaded.into_inner());
+        let files = files
+            .map(|path| {
+                let time = modified(&path);
+                (path, time)
+            })
+            .collect();
+        self.cache = Some((files, at, body.clone()));
+        Response { status: 200, body }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// Loads from the file system, and remembers what it loaded so changes to those files are seen too
+struct Watched {
+    loaded: RefCell<Vec<PathBuf>>,
+}
+
+impl Watched {
+    fn new() -> Self {
+        Self { loaded: RefCell::new(vec![]) }
+    }
+}
+
+impl Loader for Watched {
+    fn load(&self, path: &Path) -> LoadResult<String> {
+        // Even a file that doesn't exist yet, so the preview shows it once it does
+        self.loaded.borrow_mut().push(path.into());
+        FsLoader.load(path)
+    }
+}
+
+// What the page shows
+struct Render {
+    frame: String,
+    warnings: Vec<String>,
+    estimated: Duration,
+    scenes: Vec<String>,
+}
+
+/// The page for the script, with the frame as of the start of the scene `at`, or the final frame
+fn page(src: &str, base_dir: &Path, at: Option<&str>, loader: &dyn Loader) -> String {
+    page_with(&render(src, base_dir, at, loader), at)
+}
+
+fn render(src: &str, base_dir: &Path, at: Option<&str>, loader: &dyn Loader) -> Result<Render, String> {
+    let parsed = crate::parser::parse(src).map_err(|e| e.to_string())?;
+    let mut warnings = parsed.warnings().iter().map(|warning| format!("warning: {warning}")).collect::<Vec<_>>();
+    let mut instructions = compile_with_loader(parsed, loader, base_dir).map_err(|e| e.to_string())?;
+    warnings.extend(marker_collisions(&instructions).iter().map(|collision| format!("warning: {collision}")));
+
+    let estimated = duration(&instructions);
+    let scenes = scenes(&instructions).into_iter().map(|(name, _)| name).collect();
+
+    if let Some(at) = at {
+        let scene = instructions.iter().position(|inst| matches!(inst, Instruction::Scene(name) if name == at));
+        let Some(scene) = scene else { return Err(format!("no scene named `{at}`")) };
+        instructions.truncate(scene);
+    }
+
+    let grid = final_grid(instructions, SIZE, SEED).map_err(|e| e.to_string())?;
+    Ok(Render {
+        frame: html(&grid),
+        warnings,
+        estimated,
+        scenes,
+    })
+}
+
+fn page_with(render: &Result<Render, String>, at: Option<&str>) -> String {
+    let mut body = String::new();
+    match render {
+        Ok(render) => {
+            let secs = render.estimated.as_secs();
+            body.push_str(&format!("<p>estimated {}:{:02}", secs / 60, secs % 60));
+            body.push_str(&format!(" &middot; showing {}", link(None, at)));
+            for scene in &render.scenes {
+                body.push_str(&format!(" &middot; {}", link(Some(scene), at)));
+            }
+            body.push_str("</p>\n");
+
+            if !render.warnings.is_empty() {
+                body.push_str("<ul>\n");
+                for warning in &render.warnings {
+                    body.push_str(&format!("<li>{}</li>\n", escape(warning)));
+                }
+                body.push_str("</ul>\n");
+            }
+
+            body.push_str(&format!("<pre>{}</pre>\n", render.frame));
+        }
+        Err(e) => body.push_str(&format!("<p class=\"error\">{}</p>\n", escape(e))),
+    }
+
+    format!(
+        "<!doctype html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<meta http-equiv=\"refresh\" content=\"1\">
+<title>mimic preview</title>
+<style>
+body {{ background: #1d1f21; color: #c5c8c6; font-family: monospace; }}
+pre {{ border: 1px solid #373b41; display: inline-block; padding: 0.5em; }}
+a {{ color: #81a2be; }}
+.current {{ font-weight: bold; }}
+.error {{ color: #cc6666; }}
+</style>
+</head>
+<body>
+{body}</body>
+</html>
+"
+    )
+}
+
+// A link to the frame as of the start of the scene, or to the final frame
+fn link(scene: Option<&String>, at: Option<&str>) -> String {
+    let (href, text) = match scene {
+        Some(scene) => (format!("/?at={}", encode(scene)), escape(scene)),
+        None => ("/".into(), "end".into()),
+    };
+    let class = match scene.map(String::as_str) == at {
+        true => " class=\"current\"",
+        false => "",
+    };
+    format!("<a href=\"{href}\"{class}>{text}</a>")
+}
+
+fn encode(value: &str) -> String {
+    let mut out = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(byte as char),
+            byte => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        assert_eq!(route("GET / HTTP/1.1"), Route::Page { at: None });
+        assert_eq!(route("GET /?at= HTTP/1.1"), Route::Page { at: None });
+        assert_eq!(route("GET /?x=1&at=my+scene%21 HTTP/1.1"), Route::Page {
+            at: Some("my scene!".into())
+        });
+        assert_eq!(route("GET /favicon.ico HTTP/1.1"), Route::NotFound);
+        assert_eq!(route("POST / HTTP/1.1"), Route::MethodNotAllowed);
+        assert_eq!(route(""), Route::NotFound);
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(decode("a%20b+c%2"), "a b c%2");
+        assert_eq!(decode(&encode("a b/é")), "a b/é");
+    }
+
+    #[test]
+    fn final_frame() {
+        let page = page("type \"fn <main>\"\nwait 1s", Path::new(""), None, &FsLoader);
+        assert!(page.contains("estimated 0:0"));
+        assert!(page.contains("fn &lt;main&gt;"));
+        assert!(page.contains("<meta http-equiv=\"refresh\""));
+    }
+
+    #[test]
+    fn frame_at_scene() {
+        let src = "type \"before\"\nscene \"intro\"\ntype \"after\"";
+        let end = page(src, Path::new(""), None, &FsLoader);
+        assert!(end.contains("after"));
+        assert!(end.contains("<a href=\"/?at=intro\">intro</a>"));
+
+        let at = page(src, Path::new(""), Some("intro"), &FsLoader);
+        assert!(at.contains("before"));
+        assert!(!at.contains("after</"));
+        assert!(at.contains("<a href=\"/?at=intro\" class=\"current\">intro</a>"));
+
+        assert!(page(src, Path::new(""), Some("outro"), &FsLoader).contains("no scene named `outro`"));
+    }
+
+    #[test]
+    fn warnings_and_errors() {
+        let warned = page("wait 2", Path::new(""), None, &FsLoader);
+        assert!(warned.contains("<li>warning: "));
+
+        let failed = page("tpye \"a\"", Path::new(""), None, &FsLoader);
+        assert!(failed.contains("class=\"error\""));
+        assert!(!failed.contains("<pre>"));
+    }
+
+    #[test]
+    fn responses() {
+        let dir = std::env::temp_dir().join(format!("mimic-preview-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("script.echo");
+        std::fs::write(&path, "type \"one\"\ninclude \"more.echo\"").unwrap();
+        std::fs::write(dir.join("more.echo"), "type \" and more\"").unwrap();
+        let touch = |path: &Path, secs| {
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+        };
+
+        let mut preview = Preview::new(path.clone());
+        let first = preview.respond("GET / HTTP/1.1");
+        assert_eq!(first.status, 200);
+        assert!(first.body.contains("one"));
+        assert_eq!(preview.respond("GET / HTTP/1.1"), first);
+
+        // A new modification time renders the script again
+        std::fs::write(&path, "type \"two\"\ninclude \"more.echo\"").unwrap();
+        touch(&path, 60);
+        assert!(preview.respond("GET / HTTP/1.1").body.contains("two and more"));
+
+        // And so does one of a file the script loads
+        std::fs::write(dir.join("more.echo"), "type \" and less\"").unwrap();
+        touch(&dir.join("more.echo"), 60);
+        let changed = preview.respond("GET / HTTP/1.1");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(changed.body.contains("two and less"));
+
+        assert_eq!(preview.respond("GET /nope HTTP/1.1").status, 404);
+    }
+}
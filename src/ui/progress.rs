@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use super::estimate::Estimator;
+use super::instructions::Instruction;
+
+/// What is left of the instruction that was taken last
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Left {
+    /// The part of its text that is still to be typed, from 0 to 1
+    Typing(f64),
+    /// The rest of the wait it started
+    Wait(Duration),
+}
+
+// -----------------------------------------------------------------------------
+//   - Progress -
+// -----------------------------------------------------------------------------
+/// How far through the script the playback is, and the estimated time left.
+///
+/// Typed text weighs as much as its number of characters, every other
+/// instruction weighs one.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    // The weight and the estimated time of every instruction and all of the ones before it
+    done: Vec<(usize, Duration)>,
+}
+
+impl Progress {
+    pub fn new(instructions: &[Instruction]) -> Self {
+        let mut estimator = Estimator::new();
+        let mut weight = 0;
+        let done = instructions
+            .iter()
+            .map(|inst| {
+                weight += match inst {
+                    Instruction::LoadTypeBuffer(text)
+                    | Instruction::LoadTypeBufferKeepSelection(text)
+                    | Instruction::LoadTypeBufferRaw(text)
+                    | Instruction::LoadCommandBuffer(text) => text.chars().count().max(1),
+                    _ => 1,
+                };
+                estimator.apply(inst);
+                (weight, estimator.elapsed())
+            })
+            .collect();
+        Self { done }
+    }
+
+    /// From 0 to 1, once `taken` instructions have been applied and `left` is what's left of the last one
+    pub fn fraction(&self, taken: usize, left: Left) -> f32 {
+        let Some(&(total, _)) = self.done.last() else { return 1.0 };
+        let ((before, _), (weight, duration)) = self.last(taken);
+        let left = match left {
+            Left::Typing(part) => part.clamp(0.0, 1.0),
+            Left::Wait(wait) if !duration.is_zero() => (wait.as_secs_f64() / duration.as_secs_f64()).min(1.0),
+            Left::Wait(_) => 0.0,
+        };
+        ((before as f64 + weight as f64 * (1.0 - left)) / total as f64) as f32
+    }
+
+    /// The estimated time the rest of the script takes as written,
+    /// once `taken` instructions have been applied and `left` is what's left of the last one
+    pub fn remaining(&self, taken: usize, left: Left) -> Duration {
+        let Some(&(_, total)) = self.done.last() else { return Duration::ZERO };
+        let ((_, before), (_, duration)) = self.last(taken);
+        let left = match left {
+            Left::Typing(part) => duration.mul_f64(part.clamp(0.0, 1.0)),
+            Left::Wait(wait) => wait,
+        };
+        total.saturating_sub(before + duration) + left
+    }
+
+    // The weight and the estimated time of the instructions before the one taken last, and of that one
+    fn last(&self, taken: usize) -> ((usize, Duration), (usize, Duration)) {
+        let done = |taken: usize| match taken {
+            0 => (0, Duration::ZERO),
+            _ => self.done.get(taken - 1).or(self.done.last()).copied().unwrap_or_default(),
+        };
+        let (before, (weight, elapsed)) = (done(taken.saturating_sub(1)), done(taken));
+        (before, (weight - before.0, elapsed - before.1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn typing_weighs_more() {
+        let progress = Progress::new(&[
+            Instruction::SetTitle("a".into()),
+            Instruction::LoadTypeBuffer("abcdefgh".into()),
+            Instruction::Wait(Duration::from_secs(3)),
+        ]);
+        let done = Left::Wait(Duration::ZERO);
+        assert_eq!(progress.fraction(0, done), 0.0);
+        assert_eq!(progress.fraction(1, done), 0.1);
+        assert_eq!(progress.fraction(2, done), 0.9);
+        assert_eq!(progress.fraction(3, done), 1.0);
+        assert_eq!(progress.fraction(10, done), 1.0);
+
+        assert!(progress.remaining(0, done) > Duration::from_secs(3));
+        assert_eq!(progress.remaining(2, done), Duration::from_secs(3));
+        assert_eq!(progress.remaining(3, done), Duration::ZERO);
+    }
+
+    #[test]
+    fn part_of_the_last_instruction() {
+        let progress = Progress::new(&[
+            Instruction::LoadTypeBuffer("abcdefgh".into()),
+            Instruction::Wait(Duration::from_secs(4)),
+        ]);
+        let all = progress.remaining(0, Left::Wait(Duration::ZERO));
+        let typing = all - Duration::from_secs(4);
+
+        // A quarter of the text is typed
+        assert_eq!(progress.fraction(1, Left::Typing(0.75)), 2.0 / 9.0);
+        assert_eq!(progress.remaining(1, Left::Typing(0.75)), typing.mul_f64(0.75) + Duration::from_secs(4));
+        assert_eq!(progress.remaining(1, Left::Typing(1.0)), all);
+
+        // Half of the wait is over
+        assert_eq!(progress.fraction(2, Left::Wait(Duration::from_secs(2))), 8.5 / 9.0);
+        assert_eq!(progress.remaining(2, Left::Wait(Duration::from_secs(2))), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::iter::Peekable;
+
+use super::error::Result;
+use super::instructions::Instruction;
+
+type Script = Peekable<Box<dyn Iterator<Item = std::result::Result<Instruction, String>>>>;
+
+// -----------------------------------------------------------------------------
+//   - Script item -
+// -----------------------------------------------------------------------------
+/// What a script is made of: instructions, or the results of compiling them as they are taken.
+/// An error ends the script where it is.
+pub trait ScriptItem: 'static {
+    fn into_instruction(self) -> std::result::Result<Instruction, String>;
+}
+
+impl ScriptItem for Instruction {
+    fn into_instruction(self) -> std::result::Result<Instruction, String> {
+        Ok(self)
+    }
+}
+
+impl ScriptItem for Result<Instruction> {
+    fn into_instruction(self) -> std::result::Result<Instruction, String> {
+        self.map_err(|e| e.to_string())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Queue -
+// -----------------------------------------------------------------------------
+/// The instructions still to apply: anything queued while running goes in
+/// front of the rest of the script, which is only taken once it's needed.
+pub struct Queue {
+    front: VecDeque<Instruction>,
+    script: Script,
+    // Instructions taken from the script so far
+    taken: usize,
+    // A copy of them to start over with, unless the script is never started over
+    history: Option<Vec<Instruction>>,
+    // What ended the script early, until it's taken
+    error: Option<String>,
+}
+
+impl Queue {
+    pub fn new(instructions: impl IntoIterator<Item: ScriptItem, IntoIter: 'static>) -> Self {
+        Self {
+            front: VecDeque::new(),
+            script: script(instructions),
+            taken: 0,
+            history: Some(vec![]),
+            error: None,
+        }
+    }
+
+    pub fn push_front(&mut self, inst: Instruction) {
+        self.front.push_front(inst);
+    }
+
+    pub fn pop_front(&mut self) -> Option<Instruction> {
+        if let Some(inst) = self.front.pop_front() {
+            return Some(inst);
+        }
+        let inst = match self.script.next()? {
+            Ok(inst) => inst,
+            Err(e) => {
+                self.clear();
+                self.error = Some(e);
+                return None;
+            }
+        };
+        self.taken += 1;
+        if let Some(history) = &mut self.history {
+            history.push(inst.clone());
+        }
+        Some(inst)
+    }
+
+    pub fn is_empty(&mut self) -> bool {
+        self.front.is_empty() && self.script.peek().is_none()
+    }
+
+    /// Drop the next `count` instructions, or all of them if there are fewer
+    pub fn discard(&mut self, count: usize) {
+        for _ in 0..count {
+            if self.pop_front().is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Stop keeping a copy of what's taken from the script. Rewinding after this starts
+    /// over from the next instruction rather than the first one
+    pub fn forget_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Start over from the first instruction of the script, dropping anything queued in front of it
+    pub fn rewind(&mut self) {
+        let history = self.history.as_mut().map(std::mem::take).unwrap_or_default();
+        let rest = std::mem::replace(&mut self.script, script(std::iter::empty::<Instruction>()));
+        let script: Box<dyn Iterator<Item = _>> = Box::new(history.into_iter().map(Ok).chain(rest));
+        self.script = script.peekable();
+        self.front.clear();
+        self.taken = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.front.clear();
+        self.script = script(std::iter::empty::<Instruction>());
+    }
+
+    /// The error that ended the script, once
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    /// How many instructions have been taken from the script
+    pub fn position(&self) -> usize {
+        self.taken
+    }
+
+    /// How many instructions the script has in all, if that's known before taking them
+    pub fn total(&self) -> Option<usize> {
+        let (lower, upper) = self.script.size_hint();
+        (upper == Some(lower)).then_some(self.taken + lower)
+    }
+
+    /// How many instructions have been taken from the script, or `None` once there are none left
+    pub fn taken(&mut self) -> Option<usize> {
+        self.script.peek().map(|_| self.taken)
+    }
+}
+
+impl Iterator for Queue {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pop_front()
+    }
+}
+
+fn script(instructions: impl IntoIterator<Item: ScriptItem, IntoIter: 'static>) -> Script {
+    let instructions: Box<dyn Iterator<Item = _>> = Box::new(instructions.into_iter().map(ScriptItem::into_instruction));
+    instructions.peekable()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ui::error::Error;
+
+    fn title(s: &str) -> Instruction {
+        Instruction::SetTitle(s.into())
+    }
+
+    fn titles(queue: &mut Queue) -> Vec<String> {
+        queue
+            .map(|inst| match inst {
+                Instruction::SetTitle(title) => title,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn queued_before_the_script() {
+        let mut queue = Queue::new(vec![title("a"), title("b")]);
+        assert!(matches!(queue.pop_front(), Some(Instruction::SetTitle(t)) if t == "a"));
+        queue.push_front(title("x"));
+        queue.push_front(title("y"));
+        assert_eq!(queue.total(), Some(2));
+        assert_eq!(titles(&mut queue), ["y", "x", "b"]);
+        assert_eq!(queue.position(), 2);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn script_is_taken_lazily() {
+        let mut taken = 0;
+        let script = (0..).map(move |i| {
+            taken += 1;
+            assert!(taken <= 2, "took more than was needed");
+            title(&i.to_string())
+        });
+        let mut queue = Queue::new(script);
+        assert_eq!(queue.total(), None);
+        queue.push_front(title("x"));
+        queue.discard(2);
+        assert_eq!(queue.taken(), Some(1));
+        assert!(matches!(queue.pop_front(), Some(Instruction::SetTitle(t)) if t == "1"));
+    }
+
+    #[test]
+    fn rewind() {
+        let mut queue = Queue::new(vec![title("a"), title("b"), title("c")]);
+        queue.pop_front();
+        queue.push_front(title("x"));
+        queue.rewind();
+        assert_eq!(queue.total(), Some(3));
+        assert_eq!(titles(&mut queue), ["a", "b", "c"]);
+
+        queue.rewind();
+        assert_eq!(titles(&mut queue), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn forget_history() {
+        let mut queue = Queue::new(vec![title("a"), title("b"), title("c")]);
+        queue.forget_history();
+        queue.pop_front();
+        assert!(queue.history.is_none());
+        queue.rewind();
+        assert_eq!(titles(&mut queue), ["b", "c"]);
+    }
+
+    #[test]
+    fn taken_is_none_at_the_end() {
+        let mut queue = Queue::new(vec![title("a")]);
+        assert_eq!(queue.taken(), Some(0));
+        queue.pop_front();
+        assert_eq!(queue.taken(), None);
+
+        let mut queue = Queue::new(vec![title("a")]);
+        queue.clear();
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn errors_end_the_script() {
+        let script = vec![Ok(title("a")), Err(Error::FilePath("b.rs".into())), Ok(title("c"))];
+        let mut queue = Queue::new(script);
+        assert!(queue.pop_front().is_some());
+        assert_eq!(queue.take_error(), None);
+        assert!(queue.pop_front().is_none());
+        assert_eq!(queue.take_error().as_deref(), Some("file does not exist: b.rs"));
+        assert_eq!(queue.take_error(), None);
+        assert!(queue.is_empty());
+
+        // Starting over plays what was taken before the error
+        queue.rewind();
+        assert_eq!(titles(&mut queue), ["a"]);
+    }
+}
@@ -6,11 +6,16 @@ pub struct Random {
 
 impl Random {
     pub fn new() -> Self {
-        let state = SystemTime::now()
+        let seed = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("UNIX_EPOCH is always in the past")
             .as_millis() as u64;
-        Self { state }
+        Self::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self { state: seed.max(1) }
     }
 
     pub fn next(&mut self, max: u64) -> u64 {
@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use super::instructions::Instruction;
+
+// -----------------------------------------------------------------------------
+//   - Recurring task -
+// -----------------------------------------------------------------------------
+struct Recurring {
+    interval: Duration,
+    body: Vec<Instruction>,
+    next_due: Duration,
+}
+
+// -----------------------------------------------------------------------------
+//   - Schedule -
+// -----------------------------------------------------------------------------
+/// Instructions that run every N seconds alongside the main instructions.
+///
+/// To never starve the main instructions a task is only due once the
+/// instructions from the previous task, and at least one main instruction,
+/// have been consumed.
+pub struct Schedule {
+    tasks: Vec<Recurring>,
+    now: Duration,
+    // Main instructions taken when the last task was spliced in
+    gate: Option<usize>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self {
+            tasks: vec![],
+            now: Duration::ZERO,
+            gate: None,
+        }
+    }
+
+    pub fn add(&mut self, interval: Duration, body: Vec<Instruction>) {
+        self.tasks.push(Recurring {
+            interval,
+            body,
+            next_due: self.now + interval,
+        });
+    }
+
+    pub fn stop(&mut self) {
+        self.tasks.clear();
+        self.gate = None;
+    }
+
+    pub fn tick(&mut self, dt: Duration) {
+        self.now += dt;
+    }
+
+    /// Get the instructions of the next task that is due, given how many main
+    /// instructions have been taken, or `None` once there are none left.
+    pub fn poll(&mut self, taken: Option<usize>) -> Option<Vec<Instruction>> {
+        if let (Some(gate), Some(taken)) = (self.gate, taken)
+            && taken <= gate
+        {
+            return None;
+        }
+
+        let now = self.now;
+        let task = self
+            .tasks
+            .iter_mut()
+            .filter(|task| task.next_due <= now)
+            .min_by_key(|task| task.next_due)?;
+
+        // The next run is relative to now, so a late task doesn't
+        // try to catch up by running several times in a row
+        task.next_due = now + task.interval.max(Duration::from_millis(1));
+        self.gate = taken;
+        Some(task.body.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    fn popup(s: &str) -> Instruction {
+        Instruction::popup(s.into())
+    }
+
+    fn name(inst: &Instruction) -> &str {
+        match inst {
+            Instruction::Popup { message, .. } => message,
+            _ => unreachable!(),
+        }
+    }
+
+    // Simulate an executor that applies one instruction per step of `dt`,
+    // with due tasks spliced in front of the main instructions.
+    fn run(schedule: &mut Schedule, main: &mut VecDeque<Instruction>, steps: usize, dt: Duration) -> Vec<String> {
+        let mut spliced = VecDeque::new();
+        let mut taken = 0;
+        let mut executed = vec![];
+        for _ in 0..steps {
+            schedule.tick(dt);
+            if let Some(body) = schedule.poll((!main.is_empty()).then_some(taken)) {
+                body.into_iter().rev().for_each(|inst| spliced.push_front(inst));
+            }
+            let inst = spliced.pop_front().or_else(|| {
+                taken += 1;
+                main.pop_front()
+            });
+            if let Some(inst) = inst {
+                executed.push(name(&inst).to_string());
+            }
+        }
+        executed
+    }
+
+    #[test]
+    fn task_runs_when_due() {
+        let mut schedule = Schedule::new();
+        schedule.add(secs(2), vec![popup("tick")]);
+
+        let mut queue = (0..6).map(|i| popup(&i.to_string())).collect();
+        let executed = run(&mut schedule, &mut queue, 8, secs(1));
+        assert_eq!(executed, vec!["0", "tick", "1", "tick", "2", "tick", "3", "tick"]);
+    }
+
+    #[test]
+    fn task_never_starves_main_queue() {
+        let mut schedule = Schedule::new();
+        // Always due, with a body longer than a single step
+        schedule.add(Duration::ZERO, vec![popup("a"), popup("b"), popup("c")]);
+
+        let mut queue = (0..3).map(|i| popup(&i.to_string())).collect();
+        let executed = run(&mut schedule, &mut queue, 12, secs(1));
+        assert_eq!(
+            executed,
+            vec!["a", "b", "c", "0", "a", "b", "c", "1", "a", "b", "c", "2"]
+        );
+    }
+
+    #[test]
+    fn tasks_keep_running_after_main_queue() {
+        let mut schedule = Schedule::new();
+        schedule.add(secs(1), vec![popup("tick")]);
+
+        let mut queue = VecDeque::new();
+        let executed = run(&mut schedule, &mut queue, 3, secs(1));
+        assert_eq!(executed, vec!["tick", "tick", "tick"]);
+    }
+
+    #[test]
+    fn late_tasks_do_not_catch_up() {
+        let mut schedule = Schedule::new();
+        schedule.add(secs(1), vec![popup("tick")]);
+        schedule.tick(secs(10));
+
+        assert!(schedule.poll(None).is_some());
+        assert!(schedule.poll(None).is_none());
+    }
+
+    #[test]
+    fn earliest_task_first() {
+        let mut schedule = Schedule::new();
+        schedule.add(secs(3), vec![popup("slow")]);
+        schedule.add(secs(2), vec![popup("fast")]);
+        schedule.tick(secs(3));
+
+        let body = schedule.poll(None).unwrap();
+        assert_eq!(name(&body[0]), "fast");
+    }
+
+    #[test]
+    fn stop_clears_tasks() {
+        let mut schedule = Schedule::new();
+        schedule.add(secs(1), vec![popup("tick")]);
+        schedule.stop();
+        schedule.tick(secs(5));
+        assert!(schedule.poll(None).is_none());
+    }
+}
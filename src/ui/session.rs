@@ -0,0 +1,1991 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anathema::geometry::{Pos, Region, Size};
+use unicode_width::UnicodeWidthStr;
+
+use super::comments;
+use super::document::Document;
+use super::instructions::Instruction;
+use super::markers::{Markers, generate};
+use super::queue::{Queue, ScriptItem};
+use super::textbuffer::TextBuffer;
+use super::virtual_text::MAX_BELOW_LINES;
+use crate::parser::{Anchor, FindMiss};
+
+/// Pause between the lines of an animated `align` or `toggle_comment`
+pub const ALIGN_STEP: Duration = Duration::from_millis(60);
+
+// -----------------------------------------------------------------------------
+//   - Visual range -
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct VisualRange {
+    pub region: Region,
+    // Whole lines, deleted along with their line breaks
+    lines: bool,
+}
+
+impl VisualRange {
+    fn new(pos: Pos, size: Size) -> Self {
+        Self {
+            region: Region::from((pos, size)),
+            lines: false,
+        }
+    }
+
+    // As wide as the widest of the lines
+    fn lines(doc: &Document, rows: Range<usize>) -> Self {
+        let width = rows.clone().map(|row| doc.line_width(row)).max().unwrap_or(0);
+        Self {
+            region: Region::new(Pos::new(0, rows.start as i32), Pos::new(width, rows.end as i32)),
+            lines: true,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Register -
+// -----------------------------------------------------------------------------
+/// What `yank` copied, for `paste`
+#[derive(Debug, Default)]
+pub struct Register {
+    text: String,
+    // Whole lines, pasted on lines of their own
+    lines: bool,
+}
+
+// -----------------------------------------------------------------------------
+//   - Effect -
+// -----------------------------------------------------------------------------
+/// What the UI has to do after an instruction was applied to the session
+#[derive(Debug)]
+pub enum Effect {
+    /// Nothing, carry on with the next instruction
+    Next,
+    /// The document changed on this row
+    Changed(i32),
+    /// Wait before the next instruction
+    Wait(Duration),
+    Error(String),
+    /// Something looks off, but the script carries on
+    Warning(String),
+    /// The document was cleared
+    Cleared,
+    /// The command buffer was loaded and the editor cursor should be hidden
+    HideCursor,
+    ClearCommandBuffer,
+    /// Write the text to the path
+    Write(PathBuf, String),
+    /// Presentation, timing and audio: none of the session's business
+    Ui(Instruction),
+}
+
+// -----------------------------------------------------------------------------
+//   - Settings -
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct Settings {
+    pub line_pause: Duration,
+    pub extension: String,
+    pub theme: String,
+    pub command_clear_timeout: Duration,
+    pub autoindent: bool,
+    pub indent_unit: String,
+    // How many spaces a tab is worth when dedenting
+    pub tab_width: usize,
+    pub find_miss: FindMiss,
+    pub strict_markers: bool,
+    pub comment_leader: Option<String>,
+    // The leader for extensions that have none of their own
+    pub comment_prefix: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            line_pause: Duration::ZERO,
+            extension: "txt".into(),
+            theme: String::from("togglebit"),
+            command_clear_timeout: Duration::from_secs(1),
+            autoindent: false,
+            indent_unit: String::from("    "),
+            tab_width: 4,
+            find_miss: FindMiss::Stay,
+            strict_markers: false,
+            comment_leader: None,
+            comment_prefix: None,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Session -
+// -----------------------------------------------------------------------------
+/// The document, the cursor and the queued instructions,
+/// without any of the UI
+pub struct Session {
+    pub doc: Document,
+    pub cursor: Pos,
+    // Where the last move up or down left the cursor, and the column it was going for
+    // before a shorter line pulled it in
+    column: Option<(Pos, i32)>,
+    pub offset: Pos,
+    pub selection: Option<VisualRange>,
+    pub instructions: Queue,
+    pub type_buffer: TextBuffer,
+    pub type_command_buffer: TextBuffer,
+    // Backspaces still to do, one per frame
+    pub backspaces: usize,
+    // Kept when the document is cleared
+    pub register: Register,
+    // The text, markers and cursor as of `snapshot <name>`
+    snapshots: HashMap<String, (String, Markers, Pos)>,
+    pub settings: Settings,
+}
+
+impl Session {
+    pub fn new(instructions: impl IntoIterator<Item: ScriptItem, IntoIter: 'static>) -> Self {
+        Self {
+            doc: Document::new(String::new()),
+            cursor: Pos::ZERO,
+            column: None,
+            offset: Pos::ZERO,
+            selection: None,
+            instructions: Queue::new(instructions),
+            type_buffer: TextBuffer::new(),
+            type_command_buffer: TextBuffer::new(),
+            backspaces: 0,
+            register: Register::default(),
+            snapshots: HashMap::new(),
+            settings: Settings::default(),
+        }
+    }
+
+    /// Type the next grapheme from the type buffer.
+    /// Returns the grapheme and the pause owed after it.
+    pub fn type_next(&mut self) -> Option<(String, Duration)> {
+        let s = self.type_buffer.next()?;
+        self.doc.insert_str(self.cursor, s);
+
+        let mut pause = Duration::ZERO;
+        if s == "\n" {
+            self.cursor.x = 0;
+            self.cursor.y += 1;
+            pause = self.settings.line_pause;
+        } else {
+            self.cursor.x += s.width() as i32;
+        }
+
+        Some((s.to_string(), pause))
+    }
+
+    /// Remove the character before the cursor, or the line break before the line
+    /// at the start of a line. Returns false if there was nothing left to backspace.
+    pub fn backspace_next(&mut self) -> bool {
+        if self.backspaces == 0 {
+            return false;
+        }
+        self.backspaces -= 1;
+
+        if self.cursor.x > 0 {
+            self.cursor.x -= self.doc.backspace(self.cursor, 1);
+            return true;
+        }
+
+        // Nothing before the start of the document
+        let Some(row) = (self.cursor.y as usize).checked_sub(1) else {
+            self.backspaces = 0;
+            return false;
+        };
+        self.cursor = Pos::new(self.doc.remove_line_break(row) as i32, row as i32);
+        true
+    }
+
+    // Delete the selected text and put the cursor where it started.
+    // Returns false if nothing was selected.
+    fn delete_selection(&mut self) -> bool {
+        let Some(range) = self.selection.take() else { return false };
+        self.cursor = range.region.from;
+        match range.lines {
+            true => self.doc.delete_lines(range.region.from.y as usize..range.region.to.y as usize),
+            false => self.doc.delete(range.region),
+        }
+        true
+    }
+
+    // Whole lines go below or above the cursor's line, the rest goes in at the cursor
+    fn paste(&mut self, above: bool) {
+        let Register { text, lines } = &self.register;
+        if text.is_empty() {
+            return;
+        }
+        if !*lines {
+            self.doc.insert_str(self.cursor, text);
+            return;
+        }
+
+        let row = match above {
+            true => self.cursor.y as usize,
+            false => self.cursor.y as usize + 1,
+        };
+        match row < self.doc.line_count() {
+            true => self.doc.insert_str(Pos::new(0, row as i32), text),
+            // Below the last line, which has no line break to paste after
+            false => {
+                let last = Pos::new(self.doc.line_width(row - 1), row as i32 - 1);
+                self.doc.split_line(last, false);
+                self.doc.insert_str(Pos::new(0, row as i32), text.strip_suffix('\n').unwrap_or(text));
+            }
+        }
+        self.cursor = Pos::new(0, row as i32);
+    }
+
+    // The number of lines from the cursor, or else the selected lines or the cursor's line
+    fn indent_rows(&self, lines: Option<usize>) -> Range<usize> {
+        let row = self.cursor.y as usize;
+        match (lines, &self.selection) {
+            (Some(lines), _) => row..row + lines,
+            (None, Some(range)) => range.region.from.y as usize..range.region.to.y as usize,
+            (None, None) => row..row + 1,
+        }
+    }
+
+    // Keep the cursor and a selection of characters on the same characters when their lines
+    // change in front of them, and a selection of whole lines as wide as the lines are now
+    fn keep_cursor_on_text(&mut self, f: impl FnOnce(&mut Document)) {
+        let region = self.selection.as_ref().filter(|range| !range.lines).map(|range| range.region);
+        // The rows of the cursor, and of the first and last line of the selection
+        let rows = [Some(self.cursor.y), region.map(|r| r.from.y), region.map(|r| r.to.y - 1)];
+        let width = |doc: &Document, row: Option<i32>| row.map_or(0, |row| doc.line_width(row.max(0) as usize));
+        let before = rows.map(|row| width(&self.doc, row));
+        f(&mut self.doc);
+        let [cursor, from, to] = [0, 1, 2].map(|i| width(&self.doc, rows[i]) - before[i]);
+
+        self.cursor.x = (self.cursor.x + cursor).max(0);
+        if let Some(range) = self.selection.as_mut().filter(|range| !range.lines) {
+            range.region.from.x = (range.region.from.x + from).max(0);
+            range.region.to.x = (range.region.to.x + to).max(0);
+        }
+        self.refresh_line_selection();
+    }
+
+    fn refresh_line_selection(&mut self) {
+        if let Some(range) = self.selection.take_if(|range| range.lines) {
+            let rows = range.region.from.y as usize..range.region.to.y as usize;
+            self.selection = Some(VisualRange::lines(&self.doc, rows));
+        }
+    }
+
+    // Keep the cursor on the same character when the leader goes in or out in front of it,
+    // and a selection of whole lines as wide as the lines are now
+    fn comment_line(&mut self, row: usize, leader: &str, comment: bool) {
+        let Some((col, delta)) = self.doc.comment_line(row, leader, comment) else { return };
+        if self.cursor.y == row as i32 && self.cursor.x >= col {
+            self.cursor.x = (self.cursor.x + delta).max(col);
+        }
+        self.refresh_line_selection();
+    }
+
+    fn load_type_buffer(&mut self, content: String) {
+        // Make markers and all that what what
+        let (content, markers) = generate(content);
+        self.type_buffer.push(content);
+
+        if let Some(markers) = markers {
+            self.instructions.push_front(Instruction::AddMarkers {
+                row: self.cursor.y as usize,
+                markers,
+            });
+        }
+    }
+
+    pub fn apply(&mut self, instruction: Instruction) -> Effect {
+        // Moving up or down keeps going for the same column, as long as nothing else moved the cursor
+        let column = match instruction {
+            Instruction::Jump(Pos { x: 0, y }) if y != 0 => match self.column {
+                Some((at, x)) if at == self.cursor => Some(x),
+                _ => Some(self.cursor.x),
+            },
+            _ => None,
+        };
+
+        let effect = self.apply_instruction(instruction);
+        if let Some(x) = column {
+            self.cursor.x = x;
+        }
+        // Whatever the instruction did, the cursor ends up in the document
+        self.cursor = self.doc.clamp(self.cursor);
+        self.column = column.map(|x| (self.cursor, x));
+        effect
+    }
+
+    fn apply_instruction(&mut self, instruction: Instruction) -> Effect {
+        match instruction {
+            Instruction::LoadCommandBuffer(content) => {
+                self.type_command_buffer.push(content);
+                return Effect::HideCursor;
+            }
+            Instruction::LoadTypeBuffer(content) => {
+                // Typing over a selection replaces it
+                let replaced = self.delete_selection();
+                self.load_type_buffer(content);
+                if replaced {
+                    return Effect::Changed(self.cursor.y);
+                }
+            }
+            Instruction::LoadTypeBufferKeepSelection(content) => self.load_type_buffer(content),
+            Instruction::LoadTypeBufferRaw(content) => self.type_buffer.push(content),
+            Instruction::Insert(content) => {
+                let (content, markers) = generate(content);
+                // Inserting over a selection replaces it, otherwise the content goes at the start of the line
+                if !self.delete_selection() {
+                    self.cursor.x = 0;
+                }
+                self.doc.insert_str(self.cursor, &content);
+                if let Some(markers) = markers {
+                    self.instructions.push_front(Instruction::AddMarkers {
+                        row: self.cursor.y as usize,
+                        markers,
+                    });
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::AddMarkers { row, markers } => {
+                let collisions = self.doc.add_markers(row, markers);
+                if !collisions.is_empty() {
+                    let msg = collisions.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+                    return match self.settings.strict_markers {
+                        true => Effect::Error(msg),
+                        false => Effect::Warning(msg),
+                    };
+                }
+            }
+            Instruction::StrictMarkers(strict) => self.settings.strict_markers = strict,
+            Instruction::SetMarker(name) => self.doc.markers.set(name, self.cursor.y as usize),
+            Instruction::RemoveMarker(name) => {
+                if !self.doc.markers.remove(&name) {
+                    return Effect::Error(format!("marker \"{name}\" does not exist"));
+                }
+            }
+            Instruction::VirtualText { anchor, text, below } => {
+                let row = match anchor {
+                    Anchor::Row(row) => row,
+                    Anchor::Marker(name) => match self.doc.lookup_marker(&name) {
+                        Some(marker) => marker.row,
+                        None => return Effect::Error(format!("marker \"{name}\" does not exist")),
+                    },
+                };
+                if let Err(room) = self.doc.virtual_text.add(row, text, below) {
+                    return Effect::Error(format!(
+                        "virtual text can add at most {MAX_BELOW_LINES} lines below, there is room for {room} more"
+                    ));
+                }
+            }
+            Instruction::ClearVirtualText(row) => self.doc.virtual_text.clear(row),
+            Instruction::Splash(splash) => {
+                self.instructions.push_front(Instruction::HideSplash);
+                self.instructions.push_front(Instruction::Wait(splash.hold));
+                return Effect::Ui(Instruction::Splash(splash));
+            }
+            Instruction::Jump(pos) => self.cursor += pos,
+            Instruction::JumpAbsolute(pos) => self.cursor = self.doc.clamp(pos),
+            Instruction::JumpStart => {
+                self.cursor = Pos::ZERO;
+                self.offset.x = 0;
+            }
+            Instruction::JumpEnd => {
+                self.cursor = Pos::new(0, self.doc.last_non_empty_line() as i32);
+                self.offset.x = 0;
+            }
+            Instruction::Scroll(rows) => {
+                // Scrolling down is a more negative offset, and goes as far as the last line at the top
+                let last = self.doc.virtual_text.screen_row(self.doc.line_count().saturating_sub(1)) as i32;
+                self.offset.y = (self.offset.y - rows).clamp(-last, 0);
+            }
+            Instruction::JumpToMarker { name, offset } => {
+                let Some(marker) = self.doc.lookup_marker(&name) else {
+                    return Effect::Error(format!("marker \"{name}\" does not exist"));
+                };
+                self.cursor = Pos::from(marker) + offset;
+            }
+            Instruction::Select(size) if size == Size::ZERO => {}
+            Instruction::Select(size) => {
+                let visual_range = VisualRange::new(self.cursor, size);
+                self.cursor = visual_range.region.to - Pos::new(1, 1);
+                self.selection = Some(visual_range);
+            }
+            Instruction::SelectEol => {
+                // Nothing to select at the end of the line
+                let width = self.doc.line_width(self.cursor.y as usize) - self.cursor.x;
+                if width > 0 {
+                    return self.apply_instruction(Instruction::Select(Size::new(width as u16, 1)));
+                }
+            }
+            Instruction::SelectMarkers(start, end) => {
+                let row = |name: &str| match self.doc.lookup_marker(name) {
+                    Some(marker) => Ok(marker.row),
+                    None => Err(Effect::Error(format!("marker \"{name}\" does not exist"))),
+                };
+                let rows = match (row(&start), row(&end)) {
+                    (Ok(from), Ok(to)) if to < from => {
+                        return Effect::Error(format!("marker \"{end}\" comes before marker \"{start}\""));
+                    }
+                    (Ok(from), Ok(to)) => from..to,
+                    (Err(effect), _) | (_, Err(effect)) => return effect,
+                };
+                // No lines between the markers
+                if !rows.is_empty() {
+                    let visual_range = VisualRange::lines(&self.doc, rows);
+                    self.cursor = Pos::new(0, visual_range.region.to.y - 1);
+                    self.selection = Some(visual_range);
+                }
+            }
+            Instruction::Delete => {
+                if !self.delete_selection() {
+                    self.doc.delete(Region::from((self.cursor, Size::new(1, 1))));
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::DeleteLine(count) => {
+                let row = self.cursor.y as usize;
+                self.doc.delete_lines(row..(row + count).min(self.doc.line_count()));
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::DuplicateLine(count) => {
+                self.doc.duplicate_line(self.cursor.y as usize, count);
+                self.cursor.y += count as i32;
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::MoveLine { up, count } => {
+                let row = self.cursor.y as usize;
+                // As far as the line can go
+                let count = match up {
+                    true => count.min(row),
+                    false => count.min(self.doc.line_count().saturating_sub(row + 1)),
+                };
+                if count > 1 {
+                    for step in (0..count).rev() {
+                        self.instructions.push_front(Instruction::MoveLine { up, count: 1 });
+                        if step > 0 {
+                            self.instructions.push_front(Instruction::Wait(ALIGN_STEP));
+                        }
+                    }
+                    return Effect::Next;
+                }
+                if count == 1 {
+                    match up {
+                        true => {
+                            self.doc.swap_lines(row - 1);
+                            self.cursor.y -= 1;
+                        }
+                        false => {
+                            self.doc.swap_lines(row);
+                            self.cursor.y += 1;
+                        }
+                    }
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::DeleteWord => {
+                if let Some(x) = self.doc.delete_word(self.cursor) {
+                    self.cursor.x = x;
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::Snapshot(name) => {
+                let snapshot = (self.doc.text().to_string(), self.doc.markers.clone(), self.cursor);
+                self.snapshots.insert(name, snapshot);
+            }
+            Instruction::Restore(name) => {
+                let Some((text, markers, cursor)) = self.snapshots.get(&name).cloned() else {
+                    return Effect::Error(format!("no snapshot named \"{name}\""));
+                };
+                self.doc.restore(text, markers);
+                self.selection = None;
+                self.cursor = cursor;
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::Yank => {
+                let row = self.cursor.y as usize;
+                self.register = match self.selection.take() {
+                    Some(range) => {
+                        self.cursor = range.region.from;
+                        let text = match range.lines {
+                            true => self.doc.copy_lines(range.region.from.y as usize..range.region.to.y as usize),
+                            false => self.doc.copy(range.region),
+                        };
+                        Register { text, lines: range.lines }
+                    }
+                    None => Register {
+                        text: self.doc.copy_lines(row..row + 1),
+                        lines: true,
+                    },
+                };
+            }
+            Instruction::Paste { above } => {
+                self.paste(above);
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::ClearEol => {
+                // Only moves when the cursor was in the middle of a wide character
+                if let Some(x) = self.doc.clear_eol(self.cursor) {
+                    self.cursor.x = self.cursor.x.min(x);
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::ReplaceAll { needle, replacement } => {
+                self.doc.replace_all(&needle, &replacement);
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::Join(count) => {
+                for _ in 0..count {
+                    let Some(x) = self.doc.join_line(self.cursor.y as usize) else { break };
+                    self.cursor.x = x as i32;
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::SplitLine => {
+                let row = self.cursor.y;
+                let x = self.doc.split_line(self.cursor, self.settings.autoindent);
+                self.cursor = Pos::new(x as i32, self.cursor.y + 1);
+                return Effect::Changed(row);
+            }
+            Instruction::Align { c, lines, instant } => {
+                let pads = self.doc.align(self.cursor.y as usize, lines, c);
+                match instant {
+                    true => pads.into_iter().for_each(|(pos, width)| self.pad(pos, width)),
+                    false => {
+                        for (i, (pos, width)) in pads.into_iter().enumerate().rev() {
+                            self.instructions.push_front(Instruction::Pad { pos, width });
+                            if i > 0 {
+                                self.instructions.push_front(Instruction::Wait(ALIGN_STEP));
+                            }
+                        }
+                        return Effect::Next;
+                    }
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::Pad { pos, width } => {
+                self.pad(pos, width);
+                return Effect::Changed(pos.y);
+            }
+            Instruction::Backspace(count) => self.backspaces = count,
+            Instruction::AutoIndent(autoindent) => self.settings.autoindent = autoindent,
+            Instruction::Indent { lines, levels } => {
+                let rows = self.indent_rows(lines);
+                let row = rows.start as i32;
+                let unit = self.settings.indent_unit.clone();
+                self.keep_cursor_on_text(|doc| doc.indent(rows, &unit, levels));
+                return Effect::Changed(row);
+            }
+            Instruction::Dedent { lines, levels } => {
+                let rows = self.indent_rows(lines);
+                let row = rows.start as i32;
+                let (unit, tab_width) = (self.settings.indent_unit.clone(), self.settings.tab_width);
+                self.keep_cursor_on_text(|doc| doc.dedent(rows, &unit, levels, tab_width));
+                return Effect::Changed(row);
+            }
+            Instruction::IndentUnit(unit) => self.settings.indent_unit = unit,
+            Instruction::TabWidth(width) => self.settings.tab_width = width,
+            Instruction::ToggleComment { lines, instant } => {
+                // The extension can change during the script, so the leader is looked up now
+                let leader = match (&self.settings.comment_leader, comments::leader(&self.settings.extension)) {
+                    (Some(leader), _) => leader.clone(),
+                    (None, Some(leader)) => leader.to_string(),
+                    (None, None) => self.settings.comment_prefix.as_deref().unwrap_or(comments::FALLBACK).to_string(),
+                };
+                let rows = self.indent_rows(lines);
+                let (row, last) = (rows.start, self.doc.line_count().min(rows.end));
+                let comment = !self.doc.is_commented(row, last.saturating_sub(row), &leader);
+                match instant {
+                    true => (row..last).for_each(|row| self.comment_line(row, &leader, comment)),
+                    false => {
+                        for row in (row..last).rev() {
+                            self.instructions.push_front(Instruction::CommentLine {
+                                row,
+                                leader: leader.clone(),
+                                comment,
+                            });
+                            if row > rows.start {
+                                self.instructions.push_front(Instruction::Wait(ALIGN_STEP));
+                            }
+                        }
+                        return Effect::Next;
+                    }
+                }
+                return Effect::Changed(self.cursor.y);
+            }
+            Instruction::CommentLine { row, leader, comment } => {
+                self.comment_line(row, &leader, comment);
+                return Effect::Changed(row as i32);
+            }
+            Instruction::CommentLeader(leader) => self.settings.comment_leader = Some(leader),
+            Instruction::CommentPrefix(prefix) => self.settings.comment_prefix = Some(prefix),
+            Instruction::Wait(duration) => return Effect::Wait(duration),
+            Instruction::FindInCurrentLine { needle, .. } if needle.is_empty() => {}
+            Instruction::FindInCurrentLine {
+                needle,
+                end_of_word,
+                reverse,
+                count,
+                dependents,
+            } => {
+                let x = match reverse {
+                    false => self.doc.find(self.cursor, &needle, count),
+                    true => self.doc.rfind(self.cursor, &needle, count),
+                };
+                let Some(x) = x else { return self.find_miss(&needle, dependents) };
+                self.cursor.x = x as i32;
+                if end_of_word {
+                    self.cursor.x += needle.width() as i32 - 1;
+                }
+            }
+            Instruction::FindMiss(policy) => self.settings.find_miss = policy,
+            Instruction::LinePause(duration) => self.settings.line_pause = duration,
+            Instruction::SetExtension(ext) => self.settings.extension = ext,
+            Instruction::SetTheme(theme) => self.settings.theme = theme,
+            Instruction::Clear => {
+                self.doc.clear();
+                self.offset = Pos::ZERO;
+                self.cursor = Pos::ZERO;
+                return Effect::Cleared;
+            }
+            Instruction::WriteBuffer(path) => return Effect::Write(path, self.doc.text().to_string()),
+            Instruction::ClearCommandBuffer => return Effect::ClearCommandBuffer,
+            Instruction::CommandClearTimeout(duration) => self.settings.command_clear_timeout = duration,
+            Instruction::ClearCommandWait => self
+                .instructions
+                .push_front(Instruction::Wait(self.settings.command_clear_timeout)),
+            inst @ (Instruction::SetTitle(_)
+            | Instruction::Speed(_)
+            | Instruction::CommandSpeed(_)
+            | Instruction::SetJitter(_)
+            | Instruction::JitterProfile(_)
+            | Instruction::BurstPause { .. }
+            | Instruction::AutoTypo { .. }
+            | Instruction::ShowLineNumbers(_)
+            | Instruction::HScroll(_)
+            | Instruction::BreakLigatures(_)
+            | Instruction::AddLigature(_)
+            | Instruction::ColorColumn(_)
+            | Instruction::Margins(_)
+            | Instruction::ScrollOff { .. }
+            | Instruction::Wrap(_)
+            | Instruction::Every { .. }
+            | Instruction::EveryStop
+            | Instruction::Budget { .. }
+            | Instruction::BudgetEnd
+            | Instruction::Scene(_)
+            | Instruction::Transition(..)
+            | Instruction::ResetCounters
+            | Instruction::Loop { .. }
+            | Instruction::Exit
+            | Instruction::ExitOnEnd(_)
+            | Instruction::LoadAudio(_)
+            | Instruction::AudioClasses(_)
+            | Instruction::AudioOffset(_)
+            | Instruction::Metronome(_)
+            | Instruction::KeyOverlay(_)
+            | Instruction::KeyOverlayHold(_)
+            | Instruction::Hyperlinks(_)
+            | Instruction::Link { .. }
+            | Instruction::Duck { .. }
+            | Instruction::Popup { .. }
+            | Instruction::PopupAt(_)
+            | Instruction::PopupScroll(_)
+            | Instruction::PopupAutoscroll(_)
+            | Instruction::Menu { .. }
+            | Instruction::ClosePopup
+            | Instruction::HideSplash
+            | Instruction::WaitRange(..)
+            | Instruction::SetVariable(..)) => return Effect::Ui(inst),
+        }
+
+        Effect::Next
+    }
+
+    // `find` came up empty
+    fn find_miss(&mut self, needle: &str, dependents: Option<usize>) -> Effect {
+        match self.settings.find_miss {
+            FindMiss::Stay => {}
+            FindMiss::Error => return Effect::Error(format!("\"{needle}\" not found on line {}", self.cursor.y + 1)),
+            FindMiss::Skip(count) => {
+                self.instructions.discard(dependents.unwrap_or(count));
+            }
+        }
+        Effect::Next
+    }
+
+    // Insert spaces, pushing the cursor along if it's at or after the position
+    fn pad(&mut self, pos: Pos, width: usize) {
+        self.doc.insert_str(pos, " ".repeat(width));
+        if self.cursor.y == pos.y && self.cursor.x >= pos.x {
+            self.cursor.x += width as i32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{HScroll, JitterProfile, LoopCount, PopupPosition, TransitionKind, Variable};
+    use crate::ui::margins::Margins;
+    use crate::ui::markers::Markers;
+
+    trait OptVisualRange {
+        fn contains(&self, pos: Pos) -> bool;
+    }
+
+    impl OptVisualRange for Option<VisualRange> {
+        fn contains(&self, pos: Pos) -> bool {
+            match self {
+                None => false,
+                Some(range) => range.region.contains(pos),
+            }
+        }
+    }
+
+    fn session(text: &str) -> Session {
+        let mut session = Session::new(Vec::<Instruction>::new());
+        session.doc = Document::new(text);
+        session
+    }
+
+    fn type_all(session: &mut Session) -> Vec<Duration> {
+        std::iter::from_fn(|| session.type_next()).map(|(_, pause)| pause).collect()
+    }
+
+    // Apply the instruction, then type and apply whatever it queued, in the same order as the editor
+    fn apply(session: &mut Session, inst: Instruction) -> Effect {
+        let effect = session.apply(inst);
+        loop {
+            type_all(session);
+            while session.backspace_next() {}
+            let Some(inst) = session.instructions.pop_front() else { break };
+            session.apply(inst);
+        }
+        effect
+    }
+
+    #[test]
+    fn load_command_buffer() {
+        let mut session = session("");
+        let effect = apply(&mut session, Instruction::LoadCommandBuffer(":w".into()));
+        assert!(matches!(effect, Effect::HideCursor));
+        assert_eq!(session.type_command_buffer.next(), Some(":"));
+        assert_eq!(session.type_command_buffer.next(), Some("w"));
+        assert_eq!(session.doc.text(), "");
+    }
+
+    #[test]
+    fn load_type_buffer() {
+        let mut session = session("");
+        session.apply(Instruction::LoadTypeBuffer("ab\nc".into()));
+        assert_eq!(session.doc.text(), "");
+        type_all(&mut session);
+        assert_eq!(session.doc.text(), "ab\nc");
+        assert_eq!(session.cursor, Pos::new(1, 1));
+    }
+
+    #[test]
+    fn line_pause() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LinePause(Duration::from_millis(200)));
+        session.apply(Instruction::LoadTypeBuffer("a\nb".into()));
+        let pauses = type_all(&mut session);
+        assert_eq!(pauses, [Duration::ZERO, Duration::from_millis(200), Duration::ZERO]);
+    }
+
+    fn to_marker(name: &str, offset: Pos) -> Instruction {
+        Instruction::JumpToMarker {
+            name: name.into(),
+            offset,
+        }
+    }
+
+    #[test]
+    fn markers() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("a\n// @here\nb\n".into()));
+        assert_eq!(session.doc.text(), "a\nb\n");
+
+        let effect = apply(&mut session, to_marker("here", Pos::ZERO));
+        assert!(matches!(effect, Effect::Next));
+        assert_eq!(session.cursor, Pos::new(0, 1));
+
+        let effect = apply(&mut session, to_marker("nope", Pos::ZERO));
+        assert!(matches!(effect, Effect::Error(msg) if msg == "marker \"nope\" does not exist"));
+    }
+
+    #[test]
+    fn select_and_delete_between_markers() {
+        let select = |start: &str, end: &str| Instruction::SelectMarkers(start.into(), end.into());
+        let text = "fn a() {}\n// @start\nfn b() {\n    c();\n}\n// @end\nfn d() {}\n// @last\n";
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer(text.into()));
+
+        apply(&mut session, select("start", "end"));
+        let region = session.selection.as_ref().unwrap().region;
+        assert_eq!(region, Region::new(Pos::new(0, 1), Pos::new(8, 4)));
+        assert_eq!(session.cursor, Pos::new(0, 3));
+
+        apply(&mut session, Instruction::Delete);
+        assert_eq!(session.doc.text(), "fn a() {}\nfn d() {}\n");
+        assert_eq!(session.cursor, Pos::new(0, 1));
+        assert_eq!(session.doc.lookup_marker("end").unwrap().row, 1);
+        assert_eq!(session.doc.lookup_marker("last").unwrap().row, 2);
+
+        // Up to the end of the document
+        apply(&mut session, select("end", "last"));
+        apply(&mut session, Instruction::Delete);
+        assert_eq!(session.doc.text(), "fn a() {}\n");
+
+        // Nothing between the markers
+        apply(&mut session, select("start", "end"));
+        assert!(session.selection.is_none());
+    }
+
+    #[test]
+    fn delete_lines() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("a\n// @m\nbb\ncc\ndd\n// @n\ne".into()));
+        session.cursor = Pos::new(1, 1);
+
+        apply(&mut session, Instruction::DeleteLine(1));
+        assert_eq!(session.doc.text(), "a\ncc\ndd\ne");
+        assert_eq!(session.cursor, Pos::new(1, 1));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 1);
+        assert_eq!(session.doc.lookup_marker("n").unwrap().row, 3);
+
+        apply(&mut session, Instruction::DeleteLine(2));
+        assert_eq!(session.doc.text(), "a\ne");
+        assert_eq!(session.doc.lookup_marker("n").unwrap().row, 1);
+
+        // More lines than there are, and the cursor stays in the document
+        apply(&mut session, Instruction::DeleteLine(5));
+        assert_eq!(session.doc.text(), "a");
+        assert_eq!(session.cursor, Pos::new(1, 0));
+    }
+
+    #[test]
+    fn duplicate_line() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("let a = 1;\nb\n// @m\nc".into()));
+        session.cursor = Pos::new(4, 0);
+
+        apply(&mut session, Instruction::DuplicateLine(1));
+        assert_eq!(session.doc.text(), "let a = 1;\nlet a = 1;\nb\nc");
+        assert_eq!(session.cursor, Pos::new(4, 1));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 3);
+
+        apply(&mut session, Instruction::DuplicateLine(2));
+        assert_eq!(session.doc.text(), "let a = 1;\nlet a = 1;\nlet a = 1;\nlet a = 1;\nb\nc");
+        assert_eq!(session.cursor, Pos::new(4, 3));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 5);
+
+        // The last line has no line break of its own
+        session.cursor = Pos::new(0, 5);
+        apply(&mut session, Instruction::DuplicateLine(1));
+        assert_eq!(session.doc.text(), "let a = 1;\nlet a = 1;\nlet a = 1;\nlet a = 1;\nb\nc\nc");
+        assert_eq!(session.cursor, Pos::new(0, 6));
+    }
+
+    #[test]
+    fn move_lines() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("a\n// @m\nb\nc\n// @n\nd".into()));
+        session.cursor = Pos::new(1, 1);
+
+        apply(&mut session, Instruction::MoveLine { up: false, count: 1 });
+        assert_eq!(session.doc.text(), "a\nc\nb\nd");
+        assert_eq!(session.cursor, Pos::new(1, 2));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 2);
+        assert_eq!(session.doc.lookup_marker("n").unwrap().row, 3);
+
+        // More steps than there are lines below, a step at a time
+        session.cursor = Pos::ZERO;
+        session.apply(Instruction::MoveLine { up: false, count: 5 });
+        let queued = session.instructions.by_ref().collect::<Vec<_>>();
+        assert!(matches!(queued[..], [Instruction::MoveLine { count: 1, .. }, ..]));
+        assert_eq!(queued.len(), 5);
+        for inst in queued {
+            apply(&mut session, inst);
+        }
+        assert_eq!(session.doc.text(), "c\nb\nd\na");
+        assert_eq!(session.cursor, Pos::new(0, 3));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 1);
+        assert_eq!(session.doc.lookup_marker("n").unwrap().row, 2);
+
+        // Clamped at the end and the start
+        apply(&mut session, Instruction::MoveLine { up: false, count: 1 });
+        assert_eq!(session.doc.text(), "c\nb\nd\na");
+        session.cursor = Pos::ZERO;
+        apply(&mut session, Instruction::MoveLine { up: true, count: 2 });
+        assert_eq!(session.doc.text(), "c\nb\nd\na");
+        assert_eq!(session.cursor, Pos::ZERO);
+    }
+
+    #[test]
+    fn backspace_joins_lines() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("a界\n  b\n// @m\nc".into()));
+        session.cursor = Pos::new(1, 1);
+
+        apply(&mut session, Instruction::Backspace(3));
+        assert_eq!(session.doc.text(), "a b\nc");
+        assert_eq!(session.cursor, Pos::new(1, 0));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 1);
+
+        // Nothing before the start of the document
+        apply(&mut session, Instruction::Backspace(5));
+        assert_eq!(session.doc.text(), " b\nc");
+        assert_eq!(session.cursor, Pos::ZERO);
+        assert_eq!(session.backspaces, 0);
+    }
+
+    #[test]
+    fn delete_word_under_the_cursor() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("// @m\nlet old_name = 1;\n".into()));
+        session.cursor = Pos::new(7, 0);
+
+        apply(&mut session, Instruction::DeleteWord);
+        assert_eq!(session.doc.text(), "let  = 1;\n");
+        assert_eq!(session.cursor, Pos::new(4, 0));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 0);
+
+        // On whitespace
+        apply(&mut session, Instruction::DeleteWord);
+        assert_eq!(session.doc.text(), "let  = 1;\n");
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("// @m\nfn a() {}\n".into()));
+        apply(&mut session, Instruction::Snapshot("before".into()));
+
+        apply(&mut session, Instruction::JumpStart);
+        apply(&mut session, Instruction::LoadTypeBuffer("// @n\nuse std::fmt;\n".into()));
+        apply(&mut session, Instruction::SetMarker("o".into()));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 1);
+
+        let effect = apply(&mut session, Instruction::Restore("before".into()));
+        assert!(matches!(effect, Effect::Changed(1)));
+        assert_eq!(session.doc.text(), "fn a() {}\n");
+        assert_eq!(session.cursor, Pos::new(0, 1));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 0);
+        assert!(session.doc.lookup_marker("n").is_none());
+        assert!(session.doc.lookup_marker("o").is_none());
+
+        // A snapshot can be restored more than once, and through a clear
+        apply(&mut session, Instruction::Clear);
+        apply(&mut session, Instruction::Restore("before".into()));
+        assert_eq!(session.doc.text(), "fn a() {}\n");
+
+        let effect = apply(&mut session, Instruction::Restore("after".into()));
+        assert!(matches!(effect, Effect::Error(msg) if msg == "no snapshot named \"after\""));
+    }
+
+    #[test]
+    fn yank_and_paste_lines() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("fn a() {}\n// @m\nb\nc".into()));
+
+        // The cursor's line, below and above
+        session.cursor = Pos::new(3, 0);
+        apply(&mut session, Instruction::Yank);
+        apply(&mut session, Instruction::Paste { above: false });
+        assert_eq!(session.doc.text(), "fn a() {}\nfn a() {}\nb\nc");
+        assert_eq!(session.cursor, Pos::new(0, 1));
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 2);
+        apply(&mut session, Instruction::Paste { above: true });
+        assert_eq!(session.doc.text(), "fn a() {}\nfn a() {}\nfn a() {}\nb\nc");
+        assert_eq!(session.doc.lookup_marker("m").unwrap().row, 3);
+
+        // Below the last line, which has no line break
+        session.cursor = Pos::new(0, 4);
+        apply(&mut session, Instruction::Paste { above: false });
+        assert_eq!(session.doc.text(), "fn a() {}\nfn a() {}\nfn a() {}\nb\nc\nfn a() {}");
+        assert_eq!(session.cursor, Pos::new(0, 5));
+
+        // The register is kept through a clear
+        apply(&mut session, Instruction::Clear);
+        apply(&mut session, Instruction::Paste { above: true });
+        assert_eq!(session.doc.text(), "fn a() {}\n");
+    }
+
+    #[test]
+    fn yank_and_paste_a_selection() {
+        let mut session = session("");
+        apply(&mut session, Instruction::Paste { above: false });
+        assert_eq!(session.doc.text(), "");
+
+        apply(&mut session, Instruction::LoadTypeBuffer("let a = 1;\n".into()));
+        session.cursor = Pos::new(4, 0);
+        apply(&mut session, Instruction::Select(Size::new(5, 1)));
+        apply(&mut session, Instruction::Yank);
+        assert!(session.selection.is_none());
+        assert_eq!(session.cursor, Pos::new(4, 0));
+
+        session.cursor = Pos::new(0, 1);
+        apply(&mut session, Instruction::Paste { above: false });
+        assert_eq!(session.doc.text(), "let a = 1;\na = 1");
+        assert_eq!(session.cursor, Pos::new(0, 1));
+    }
+
+    #[test]
+    fn clear_to_the_end_of_the_line() {
+        let mut session = session("fn a() -> u8 {\n}");
+        session.cursor = Pos::new(7, 0);
+        apply(&mut session, Instruction::ClearEol);
+        assert_eq!(session.doc.text(), "fn a() \n}");
+        assert_eq!(session.cursor, Pos::new(7, 0));
+
+        apply(&mut session, Instruction::LoadTypeBuffer("{".into()));
+        assert_eq!(session.doc.text(), "fn a() {\n}");
+    }
+
+    #[test]
+    fn select_between_missing_or_swapped_markers() {
+        let mut session = session("");
+        apply(&mut session, Instruction::LoadTypeBuffer("// @a\nx\n// @b\ny".into()));
+
+        let effect = apply(&mut session, Instruction::SelectMarkers("a".into(), "nope".into()));
+        assert!(matches!(effect, Effect::Error(msg) if msg == "marker \"nope\" does not exist"));
+
+        let effect = apply(&mut session, Instruction::SelectMarkers("b".into(), "a".into()));
+        assert!(matches!(effect, Effect::Error(msg) if msg == "marker \"a\" comes before marker \"b\""));
+        assert!(session.selection.is_none());
+    }
+
+    #[test]
+    fn add_markers() {
+        let mut session = session("a\nb\nc");
+        let (_, markers) = generate("// @m\n");
+        apply(&mut session, Instruction::AddMarkers {
+            row: 2,
+            markers: markers.unwrap(),
+        });
+        apply(&mut session, to_marker("m", Pos::ZERO));
+        assert_eq!(session.cursor, Pos::new(0, 2));
+    }
+
+    #[test]
+    fn mark_and_unmark() {
+        let mut session = session("a\nb\nc\nd");
+        apply(&mut session, Instruction::JumpAbsolute(Pos::new(1, 2)));
+        apply(&mut session, Instruction::SetMarker("here".into()));
+        apply(&mut session, Instruction::JumpStart);
+
+        // Moved by the lines above it, like any other marker
+        apply(&mut session, Instruction::LoadTypeBuffer("new\n".into()));
+        apply(&mut session, to_marker("here", Pos::ZERO));
+        assert_eq!(session.cursor, Pos::new(0, 3));
+
+        // Marking again moves the marker
+        apply(&mut session, Instruction::JumpStart);
+        apply(&mut session, Instruction::SetMarker("here".into()));
+        apply(&mut session, Instruction::JumpEnd);
+        apply(&mut session, to_marker("here", Pos::ZERO));
+        assert_eq!(session.cursor, Pos::ZERO);
+        assert_eq!(session.doc.markers.rows().count(), 1);
+
+        apply(&mut session, Instruction::RemoveMarker("here".into()));
+        assert!(session.doc.lookup_marker("here").is_none());
+        let effect = apply(&mut session, Instruction::RemoveMarker("here".into()));
+        assert!(matches!(effect, Effect::Error(msg) if msg == "marker \"here\" does not exist"));
+    }
+
+    #[test]
+    fn markers_from_macros() {
+        let run = |src: &str| {
+            let parsed = crate::parser::parse(src).unwrap();
+            let mut session = session("");
+            for inst in crate::ui::compile::compile_all(parsed, None).unwrap() {
+                apply(&mut session, inst);
+            }
+            apply(&mut session, to_marker("b", Pos::ZERO));
+            (session.doc.text().to_string(), session.cursor)
+        };
+
+        let called = run("def item {\n    type \"// @a\\nx\\n// @b\\n\"\n}\ntype \"list\\n\"\ncall item");
+        let written = run("type \"list\\n\"\ntype \"// @a\\nx\\n// @b\\n\"");
+        assert_eq!(called, written);
+        assert_eq!(called.1, Pos::new(0, 2));
+    }
+
+    #[test]
+    fn marker_columns_and_offsets() {
+        let mut session = session("");
+        let text = "fn main() {\n    // @body col=4\n    let a = 1;\n    let b = 2;\n}";
+        apply(&mut session, Instruction::Insert(text.into()));
+
+        apply(&mut session, to_marker("body", Pos::ZERO));
+        assert_eq!(session.cursor, Pos::new(4, 1));
+        apply(&mut session, to_marker("body", Pos::new(4, 1)));
+        assert_eq!(session.cursor, Pos::new(8, 2));
+
+        // Lines typed above the marker move it down, the column stays
+        apply(&mut session, Instruction::Jump(Pos::new(-8, -2)));
+        apply(&mut session, Instruction::LoadTypeBuffer("use std::fmt;\n\n".into()));
+        apply(&mut session, to_marker("body", Pos::ZERO));
+        assert_eq!(session.cursor, Pos::new(4, 3));
+        apply(&mut session, to_marker("body", Pos::new(-4, -1)));
+        assert_eq!(session.cursor, Pos::new(0, 2));
+    }
+
+    #[test]
+    fn insert() {
+        let mut session = session("b");
+        session.cursor = Pos::new(1, 0);
+        let effect = apply(&mut session, Instruction::Insert("a\n// @m\n".into()));
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(session.doc.text(), "a\nb");
+        assert_eq!(session.cursor, Pos::ZERO);
+        assert!(session.doc.lookup_marker("m").is_some());
+    }
+
+    #[test]
+    fn jump() {
+        let mut session = session("abcd\nefgh\nijkl");
+        apply(&mut session, Instruction::Jump(Pos::new(3, 2)));
+        assert_eq!(session.cursor, Pos::new(3, 2));
+        apply(&mut session, Instruction::Jump(Pos::new(-5, -1)));
+        assert_eq!(session.cursor, Pos::new(0, 1));
+
+        // Never past the last line, or past the end of the line
+        apply(&mut session, Instruction::Jump(Pos::new(10, 10)));
+        assert_eq!(session.cursor, Pos::new(4, 2));
+    }
+
+    #[test]
+    fn jump_up_and_down_keeps_the_column() {
+        let mut session = session("abcdef
+ab
+
+abcdef
+abcdef");
+        apply(&mut session, Instruction::Jump(Pos::new(5, 0)));
+        apply(&mut session, Instruction::Jump(Pos::new(0, 1)));
+        assert_eq!(session.cursor, Pos::new(2, 1));
+        apply(&mut session, Instruction::Jump(Pos::new(0, 1)));
+        assert_eq!(session.cursor, Pos::new(0, 2));
+        apply(&mut session, Instruction::Jump(Pos::new(0, 1)));
+        assert_eq!(session.cursor, Pos::new(5, 3));
+
+        // Anything else that moves the cursor picks a new column
+        apply(&mut session, Instruction::Jump(Pos::new(0, -2)));
+        apply(&mut session, Instruction::Jump(Pos::new(-1, 0)));
+        apply(&mut session, Instruction::Jump(Pos::new(0, 3)));
+        assert_eq!(session.cursor, Pos::new(1, 4));
+
+        apply(&mut session, Instruction::Jump(Pos::new(0, -3)));
+        assert_eq!(session.cursor, Pos::new(1, 1));
+        session.type_buffer.push("x");
+        session.type_next();
+        apply(&mut session, Instruction::Jump(Pos::new(0, -1)));
+        assert_eq!(session.cursor, Pos::new(2, 0));
+    }
+
+    #[test]
+    fn jump_start_and_end() {
+        let mut session = session("abcd\nef\n\n  \n");
+        session.cursor = Pos::new(2, 1);
+        session.offset.x = -3;
+        apply(&mut session, Instruction::JumpEnd);
+        assert_eq!(session.cursor, Pos::new(0, 1));
+        assert_eq!(session.offset.x, 0);
+
+        // Whatever was typed since
+        apply(&mut session, Instruction::JumpAbsolute(Pos::new(0, 4)));
+        apply(&mut session, Instruction::LoadTypeBuffer("x\n\n".into()));
+        apply(&mut session, Instruction::JumpEnd);
+        assert_eq!(session.cursor, Pos::new(0, 4));
+
+        session.offset.x = -3;
+        apply(&mut session, Instruction::JumpStart);
+        assert_eq!(session.cursor, Pos::ZERO);
+        assert_eq!(session.offset.x, 0);
+    }
+
+    #[test]
+    fn jump_absolute() {
+        let mut session = session("abcd
+ef
+ijkl");
+        session.cursor = Pos::new(3, 2);
+        apply(&mut session, Instruction::JumpAbsolute(Pos::new(1, 0)));
+        assert_eq!(session.cursor, Pos::new(1, 0));
+
+        // As close as the document goes
+        apply(&mut session, Instruction::JumpAbsolute(Pos::new(3, 1)));
+        assert_eq!(session.cursor, Pos::new(2, 1));
+        apply(&mut session, Instruction::JumpAbsolute(Pos::new(9, 12)));
+        assert_eq!(session.cursor, Pos::new(4, 2));
+    }
+
+    #[test]
+    fn select_and_delete() {
+        let mut session = session("abcd\nefgh");
+        session.cursor = Pos::new(1, 0);
+
+        apply(&mut session, Instruction::Select(Size::ZERO));
+        assert!(session.selection.is_none());
+
+        apply(&mut session, Instruction::Select(Size::new(2, 1)));
+        assert_eq!(session.cursor, Pos::new(2, 0));
+        assert!(session.selection.contains(Pos::new(1, 0)));
+        assert!(!session.selection.contains(Pos::new(3, 0)));
+
+        let effect = apply(&mut session, Instruction::Delete);
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(session.doc.text(), "ad\nefgh");
+        assert_eq!(session.cursor, Pos::new(1, 0));
+        assert!(session.selection.is_none());
+
+        // Without a selection a single character is deleted
+        apply(&mut session, Instruction::Delete);
+        assert_eq!(session.doc.text(), "a\nefgh");
+    }
+
+    #[test]
+    fn select_to_the_end_of_the_line() {
+        let mut line = session("let a = 界;\nb");
+        line.cursor = Pos::new(4, 0);
+        apply(&mut line, Instruction::SelectEol);
+        assert_eq!(line.cursor, Pos::new(10, 0));
+        assert!(line.selection.contains(Pos::new(4, 0)));
+        assert!(line.selection.contains(Pos::new(10, 0)));
+
+        apply(&mut line, Instruction::Delete);
+        assert_eq!(line.doc.text(), "let \nb");
+
+        // At the end of the line, and on an empty line
+        apply(&mut line, Instruction::SelectEol);
+        assert!(line.selection.is_none());
+        let mut empty = session("\nb");
+        apply(&mut empty, Instruction::SelectEol);
+        assert!(empty.selection.is_none());
+        apply(&mut empty, Instruction::Delete);
+        assert_eq!(empty.doc.text(), "\nb");
+    }
+
+    #[test]
+    fn typing_replaces_the_selection() {
+        let mut session = session("let a = 1;\nlet b = 2;");
+        session.cursor = Pos::new(4, 0);
+        apply(&mut session, Instruction::Select(Size::new(1, 1)));
+        let effect = apply(&mut session, Instruction::LoadTypeBuffer("value".into()));
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(session.doc.text(), "let value = 1;\nlet b = 2;");
+        assert_eq!(session.cursor, Pos::new(9, 0));
+        assert!(session.selection.is_none());
+
+        // The same columns on both lines
+        session.cursor = Pos::new(0, 0);
+        apply(&mut session, Instruction::Select(Size::new(4, 2)));
+        apply(&mut session, Instruction::LoadTypeBuffer("const ".into()));
+        assert_eq!(session.doc.text(), "const value = 1;\nb = 2;");
+    }
+
+    #[test]
+    fn typing_can_keep_the_selection() {
+        let mut session = session("abc");
+        session.cursor = Pos::new(1, 0);
+        apply(&mut session, Instruction::Select(Size::new(1, 1)));
+        apply(&mut session, Instruction::LoadTypeBufferKeepSelection("x".into()));
+        assert_eq!(session.doc.text(), "axbc");
+        assert!(session.selection.contains(Pos::new(1, 0)));
+    }
+
+    #[test]
+    fn insert_replaces_the_selection() {
+        let mut session = session("abcd\nefgh\nijkl");
+        session.cursor = Pos::new(1, 0);
+        apply(&mut session, Instruction::Select(Size::new(2, 2)));
+        let effect = apply(&mut session, Instruction::Insert("XY".into()));
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(session.doc.text(), "aXYd\neh\nijkl");
+        assert_eq!(session.cursor, Pos::new(1, 0));
+
+        // Without a selection the content goes at the start of the line
+        apply(&mut session, Instruction::Insert("_".into()));
+        assert_eq!(session.doc.text(), "_aXYd\neh\nijkl");
+    }
+
+    #[test]
+    fn join() {
+        let mut session = session("a\n  b\nc");
+        let effect = apply(&mut session, Instruction::Join(5));
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(session.doc.text(), "a b c");
+        assert_eq!(session.cursor.x, 3);
+    }
+
+    #[test]
+    fn split_line() {
+        let mut plain = session("    ab");
+        plain.cursor = Pos::new(5, 0);
+        let effect = apply(&mut plain, Instruction::SplitLine);
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(plain.doc.text(), "    a\nb");
+        assert_eq!(plain.cursor, Pos::new(0, 1));
+
+        let mut indented = session("    ab");
+        indented.cursor = Pos::new(5, 0);
+        apply(&mut indented, Instruction::AutoIndent(true));
+        apply(&mut indented, Instruction::SplitLine);
+        assert_eq!(indented.doc.text(), "    a\n    b");
+        assert_eq!(indented.cursor, Pos::new(4, 1));
+    }
+
+    #[test]
+    fn indent_and_dedent() {
+        let mut session = session("a\nb");
+        session.cursor = Pos::new(1, 0);
+
+        let effect = apply(&mut session, Instruction::Indent {
+            lines: Some(2),
+            levels: 1,
+        });
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(session.doc.text(), "    a\n    b");
+        assert_eq!(session.cursor.x, 5);
+
+        apply(&mut session, Instruction::IndentUnit("\t".into()));
+        apply(&mut session, Instruction::Indent {
+            lines: Some(1),
+            levels: 1,
+        });
+        assert_eq!(session.doc.text(), "\t    a\n    b");
+
+        let effect = apply(&mut session, Instruction::Dedent {
+            lines: Some(2),
+            levels: 3,
+        });
+        assert!(matches!(effect, Effect::Changed(0)));
+        assert_eq!(session.doc.text(), "a\nb");
+        // The tab has no width for the cursor
+        assert_eq!(session.cursor.x, 1);
+    }
+
+    #[test]
+    fn indent_and_dedent_the_selection() {
+        let indent = Instruction::Indent { lines: None, levels: 1 };
+        let dedent = Instruction::Dedent { lines: None, levels: 1 };
+        let mut session = session("a\nb\nc\nd");
+        session.cursor = Pos::new(0, 1);
+        apply(&mut session, Instruction::Select(Size::new(1, 2)));
+
+        apply(&mut session, indent.clone());
+        apply(&mut session, indent.clone());
+        assert_eq!(session.doc.text(), "a\n        b\n        c\nd");
+        assert_eq!(session.cursor, Pos::new(8, 2));
+        // Still on the same characters
+        let region = session.selection.as_ref().unwrap().region;
+        assert_eq!((region.from, region.to), (Pos::new(8, 1), Pos::new(9, 3)));
+
+        // Two spaces for a tab
+        apply(&mut session, Instruction::TabWidth(2));
+        apply(&mut session, Instruction::IndentUnit("\t".into()));
+        apply(&mut session, dedent.clone());
+        apply(&mut session, dedent.clone());
+        assert_eq!(session.doc.text(), "a\n    b\n    c\nd");
+        let region = session.selection.as_ref().unwrap().region;
+        assert_eq!((region.from, region.to), (Pos::new(4, 1), Pos::new(5, 3)));
+
+        // Only the cursor's line without a selection
+        session.selection = None;
+        apply(&mut session, dedent);
+        assert_eq!(session.doc.text(), "a\n    b\n  c\nd");
+        assert_eq!(session.cursor, Pos::new(2, 2));
+    }
+
+    #[test]
+    fn align() {
+        let mut session = session("a = 1\nlong = 2\nnothing\nab = 3");
+        session.cursor = Pos::new(4, 0);
+
+        let effect = session.apply(Instruction::Align {
+            c: '=',
+            lines: 4,
+            instant: false,
+        });
+        assert!(matches!(effect, Effect::Next));
+        assert_eq!(session.doc.text(), "a = 1\nlong = 2\nnothing\nab = 3");
+
+        let queued = session.instructions.by_ref().collect::<Vec<_>>();
+        assert!(matches!(queued[..], [
+            Instruction::Pad { width: 3, .. },
+            Instruction::Wait(ALIGN_STEP),
+            Instruction::Pad { width: 2, .. },
+        ]));
+
+        for inst in queued {
+            session.apply(inst);
+        }
+        assert_eq!(session.doc.text(), "a    = 1\nlong = 2\nnothing\nab   = 3");
+        assert_eq!(session.cursor, Pos::new(7, 0));
+    }
+
+    #[test]
+    fn align_instant() {
+        let mut session = session("x\na = 1\nlong = 2");
+        session.cursor = Pos::new(0, 1);
+
+        let effect = session.apply(Instruction::Align {
+            c: '=',
+            lines: 2,
+            instant: true,
+        });
+        assert!(matches!(effect, Effect::Changed(1)));
+        assert!(session.instructions.is_empty());
+        assert_eq!(session.doc.text(), "x\na    = 1\nlong = 2");
+        // The cursor was before the padding
+        assert_eq!(session.cursor, Pos::new(0, 1));
+    }
+
+    fn toggle_comment(session: &mut Session, lines: usize) {
+        apply(session, Instruction::ToggleComment { lines: Some(lines), instant: true });
+    }
+
+    #[test]
+    fn toggle_comment_per_extension() {
+        for (ext, text, commented) in [
+            ("rs", "fn a() {\n    b();\n}", "// fn a() {\n    // b();\n// }"),
+            ("py", "def a():\n    b()", "# def a():\n    # b()"),
+            ("lua", "local a = 1", "-- local a = 1"),
+            ("clj", "(a b)", "; (a b)"),
+            ("txt", "a", "// a"),
+        ] {
+            let mut session = session(text);
+            apply(&mut session, Instruction::SetExtension(ext.into()));
+            toggle_comment(&mut session, 5);
+            assert_eq!(session.doc.text(), commented, "{ext}");
+            toggle_comment(&mut session, 5);
+            assert_eq!(session.doc.text(), text, "{ext}");
+        }
+    }
+
+    #[test]
+    fn toggle_comment_mixed_block() {
+        let mut session = session("// a\nb\n\n// c");
+        toggle_comment(&mut session, 4);
+        assert_eq!(session.doc.text(), "// // a\n// b\n\n// // c");
+        toggle_comment(&mut session, 4);
+        assert_eq!(session.doc.text(), "// a\nb\n\n// c");
+    }
+
+    #[test]
+    fn toggle_comment_leader_and_cursor() {
+        let mut session = session("  x = 1\ny");
+        session.cursor = Pos::new(4, 0);
+        apply(&mut session, Instruction::CommentLeader("REM".into()));
+        toggle_comment(&mut session, 1);
+        assert_eq!(session.doc.text(), "  REM x = 1\ny");
+        assert_eq!(session.cursor, Pos::new(8, 0));
+
+        toggle_comment(&mut session, 1);
+        assert_eq!(session.cursor, Pos::new(4, 0));
+    }
+
+    #[test]
+    fn toggle_comment_selection_and_prefix() {
+        let toggle = Instruction::ToggleComment { lines: None, instant: true };
+        let mut session = session("a\nb\nc\nd");
+        apply(&mut session, Instruction::SetExtension("txt".into()));
+        apply(&mut session, Instruction::CommentPrefix("#".into()));
+        session.cursor = Pos::new(0, 1);
+        apply(&mut session, Instruction::Select(Size::new(1, 2)));
+
+        apply(&mut session, toggle.clone());
+        assert_eq!(session.doc.text(), "a\n# b\n# c\nd");
+        assert!(session.selection.is_some());
+        apply(&mut session, toggle.clone());
+        assert_eq!(session.doc.text(), "a\nb\nc\nd");
+
+        // The extension's own leader wins over the prefix, and without a selection it's the cursor's line
+        session.selection = None;
+        session.cursor = Pos::new(0, 1);
+        apply(&mut session, Instruction::SetExtension("lua".into()));
+        apply(&mut session, toggle);
+        assert_eq!(session.doc.text(), "a\n-- b\nc\nd");
+    }
+
+    #[test]
+    fn toggle_comment_keeps_markers() {
+        let toggle = Instruction::ToggleComment { lines: None, instant: true };
+        let mut session = session("a = 1\nb = 2\n  c");
+        let (_, markers) = crate::ui::markers::generate("// @x col=4\n\n// @y\n\n// @z col=1\n");
+        apply(&mut session, Instruction::AddMarkers { row: 0, markers: markers.unwrap() });
+        apply(&mut session, Instruction::SetExtension("txt".into()));
+        apply(&mut session, Instruction::CommentPrefix("#".into()));
+        apply(&mut session, Instruction::Select(Size::new(1, 3)));
+        let marker = |session: &Session, name| Pos::from(session.doc.markers.get(name).unwrap());
+
+        apply(&mut session, toggle.clone());
+        assert_eq!(session.doc.text(), "# a = 1\n# b = 2\n  # c");
+        assert_eq!(marker(&session, "x"), Pos::new(6, 0));
+        assert_eq!(marker(&session, "y"), Pos::new(0, 1));
+        // In the indentation, in front of the leader
+        assert_eq!(marker(&session, "z"), Pos::new(1, 2));
+
+        apply(&mut session, toggle);
+        assert_eq!(session.doc.text(), "a = 1\nb = 2\n  c");
+        assert_eq!(marker(&session, "x"), Pos::new(4, 0));
+        assert_eq!(marker(&session, "y"), Pos::new(0, 1));
+        assert_eq!(marker(&session, "z"), Pos::new(1, 2));
+    }
+
+    #[test]
+    fn toggle_comment_animated() {
+        let mut session = session("a\nb");
+        let effect = session.apply(Instruction::ToggleComment {
+            lines: Some(2),
+            instant: false,
+        });
+        assert!(matches!(effect, Effect::Next));
+        assert_eq!(session.doc.text(), "a\nb");
+
+        let queued = session.instructions.by_ref().collect::<Vec<_>>();
+        assert!(matches!(&queued[..], [
+            Instruction::CommentLine { row: 0, comment: true, .. },
+            Instruction::Wait(ALIGN_STEP),
+            Instruction::CommentLine { row: 1, comment: true, .. },
+        ]));
+
+        for inst in queued {
+            session.apply(inst);
+        }
+        assert_eq!(session.doc.text(), "// a\n// b");
+    }
+
+    #[test]
+    fn find() {
+        let mut session = session("let a = a + b;");
+
+        apply(&mut session, Instruction::FindInCurrentLine {
+            needle: String::new(),
+            end_of_word: false,
+            reverse: false,
+            count: 1,
+            dependents: None,
+        });
+        assert_eq!(session.cursor.x, 0);
+
+        apply(&mut session, Instruction::FindInCurrentLine {
+            needle: "a".into(),
+            end_of_word: false,
+            reverse: false,
+            count: 2,
+            dependents: None,
+        });
+        assert_eq!(session.cursor.x, 8);
+
+        session.cursor.x = 0;
+        apply(&mut session, Instruction::FindInCurrentLine {
+            needle: "let".into(),
+            end_of_word: true,
+            reverse: false,
+            count: 1,
+            dependents: None,
+        });
+        assert_eq!(session.cursor.x, 2);
+
+        let effect = apply(&mut session, Instruction::FindInCurrentLine {
+            needle: "x".into(),
+            end_of_word: false,
+            reverse: false,
+            count: 1,
+            dependents: None,
+        });
+        assert!(matches!(effect, Effect::Next));
+        assert_eq!(session.cursor.x, 2);
+    }
+
+    #[test]
+    fn rfind() {
+        let mut session = session("let a = a + b;");
+        session.cursor.x = 14;
+        let rfind = |needle: &str, end_of_word, count| Instruction::FindInCurrentLine {
+            needle: needle.into(),
+            end_of_word,
+            reverse: true,
+            count,
+            dependents: None,
+        };
+
+        apply(&mut session, rfind("a", false, 2));
+        assert_eq!(session.cursor.x, 4);
+
+        session.cursor.x = 14;
+        apply(&mut session, rfind("a +", true, 1));
+        assert_eq!(session.cursor.x, 10);
+        // The match the cursor is at the end of isn't before the cursor
+        apply(&mut session, rfind("a", true, 1));
+        assert_eq!(session.cursor.x, 8);
+
+        let effect = apply(&mut session, rfind("b", false, 1));
+        assert!(matches!(effect, Effect::Next));
+        assert_eq!(session.cursor.x, 8);
+    }
+
+    fn replace(script: &str, text: &str) -> (Session, Option<String>) {
+        let instructions = crate::ui::compile_all(crate::parser::parse(script).unwrap(), None).unwrap();
+        let mut session = session(text);
+        session.instructions = Queue::new(instructions);
+
+        while let Some(inst) = session.instructions.pop_front() {
+            if let Effect::Error(msg) = session.apply(inst) {
+                return (session, Some(msg));
+            }
+            type_all(&mut session);
+        }
+        (session, None)
+    }
+
+    #[test]
+    fn find_miss() {
+        // Today's behaviour: the select and delete go ahead where the cursor is
+        let (session, _) = replace("replace \"x\" \"y\"", "let a = 1;");
+        assert_eq!(session.doc.text(), "yet a = 1;");
+
+        let (session, error) = replace("find_miss error\nreplace \"x\" \"y\"", "let a = 1;");
+        assert_eq!(error.unwrap(), "\"x\" not found on line 1");
+        assert_eq!(session.doc.text(), "let a = 1;");
+
+        // The replace drops its own instructions, whatever the count
+        let (session, error) = replace("find_miss skip 1\nreplace \"x\" \"y\"\ntype \"!\"", "let a = 1;");
+        assert!(error.is_none());
+        assert_eq!(session.doc.text(), "!let a = 1;");
+
+        // And still replaces when the find hits
+        let (session, _) = replace("find_miss skip 1\nreplace \"a\" \"b\"", "let a = 1;");
+        assert_eq!(session.doc.text(), "let b = 1;");
+
+        let (session, _) = replace("find_miss skip 2\nfind \"x\"\ntype \"a\"\ntype \"b\"\ntype \"c\"", "");
+        assert_eq!(session.doc.text(), "c");
+
+        // Can't skip more than there is
+        let (session, _) = replace("find_miss skip 5\nfind \"x\"\ntype \"a\"", "");
+        assert_eq!(session.doc.text(), "");
+    }
+
+    #[test]
+    fn marker_collisions() {
+        // The markers are added by an instruction that is queued by the typing
+        fn add_markers(session: &mut Session, inst: Instruction) -> Effect {
+            session.apply(inst);
+            type_all(session);
+            let add_markers = session.instructions.pop_front().unwrap();
+            session.apply(add_markers)
+        }
+
+        let mut session = session("// @setup\nfn a() {}\n");
+        session.cursor.y = 1;
+        let effect = add_markers(&mut session, Instruction::LoadTypeBuffer("// @setup\nfn b() {}\n".into()));
+        assert!(matches!(effect, Effect::Warning(msg) if msg == "marker \"setup\" on row 1 is redefined on row 2"));
+        assert_eq!(session.doc.lookup_marker("setup").unwrap().row, 1);
+
+        apply(&mut session, Instruction::StrictMarkers(true));
+        let effect = add_markers(&mut session, Instruction::Insert("// @setup\n".into()));
+        assert!(matches!(effect, Effect::Error(msg) if msg == "marker \"setup\" on row 2 is redefined on row 3"));
+    }
+
+    #[test]
+    fn virtual_text() {
+        let mut session = session("fn a() {}\n// @call\na();\n");
+        let row = session.doc.lookup_marker("call").unwrap().row;
+        let text = |text: &str, below| Instruction::VirtualText {
+            anchor: Anchor::Marker("call".into()),
+            text: text.into(),
+            below,
+        };
+        apply(&mut session, text("=> ()", false));
+        apply(&mut session, text("called a", true));
+        assert_eq!(session.doc.virtual_text.eol(row).collect::<Vec<_>>(), ["=> ()"]);
+
+        // Not part of the document
+        assert!(!session.doc.text().contains("=> ()"));
+
+        // Moves with the line
+        apply(&mut session, Instruction::SplitLine);
+        assert_eq!(session.doc.virtual_text.eol(row + 1).collect::<Vec<_>>(), ["=> ()"]);
+        assert_eq!(session.doc.virtual_text.screen_row(row + 2), row + 3);
+
+        apply(&mut session, Instruction::ClearVirtualText(Some(row + 1)));
+        assert_eq!(session.doc.virtual_text.screen_row(row + 2), row + 2);
+
+        let missing = Instruction::VirtualText {
+            anchor: Anchor::Marker("nope".into()),
+            text: "x".into(),
+            below: false,
+        };
+        let effect = apply(&mut session, missing);
+        assert!(matches!(effect, Effect::Error(msg) if msg == "marker \"nope\" does not exist"));
+
+        let effect = apply(&mut session, text(&"x\n".repeat(MAX_BELOW_LINES + 1), true));
+        assert!(matches!(effect, Effect::Error(_)));
+    }
+
+    #[test]
+    fn wait() {
+        let mut session = session("");
+        let effect = apply(&mut session, Instruction::Wait(Duration::from_secs(2)));
+        assert!(matches!(effect, Effect::Wait(d) if d == Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn settings() {
+        let mut session = session("");
+        apply(&mut session, Instruction::SetExtension("rs".into()));
+        apply(&mut session, Instruction::SetTheme("dark".into()));
+        apply(&mut session, Instruction::CommandClearTimeout(Duration::from_millis(300)));
+        assert_eq!(session.settings.extension, "rs");
+        assert_eq!(session.settings.theme, "dark");
+        assert_eq!(session.settings.command_clear_timeout, Duration::from_millis(300));
+    }
+
+    #[test]
+    fn clear() {
+        let mut session = session("a\nb // @m");
+        session.cursor = Pos::new(1, 1);
+        session.offset = Pos::new(0, -3);
+        let effect = apply(&mut session, Instruction::Clear);
+        assert!(matches!(effect, Effect::Cleared));
+        assert_eq!(session.doc.text(), "");
+        assert_eq!(session.cursor, Pos::ZERO);
+        assert_eq!(session.offset, Pos::ZERO);
+    }
+
+    #[test]
+    fn write_buffer() {
+        let mut session = session("hello");
+        let effect = apply(&mut session, Instruction::WriteBuffer("out.txt".into()));
+        assert!(matches!(effect, Effect::Write(path, text) if path.as_os_str() == "out.txt" && text == "hello"));
+    }
+
+    #[test]
+    fn command_buffer() {
+        let mut session = session("");
+        let effect = apply(&mut session, Instruction::ClearCommandBuffer);
+        assert!(matches!(effect, Effect::ClearCommandBuffer));
+
+        session.settings.command_clear_timeout = Duration::from_millis(250);
+        session.apply(Instruction::ClearCommandWait);
+        assert!(matches!(
+            session.instructions.pop_front(),
+            Some(Instruction::Wait(d)) if d == Duration::from_millis(250)
+        ));
+    }
+
+    // Every instruction that touches the text or the cursor, on a document with one empty line
+    #[test]
+    fn empty_document() {
+        let instructions = vec![
+            Instruction::Jump(Pos::new(5, 3)),
+            Instruction::Jump(Pos::new(-5, -3)),
+            Instruction::JumpAbsolute(Pos::new(5, 3)),
+            Instruction::JumpStart,
+            Instruction::JumpEnd,
+            Instruction::Scroll(3),
+            Instruction::SelectEol,
+            Instruction::DeleteLine(2),
+            Instruction::DuplicateLine(1),
+            Instruction::MoveLine { up: true, count: 2 },
+            Instruction::MoveLine { up: false, count: 2 },
+            Instruction::DeleteWord,
+            Instruction::ClearEol,
+            Instruction::Snapshot("s".into()),
+            Instruction::Restore("s".into()),
+            Instruction::Yank,
+            Instruction::Paste { above: false },
+            Instruction::Paste { above: true },
+            Instruction::ReplaceAll {
+                needle: "a".into(),
+                replacement: "b\n".into(),
+            },
+            Instruction::SelectMarkers("a".into(), "b".into()),
+            to_marker("nope", Pos::new(5, 5)),
+            Instruction::Select(Size::new(3, 3)),
+            Instruction::Delete,
+            Instruction::Backspace(3),
+            Instruction::LoadTypeBuffer(String::new()),
+            Instruction::LoadTypeBufferKeepSelection(String::new()),
+            Instruction::Insert(String::new()),
+            Instruction::Join(3),
+            Instruction::SplitLine,
+            Instruction::Align {
+                c: '=',
+                lines: 3,
+                instant: true,
+            },
+            Instruction::Align {
+                c: '=',
+                lines: 3,
+                instant: false,
+            },
+            Instruction::Pad {
+                pos: Pos::ZERO,
+                width: 0,
+            },
+            Instruction::Indent {
+                lines: Some(3),
+                levels: 1,
+            },
+            Instruction::Indent { lines: None, levels: 1 },
+            Instruction::Dedent {
+                lines: Some(3),
+                levels: 1,
+            },
+            Instruction::Dedent { lines: None, levels: 1 },
+            Instruction::TabWidth(2),
+            Instruction::ToggleComment {
+                lines: Some(3),
+                instant: true,
+            },
+            Instruction::ToggleComment {
+                lines: Some(3),
+                instant: false,
+            },
+            Instruction::FindInCurrentLine {
+                needle: "x".into(),
+                end_of_word: false,
+                reverse: false,
+                count: 1,
+                dependents: None,
+            },
+            Instruction::FindInCurrentLine {
+                needle: "x".into(),
+                end_of_word: true,
+                reverse: false,
+                count: 3,
+                dependents: None,
+            },
+            Instruction::AddMarkers {
+                row: 0,
+                markers: Markers::new(),
+            },
+            Instruction::SetMarker("m".into()),
+            Instruction::RemoveMarker("m".into()),
+            Instruction::VirtualText {
+                anchor: Anchor::Row(3),
+                text: "text".into(),
+                below: true,
+            },
+            Instruction::ClearVirtualText(Some(3)),
+            Instruction::Clear,
+        ];
+
+        for inst in instructions {
+            // Only splitting and duplicating the line make room for the cursor to move
+            let expected = match inst {
+                Instruction::SplitLine | Instruction::DuplicateLine(_) => Pos::new(0, 1),
+                _ => Pos::ZERO,
+            };
+            // From the only position there is, and from a jump past the end
+            for jump in [Pos::ZERO, Pos::new(5, 3)] {
+                let mut session = session("");
+                apply(&mut session, Instruction::Jump(jump));
+                apply(&mut session, inst.clone());
+                assert_eq!(session.cursor, expected, "{inst:?}");
+                assert_eq!(session.doc.text().trim(), "", "{inst:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn typing_after_jumping_past_the_end() {
+        let mut session = session("");
+        apply(&mut session, Instruction::Jump(Pos::new(4, 5)));
+        apply(&mut session, Instruction::Delete);
+        apply(&mut session, Instruction::LoadTypeBuffer("abc".into()));
+        assert_eq!(session.doc.text(), "abc");
+        assert_eq!(session.cursor, Pos::new(3, 0));
+    }
+
+    #[test]
+    fn ui_instructions() {
+        let instructions = vec![
+            Instruction::SetTitle("title".into()),
+            Instruction::Speed(Duration::from_millis(10)),
+            Instruction::CommandSpeed(None),
+            Instruction::SetJitter(5),
+            Instruction::JitterProfile(JitterProfile::Burst),
+            Instruction::BurstPause {
+                min: Duration::ZERO,
+                max: Duration::ZERO,
+            },
+            Instruction::ShowLineNumbers(true),
+            Instruction::HScroll(HScroll::default()),
+            Instruction::BreakLigatures(true),
+            Instruction::AddLigature("->".into()),
+            Instruction::ColorColumn(vec![80]),
+            Instruction::Margins(Margins::default()),
+            Instruction::ScrollOff { rows: 0, columns: None },
+            Instruction::Wrap(None),
+            Instruction::Every {
+                interval: Duration::from_secs(1),
+                body: vec![],
+            },
+            Instruction::EveryStop,
+            Instruction::Budget {
+                line: 1,
+                limit: Duration::from_secs(1),
+            },
+            Instruction::BudgetEnd,
+            Instruction::Scene("scene".into()),
+            Instruction::Transition(TransitionKind::Dissolve, Duration::from_secs(1)),
+            Instruction::ResetCounters,
+            Instruction::LoadAudio("audio".into()),
+            Instruction::AudioClasses(true),
+            Instruction::AudioOffset(10),
+            Instruction::Metronome(true),
+            Instruction::KeyOverlay(true),
+            Instruction::KeyOverlayHold(Duration::from_secs(1)),
+            Instruction::Hyperlinks(true),
+            Instruction::Link {
+                text: "text".into(),
+                url: "url".into(),
+            },
+            Instruction::Duck {
+                percent: 50,
+                attack: Duration::ZERO,
+                release: Duration::ZERO,
+            },
+            Instruction::popup("popup".into()),
+            Instruction::PopupAt(PopupPosition::Top),
+            Instruction::PopupScroll(1),
+            Instruction::PopupAutoscroll(1),
+            Instruction::Menu {
+                items: vec!["a".into()],
+                choice: 0,
+                step: Duration::ZERO,
+                then_type: false,
+            },
+            Instruction::ClosePopup,
+            Instruction::Loop {
+                count: LoopCount::Forever,
+                pause: Duration::ZERO,
+            },
+            Instruction::Exit,
+            Instruction::ExitOnEnd(Duration::ZERO),
+            Instruction::SetVariable("x".into(), Variable::Int(1)),
+        ];
+
+        let mut session = session("text");
+        for inst in instructions {
+            let kind = std::mem::discriminant(&inst);
+            // Passed along as it is
+            let effect = apply(&mut session, inst);
+            assert!(matches!(&effect, Effect::Ui(ui) if std::mem::discriminant(ui) == kind), "{effect:?}");
+        }
+        assert_eq!(session.doc.text(), "text");
+        assert_eq!(session.cursor, Pos::ZERO);
+    }
+}
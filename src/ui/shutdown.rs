@@ -0,0 +1,226 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub const DEADLINE: Duration = Duration::from_secs(2);
+
+// -----------------------------------------------------------------------------
+//   - Pending side effect -
+// -----------------------------------------------------------------------------
+struct Pending {
+    // Temporary file that should not be left behind
+    path: PathBuf,
+    // Work that should be allowed to complete before exiting
+    task: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    pending: Vec<Pending>,
+    errors: Vec<String>,
+}
+
+// -----------------------------------------------------------------------------
+//   - Shutdown coordinator -
+// -----------------------------------------------------------------------------
+/// Keeps track of temporary files and in-flight writes so they can be
+/// completed or cleaned up before the program exits.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // A panic while holding the lock does not invalidate the list of paths
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Register a temporary file that should be removed on shutdown
+    pub fn register(&self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        self.lock().pending.push(Pending { path, task: None });
+    }
+
+    // Attach the work producing the temporary file.
+    // If the work has already completed this does nothing.
+    fn attach(&self, path: &Path, task: JoinHandle<()>) {
+        let mut inner = self.lock();
+        if let Some(pending) = inner.pending.iter_mut().find(|pending| pending.path == path) {
+            pending.task = Some(task);
+        }
+    }
+
+    /// The temporary file is no longer pending
+    pub fn complete(&self, path: &Path) {
+        self.lock().pending.retain(|pending| pending.path != path);
+    }
+
+    /// Errors from writes that happened in the background
+    pub fn take_error(&self) -> Option<String> {
+        self.lock().errors.pop()
+    }
+
+    /// Write the content to a temporary file next to `path` on a separate thread,
+    /// and link it into place once it's written.
+    /// This will fail if the file already exists.
+    pub fn write(&self, path: PathBuf, content: String) -> Result<(), String> {
+        if path.exists() {
+            return Err(format!("can't write to {path:?}, file already exists"));
+        }
+
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            return Err(format!("invalid file name: {path:?}"));
+        };
+        let tmp = path.with_file_name(format!(".{file_name}.mimic-tmp"));
+
+        self.register(&tmp);
+
+        let shutdown = self.clone();
+        let tmp_path = tmp.clone();
+        let task = std::thread::spawn(move || {
+            let res = write_tmp(&tmp_path, content.as_bytes())
+                .and_then(|_| std::fs::hard_link(&tmp_path, &path))
+                .map_err(|e| format!("failed to write {path:?} : {e}"));
+            _ = std::fs::remove_file(&tmp_path);
+
+            shutdown.complete(&tmp_path);
+            if let Err(e) = res {
+                shutdown.lock().errors.push(e);
+            }
+        });
+
+        self.attach(&tmp, task);
+        Ok(())
+    }
+
+    /// Wait for in-flight work to complete and remove temporary files.
+    /// Once the deadline is reached the cleanup is abandoned.
+    ///
+    /// Returns the paths that were left behind.
+    pub fn finish(&self, deadline: Duration) -> Vec<PathBuf> {
+        let deadline = Instant::now() + deadline;
+        let pending = std::mem::take(&mut self.lock().pending);
+        let mut leftovers = vec![];
+
+        for Pending { path, task } in pending {
+            if let Some(task) = task {
+                while !task.is_finished() && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+
+                if task.is_finished() {
+                    // The task cleans up after itself
+                    _ = task.join();
+                    continue;
+                }
+            }
+
+            if Instant::now() >= deadline {
+                leftovers.push(path);
+                continue;
+            }
+
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => leftovers.push(path),
+                _ => {}
+            }
+        }
+
+        leftovers.retain(|path| path.exists());
+        leftovers
+    }
+}
+
+fn write_tmp(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut file = std::fs::File::create_new(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mimic-shutdown-{name}-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn finish_removes_temp_files() {
+        let dir = tmp_dir("remove");
+        let path = dir.join("a.tmp");
+        std::fs::write(&path, "a").unwrap();
+
+        let shutdown = Shutdown::new();
+        shutdown.register(&path);
+
+        let leftovers = shutdown.finish(DEADLINE);
+        assert!(leftovers.is_empty());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn completed_files_are_kept() {
+        let dir = tmp_dir("complete");
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "a").unwrap();
+
+        let shutdown = Shutdown::new();
+        shutdown.register(&path);
+        shutdown.complete(&path);
+
+        assert!(shutdown.finish(DEADLINE).is_empty());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn finish_waits_for_writes() {
+        let dir = tmp_dir("write");
+        let path = dir.join("out.txt");
+
+        let shutdown = Shutdown::new();
+        shutdown.write(path.clone(), "hello world".into()).unwrap();
+
+        assert!(shutdown.finish(DEADLINE).is_empty());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(shutdown.take_error().is_none());
+    }
+
+    #[test]
+    fn write_existing_file_fails() {
+        let dir = tmp_dir("exists");
+        let path = dir.join("out.txt");
+        std::fs::write(&path, "a").unwrap();
+
+        let shutdown = Shutdown::new();
+        assert!(shutdown.write(path.clone(), "b".into()).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a");
+    }
+
+    #[test]
+    fn deadline_abandons_cleanup() {
+        let dir = tmp_dir("deadline");
+        let path = dir.join("slow.tmp");
+        std::fs::write(&path, "a").unwrap();
+
+        let shutdown = Shutdown::new();
+        let task = std::thread::spawn(|| std::thread::sleep(Duration::from_millis(500)));
+        shutdown.register(&path);
+        shutdown.attach(&path, task);
+
+        let leftovers = shutdown.finish(Duration::from_millis(20));
+        assert_eq!(leftovers, vec![path.clone()]);
+        assert!(path.exists());
+    }
+}
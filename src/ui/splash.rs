@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use anathema::geometry::Size;
+use anathema::widgets::Style;
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthStr;
+
+use super::json::millis;
+use crate::parser::TextStyle;
+
+/// How long the splash is shown unless the block says otherwise
+pub const DEFAULT_HOLD: Duration = Duration::from_secs(3);
+
+// -----------------------------------------------------------------------------
+//   - Splash -
+// -----------------------------------------------------------------------------
+/// An intro card shown on its own, before (or instead of) the document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Splash {
+    pub lines: Vec<String>,
+    pub styles: Vec<TextStyle>,
+    #[serde(with = "millis")]
+    pub hold: Duration,
+}
+
+impl Splash {
+    /// The top left corner of the block when it's centred on a canvas of the size.
+    /// The lines keep their alignment within the block, so ASCII art stays intact.
+    pub fn origin(&self, size: Size) -> (i32, i32) {
+        let width = self.lines.iter().map(|line| line.width()).max().unwrap_or(0);
+        let x = centre(size.width as usize, width);
+        let y = centre(size.height as usize, self.lines.len());
+        (x, y)
+    }
+
+    pub fn style(&self) -> Style {
+        let mut style = Style::new();
+        for text_style in &self.styles {
+            match text_style {
+                TextStyle::Bold => style.set_bold(true),
+                TextStyle::Dim => style.set_dim(true),
+                TextStyle::Italic => style.set_italic(true),
+                TextStyle::Underlined => style.set_underlined(true),
+                TextStyle::Reversed => style.set_reversed(true),
+            }
+        }
+        style
+    }
+}
+
+// Where something of `len` starts to be centred in `total`. An odd remainder
+// leaves the extra cell after it, and anything too big starts before zero
+fn centre(total: usize, len: usize) -> i32 {
+    (total as i32 - len as i32).div_euclid(2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn splash(lines: &[&str]) -> Splash {
+        Splash {
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            styles: vec![],
+            hold: DEFAULT_HOLD,
+        }
+    }
+
+    #[test]
+    fn centre_odd_and_even() {
+        assert_eq!(centre(10, 4), 3);
+        assert_eq!(centre(10, 3), 3);
+        assert_eq!(centre(11, 4), 3);
+        assert_eq!(centre(11, 3), 4);
+        assert_eq!(centre(4, 4), 0);
+        assert_eq!(centre(0, 0), 0);
+        // Too big to fit, so it's clipped on both sides
+        assert_eq!(centre(4, 7), -2);
+    }
+
+    #[test]
+    fn origin() {
+        let splash = splash(&[" /\\_/\\ ", "( o.o )", "mimic"]);
+        assert_eq!(splash.origin(Size::new(80, 24)), (36, 10));
+        assert_eq!(splash.origin(Size::new(81, 25)), (37, 11));
+        assert_eq!(splash.origin(Size::new(7, 3)), (0, 0));
+        assert_eq!(splash.origin(Size::new(5, 1)), (-1, -1));
+    }
+
+    #[test]
+    fn origin_of_wide_chars() {
+        let splash = splash(&["界界"]);
+        assert_eq!(splash.origin(Size::new(10, 3)), (3, 1));
+    }
+}
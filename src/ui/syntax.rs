@@ -1,11 +1,16 @@
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+
 use anathema::state::Color;
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{FontStyle, Style, ThemeSet};
 use syntect::parsing::SyntaxSet;
 use syntect::util::LinesWithEndings;
 
+use super::budget::Timings;
 use super::error::{Error, Result};
-use super::setup_paths::{syntax_root, theme_root};
+use super::setup_paths::{THEME, cache_root, syntax_root, theme_root};
+use super::syntax_cache;
 
 // -----------------------------------------------------------------------------
 //   - Scratch buffer -
@@ -17,7 +22,7 @@ pub struct InactiveScratch {
 impl InactiveScratch {
     pub unsafe fn activate<'a>(&mut self, code: &'a str) -> ActiveScratch<'a> {
         ActiveScratch {
-            lines: unsafe { std::mem::transmute(&mut self.lines) },
+            lines: unsafe { std::mem::transmute::<&mut Lines<'static>, &mut Lines<'a>>(&mut self.lines) },
             code,
         }
     }
@@ -74,20 +79,16 @@ impl<'a> Lines<'a> {
         self.spans.push(span);
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &[Span<'_>]> {
-        let mut index = 0;
-        let mut prev = 0;
-        std::iter::from_fn(move || {
-            if index == self.lines.len() {
-                return None;
-            }
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
 
-            let range = prev..self.lines[index];
-            prev = self.lines[index];
-            index += 1;
-            let spans = &self.spans[range];
-            Some(spans)
-        })
+    pub fn line(&self, index: usize) -> &[Span<'_>] {
+        let start = match index {
+            0 => 0,
+            _ => self.lines[index - 1],
+        };
+        &self.spans[start..self.lines[index]]
     }
 }
 
@@ -117,43 +118,83 @@ impl Span<'_> {
 //   - Highligher -
 // -----------------------------------------------------------------------------
 pub struct Highlighter {
+    sets: OnceLock<Sets>,
+    // Loading in the background, until the first time the sets are needed
+    pending: Mutex<Option<JoinHandle<Sets>>>,
+}
+
+struct Sets {
     set: SyntaxSet,
     theme_set: ThemeSet,
 }
 
 impl Highlighter {
+    /// The bundled and user syntaxes and themes, from the cache if they haven't changed since it was written
     pub fn new() -> Self {
-        #[cfg(not(debug_assertions))]
-        let set = SyntaxSet::load_defaults_newlines();
-        #[cfg(debug_assertions)]
-        let set = SyntaxSet::new();
-        let mut builder = set.into_builder();
+        let (set, theme_set, _) = syntax_cache::load(&syntax_root(), &theme_root(), &cache_root());
+        Self::loaded(set, theme_set)
+    }
 
-        // Try to load syntaxes from a config dir
-        let syntaxes = syntax_root();
-        _ = builder.add_from_folder(syntaxes, true);
+    /// Same as [`Highlighter::new`], but loading on another thread so the runtime can start in the meantime.
+    /// The time it took is recorded in the timings.
+    pub fn background(timings: Timings) -> Self {
+        let handle = std::thread::spawn(move || {
+            let (set, theme_set, load) = syntax_cache::load(&syntax_root(), &theme_root(), &cache_root());
+            timings.loaded(load);
+            Sets { set, theme_set }
+        });
+
+        Self {
+            sets: OnceLock::new(),
+            pending: Mutex::new(Some(handle)),
+        }
+    }
 
-        builder.add_plain_text_syntax();
-        let set = builder.build();
+    fn loaded(set: SyntaxSet, theme_set: ThemeSet) -> Self {
+        Self {
+            sets: OnceLock::from(Sets { set, theme_set }),
+            pending: Mutex::new(None),
+        }
+    }
 
+    /// The bundled syntaxes and themes, without touching the config dir,
+    /// so the output doesn't depend on what is installed
+    pub fn bundled() -> Self {
         let mut theme_set = ThemeSet::load_defaults();
-        theme_set
-            .add_from_folder(theme_root())
-            .expect("the theme directory should be created the first time the program is run");
+        let theme = ThemeSet::load_from_reader(&mut std::io::Cursor::new(THEME))
+            .expect("the bundled theme should be valid");
+        theme_set.themes.insert("togglebit".into(), theme);
+
+        Self::loaded(SyntaxSet::load_defaults_newlines(), theme_set)
+    }
 
-        Self { set, theme_set }
+    // Only plain text and the default themes, without touching the config dir
+    #[cfg(test)]
+    pub fn plain_text() -> Self {
+        let mut builder = SyntaxSet::new().into_builder();
+        builder.add_plain_text_syntax();
+        Self::loaded(builder.build(), ThemeSet::load_defaults())
+    }
+
+    // Waits for the background thread the first time
+    fn sets(&self) -> &Sets {
+        self.sets.get_or_init(|| {
+            let handle = self.pending.lock().unwrap().take();
+            handle
+                .and_then(|handle| handle.join().ok())
+                .expect("loading the syntaxes and themes failed")
+        })
     }
 
     pub fn highlight<'a>(&self, theme_name: &str, src: &'a str, ext: &str, buffer: &mut Lines<'a>) -> Result<()> {
         buffer.reset();
 
-        let syntax = self
-            .set
+        let Sets { set, theme_set } = self.sets();
+        let syntax = set
             .find_syntax_by_extension(ext)
-            .unwrap_or_else(|| self.set.find_syntax_plain_text());
+            .unwrap_or_else(|| set.find_syntax_plain_text());
 
-        let theme = self
-            .theme_set
+        let theme = theme_set
             .themes
             .get(theme_name)
             .ok_or_else(|| Error::InvalidTheme(theme_name.into()))?;
@@ -161,7 +202,7 @@ impl Highlighter {
 
         for line in LinesWithEndings::from(src) {
             // LinesWithEndings enables use of newlines mode
-            let spans = h.highlight_line(line, &self.set)?;
+            let spans = h.highlight_line(line, set)?;
             for (style, src) in spans {
                 buffer.push_span(Span { style, src });
             }
@@ -171,14 +212,23 @@ impl Highlighter {
         Ok(())
     }
 
+    pub fn has_theme(&self, name: &str) -> bool {
+        self.sets().theme_set.themes.contains_key(name)
+    }
+
+    /// Whether a syntax is found for the extension, rather than falling back to plain text
+    pub fn has_extension(&self, ext: &str) -> bool {
+        self.sets().set.find_syntax_by_extension(ext).is_some()
+    }
+
     pub(crate) fn print_syntaxes(&self) {
-        for syntax in self.set.syntaxes() {
+        for syntax in self.sets().set.syntaxes() {
             println!("{}", syntax.name);
         }
     }
 
     pub(crate) fn print_themes(&self) {
-        for name in self.theme_set.themes.keys() {
+        for name in self.sets().theme_set.themes.keys() {
             println!("{name}");
         }
     }
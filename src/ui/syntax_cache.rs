@@ -0,0 +1,238 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use syntect::dumps::{dump_to_uncompressed_file, from_uncompressed_dump_file};
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+
+const STAMP: &str = "stamp";
+const SYNTAXES: &str = "syntaxes.bin";
+const THEMES: &str = "themes.bin";
+
+// -----------------------------------------------------------------------------
+//   - Load -
+// -----------------------------------------------------------------------------
+/// How long it took to get the syntaxes and themes ready, for `--stats`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Load {
+    pub elapsed: Duration,
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// Parsed from the bundled and user files, and cached for next time
+    Parsed,
+    /// Read from the cache. `parse_time` is how long parsing took when the cache was written.
+    Cache { parse_time: Duration },
+}
+
+impl std::fmt::Display for Load {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        match self.source {
+            Source::Parsed => write!(f, "parsed in {:.1}ms", ms(self.elapsed)),
+            Source::Cache { parse_time } => write!(
+                f,
+                "loaded from cache in {:.1}ms (parsing took {:.1}ms)",
+                ms(self.elapsed),
+                ms(parse_time)
+            ),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//   - Cache -
+// -----------------------------------------------------------------------------
+/// Load the syntaxes and themes from the dumps in `cache`, as long as nothing under
+/// `syntaxes` or `themes` changed since they were written. Otherwise parse them and
+/// write new dumps.
+pub fn load(syntaxes: &Path, themes: &Path, cache: &Path) -> (SyntaxSet, ThemeSet, Load) {
+    let start = Instant::now();
+    let key = key(&[syntaxes, themes]);
+
+    if let Some((parse_time, set, theme_set)) = read(cache, key) {
+        let load = Load {
+            elapsed: start.elapsed(),
+            source: Source::Cache { parse_time },
+        };
+        return (set, theme_set, load);
+    }
+
+    let (set, theme_set) = parse(syntaxes, themes);
+    let elapsed = start.elapsed();
+    // A cache that can't be written only costs the next startup
+    _ = write(cache, key, elapsed, &set, &theme_set);
+    let load = Load {
+        elapsed,
+        source: Source::Parsed,
+    };
+    (set, theme_set, load)
+}
+
+fn parse(syntaxes: &Path, themes: &Path) -> (SyntaxSet, ThemeSet) {
+    #[cfg(not(debug_assertions))]
+    let set = SyntaxSet::load_defaults_newlines();
+    #[cfg(debug_assertions)]
+    let set = SyntaxSet::new();
+    let mut builder = set.into_builder();
+
+    // Try to load syntaxes from a config dir
+    _ = builder.add_from_folder(syntaxes, true);
+
+    builder.add_plain_text_syntax();
+    let set = builder.build();
+
+    let mut theme_set = ThemeSet::load_defaults();
+    theme_set
+        .add_from_folder(themes)
+        .expect("the theme directory should be created the first time the program is run");
+
+    (set, theme_set)
+}
+
+fn read(cache: &Path, key: u64) -> Option<(Duration, SyntaxSet, ThemeSet)> {
+    let stamp = std::fs::read_to_string(cache.join(STAMP)).ok()?;
+    let (stored, micros) = stamp.trim().split_once(' ')?;
+    if u64::from_str_radix(stored, 16).ok()? != key {
+        return None;
+    }
+
+    let parse_time = Duration::from_micros(micros.parse().ok()?);
+    let set = from_uncompressed_dump_file(cache.join(SYNTAXES)).ok()?;
+    let theme_set = from_uncompressed_dump_file(cache.join(THEMES)).ok()?;
+    Some((parse_time, set, theme_set))
+}
+
+fn write(cache: &Path, key: u64, parse_time: Duration, set: &SyntaxSet, theme_set: &ThemeSet) -> std::io::Result<()> {
+    std::fs::create_dir_all(cache)?;
+
+    // The stamp is written last, so a cache that was only partly written is never read
+    _ = std::fs::remove_file(cache.join(STAMP));
+    dump_to_uncompressed_file(set, cache.join(SYNTAXES)).map_err(std::io::Error::other)?;
+    dump_to_uncompressed_file(theme_set, cache.join(THEMES)).map_err(std::io::Error::other)?;
+    std::fs::write(cache.join(STAMP), format!("{key:016x} {}", parse_time.as_micros()))
+}
+
+// Changes whenever a file under one of the dirs is added, removed or modified,
+// and with every version of mimic, since the bundled sets might have changed
+fn key(dirs: &[&Path]) -> u64 {
+    let mut files = vec![];
+    for dir in dirs {
+        collect(dir, &mut files);
+    }
+    files.sort();
+
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    cfg!(debug_assertions).hash(&mut hasher);
+    files.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Every file under the dir with its size and modification time
+fn collect(dir: &Path, files: &mut Vec<(PathBuf, u64, u128)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(meta) = entry.metadata() else { continue };
+        if meta.is_dir() {
+            collect(&path, files);
+            continue;
+        }
+
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_nanos());
+        files.push((path, meta.len(), modified));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::ui::setup_paths::THEME;
+
+    struct Dirs {
+        syntaxes: PathBuf,
+        themes: PathBuf,
+        cache: PathBuf,
+    }
+
+    impl Dirs {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("mimic-syntax-cache-{name}-{}", std::process::id()));
+            _ = std::fs::remove_dir_all(&root);
+            let dirs = Self {
+                syntaxes: root.join("syntax"),
+                themes: root.join("themes"),
+                cache: root.join("cache"),
+            };
+            std::fs::create_dir_all(&dirs.syntaxes).unwrap();
+            std::fs::create_dir_all(&dirs.themes).unwrap();
+            std::fs::write(dirs.themes.join("togglebit.tmTheme"), THEME).unwrap();
+            dirs
+        }
+
+        fn load(&self) -> (ThemeSet, Source) {
+            let (_, theme_set, load) = load(&self.syntaxes, &self.themes, &self.cache);
+            (theme_set, load.source)
+        }
+    }
+
+    fn is_cached(source: Source) -> bool {
+        matches!(source, Source::Cache { .. })
+    }
+
+    #[test]
+    fn second_load_is_cached() {
+        let dirs = Dirs::new("cached");
+        let (themes, source) = dirs.load();
+        assert_eq!(source, Source::Parsed);
+        assert!(themes.themes.contains_key("togglebit"));
+
+        let (themes, source) = dirs.load();
+        assert!(is_cached(source));
+        assert!(themes.themes.contains_key("togglebit"));
+    }
+
+    #[test]
+    fn adding_a_file_invalidates_the_cache() {
+        let dirs = Dirs::new("added");
+        dirs.load();
+        std::fs::write(dirs.themes.join("other.tmTheme"), THEME).unwrap();
+
+        let (themes, source) = dirs.load();
+        assert_eq!(source, Source::Parsed);
+        assert!(themes.themes.contains_key("other"));
+        assert!(is_cached(dirs.load().1));
+    }
+
+    #[test]
+    fn modifying_a_file_invalidates_the_cache() {
+        let dirs = Dirs::new("modified");
+        dirs.load();
+
+        let file = std::fs::File::options().write(true).open(dirs.themes.join("togglebit.tmTheme")).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(60)).unwrap();
+        drop(file);
+
+        assert_eq!(dirs.load().1, Source::Parsed);
+        assert!(is_cached(dirs.load().1));
+    }
+
+    #[test]
+    fn partly_written_cache_is_ignored() {
+        let dirs = Dirs::new("partial");
+        dirs.load();
+        std::fs::remove_file(dirs.cache.join(STAMP)).unwrap();
+        assert_eq!(dirs.load().1, Source::Parsed);
+    }
+}
@@ -28,18 +28,54 @@ impl TextBuffer {
             return None;
         }
 
+        let len = self.peek()?.len();
+        let retval = &self.inner[self.index..self.index + len];
+        self.index += len;
+
+        Some(retval)
+    }
+
+    /// The word about to be typed, if the next character starts one.
+    /// Only plain ascii words, and not the start of an identifier like `foo_bar` or `v2`.
+    pub fn word(&self) -> Option<&str> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        if self.inner[..self.index].chars().next_back().is_some_and(is_word) {
+            return None;
+        }
+
+        let next = &self.inner[self.index..];
+        let len = next.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(next.len());
+        match next[len..].chars().next() {
+            Some(c) if is_word(c) => None,
+            _ => Some(&next[..len]).filter(|word| !word.is_empty()),
+        }
+    }
+
+    /// The part of the text that is still to be typed, from 0 to 1
+    pub fn left(&self) -> f64 {
+        match self.inner.is_empty() {
+            true => 0.0,
+            false => self.inner[self.index..].chars().count() as f64 / self.inner.chars().count() as f64,
+        }
+    }
+
+    /// Everything that is left to type, leaving the buffer empty
+    pub fn take(&mut self) -> String {
+        let rest = self.inner[self.index..].to_string();
+        self.inner.clear();
+        self.index = 0;
+        rest
+    }
+
+    /// The next string to type, without consuming it
+    pub fn peek(&self) -> Option<&str> {
         let next = &self.inner[self.index..];
         if next.starts_with(TAB) {
-            self.index += TAB.len();
             return Some(TAB);
         }
 
         let next_index = next.chars().next()?.len_utf8();
-        let retval = &next[..next_index];
-
-        self.index += next_index;
-
-        Some(retval)
+        Some(&next[..next_index])
     }
 }
 
@@ -56,7 +92,35 @@ mod test {
         assert_eq!("    ", buf.next().unwrap());
         assert_eq!("🐇", buf.next().unwrap());
         assert_eq!("b", buf.next().unwrap());
+        assert_eq!("c", buf.peek().unwrap());
         assert_eq!("c", buf.next().unwrap());
+        assert!(buf.peek().is_none());
+        assert!(buf.next().is_none());
+    }
+
+    #[test]
+    fn words() {
+        let mut buf = TextBuffer::new();
+        buf.push("let foo_bar = v2(bäz, qux);");
+
+        let mut words = vec![];
+        loop {
+            if let Some(word) = buf.word() {
+                words.push(word.to_string());
+            }
+            if buf.next().is_none() {
+                break;
+            }
+        }
+        assert_eq!(words, ["let", "qux"]);
+    }
+
+    #[test]
+    fn take_the_rest() {
+        let mut buf = TextBuffer::new();
+        buf.push("abc");
+        buf.next();
+        assert_eq!(buf.take(), "bc");
         assert!(buf.next().is_none());
     }
 }
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use anathema::default_widgets::Canvas;
+use anathema::geometry::{LocalPos, Size};
+use anathema::widgets::Style;
+
+use super::random::Random;
+use crate::parser::TransitionKind;
+
+// -----------------------------------------------------------------------------
+//   - Transition -
+// -----------------------------------------------------------------------------
+/// Reveal the new scene cell by cell, on top of a snapshot of the old scene.
+pub struct Transition {
+    duration: Duration,
+    size: Size,
+    // The order in which each cell (by index) is revealed
+    order: Vec<usize>,
+    snapshot: Option<Vec<Option<(char, Style)>>>,
+    // `None` until the first tick, so the instructions that follow
+    // the `clear` in the same tick are part of the new scene
+    elapsed: Option<Duration>,
+}
+
+impl Transition {
+    pub fn new(kind: TransitionKind, duration: Duration, size: Size, rand: &mut Random) -> Self {
+        Self {
+            duration,
+            size,
+            order: reveal_order(kind, size, rand),
+            snapshot: None,
+            elapsed: None,
+        }
+    }
+
+    pub fn tick(&mut self, dt: Duration) {
+        self.elapsed = Some(match self.elapsed {
+            Some(elapsed) => elapsed + dt,
+            None => Duration::ZERO,
+        });
+    }
+
+    /// Instructions are held while the transition is running
+    pub fn is_blocking(&self) -> bool {
+        self.elapsed.is_some() && !self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.elapsed.is_some_and(|elapsed| elapsed >= self.duration)
+    }
+
+    /// Number of cells showing the new scene
+    pub fn revealed(&self) -> usize {
+        let Some(elapsed) = self.elapsed else { return 0 };
+        if self.duration.is_zero() {
+            return self.order.len();
+        }
+        let total = self.order.len() as u128;
+        (total * elapsed.as_micros() / self.duration.as_micros()).min(total) as usize
+    }
+
+    /// Take a snapshot of the old scene, unless one has already been taken.
+    pub fn snapshot(&mut self, canvas: &mut Canvas) {
+        if self.snapshot.is_some() {
+            return;
+        }
+
+        let width = self.size.width as usize;
+        let cells = (0..self.order.len())
+            .map(|index| canvas.get(LocalPos::new((index % width) as u16, (index / width) as u16)))
+            .collect();
+        self.snapshot = Some(cells);
+    }
+
+    /// Cover the cells of the new scene that are not yet revealed with the old scene.
+    pub fn cover(&self, canvas: &mut Canvas) {
+        let Some(snapshot) = &self.snapshot else { return };
+        let revealed = self.revealed();
+        let width = self.size.width as usize;
+
+        for (index, old) in snapshot.iter().enumerate() {
+            if self.order[index] < revealed {
+                continue;
+            }
+            let pos = LocalPos::new((index % width) as u16, (index / width) as u16);
+            match old {
+                Some((c, style)) => canvas.put(*c, *style, pos),
+                None => canvas.erase(pos),
+            }
+        }
+    }
+}
+
+// The rank of each cell: a cell is revealed once `rank < revealed`
+fn reveal_order(kind: TransitionKind, size: Size, rand: &mut Random) -> Vec<usize> {
+    let (width, height) = (size.width as usize, size.height as usize);
+    let len = width * height;
+
+    match kind {
+        TransitionKind::Dissolve => {
+            let mut cells = (0..len).collect::<Vec<_>>();
+            for i in (1..len).rev() {
+                let j = rand.next(i as u64 + 1) as usize;
+                cells.swap(i, j);
+            }
+            let mut order = vec![0; len];
+            for (rank, index) in cells.into_iter().enumerate() {
+                order[index] = rank;
+            }
+            order
+        }
+        // Sweep the columns from the right edge to the left
+        TransitionKind::WipeLeft => (0..len)
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                (width - 1 - x) * height + y
+            })
+            .collect(),
+        // Sweep the rows from the top down
+        TransitionKind::WipeDown => (0..len).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(10);
+
+    fn transition(kind: TransitionKind, seed: u64) -> Transition {
+        let mut rand = Random::with_seed(seed);
+        Transition::new(kind, Duration::from_millis(100), Size::new(4, 3), &mut rand)
+    }
+
+    #[test]
+    fn dissolve_is_deterministic() {
+        let a = transition(TransitionKind::Dissolve, 123);
+        let b = transition(TransitionKind::Dissolve, 123);
+        assert_eq!(a.order, b.order);
+
+        let mut sorted = a.order.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn wipe_left_sweeps_columns() {
+        let t = transition(TransitionKind::WipeLeft, 1);
+        #[rustfmt::skip]
+        let expected = vec![
+            9, 6, 3, 0,
+            10, 7, 4, 1,
+            11, 8, 5, 2,
+        ];
+        assert_eq!(t.order, expected);
+    }
+
+    #[test]
+    fn wipe_down_sweeps_rows() {
+        let t = transition(TransitionKind::WipeDown, 1);
+        assert_eq!(t.order, (0..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn completes_after_duration() {
+        let mut t = transition(TransitionKind::Dissolve, 1);
+        assert!(!t.is_blocking());
+        assert_eq!(t.revealed(), 0);
+
+        // The first tick starts the transition
+        t.tick(FRAME);
+        assert!(t.is_blocking());
+        assert_eq!(t.revealed(), 0);
+
+        let mut revealed = 0;
+        for _ in 0..9 {
+            t.tick(FRAME);
+            assert!(t.revealed() >= revealed);
+            revealed = t.revealed();
+            assert!(t.is_blocking());
+        }
+
+        t.tick(FRAME);
+        assert!(t.is_done());
+        assert!(!t.is_blocking());
+        assert_eq!(t.revealed(), 12);
+    }
+
+    #[test]
+    fn zero_duration_is_instant() {
+        let mut rand = Random::with_seed(1);
+        let mut t = Transition::new(TransitionKind::WipeDown, Duration::ZERO, Size::new(2, 2), &mut rand);
+        t.tick(FRAME);
+        assert!(t.is_done());
+        assert_eq!(t.revealed(), 4);
+    }
+}
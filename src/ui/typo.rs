@@ -0,0 +1,191 @@
+use std::time::Duration;
+
+use super::instructions::Instruction;
+use super::random::Random;
+use super::textbuffer::TextBuffer;
+
+// How long it takes to notice the typo, before backspacing
+const NOTICE: Duration = Duration::from_millis(300);
+
+const QWERTY: [&str; 3] = ["qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+// -----------------------------------------------------------------------------
+//   - Auto typo -
+// -----------------------------------------------------------------------------
+/// Typos in random words. A typo is typed, noticed, backspaced and then the word
+/// is typed the way it was written, so the text ends up the same.
+#[derive(Debug, Default)]
+pub struct AutoTypo {
+    rate: u8,
+    max_per_line: Option<usize>,
+    // The row of the last typo and the number of typos on it
+    line: (i32, usize),
+    // Word starts to leave alone: the typo and the retyped word
+    skip: usize,
+}
+
+impl AutoTypo {
+    pub fn new(rate: u8, max_per_line: Option<usize>) -> Self {
+        Self {
+            rate,
+            max_per_line,
+            ..Default::default()
+        }
+    }
+
+    /// The instructions for a typo in the word about to be typed, if it gets one.
+    /// What was left to type is taken from the buffer and typed again after the typo.
+    pub fn next(&mut self, buffer: &mut TextBuffer, row: i32, rand: &mut Random) -> Option<Vec<Instruction>> {
+        if self.rate == 0 {
+            return None;
+        }
+        let word = buffer.word()?;
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+
+        if self.line.0 != row {
+            self.line = (row, 0);
+        }
+        if self.max_per_line.is_some_and(|max| self.line.1 >= max) || rand.next(100) >= self.rate as u64 {
+            return None;
+        }
+
+        let typo = mutate(word, rand)?;
+        self.line.1 += 1;
+        self.skip = 2;
+
+        Some(vec![
+            Instruction::LoadTypeBufferRaw(typo),
+            Instruction::Wait(NOTICE),
+            Instruction::Backspace(word.chars().count()),
+            Instruction::LoadTypeBufferRaw(buffer.take()),
+        ])
+    }
+}
+
+// Hit a key next to one of the letters, or swap two letters
+fn mutate(word: &str, rand: &mut Random) -> Option<String> {
+    if word.len() < 2 {
+        return None;
+    }
+
+    let mut chars = word.chars().collect::<Vec<_>>();
+    let swappable = (0..chars.len() - 1).filter(|&i| chars[i] != chars[i + 1]).collect::<Vec<_>>();
+    if rand.next(2) == 0 && !swappable.is_empty() {
+        let i = swappable[rand.next(swappable.len() as u64) as usize];
+        chars.swap(i, i + 1);
+    } else {
+        let i = rand.next(chars.len() as u64) as usize;
+        let near = neighbours(chars[i].to_ascii_lowercase());
+        let c = near[rand.next(near.len() as u64) as usize];
+        chars[i] = match chars[i].is_ascii_uppercase() {
+            true => c.to_ascii_uppercase(),
+            false => c,
+        };
+    }
+
+    Some(chars.into_iter().collect())
+}
+
+// The keys around a letter on a qwerty keyboard, where every row is shifted
+// a little to the right of the row above it
+fn neighbours(c: char) -> Vec<char> {
+    let Some((row, col)) = QWERTY.iter().enumerate().find_map(|(row, keys)| Some((row, keys.find(c)?))) else {
+        return vec![];
+    };
+
+    let key = |row: usize, col: usize| QWERTY.get(row)?.as_bytes().get(col).map(|&b| b as char);
+    let mut near = vec![];
+    near.extend(col.checked_sub(1).and_then(|col| key(row, col)));
+    near.extend(key(row, col + 1));
+    if let Some(above) = row.checked_sub(1) {
+        near.extend(key(above, col));
+        near.extend(key(above, col + 1));
+    }
+    near.extend(col.checked_sub(1).and_then(|col| key(row + 1, col)));
+    near.extend(key(row + 1, col));
+    near
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn buffer(text: &str) -> TextBuffer {
+        let mut buffer = TextBuffer::new();
+        buffer.push(text);
+        buffer
+    }
+
+    #[test]
+    fn neighbouring_keys() {
+        assert_eq!(neighbours('s'), ['a', 'd', 'w', 'e', 'z', 'x']);
+        assert_eq!(neighbours('q'), ['w', 'a']);
+        assert_eq!(neighbours('m'), ['n', 'j', 'k']);
+        assert!(neighbours('1').is_empty());
+    }
+
+    #[test]
+    fn typos_are_near_misses() {
+        let mut rand = Random::with_seed(5);
+        for _ in 0..100 {
+            let typo = mutate("Hello", &mut rand).unwrap();
+            assert_ne!(typo, "Hello");
+            assert_eq!(typo.len(), 5);
+
+            let mut sorted = typo.to_ascii_lowercase().chars().collect::<Vec<_>>();
+            sorted.sort();
+            let swapped = sorted == ['e', 'h', 'l', 'l', 'o'];
+            let replaced = typo.chars().zip("Hello".chars()).filter(|(a, b)| a != b).count() == 1;
+            assert!(swapped || replaced, "{typo}");
+        }
+        assert!(mutate("a", &mut rand).is_none());
+    }
+
+    #[test]
+    fn typo_then_the_rest() {
+        let mut typos = AutoTypo::new(100, None);
+        let mut buf = buffer("fn main");
+        let instructions = typos.next(&mut buf, 0, &mut Random::with_seed(1)).unwrap();
+
+        assert!(matches!(&instructions[0], Instruction::LoadTypeBufferRaw(typo) if typo != "fn"));
+        assert!(matches!(instructions[2], Instruction::Backspace(2)));
+        assert!(matches!(instructions.last(), Some(Instruction::LoadTypeBufferRaw(rest)) if rest == "fn main"));
+        assert!(buf.next().is_none());
+    }
+
+    #[test]
+    fn typo_and_retyped_word_are_left_alone() {
+        let mut typos = AutoTypo::new(100, None);
+        let mut rand = Random::with_seed(1);
+        assert!(typos.next(&mut buffer("fn"), 0, &mut rand).is_some());
+        assert!(typos.next(&mut buffer("nf"), 0, &mut rand).is_none());
+        assert!(typos.next(&mut buffer("fn"), 0, &mut rand).is_none());
+        assert!(typos.next(&mut buffer("main"), 0, &mut rand).is_some());
+    }
+
+    #[test]
+    fn max_per_line() {
+        let mut typos = AutoTypo::new(100, Some(1));
+        let mut rand = Random::with_seed(1);
+        let mut typo = |row| {
+            let typo = typos.next(&mut buffer("word"), row, &mut rand).is_some();
+            // Past the typo and the retyped word
+            typos.skip = 0;
+            typo
+        };
+
+        assert!(typo(0));
+        assert!(!typo(0));
+        assert!(typo(1));
+    }
+
+    #[test]
+    fn off() {
+        let mut typos = AutoTypo::new(0, None);
+        let typo = typos.next(&mut buffer("word"), 0, &mut Random::with_seed(1));
+        assert!(typo.is_none());
+    }
+}
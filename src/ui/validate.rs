@@ -0,0 +1,310 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::Path;
+
+use super::compile::base_dir;
+use super::context::Context;
+use super::loader::{Files, FsLoader, Loader};
+use super::markers::generate;
+use super::syntax::Highlighter;
+use crate::parser::{Anchor, Dest, Expr, Instruction, Instructions, Source};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Severity {
+    /// Runs, but probably not as intended
+    Warning,
+    /// Fails while running
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a script before running it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The line of the instruction, or of the `include` it's in
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: line {}: {}", self.severity, self.line, self.message)
+    }
+}
+
+/// Look for problems that would otherwise only show up while running:
+/// markers that are never defined, `write` to a directory that doesn't exist,
+/// unknown themes and extensions, and selections of nothing.
+///
+/// Paths are relative to the directory of `script`, like they are for [`compile`](super::compile).
+pub fn validate(parsed: &Instructions, script: Option<&Path>) -> Vec<Diagnostic> {
+    validate_with_loader(parsed, &FsLoader, base_dir(script))
+}
+
+/// Validate with `load` and `include` read through `loader`, relative to `base_dir`
+pub fn validate_with_loader(parsed: &Instructions, loader: &dyn Loader, base_dir: &Path) -> Vec<Diagnostic> {
+    validate_with(parsed, loader, base_dir, &Highlighter::new())
+}
+
+pub(crate) fn validate_with(
+    parsed: &Instructions,
+    loader: &dyn Loader,
+    base_dir: &Path,
+    highlighter: &Highlighter,
+) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        files: Files::new(loader, base_dir),
+        highlighter,
+        loaded: HashMap::new(),
+        context: Context::new(),
+        defined: HashSet::new(),
+        unknown_content: false,
+        targets: vec![],
+        diagnostics: vec![],
+    };
+    validator.walk(parsed, None);
+
+    // Text that is only known while running could define any marker
+    let severity = match validator.unknown_content {
+        true => Severity::Warning,
+        false => Severity::Error,
+    };
+    for (line, name) in std::mem::take(&mut validator.targets) {
+        if !validator.defined.contains(&name) {
+            validator.push(severity, line, format!("marker \"{name}\" is never defined"));
+        }
+    }
+
+    validator.diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    validator.diagnostics
+}
+
+struct Validator<'a> {
+    files: Files<'a>,
+    highlighter: &'a Highlighter,
+    // The content of everything loaded, or `None` if it's only known while running
+    loaded: HashMap<String, Option<String>>,
+    // What `${name}` in the script is, as far as it's known before running
+    context: Context,
+    defined: HashSet<String>,
+    unknown_content: bool,
+    targets: Vec<(usize, String)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    fn push(&mut self, severity: Severity, line: usize, message: String) {
+        self.diagnostics.push(Diagnostic { severity, line, message });
+    }
+
+    // Everything in an included file is reported on the line of the `include`
+    fn walk(&mut self, instructions: &Instructions, include: Option<usize>) {
+        for (line, inst) in instructions.iter() {
+            let line = include.unwrap_or(line);
+            match inst {
+                Instruction::Load(path, key, _) => {
+                    let content = match self.files.load(path) {
+                        Ok(content) => Some(content),
+                        Err(e) => {
+                            self.push(Severity::Error, line, e.to_string());
+                            None
+                        }
+                    };
+                    if let Some(content) = &content {
+                        self.context.set(key.clone(), content.clone());
+                    }
+                    self.loaded.insert(key.clone(), content);
+                }
+                Instruction::SetVariable(name, value) => _ = self.context.set_variable(name.clone(), value.clone()),
+                Instruction::Exec { key, .. } => _ = self.loaded.insert(key.clone(), None),
+                Instruction::Type { source, .. }
+                | Instruction::Insert { source, .. }
+                | Instruction::Replace { replacement: source, .. }
+                | Instruction::ReplaceAll { replacement: source, .. } => self.define(source),
+                Instruction::SetMarker(name) => _ = self.defined.insert(name.clone()),
+                Instruction::Goto(Dest::Marker { name, .. }) => self.targets.push((line, name.clone())),
+                Instruction::SelectMarkers { start, end } => {
+                    self.targets.push((line, start.clone()));
+                    self.targets.push((line, end.clone()));
+                }
+                Instruction::VirtualText {
+                    anchor: Anchor::Marker(name),
+                    ..
+                } => self.targets.push((line, name.clone())),
+                Instruction::WriteBuffer(path) => {
+                    let path = self.files.resolve(path);
+                    if path.parent().is_some_and(|dir| !dir.as_os_str().is_empty() && !dir.is_dir()) {
+                        let message = format!("can't write \"{}\", the directory doesn't exist", path.display());
+                        self.push(Severity::Error, line, message);
+                    }
+                }
+                Instruction::SetTheme(theme) => self.theme(theme, line),
+                Instruction::SetExtension(ext) => self.extension(ext, line),
+                Instruction::Scene { theme, extension, .. } => {
+                    if let Some(theme) = theme {
+                        self.theme(theme, line);
+                    }
+                    if let Some(ext) = extension {
+                        self.extension(ext, line);
+                    }
+                }
+                Instruction::Select {
+                    width: Expr::Int(width),
+                    height: Expr::Int(height),
+                } if *width == 0 || *height == 0 => {
+                    self.push(Severity::Warning, line, format!("`select {width} {height}` selects nothing"))
+                }
+                Instruction::Every { body, .. } | Instruction::Repeat { body, .. } | Instruction::Def { body, .. } => {
+                    self.walk(body, include)
+                }
+                Instruction::If { then, otherwise, .. } => {
+                    self.walk(then, include);
+                    self.walk(otherwise, include);
+                }
+                Instruction::Include { path, .. } => match self.files.enter(path) {
+                    Ok(src) => {
+                        match crate::parser::parse(&src) {
+                            Ok(included) => {
+                                for warning in included.warnings() {
+                                    self.push(Severity::Warning, line, format!("in \"{}\" on {warning}", path.display()));
+                                }
+                                self.walk(&included, Some(line));
+                            }
+                            Err(e) => self.push(Severity::Error, line, e.to_string()),
+                        }
+                        self.files.leave();
+                    }
+                    Err(e) => self.push(Severity::Error, line, e.to_string()),
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn define(&mut self, source: &Source) {
+        let content = match source {
+            Source::Str(content) => Some(content),
+            Source::Ident(key) => self.loaded.get(key).and_then(Option::as_ref),
+            Source::Env { .. } => None,
+        };
+        let Some(content) = content else {
+            self.unknown_content = true;
+            return;
+        };
+        // Variables that are only known while running could hold anything
+        let content = match self.context.substitute(content) {
+            Ok(content) => content,
+            Err(_) => {
+                self.unknown_content = true;
+                content.clone()
+            }
+        };
+
+        if let (_, Some(markers)) = generate(content.as_str()) {
+            self.defined.extend(markers.rows().map(|(name, _)| name.to_string()));
+        }
+    }
+
+    fn theme(&mut self, theme: &str, line: usize) {
+        if !self.highlighter.has_theme(theme) {
+            self.push(Severity::Error, line, format!("no theme named \"{theme}\""));
+        }
+    }
+
+    fn extension(&mut self, ext: &str, line: usize) {
+        if !self.highlighter.has_extension(ext) {
+            self.push(Severity::Warning, line, format!("no syntax for \"{ext}\", it will be plain text"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ui::loader::MemoryLoader;
+
+    fn validate(src: &str, loader: &MemoryLoader) -> Vec<String> {
+        let parsed = crate::parser::parse(src).unwrap();
+        let diagnostics = validate_with(&parsed, loader, Path::new(""), &Highlighter::plain_text());
+        diagnostics.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn undefined_markers() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("a.rs", "// @loaded\nfn a() {}\n");
+
+        let src = "load \"a.rs\" as a
+type a
+type \"// @typed\\nx\"
+mark made
+goto @typed
+goto @loaded
+goto @made
+repeat 2 {
+    goto @missing
+}";
+        let diagnostics = validate(src, &loader);
+        assert_eq!(diagnostics, ["error: line 9: marker \"missing\" is never defined"]);
+
+        // Content from outside the script could define it
+        let diagnostics = validate(&format!("{src}\ntype env(\"CODE\")"), &loader);
+        assert_eq!(diagnostics, ["warning: line 9: marker \"missing\" is never defined"]);
+
+        // Variables are substituted first
+        let src = "load \"a.rs\" as a
+set code \"// @here\\nfn main() {}\"
+type \"${code}\"
+insert \"${a}\"
+goto @here
+goto @loaded";
+        assert!(validate(src, &loader).is_empty());
+        // unless they are only known while running
+        let src = "exec \"echo\" as out\ntype \"${out}\"\ngoto @here";
+        assert_eq!(validate(src, &loader), ["warning: line 3: marker \"here\" is never defined"]);
+    }
+
+    #[test]
+    fn themes_extensions_writes_and_selections() {
+        let src = "theme \"base16-ocean.dark\"
+theme \"nope\"
+extension \"txt\"
+extension \"nope\"
+write \"out.txt\"
+write \"/no/such/dir/out.txt\"
+select 0 2
+select 2 2
+load \"missing.rs\" as m";
+        let diagnostics = validate(src, &MemoryLoader::new());
+        assert_eq!(diagnostics, [
+            "error: line 2: no theme named \"nope\"",
+            "warning: line 4: no syntax for \"nope\", it will be plain text",
+            "error: line 6: can't write \"/no/such/dir/out.txt\", the directory doesn't exist",
+            "warning: line 7: `select 0 2` selects nothing",
+            "error: line 9: failed to load \"missing.rs\"",
+        ]);
+    }
+
+    #[test]
+    fn includes_report_on_the_include() {
+        let mut loader = MemoryLoader::new();
+        loader.insert("part.echo", "type \"// @part\\n\"\ntheme \"nope\"");
+
+        let diagnostics = validate("wait 1s\ninclude \"part.echo\"\ngoto @part", &loader);
+        assert_eq!(diagnostics, ["error: line 2: no theme named \"nope\""]);
+
+        // Including the warnings from parsing it
+        loader.insert("old.echo", "wait 1s\nwait 2");
+        let diagnostics = validate("include \"old.echo\"", &loader);
+        assert_eq!(diagnostics, ["warning: line 1: in \"old.echo\" on line 2: `2` without a unit is read as seconds, write `2s`"]);
+    }
+}
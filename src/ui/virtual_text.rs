@@ -0,0 +1,194 @@
+use super::anchor::{Anchored, Shift};
+
+/// The most lines `below` virtual text can add to the document, so the rows
+/// on screen never drift too far from the rows in the document
+pub const MAX_BELOW_LINES: usize = 32;
+
+// -----------------------------------------------------------------------------
+//   - Virtual text -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    row: usize,
+    text: String,
+    below: bool,
+}
+
+impl Entry {
+    fn lines(&self) -> usize {
+        match self.below {
+            true => self.text.lines().count().max(1),
+            false => 0,
+        }
+    }
+}
+
+/// What is drawn on a row of the screen
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScreenRow<'a> {
+    /// A line of the document
+    Line(usize),
+    /// A line of virtual text below a line of the document
+    Virtual(usize, &'a str),
+    /// The rest of a wrapped line, from a column of the line
+    Wrapped(usize, usize),
+}
+
+/// Text that is drawn next to, or below, a line without being part of the document.
+///
+/// Entries are anchored to a row, and move with it the same way markers do.
+#[derive(Debug, Default)]
+pub struct VirtualText {
+    // Sorted by row, and in the order they were added within a row
+    entries: Vec<Entry>,
+}
+
+impl VirtualText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add text to the end of the row, or below it.
+    /// Fails with the number of lines that can still be added below if there is no room.
+    pub fn add(&mut self, row: usize, text: String, below: bool) -> Result<(), usize> {
+        let entry = Entry { row, text, below };
+        let used = self.entries.iter().map(Entry::lines).sum::<usize>();
+        if used + entry.lines() > MAX_BELOW_LINES {
+            return Err(MAX_BELOW_LINES - used);
+        }
+
+        let index = self.entries.partition_point(|e| e.row <= row);
+        self.entries.insert(index, entry);
+        Ok(())
+    }
+
+    /// Remove the text of a row, or all of it
+    pub fn clear(&mut self, row: Option<usize>) {
+        match row {
+            Some(row) => self.entries.retain(|e| e.row != row),
+            None => self.entries.clear(),
+        }
+    }
+
+    /// The text drawn after the end of the row
+    pub fn eol(&self, row: usize) -> impl Iterator<Item = &str> {
+        self.entries.iter().filter(move |e| e.row == row && !e.below).map(|e| e.text.as_str())
+    }
+
+    // The lines drawn below the row
+    fn below(&self, row: usize) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .filter(move |e| e.row == row && e.below)
+            .flat_map(|e| match e.text.is_empty() {
+                true => vec![""],
+                false => e.text.lines().collect(),
+            })
+    }
+
+    /// The row on screen of a row in the document, before scrolling
+    pub fn screen_row(&self, row: usize) -> usize {
+        row + self.entries.iter().filter(|e| e.row < row).map(Entry::lines).sum::<usize>()
+    }
+
+    /// What is on each screen row for a document of `line_count` lines, before scrolling
+    pub fn screen_rows(&self, line_count: usize) -> Vec<ScreenRow<'_>> {
+        let mut rows = vec![];
+        for row in 0..line_count {
+            rows.push(ScreenRow::Line(row));
+            rows.extend(self.below(row).map(|text| ScreenRow::Virtual(row, text)));
+        }
+        rows
+    }
+}
+
+impl Anchored for VirtualText {
+    fn shift(&mut self, shift: Shift) {
+        self.entries.retain_mut(|e| match shift.row(e.row) {
+            Some(row) => {
+                e.row = row;
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn virtual_text(entries: &[(usize, &str, bool)]) -> VirtualText {
+        let mut virtual_text = VirtualText::new();
+        for &(row, text, below) in entries {
+            virtual_text.add(row, text.into(), below).unwrap();
+        }
+        virtual_text
+    }
+
+    #[test]
+    fn row_mapping() {
+        let virtual_text = virtual_text(&[(1, "=> 3", true), (1, "eol", false), (3, "a\nb", true)]);
+
+        let screen = (0..6).map(|row| virtual_text.screen_row(row)).collect::<Vec<_>>();
+        assert_eq!(screen, [0, 1, 3, 4, 7, 8]);
+
+        let rows = virtual_text.screen_rows(5);
+        let expected = [
+            ScreenRow::Line(0),
+            ScreenRow::Line(1),
+            ScreenRow::Virtual(1, "=> 3"),
+            ScreenRow::Line(2),
+            ScreenRow::Line(3),
+            ScreenRow::Virtual(3, "a"),
+            ScreenRow::Virtual(3, "b"),
+            ScreenRow::Line(4),
+        ];
+        assert_eq!(rows, expected);
+
+        // The screen row of every line is where `screen_rows` puts it
+        for row in 0..5 {
+            assert_eq!(rows[virtual_text.screen_row(row)], ScreenRow::Line(row));
+        }
+    }
+
+    #[test]
+    fn eol() {
+        let virtual_text = virtual_text(&[(2, "a", false), (2, "b", true), (2, "c", false)]);
+        assert_eq!(virtual_text.eol(2).collect::<Vec<_>>(), ["a", "c"]);
+        assert_eq!(virtual_text.eol(1).count(), 0);
+    }
+
+    #[test]
+    fn anchors_move_with_lines() {
+        let mut virtual_text = virtual_text(&[(1, "a", true), (4, "b", false)]);
+        virtual_text.shift(Shift::Down { from: 2, count: 3 });
+        assert_eq!(virtual_text.eol(7).collect::<Vec<_>>(), ["b"]);
+        assert_eq!(virtual_text.screen_row(2), 3);
+
+        // Joining the first two lines
+        virtual_text.shift(Shift::Up { row: 0, count: 1 });
+        assert_eq!(virtual_text.screen_rows(2)[1], ScreenRow::Virtual(0, "a"));
+        assert_eq!(virtual_text.eol(6).collect::<Vec<_>>(), ["b"]);
+    }
+
+    #[test]
+    fn clear() {
+        let mut virtual_text = virtual_text(&[(0, "a", true), (1, "b", true), (1, "c", false)]);
+        virtual_text.clear(Some(1));
+        assert_eq!(virtual_text.screen_rows(2).len(), 3);
+        virtual_text.clear(None);
+        assert_eq!(virtual_text.screen_rows(2).len(), 2);
+    }
+
+    #[test]
+    fn cap_below_lines() {
+        let mut virtual_text = VirtualText::new();
+        let text = "line\n".repeat(MAX_BELOW_LINES - 1);
+        virtual_text.add(0, text, true).unwrap();
+        assert_eq!(virtual_text.add(0, "a\nb".into(), true), Err(1));
+        // End of line text doesn't count
+        assert!(virtual_text.add(0, "a\nb".into(), false).is_ok());
+        assert!(virtual_text.add(0, "a".into(), true).is_ok());
+    }
+}
@@ -0,0 +1,113 @@
+use anathema::geometry::Pos;
+use unicode_width::UnicodeWidthChar;
+
+use super::virtual_text::ScreenRow;
+
+// -----------------------------------------------------------------------------
+//   - Wrap -
+// -----------------------------------------------------------------------------
+/// The columns a line continues from on new rows, given the display width of each
+/// of its characters. The first row is `width` wide and the ones after it start
+/// `indent` columns in. Characters are never split, and every row holds at least one.
+pub fn breaks(widths: impl IntoIterator<Item = usize>, width: usize, indent: usize) -> Vec<usize> {
+    let mut breaks = vec![];
+    let mut capacity = width.max(1);
+    let mut col = 0;
+    let mut used = 0;
+    for w in widths {
+        if used > 0 && used + w > capacity {
+            breaks.push(col);
+            capacity = width.saturating_sub(indent).max(1);
+            used = 0;
+        }
+        used += w;
+        col += w;
+    }
+    breaks
+}
+
+/// The screen rows with every line of `text` that doesn't fit in `width` continued on `Wrapped` rows.
+/// The cursor at the end of its line takes a cell, so it moves to the next row rather than off the screen.
+pub fn wrap_rows<'a>(
+    rows: Vec<ScreenRow<'a>>,
+    text: &str,
+    width: usize,
+    indent: usize,
+    cursor: Pos,
+) -> Vec<ScreenRow<'a>> {
+    let lines = text.split('\n').collect::<Vec<_>>();
+    let mut wrapped = Vec::with_capacity(rows.len());
+    for screen_row in rows {
+        let ScreenRow::Line(row) = screen_row else {
+            wrapped.push(screen_row);
+            continue;
+        };
+
+        let line = lines.get(row).copied().unwrap_or_default();
+        let mut widths = line.chars().map(|c| c.width().unwrap_or(0)).collect::<Vec<_>>();
+        if cursor.y as usize == row && cursor.x as usize >= widths.iter().sum() {
+            widths.push(1);
+        }
+
+        wrapped.push(screen_row);
+        wrapped.extend(breaks(widths, width, indent).into_iter().map(|col| ScreenRow::Wrapped(row, col)));
+    }
+    wrapped
+}
+
+/// Where the cursor is on screen before scrolling: on the last row of its line that starts at or before it
+pub fn cursor(rows: &[ScreenRow<'_>], cursor: Pos, indent: usize) -> (i32, i32) {
+    let row = cursor.y as usize;
+    let mut screen = (cursor.x, rows.len() as i32);
+    for (y, screen_row) in rows.iter().enumerate() {
+        match *screen_row {
+            ScreenRow::Line(r) if r == row => screen = (cursor.x, y as i32),
+            ScreenRow::Wrapped(r, start) if r == row && start as i32 <= cursor.x => {
+                screen = (cursor.x - start as i32 + indent as i32, y as i32)
+            }
+            ScreenRow::Line(r) if r > row => break,
+            _ => {}
+        }
+    }
+    screen
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn break_by_display_width() {
+        assert!(breaks([1; 4], 4, 0).is_empty());
+        assert_eq!(breaks([1; 10], 4, 0), [4, 8]);
+        // Rows after the first are narrower by the indent
+        assert_eq!(breaks([1; 10], 4, 2), [4, 6, 8]);
+        // A wide character that doesn't fit moves to the next row
+        assert_eq!(breaks([1, 1, 1, 2, 1], 4, 0), [3]);
+        // Even if the row is narrower than the character
+        assert_eq!(breaks([2, 2], 1, 0), [2]);
+    }
+
+    #[test]
+    fn rows_and_cursor() {
+        let rows = vec![ScreenRow::Line(0), ScreenRow::Virtual(0, "note"), ScreenRow::Line(1)];
+        let text = "abcdefgh\nab";
+
+        let rows = wrap_rows(rows, text, 5, 1, Pos::new(1, 1));
+        let expected = [
+            ScreenRow::Line(0),
+            ScreenRow::Wrapped(0, 5),
+            ScreenRow::Virtual(0, "note"),
+            ScreenRow::Line(1),
+        ];
+        assert_eq!(rows, expected);
+        assert_eq!(cursor(&rows, Pos::new(4, 0), 1), (4, 0));
+        assert_eq!(cursor(&rows, Pos::new(6, 0), 1), (2, 1));
+        assert_eq!(cursor(&rows, Pos::new(1, 1), 1), (1, 3));
+
+        // The cursor at the end of a full row goes on the next one
+        let rows = wrap_rows(vec![ScreenRow::Line(0)], "abcde", 5, 0, Pos::new(5, 0));
+        assert_eq!(rows, [ScreenRow::Line(0), ScreenRow::Wrapped(0, 5)]);
+        assert_eq!(cursor(&rows, Pos::new(5, 0), 0), (0, 1));
+    }
+}
@@ -0,0 +1,9 @@
+fn main() {
+    // @body
+    let x = 1;
+}
+
+fn other() {
+    // @other
+    let y = 2;
+}